@@ -0,0 +1,122 @@
+use crate::casemap;
+use crate::config;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How far back messages count toward how busy a channel is.
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Scales the chance of a random reply by how busy a channel is, so the bot chimes in less when everyone's talking
+/// and more when it's quiet.
+///
+/// At `target` messages per minute, the chance is left as it is; at twice that it's halved, at half that it's
+/// doubled, and so on, but it never goes below `min_chance` or above `max_chance`. A target of 0 turns this off.
+#[derive(Clone, Debug)]
+pub struct Activity {
+    target: f64,
+    min_chance: f64,
+    max_chance: f64,
+    /// When each recent message was said in each channel, casefolded, oldest first.
+    messages: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Activity {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let min_chance = config::chance_option(options, "activity_min_chance", 0.0)?;
+        let max_chance = config::chance_option(options, "activity_max_chance", 0.1)?;
+        if min_chance > max_chance {
+            return Err("activity_min_chance is more than activity_max_chance".to_string());
+        }
+        Ok(Activity {
+            target: config::option(options, "activity_target")?.unwrap_or(0.0),
+            min_chance,
+            max_chance,
+            messages: HashMap::new(),
+        })
+    }
+
+    /// Takes the settings from a freshly built tracker, keeping track of recent messages.
+    pub fn reconfigure(&mut self, fresh: Activity) {
+        self.target = fresh.target;
+        self.min_chance = fresh.min_chance;
+        self.max_chance = fresh.max_chance;
+    }
+
+    /// Counts a message said in a channel.
+    pub fn record_message(&mut self, channel: &str, now: Instant) {
+        let times = self.messages.entry(casemap::fold(channel)).or_default();
+        times.push_back(now);
+        while times.front().map(|&time| now.duration_since(time) > WINDOW).unwrap_or(false) {
+            times.pop_front();
+        }
+    }
+
+    /// Gets how many messages a minute have been said in a channel lately.
+    pub fn per_minute(&self, channel: &str, now: Instant) -> f64 {
+        let recent = self.messages
+            .get(&casemap::fold(channel))
+            .map(|times| times.iter().filter(|&&time| now.duration_since(time) <= WINDOW).count())
+            .unwrap_or(0);
+        recent as f64 * 60.0 / WINDOW.as_secs() as f64
+    }
+
+    /// Scales a chance of replying in a channel by how busy it is.
+    pub fn scale(&self, channel: &str, chance: f64, now: Instant) -> f64 {
+        if self.target <= 0.0 || chance <= 0.0 {
+            return chance;
+        }
+        // a channel that's been silent the whole window counts as one message short of it
+        let rate = self.per_minute(channel, now).max(60.0 / WINDOW.as_secs() as f64);
+        (chance * self.target / rate).max(self.min_chance).min(self.max_chance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Activity;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn activity(target: &str) -> Activity {
+        let options = [("activity_target", target), ("activity_min_chance", "0.01"), ("activity_max_chance", "0.2")]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        Activity::from_options(&options).unwrap()
+    }
+
+    #[test]
+    fn counts_messages_in_the_window() {
+        let mut activity = activity("1");
+        let start = Instant::now();
+        for i in 0..20 {
+            activity.record_message("#a", start + Duration::from_secs(i * 30));
+        }
+        let end = start + Duration::from_secs(19 * 30);
+        assert_eq!(activity.per_minute("#A", end), 2.0);
+        assert_eq!(activity.per_minute("#b", end), 0.0);
+        assert_eq!(activity.per_minute("#a", end + Duration::from_secs(3600)), 0.0);
+    }
+
+    #[test]
+    fn scales_chance_against_the_target() {
+        let mut activity = activity("2");
+        let start = Instant::now();
+        // 2 a minute is right on target, and 4 a minute halves the chance
+        for i in 0..20 {
+            activity.record_message("#a", start + Duration::from_secs(i * 30));
+        }
+        let end = start + Duration::from_secs(19 * 30);
+        assert!((activity.scale("#a", 0.1, end) - 0.1).abs() < 1e-9);
+        for i in 0..20 {
+            activity.record_message("#a", start + Duration::from_secs(i * 30 + 15));
+        }
+        assert!((activity.scale("#a", 0.1, end) - 0.05).abs() < 1e-9);
+        // quiet channels go up, but only as far as the bounds
+        assert_eq!(activity.scale("#quiet", 0.1, end), 0.2);
+        assert_eq!(activity.scale("#a", 0.001, end), 0.01);
+        // turned off, or with no chance to begin with, nothing changes
+        assert_eq!(self::activity("0").scale("#quiet", 0.1, end), 0.1);
+        assert_eq!(activity.scale("#quiet", 0.0, end), 0.0);
+    }
+}
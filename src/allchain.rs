@@ -0,0 +1,168 @@
+use crate::casemap;
+use markov_chain::Chain;
+use crate::stats::ChainStats;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// An allchain that's partway through being built.
+struct Pending {
+    chain: Chain<String>,
+    /// Users whose chains still have to be merged in, as canonical nicks.
+    remaining: HashSet<String>,
+    /// How many chains were left out for having a different order.
+    skipped: usize,
+}
+
+/// A cache of each channel's allchain, the chain of everything said there by everyone.
+///
+/// An allchain is only ever derived from the user chains: it's built by merging them, kept up to date as they're
+/// trained, and thrown out whenever they change in any other way, so that it gets built again from what they are now.
+/// Nothing about an allchain is saved.
+///
+/// Building one can be spread out over time by merging in one user chain at a time. Messages trained on in the
+/// meantime go into the partly built allchain if their user's chain has already been merged into it.
+///
+/// The global chain, merged from the allchains of every channel that shares its chains, is kept here too. It isn't
+/// trained as messages come in, so it's only good for a while after it's built, and it's thrown out along with any
+/// allchain.
+#[derive(Default)]
+pub struct AllChains {
+    /// Allchains that have been built, keyed by casefolded channel.
+    chains: HashMap<String, Chain<String>>,
+    /// Allchains that are being built, keyed by casefolded channel.
+    pending: HashMap<String, Pending>,
+    /// The global chain, with when it was built.
+    global: Option<(Chain<String>, Instant)>,
+}
+
+impl AllChains {
+    pub fn new() -> Self {
+        AllChains::default()
+    }
+
+    /// Gets a channel's allchain, if it's been built.
+    pub fn get(&self, channel: &str) -> Option<&Chain<String>> {
+        self.chains.get(&casemap::fold(channel))
+    }
+
+    /// Gets the global chain, if it was built less than `max_age` ago.
+    pub fn global(&self, max_age: Duration, now: Instant) -> Option<&Chain<String>> {
+        match self.global {
+            Some((ref chain, built)) if now.duration_since(built) < max_age => Some(chain),
+            _ => None,
+        }
+    }
+
+    /// Keeps a global chain that was just built.
+    pub fn set_global(&mut self, chain: Chain<String>, now: Instant) {
+        self.global = Some((chain, now));
+    }
+
+    /// Gets whether a channel's allchain is being built.
+    pub fn is_pending(&self, channel: &str) -> bool {
+        self.pending.contains_key(&casemap::fold(channel))
+    }
+
+    /// Starts building a channel's allchain from the chains of the given users.
+    pub fn begin(&mut self, channel: &str, order: usize, users: HashSet<String>) {
+        self.pending.insert(casemap::fold(channel), Pending {
+            chain: Chain::new(order),
+            remaining: users,
+            skipped: 0,
+        });
+    }
+
+    /// Gets the next user whose chain goes into a channel's allchain, or None if they're all merged in.
+    pub fn next_user(&self, channel: &str) -> Option<String> {
+        self.pending
+            .get(&casemap::fold(channel))
+            .and_then(|pending| pending.remaining.iter().next().cloned())
+    }
+
+    /// Merges a user's chain into a channel's partly built allchain, or just crosses them off if they turn out not to
+    /// have one.
+    pub fn merge(&mut self, channel: &str, user: &str, chain: Option<&Chain<String>>) {
+        if let Some(pending) = self.pending.get_mut(&casemap::fold(channel)) {
+            pending.remaining.remove(user);
+            match chain {
+                Some(chain) if chain.order() == pending.chain.order() => {
+                    pending.chain.merge(chain);
+                }
+                Some(_) => pending.skipped += 1,
+                None => {}
+            }
+        }
+    }
+
+    /// Finishes building a channel's allchain once every user chain is merged in, returning whether it's built.
+    pub fn finish(&mut self, channel: &str) -> bool {
+        let channel = casemap::fold(channel);
+        match self.pending.get(&channel) {
+            Some(pending) if pending.remaining.is_empty() => {}
+            Some(_) => return false,
+            None => return self.chains.contains_key(&channel),
+        }
+        let pending = self.pending.remove(&channel).unwrap();
+        if pending.skipped > 0 {
+            warn!("left {} chains out of the allchain for {} because their order isn't {}; \
+                   they keep their old order until forgotten", pending.skipped, channel, pending.chain.order());
+        }
+        self.chains.insert(channel, pending.chain);
+        true
+    }
+
+    /// Trains a channel's allchain on a message that a user's chain, of the given order, was just trained on.
+    ///
+    /// Allchains that haven't been built yet are left alone, since they'll pick the message up from the user chain
+    /// when they are, and so are allchains of a different order, which leave that user chain out.
+    pub fn train(&mut self, channel: &str, user: &str, tokens: Vec<String>, order: usize) {
+        let channel = casemap::fold(channel);
+        if let Some(chain) = self.chains.get_mut(&channel) {
+            if chain.order() == order {
+                chain.train(tokens);
+            }
+        } else if let Some(pending) = self.pending.get_mut(&channel) {
+            if pending.chain.order() == order && !pending.remaining.contains(user) {
+                pending.chain.train(tokens);
+            }
+        }
+    }
+
+    /// Drops a channel's allchain after one of its user chains was changed some other way than by training.
+    pub fn invalidate(&mut self, channel: &str) {
+        let channel = casemap::fold(channel);
+        let built = self.chains.remove(&channel).is_some();
+        if self.pending.remove(&channel).is_some() || built {
+            debug!("dropped the allchain for {}", channel);
+        }
+        self.global = None;
+    }
+
+    /// Drops every allchain whose order isn't the one its channel is configured with now.
+    pub fn invalidate_stale<F>(&mut self, order_for: F)
+    where
+        F: Fn(&str) -> usize,
+    {
+        self.chains.retain(|channel, chain| chain.order() == order_for(channel));
+        self.pending.retain(|channel, pending| pending.chain.order() == order_for(channel));
+        // which channels are shared may have changed too
+        self.global = None;
+    }
+
+    /// Drops every allchain.
+    pub fn clear(&mut self) {
+        self.chains.clear();
+        self.pending.clear();
+        self.global = None;
+    }
+
+    /// Gets roughly how much memory the allchains take up, including partly built ones.
+    pub fn bytes(&self) -> u64 {
+        self.chains
+            .values()
+            .chain(self.pending.values().map(|pending| &pending.chain))
+            .chain(self.global.iter().map(|(chain, _)| chain))
+            .map(|chain| ChainStats::of(chain).bytes)
+            .sum()
+    }
+}
@@ -0,0 +1,105 @@
+use crate::casemap;
+use crate::chat::ChatServer;
+use crate::config::{self, Server as ServerConfig};
+use irc::client::prelude::*;
+use std::time::{Duration, Instant};
+
+/// How often to try for the configured nick while on an alt nick, in seconds, unless `nick_reclaim_interval` says
+/// otherwise.
+pub const DEFAULT_RECLAIM_INTERVAL: u64 = 300;
+
+/// Keeps track of which of the configured nicks the bot is on, and takes the first one back when it's free.
+///
+/// While registering, the irc crate itself moves on to the next of `alt_nicks` each time the server says a nick is in
+/// use; this only says so in the log. Once registered on an alt nick, the configured nick is asked for again as soon
+/// as whoever has it quits or changes nicks, and every `nick_reclaim_interval` seconds besides, in case that was
+/// missed. With a NickServ password, NickServ takes the nick back instead.
+#[derive(Clone, Debug)]
+pub struct AltNicks {
+    /// The nick from the config, followed by the alt nicks, in the order they're tried.
+    nicks: Vec<String>,
+    /// Whether to take the configured nick back; NickServ does it when there's a password.
+    reclaim: bool,
+    interval: Duration,
+    /// When the configured nick was last asked for, or when we registered without it.
+    last_try: Instant,
+}
+
+impl AltNicks {
+    pub fn from_config(config: &ServerConfig) -> Result<Self, String> {
+        let mut nicks = vec![config.nick.clone()];
+        nicks.extend(config.alt_nicks());
+        let interval = match config.options {
+            Some(ref options) => config::option(options, "nick_reclaim_interval")?,
+            None => None,
+        };
+        Ok(AltNicks {
+            nicks,
+            reclaim: config.nickserv_password.is_none(),
+            interval: Duration::from_secs(interval.unwrap_or(DEFAULT_RECLAIM_INTERVAL)),
+            last_try: Instant::now(),
+        })
+    }
+
+    /// Follows a message, given the nick the server knows us by, if it's said yet.
+    pub fn handle(&mut self, server: &dyn ChatServer, msg: &Message, current: Option<&str>) {
+        let wanted = self.nicks[0].clone();
+        let on_wanted = current.map(|nick| casemap::eq(nick, &wanted)).unwrap_or(true);
+        match msg.command {
+            Command::Response(Response::ERR_NICKNAMEINUSE, ref args) if current.is_none() => {
+                // <me> <nick>
+                let taken = args.get(1).map(String::as_str).unwrap_or("?");
+                let next = self.nicks
+                    .iter()
+                    .position(|nick| casemap::eq(nick, taken))
+                    .and_then(|i| self.nicks.get(i + 1));
+                match next {
+                    Some(next) => event!(warn, "nick", "nick {} is in use; trying {}", taken, next),
+                    None => event!(error, "nick", "nick {} is in use, and there are no alt nicks left to try", taken),
+                }
+            }
+            Command::Response(Response::ERR_NICKNAMEINUSE, _) if self.reclaim => {
+                debug!("nick {} is still in use", wanted);
+            }
+            Command::Response(Response::RPL_WELCOME, ref args) => {
+                if let Some(nick) = args.first().filter(|nick| !casemap::eq(nick, &wanted)) {
+                    event!(warn, "nick", "registered as {}, since {} is in use", nick, wanted);
+                    self.last_try = Instant::now();
+                }
+            }
+            // whoever had the nick let go of it
+            Command::QUIT(_) | Command::NICK(_)
+                if !on_wanted
+                    && self.reclaim
+                    && msg.source_nickname().map(|nick| casemap::eq(nick, &wanted)).unwrap_or(false) =>
+            {
+                event!(info, "nick", "{} is free; taking it back", wanted);
+                self.ask_for_wanted(server);
+            }
+            _ => {}
+        }
+    }
+
+    /// Asks for the configured nick again if we're on an alt nick and it's been long enough since the last try,
+    /// returning how long until the next one.
+    pub fn reclaim(&mut self, server: &dyn ChatServer, current: Option<&str>, now: Instant) -> Option<Duration> {
+        let current = current?;
+        if !self.reclaim || self.interval.as_secs() == 0 || casemap::eq(current, &self.nicks[0]) {
+            return None;
+        }
+        let next = self.last_try + self.interval;
+        if next > now {
+            return Some(next - now);
+        }
+        debug!("trying for nick {} again", self.nicks[0]);
+        self.ask_for_wanted(server);
+        Some(self.interval)
+    }
+
+    fn ask_for_wanted(&mut self, server: &dyn ChatServer) {
+        self.last_try = Instant::now();
+        if let Err(e) = server.send(Command::NICK(self.nicks[0].clone())) {
+            error!("could not ask for nick {}: {}", self.nicks[0], e);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::checksum::ChecksumWriter;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Writes a file without ever leaving it half-written.
+///
+/// The data goes to a temporary file next to `path` which is then renamed over it, so a crash mid-write leaves
+/// the previous file intact. Up to `backups` older copies are kept as `path.1`, `path.2`, and so on. A checksum of the
+/// data is written after it, so that a file that gets damaged later is caught when it's read.
+pub fn write(path: &str, data: &[u8], backups: usize) -> io::Result<()> {
+    write_with(path, backups, |file| file.write_all(data))
+}
+
+/// Writes a file the same way as `write`, with whatever `write_data` writes to it as it goes, so the data never has
+/// to be all in memory at once.
+pub fn write_with<F>(path: &str, backups: usize, write_data: F) -> io::Result<()>
+    where F: FnOnce(&mut dyn Write) -> io::Result<()>
+{
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        let mut writer = ChecksumWriter::new(BufWriter::new(file));
+        write_data(&mut writer)?;
+        let file = writer.finish()?.into_inner()?;
+        file.sync_all()?;
+    }
+    rotate_backups(path, backups)?;
+    fs::rename(&tmp_path, path)?;
+    // make sure the rename itself hits the disk; not every platform lets us open a directory, so this is best-effort
+    let dir = match Path::new(path).parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Shifts `path.1` through `path.{backups - 1}` up by one and makes `path.1` a copy of the current file.
+fn rotate_backups(path: &str, backups: usize) -> io::Result<()> {
+    if backups == 0 || !Path::new(path).exists() {
+        return Ok(());
+    }
+    for i in (1..backups).rev() {
+        let from = format!("{}.{}", path, i);
+        if Path::new(&from).exists() {
+            fs::rename(&from, format!("{}.{}", path, i + 1))?;
+        }
+    }
+    let first = format!("{}.1", path);
+    if Path::new(&first).exists() {
+        fs::remove_file(&first)?;
+    }
+    // a hard link is free, and the rename that follows leaves it pointing at the old data
+    fs::hard_link(path, &first).or_else(|_| fs::copy(path, &first).map(|_| ()))
+}
@@ -0,0 +1,126 @@
+use crate::bot::{BlobFile, IrcBot};
+use chrono::{DateTime, Local};
+use crate::compress::Compression;
+use crate::config::{self, Server as ServerConfig};
+use crate::encrypt::Key;
+use crate::s3::S3Store;
+use crate::snapshot::{self, Snapshot};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// Somewhere a blob of chains is kept, along with snapshots of it.
+///
+/// CBOR storage keeps the blob in a file, and S3 storage keeps it in a bucket, so that a bot on a host that doesn't
+/// keep its disk between restarts doesn't lose its chains. SQLite and split storage save chains their own way, but
+/// still keep their snapshots in a file store.
+pub trait BlobStore: Send {
+    /// Says where the blob is kept, for messages.
+    fn describe(&self) -> String;
+
+    /// Reads the blob, failing with `NotFound` if nothing has been saved yet.
+    fn load(&self) -> io::Result<BlobFile>;
+
+    /// Saves a blob over the last one, keeping up to `backups` older copies if the store keeps them itself. Returns
+    /// how big it is where it's kept, and how big it was before it was compressed.
+    fn save(&self, blob: &BlobFile, compression: Compression, backups: usize) -> io::Result<(u64, u64)>;
+
+    /// Saves a blob as a snapshot with the given name.
+    fn save_snapshot(&self, name: &str, blob: &BlobFile, compression: Compression, now: DateTime<Local>)
+        -> io::Result<Snapshot>;
+
+    /// Lists the snapshots, oldest first.
+    fn snapshots(&self) -> io::Result<Vec<Snapshot>>;
+
+    /// Reads one of the snapshots this store listed.
+    fn load_snapshot(&self, snapshot: &Snapshot) -> io::Result<BlobFile>;
+
+    /// Finds the newest snapshot with the given name.
+    fn find_snapshot(&self, name: &str) -> io::Result<Option<Snapshot>> {
+        Ok(self.snapshots()?.into_iter().rfind(|s| s.name == name))
+    }
+}
+
+/// Opens the blob store a server's `storage` option says to use, for the given chain file, encrypting what it saves
+/// if there's an encryption key.
+pub fn open(config: &ServerConfig, chain_file: &str) -> io::Result<Box<dyn BlobStore>> {
+    let options = config.options.clone().unwrap_or_default();
+    let key = Key::from_options(&options)?;
+    match options.get("storage").map(String::as_str) {
+        Some("s3") => Ok(Box::new(S3Store::from_options(&options, chain_file, key)?)),
+        _ => Ok(Box::new(FileStore::new(chain_file, key))),
+    }
+}
+
+/// Checks that the `storage` option names a kind of storage, and that the options that only work with some kinds of
+/// it go with the one that's used.
+pub fn check_options(options: &HashMap<String, String>) -> Result<(), String> {
+    let storage = options.get("storage").map(String::as_str);
+    match storage {
+        None | Some("cbor") | Some("sqlite") | Some("split") => {}
+        Some("s3") if !options.contains_key("s3_bucket") => {
+            return Err("storage = \"s3\" needs the s3_bucket to keep chains in".to_string());
+        }
+        Some("s3") => {}
+        Some(other) => {
+            return Err(format!("storage must be \"cbor\", \"sqlite\", \"split\", or \"s3\", not {:?}", other))
+        }
+    }
+    let in_blob = storage != Some("sqlite") && storage != Some("split");
+    if config::option::<u64>(options, "memory_limit")?.unwrap_or(0) != 0 && storage != Some("sqlite") {
+        return Err("memory_limit needs storage = \"sqlite\" to load evicted chains back from".to_string());
+    }
+    if options.contains_key("era_buckets") && !in_blob {
+        return Err("era_buckets keeps its chains in the blob, so it needs storage = \"cbor\" or \"s3\"".to_string());
+    }
+    if let Some(key) = options.get("encryption_key") {
+        Key::parse(key)?;
+        if !in_blob {
+            return Err("encryption_key only encrypts blobs, so it needs storage = \"cbor\" or \"s3\"".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Keeps a blob in a file, with its backups next to it and its snapshots in `<file>.snapshots`.
+pub struct FileStore {
+    path: String,
+    key: Option<Key>,
+}
+
+impl FileStore {
+    pub fn new(path: &str, key: Option<Key>) -> Self {
+        FileStore { path: path.to_string(), key }
+    }
+}
+
+impl BlobStore for FileStore {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn load(&self) -> io::Result<BlobFile> {
+        IrcBot::read_blob_or_backup(&self.path, self.key.as_ref())
+    }
+
+    fn save(&self, blob: &BlobFile, compression: Compression, backups: usize) -> io::Result<(u64, u64)> {
+        let raw_size = blob.write(&self.path, backups, compression, self.key.as_ref())?;
+        Ok((fs::metadata(&self.path)?.len(), raw_size))
+    }
+
+    fn save_snapshot(&self, name: &str, blob: &BlobFile, compression: Compression, now: DateTime<Local>)
+        -> io::Result<Snapshot>
+    {
+        let snapshot = snapshot::new_path(&self.path, name, now)?;
+        blob.write(&snapshot.location, 0, compression, self.key.as_ref())?;
+        Ok(snapshot)
+    }
+
+    fn snapshots(&self) -> io::Result<Vec<Snapshot>> {
+        snapshot::list(&self.path)
+    }
+
+    fn load_snapshot(&self, snapshot: &Snapshot) -> io::Result<BlobFile> {
+        IrcBot::read_blob(&snapshot.location, self.key.as_ref())
+    }
+}
@@ -0,0 +1,2093 @@
+use crate::casemap;
+use crate::colors;
+use crate::config;
+use crate::era;
+use crate::generate;
+use crate::provenance::{self, Generated};
+use crate::quote;
+use crate::quality::LengthBias;
+use crate::reply;
+use crate::stats::{self, ChainStats};
+use markov_chain::Chain;
+use chrono::Local;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+use super::{IrcBot, ALL_CHANNELS};
+
+/// Most words listed by `!markov topwords`.
+const TOP_WORDS: usize = 10;
+/// How long someone has to confirm `!markov deleteme`.
+const DELETE_CONFIRM_TIME: Duration = Duration::from_secs(60);
+/// Most snapshots listed by `!markov snapshots`.
+const LISTED_SNAPSHOTS: usize = 10;
+
+/// Where a command was sent, and what was sent with it.
+pub struct Context<'a> {
+    pub sender: &'a str,
+    /// The channel the command was said in, or None if it was sent in private.
+    pub channel: Option<&'a str>,
+    /// What the command started with, which depends on the server and channel.
+    pub prefix: &'a str,
+    /// The command being run.
+    pub command: &'static dyn Command,
+    /// The words after the command's name.
+    pub args: &'a [&'a str],
+}
+
+impl<'a> Context<'a> {
+    fn arg(&self, i: usize) -> Option<&'a str> {
+        self.args.get(i).cloned()
+    }
+
+    /// Gets whether an argument is there and names a channel.
+    fn is_channel(&self, i: usize) -> bool {
+        self.arg(i).map(config::is_channel_name).unwrap_or(false)
+    }
+
+    /// Describes how to use the command, with the prefix it was run with.
+    fn usage(&self) -> String {
+        usage(self.prefix, self.command, self.channel.is_some())
+    }
+}
+
+/// Who can run a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Privilege {
+    Anyone,
+    /// Only the server's owners.
+    Admin,
+}
+
+/// A subcommand, like `emulate` in `!markov emulate`.
+///
+/// Every command is listed in `COMMANDS`; adding one there is all it takes for the bot to run it and list it in help.
+pub trait Command: Sync {
+    /// The word after the prefix that runs the command.
+    fn name(&self) -> &'static str;
+
+    /// What goes after the command's name in a channel, with alternatives separated by ` | `.
+    fn usage(&self) -> &'static str;
+
+    /// What goes after the command's name in private, where commands that need a channel take it as an argument.
+    fn private_usage(&self) -> &'static str {
+        self.usage()
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Anyone
+    }
+
+    /// Subcommands, the word right after the command's name, that need a different privilege than the command does.
+    fn subcommands(&self) -> &'static [(&'static str, Privilege)] {
+        &[]
+    }
+
+    /// Whether the command can be said in a channel.
+    fn public(&self) -> bool {
+        true
+    }
+
+    /// Whether the command can be sent in private.
+    fn private(&self) -> bool {
+        false
+    }
+
+    /// Runs the command, returning what to reply with, if anything.
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String>;
+}
+
+/// Every command the bot knows, in the order help lists them.
+pub static COMMANDS: &[&dyn Command] = &[
+    &Emulate, &Mix, &Force, &All, &Global, &Topic, &Quote, &Again, &Longer, &Shorter, &Ignore, &Listen, &Private,
+    &Public, &Chance, &Temp, &Forget, &Scrub, &Alias, &Status, &Ignored, &Stats, &Reload, &Save, &Settings, &Shutdown,
+    &Prune, &Snapshot, &Restore, &Snapshots, &Join, &Part, &MyData, &DeleteMe, &TopWords, &Vocab, &Source, &Chat,
+    &Help,
+];
+
+/// Finds a command by name.
+fn find(name: &str) -> Option<&'static dyn Command> {
+    COMMANDS.iter().find(|command| command.name() == name).cloned()
+}
+
+/// Gets whether there's a command with the given name, for checking command aliases.
+pub fn is_command(name: &str) -> bool {
+    find(name).is_some()
+}
+
+/// Gets whether a command can be run in a channel, or in private if `public` isn't set.
+fn available(command: &dyn Command, public: bool) -> bool {
+    if public { command.public() } else { command.private() }
+}
+
+/// Gets the privilege it takes to run a command with the given arguments, which is the subcommand's if the first one
+/// names a subcommand.
+fn privilege(command: &dyn Command, args: &[&str]) -> Privilege {
+    args.first()
+        .and_then(|arg| command.subcommands().iter().find(|&&(name, _)| name == *arg))
+        .map(|&(_, privilege)| privilege)
+        .unwrap_or_else(|| command.privilege())
+}
+
+/// Describes how to use a command in a channel, or in private if `public` isn't set.
+///
+/// Forms that start with admin-only subcommands, like `bot|human <nick>`, are marked as such.
+fn usage(prefix: &str, command: &dyn Command, public: bool) -> String {
+    let usage = if public { command.usage() } else { command.private_usage() };
+    let forms = usage
+        .split(" | ")
+        .map(|args| {
+            let form = format!("{} {} {}", prefix, command.name(), args).trim_end().to_string();
+            let first = args.split(' ').next().unwrap_or("");
+            let admin = command.privilege() != Privilege::Admin && !first.is_empty()
+                && first.split('|').all(|sub| privilege(command, &[sub]) == Privilege::Admin);
+            if admin { format!("{} (admins only)", form) } else { form }
+        })
+        .collect::<Vec<_>>();
+    format!("Usage: {}", forms.join(" | "))
+}
+
+/// Lists the commands that can be run in a channel, or in private if `public` isn't set.
+fn command_list(prefix: &str, public: bool) -> String {
+    let names = COMMANDS
+        .iter()
+        .filter(|command| available(**command, public))
+        .map(|command| command.name())
+        .collect::<Vec<_>>();
+    format!("Commands: {}; say \"{} help <command>\" for how to use one", names.join(", "), prefix)
+}
+
+impl IrcBot {
+    /// Handles a command said in a channel.
+    pub(super) fn handle_command(&mut self, sender: &str, channel: &str, parts: &[&str]) {
+        let cost = self.limiter.limits().command_cost;
+        if !self.allow(channel, sender, cost, true) {
+            return;
+        }
+        if let Some((name, message)) = self.run_command(sender, Some(channel), parts) {
+            self.reply(name, channel, sender, &message);
+        }
+    }
+
+    /// Handles a command sent in private.
+    pub(super) fn private_command(&mut self, sender: &str, parts: &[&str]) {
+        if let Some((_, message)) = self.run_command(sender, None, parts) {
+            self.send_privmsg(sender, &message);
+        }
+    }
+
+    /// Runs the command in `parts`, which start with the prefix, returning the name of the command to route the reply
+    /// by along with the reply itself.
+    ///
+    /// Commands that don't exist, or can't be run where they were sent, get the list of commands in reply.
+    fn run_command(&mut self, sender: &str, channel: Option<&str>, parts: &[&str])
+        -> Option<(&'static str, String)>
+    {
+        let (prefix, public) = (parts[0], channel.is_some());
+        let command = match parts.get(1).and_then(|name| find(name)) {
+            Some(command) if available(command, public) => command,
+            _ => {
+                let list = command_list(prefix, public);
+                return Some((Help.name(), match parts.get(1) {
+                    Some(name) => format!("I don't know \"{}\". {}", name, list),
+                    None => list,
+                }));
+            }
+        };
+        let args = &parts[2..];
+        if privilege(command, args) == Privilege::Admin && !self.is_admin(sender) {
+            let name = match command.privilege() {
+                Privilege::Admin => command.name().to_string(),
+                Privilege::Anyone => format!("{} {}", command.name(), args[0]),
+            };
+            return Some((command.name(), format!("Only admins can use {} {}", prefix, name)));
+        }
+        self.lifetime.commands_handled += 1;
+        let ctx = Context { sender, channel, prefix, command, args };
+        command.run(self, &ctx).map(|message| (command.name(), message))
+    }
+
+    /// Sends a user everything stored about them, in private.
+    pub(super) fn send_my_data(&mut self, sender: &str, prefix: &str) {
+        let user = self.canonical_nick(sender);
+        let mut lines = Vec::new();
+
+        let mut aliases = self.aliases
+            .iter()
+            .filter(|&(_, nick)| *nick == user)
+            .map(|(alias, _)| alias.as_str())
+            .collect::<Vec<_>>();
+        if !aliases.is_empty() {
+            aliases.sort();
+            lines.push(format!("These nicks count as you: {}", aliases.join(", ")));
+        }
+        if let Some(&bot) = self.bot_overrides.get(&user) {
+            lines.push(format!("An admin marked you as {}", if bot { "a bot" } else { "a human" }));
+        }
+        self.restore_where(|(_, u)| *u == user);
+
+        let mut channels = self.chains
+            .keys()
+            .chain(self.user_settings.keys())
+            .chain(self.quotes.keys())
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels.dedup();
+        for channel in channels {
+            let mut facts = Vec::new();
+            if let Some(chain) = self.chains.get(channel).and_then(|users| users.get(&user)) {
+                let learned = ChainStats::of(chain);
+                facts.push(format!("a chain of {} tokens and {} states", learned.tokens, learned.states));
+            }
+            if let Some(settings) = self.user_settings.get(channel).and_then(|users| users.get(&user)) {
+                if settings.ignore {
+                    facts.push("you asked to be ignored".to_string());
+                }
+                if settings.no_emulate {
+                    facts.push("you asked not to be emulated".to_string());
+                }
+                if channel != ALL_CHANNELS {
+                    facts.push(format!("reply chance {}", settings.chance));
+                }
+                if settings.capped_tokens > 0 {
+                    facts.push(format!("{} tokens counted toward the training cap", settings.capped_tokens));
+                }
+            }
+            let quoted = self.quotes.get(channel).map(|quotes| quotes.iter().filter(|q| q.user == user).count());
+            if let Some(quoted) = quoted.filter(|&quoted| quoted > 0) {
+                facts.push(format!("{} saved quotes", quoted));
+            }
+            if !facts.is_empty() {
+                let channel = if channel == ALL_CHANNELS { "Every channel" } else { channel.as_str() };
+                lines.push(format!("{}: {}", channel, facts.join(", ")));
+            }
+        }
+
+        if lines.is_empty() {
+            lines.push(format!("I don't have anything stored about {}", sender));
+        } else {
+            lines.insert(0, format!("Here's everything I have stored about {}:", user));
+            // everyone who talks gets one of these, so it's only worth mentioning alongside something else
+            if self.last_seen.contains_key(&user) {
+                lines.push("When you were last around, so your settings can be forgotten if you're gone long enough"
+                    .to_string());
+            }
+            lines.push(format!("Use {} deleteme to delete all of it", prefix));
+        }
+        for line in lines {
+            self.send_privmsg(sender, &line);
+        }
+    }
+
+    /// Deletes everything stored about a user once they've confirmed that's what they want.
+    pub(super) fn delete_me_command(&mut self, sender: &str, prefix: &str, arg: Option<&str>) -> String {
+        let user = self.canonical_nick(sender);
+        let now = Instant::now();
+        self.pending_deletions.retain(|_, &mut asked| now.duration_since(asked) < DELETE_CONFIRM_TIME);
+        match arg {
+            None => {
+                self.pending_deletions.insert(user, now);
+                format!("This deletes everything I've learned from you and all of your settings, in every channel, \
+                         and can't be undone. Say \"{} deleteme confirm\" within {} seconds to go ahead",
+                        prefix, DELETE_CONFIRM_TIME.as_secs())
+            }
+            Some("confirm") if self.pending_deletions.remove(&user).is_some() => {
+                let channels = self.delete_user(&user);
+                event!(info, "deleteme", "deleted {} from {} channels at their request", user, channels);
+                format!("Deleted your chains in {} channels and all of your settings. I'll learn from you again \
+                         unless you use {} ignore --all", channels, prefix)
+            }
+            Some("confirm") => format!("Say \"{} deleteme\" first", prefix),
+            Some(_) => usage(prefix, &DeleteMe, true),
+        }
+    }
+
+    /// Describes everything the bot has learned and done, along with how it's been running this session.
+    pub(super) fn bot_stats(&self) -> String {
+        let learned = self.chains
+            .values()
+            .flat_map(|users| users.values())
+            .map(ChainStats::of)
+            .fold(ChainStats::default(), |a, b| a + b);
+        let users = self.chains.values().map(|users| users.len()).sum::<usize>();
+        let counters = &self.counters;
+        let blob_size = match (counters.blob_size, counters.blob_raw_size) {
+            (Some(size), Some(raw)) if raw != size => {
+                format!("{} ({} uncompressed)", stats::format_size(size), stats::format_size(raw))
+            }
+            (Some(size), _) => stats::format_size(size),
+            (None, _) => "unknown".to_string(),
+        };
+        let last_save = counters.last_save
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or("never".to_string());
+        let evicted = if self.evicted.is_empty() {
+            String::new()
+        } else {
+            format!(" plus {} evicted chains", self.evicted.len())
+        };
+        let unloaded = if self.unloaded.is_empty() {
+            String::new()
+        } else {
+            format!(" plus {} channels not loaded yet", self.unloaded.len())
+        };
+        let shadowed = if counters.shadowed == 0 {
+            String::new()
+        } else {
+            format!(", held back {} in shadow mode", counters.shadowed)
+        };
+        let lifetime = &self.lifetime;
+        format!("{} tokens, {} states, {} users in {} channels, about {} in memory{}{}; blob {}, last saved {}; \
+                 up {}, seen {} messages, trained on {}, sent {}{}; \
+                 all time: trained on {} messages, sent {} replies, handled {} commands",
+                learned.tokens, learned.states, users, self.chains.len(), stats::format_size(learned.bytes), evicted, unloaded,
+                blob_size, last_save,
+                stats::format_duration(counters.uptime()), counters.messages_seen,
+                counters.messages_trained, self.queue.sent(), shadowed,
+                lifetime.total_trained(), lifetime.replies_sent, lifetime.commands_handled)
+    }
+
+    /// Describes what the bot has learned in a channel.
+    pub(super) fn channel_stats(&mut self, channel: &str) -> String {
+        self.load_channel(channel);
+        match self.chains.get(&casemap::fold(channel)) {
+            Some(users) => {
+                let learned = users
+                    .values()
+                    .map(ChainStats::of)
+                    .fold(ChainStats::default(), |a, b| a + b);
+                let here = self.presence
+                    .count(channel)
+                    .map(|count| format!(", {} here now", count))
+                    .unwrap_or_default();
+                format!("{}: {} tokens, {} states, {} users, order {}{}",
+                        channel, self.figure(channel, learned.tokens), self.figure(channel, learned.states),
+                        self.figure(channel, users.len()), self.order_for(channel), here)
+            }
+            None => format!("I don't know anything about {}", channel),
+        }
+    }
+
+    /// Gets a nick the way replies about a channel show it, in its own color if the channel uses colors.
+    fn show_nick(&self, channel: &str, nick: &str) -> String {
+        if self.uses_colors(channel) { colors::nick(nick) } else { nick.to_string() }
+    }
+
+    /// Gets a figure the way stats about a channel show it, in bold if the channel uses colors.
+    fn figure<T: Display>(&self, channel: &str, figure: T) -> String {
+        let figure = figure.to_string();
+        if self.uses_colors(channel) { colors::bold(&figure) } else { figure }
+    }
+
+    /// Describes what the bot has learned from a user in a channel.
+    pub(super) fn user_stats(&mut self, channel: &str, user: &str) -> String {
+        self.restore_chain(channel, user);
+        match self.find_chain(channel, user) {
+            Ok(chain) => {
+                let learned = ChainStats::of(chain);
+                let total = self.chains
+                    .get(&casemap::fold(channel))
+                    .map(|users| users.values().map(|c| ChainStats::of(c).tokens).sum::<u64>())
+                    .unwrap_or(0);
+                let share = if total == 0 { 0.0 } else { learned.tokens as f64 / total as f64 * 100.0 };
+                let trained = self.lifetime.trained_from(&casemap::fold(channel), &self.canonical_nick(user));
+                format!("{} in {}: {} tokens, {} states, order {}, {}% of the channel, trained on {} messages",
+                        self.show_nick(channel, user), channel, self.figure(channel, learned.tokens),
+                        self.figure(channel, learned.states), chain.order(),
+                        self.figure(channel, format!("{:.2}", share)), self.figure(channel, trained))
+            }
+            Err(e) => e,
+        }
+    }
+
+    pub(super) fn word_command(&mut self, command: &str, channel: &str, user: Option<&str>) -> String {
+        if command == "topwords" {
+            self.top_words(channel, user)
+        } else {
+            self.vocab(channel, user)
+        }
+    }
+
+    /// Gets a user's chain in a channel, or everyone's if there's no user, along with who it belongs to.
+    pub(super) fn chain_for(&mut self, channel: &str, user: Option<&str>) -> Result<(&Chain<String>, String), String> {
+        match user {
+            Some(user) => {
+                self.restore_chain(channel, user);
+                let who = self.show_nick(channel, user);
+                self.find_chain(channel, user).map(|chain| (chain, who))
+            }
+            None => {
+                self.load_channel(channel);
+                if !self.chains.contains_key(&casemap::fold(channel)) {
+                    return Err(format!("I don't know anything about {}", channel));
+                }
+                Ok((self.allchain(channel), format!("everyone in {}", channel)))
+            }
+        }
+    }
+
+    /// Lists the words a user, or the whole channel, says the most and starts sentences with the most.
+    pub(super) fn top_words(&mut self, channel: &str, user: Option<&str>) -> String {
+        let (chain, who) = match self.chain_for(channel, user) {
+            Ok(found) => found,
+            Err(e) => return e,
+        };
+        let list = |counts: Vec<(&str, u64)>| {
+            counts.into_iter()
+                .take(TOP_WORDS)
+                .map(|(word, count)| format!("{} ({})", word, count))
+                .collect::<Vec<_>>()
+        };
+        let words = list(stats::word_counts(chain));
+        if words.is_empty() {
+            return format!("{} hasn't said anything I can use", who);
+        }
+        let starts = list(stats::start_counts(chain));
+        let mut message = reply::fit_list(&format!("Top words for {}: ", who), &words);
+        // the words come first, and the starts only go along if they fit too
+        if !starts.is_empty() {
+            let starts = reply::fit_list("; starts with ", &starts);
+            if message.len() + starts.len() <= reply::MAX_REPLY_LEN {
+                message.push_str(&starts);
+            }
+        }
+        message
+    }
+
+    /// Says how many distinct words a user, or the whole channel, has used.
+    pub(super) fn vocab(&mut self, channel: &str, user: Option<&str>) -> String {
+        match self.chain_for(channel, user) {
+            Ok((chain, who)) => format!("{} has used {} different words", who, stats::vocabulary(chain)),
+            Err(e) => e,
+        }
+    }
+
+    /// Says whose chains a sentence the bot generated could have come from, going by its number, or the last one
+    /// generated for a channel if there's no number.
+    pub(super) fn source(&mut self, channel: Option<&str>, id: Option<u64>) -> String {
+        let found = match (id, channel) {
+            (Some(id), _) => self.generated.get(id),
+            (None, Some(channel)) => self.generated.last_in(channel),
+            (None, None) => None,
+        };
+        let generated = match (found.cloned(), id, channel) {
+            (Some(generated), _, _) => generated,
+            (None, Some(id), _) => return format!("I don't remember generating #{}", id),
+            (None, None, Some(channel)) => return format!("I haven't generated anything in {} lately", channel),
+            (None, None, None) => return "I haven't generated anything lately".to_string(),
+        };
+        let Generated { id, channel, source, text, .. } = generated;
+        let (keys, from) = match source {
+            provenance::Source::User(user) => {
+                let from = format!("{}'s chain", self.show_nick(&channel, &user));
+                (vec![(channel.clone(), user)], from)
+            }
+            provenance::Source::Users(users) => {
+                let names = users.iter().map(|user| self.show_nick(&channel, user)).collect::<Vec<_>>();
+                let from = format!("{}'s chains", names.join(" and "));
+                (users.into_iter().map(|user| (channel.clone(), user)).collect(), from)
+            }
+            provenance::Source::Channel => {
+                self.load_channel(&channel);
+                (self.chain_keys(|c| c == channel), format!("everyone in {}", channel))
+            }
+            provenance::Source::Global => (self.chain_keys(|c| self.shares_globally(c)), "everyone".to_string()),
+            // the topic chain isn't anyone's, so there's nobody to break it down by
+            provenance::Source::Topics => return format!("#{} in {} came from the topics set there", id, channel),
+        };
+        // chains that were evicted are looked at straight from the store, without keeping them around
+        let evicted = keys.iter()
+            .filter(|key| self.evicted.contains(*key))
+            .filter_map(|key| self.load_evicted(&key.0, &key.1).map(|chain| (key.1.clone(), chain)))
+            .collect::<Vec<_>>();
+        let chains = keys.iter()
+            .filter_map(|(c, user)| {
+                self.chains.get(c).and_then(|users| users.get(user)).map(|chain| (user.as_str(), chain))
+            })
+            .chain(evicted.iter().map(|(user, chain)| (user.as_str(), chain)));
+        let tokens = self.tokenizer_for(&channel).tokenize(&text);
+        let (steps, counts) = provenance::contributions(tokens, chains);
+        let prefix = format!("#{} in {} came from {}", id, channel, from);
+        if counts.is_empty() {
+            return format!("{}, but none of it is in their chains anymore", prefix);
+        }
+        let counts = counts.into_iter()
+            .map(|(user, count)| format!("{} {}/{}", self.show_nick(&channel, &user), count, steps))
+            .collect::<Vec<_>>();
+        reply::fit_list(&format!("{}; steps each could have taken: ", prefix), &counts)
+    }
+
+    /// Generates the last sentence for a channel over again, from the same chains and picking up from the same words,
+    /// aiming for one longer or shorter than it was if that's asked for.
+    pub(super) fn regenerate(&mut self, channel: &str, bias: LengthBias) -> String {
+        let last = match self.generated.last_in(channel) {
+            Some(last) => last.clone(),
+            None => return format!("I haven't generated anything in {} lately", channel),
+        };
+        let words = self.tokenizer_for(channel).count_words(&last.text);
+        let filter = match self.filter_for(channel).biased(bias, words) {
+            Some(filter) => filter,
+            None => return "That's as short as it gets".to_string(),
+        };
+        let seeds = &last.seeds;
+        let generated = match last.source {
+            provenance::Source::User(ref user) => {
+                if self.no_emulate(user) {
+                    return format!("{} has asked not to be emulated", user);
+                }
+                self.restore_chain(channel, user);
+                self.chains
+                    .get(&casemap::fold(channel))
+                    .and_then(|users| users.get(user))
+                    .filter(|chain| !chain.is_empty())
+                    .and_then(|chain| self.generate_filtered(channel, chain, seeds, filter))
+            }
+            provenance::Source::Users(ref users) => {
+                if let Some(user) = users.iter().find(|user| self.no_emulate(user)) {
+                    return format!("{} has asked not to be emulated", user);
+                }
+                for user in users {
+                    self.restore_chain(channel, user);
+                }
+                let blended = {
+                    let chains = users.iter()
+                        .filter_map(|user| self.chains.get(&casemap::fold(channel)).and_then(|c| c.get(user)))
+                        .collect::<Vec<_>>();
+                    generate::blend(&chains)
+                };
+                blended.ok()
+                    .filter(|chain| !chain.is_empty())
+                    .and_then(|chain| self.generate_filtered(channel, &chain, seeds, filter))
+            }
+            provenance::Source::Channel => {
+                self.build_allchain(channel);
+                self.allchains
+                    .get(channel)
+                    .filter(|chain| !chain.is_empty())
+                    .and_then(|chain| self.generate_filtered(channel, chain, seeds, filter))
+            }
+            provenance::Source::Global => {
+                self.build_global();
+                self.allchains
+                    .global(self.global_max_age, Instant::now())
+                    .filter(|chain| !chain.is_empty())
+                    .and_then(|chain| self.generate_filtered(channel, chain, seeds, filter))
+            }
+            provenance::Source::Topics => {
+                self.topic_chains
+                    .get(&casemap::fold(channel))
+                    .filter(|chain| !chain.is_empty())
+                    .and_then(|chain| self.generate_filtered(channel, chain, seeds, filter))
+            }
+        };
+        self.note_generated(channel, last.source.clone(), seeds, generated)
+            .unwrap_or_else(|| format!("Couldn't come up with anything else that's allowed in {}", channel))
+    }
+
+    /// Gets the channel and user of every chain, loaded or evicted, in the channels `include` picks.
+    fn chain_keys<F>(&self, include: F) -> Vec<(String, String)>
+        where F: Fn(&str) -> bool
+    {
+        let mut keys = self.chains
+            .iter()
+            .filter(|&(channel, _)| include(channel))
+            .flat_map(|(channel, users)| users.keys().map(move |user| (channel.clone(), user.clone())))
+            .collect::<Vec<_>>();
+        keys.extend(self.evicted.iter().filter(|&(channel, _)| include(channel)).cloned());
+        keys
+    }
+
+    /// Generates a sentence from a user's chain in a channel, or from only what they said there during an era if one's
+    /// given, or says why it can't.
+    pub(super) fn emulate(&mut self, channel: &str, user: &str, era: Option<&str>) -> String {
+        // what the bot has said is under its configured nick, even while it's on an alt nick
+        let user = &if casemap::eq(user, self.current_nick()) { self.config.nick.clone() } else { user.to_string() };
+        if self.no_emulate(user) {
+            return format!("{} has asked not to be emulated", user);
+        }
+        if let Some(era) = era {
+            return self.emulate_era(channel, user, era);
+        }
+        self.restore_chain(channel, user);
+        let generated = match self.find_chain(channel, user) {
+            Ok(chain) if chain.is_empty() => return format!("{} hasn't said anything I can use in {}", user, channel),
+            Ok(chain) => self.generate(channel, chain),
+            Err(e) => return e,
+        };
+        let source = provenance::Source::User(self.canonical_nick(user));
+        self.note_generated(channel, source, &[], generated).unwrap_or_else(|| {
+            format!("Couldn't come up with anything from {} that's allowed in {}", user, channel)
+        })
+    }
+
+    /// Generates a sentence from a user's chains for every era within the one asked for, like each quarter of a year.
+    fn emulate_era(&mut self, channel: &str, user: &str, era: &str) -> String {
+        if self.era_buckets.is_none() {
+            return "I'm not keeping track of eras here; era_buckets isn't set".to_string();
+        }
+        if !era::is_era(era) {
+            return format!("{} isn't an era; try a year like 2019, or a quarter like 2019-Q2", era);
+        }
+        let merged = self.eras
+            .get(&casemap::fold(channel))
+            .and_then(|users| users.get(&self.canonical_nick(user)))
+            .and_then(|eras| era::merged(eras, era));
+        let generated = match merged {
+            Some(ref chain) if !chain.is_empty() => self.generate(channel, chain),
+            _ => return format!("{} didn't say anything I can use in {} during {}", user, channel, era),
+        };
+        let source = provenance::Source::User(self.canonical_nick(user));
+        self.note_generated(channel, source, &[], generated).unwrap_or_else(|| {
+            format!("Couldn't come up with anything from {} during {} that's allowed in {}", user, era, channel)
+        })
+    }
+
+    /// Saves the last thing a user said in a channel as a quote, or says why it can't.
+    pub(super) fn add_quote(&mut self, channel: &str, user: &str, added_by: &str) -> String {
+        if self.no_emulate(user) {
+            return format!("{} has asked not to be emulated", user);
+        }
+        let (channel_key, user_key) = (casemap::fold(channel), self.canonical_nick(user));
+        let (nick, text) = match self.last_said.get(&(channel_key.clone(), user_key.clone())) {
+            Some(said) => said.clone(),
+            None => return format!("{} hasn't said anything in {} that I can quote", user, channel),
+        };
+        let added = Local::now().timestamp();
+        let quote = quote::Quote { user: user_key, nick, text, added_by: added_by.to_string(), added };
+        match quote::add(&mut self.quotes, &channel_key, quote) {
+            Some(saved) => {
+                self.dirty = true;
+                format!("Saved that from {}; {} has {} quotes now", user, channel, saved)
+            }
+            None => format!("I already have that one from {}", user),
+        }
+    }
+
+    /// Picks a saved quote from a channel at random, from one user's if one is given, leaving out anyone who has asked
+    /// not to be emulated.
+    pub(super) fn random_quote(&self, channel: &str, user: Option<&str>) -> String {
+        if let Some(user) = user.filter(|user| self.no_emulate(user)) {
+            return format!("{} has asked not to be emulated", user);
+        }
+        let wanted = user.map(|user| self.canonical_nick(user));
+        let found = quote::random(&self.quotes, &casemap::fold(channel), |quoted| {
+            wanted.as_ref().map(|wanted| wanted == quoted).unwrap_or(true) && !self.no_emulate(quoted)
+        });
+        match (found, user) {
+            (Some(quote), _) if self.highlight_safe(channel) => self.presence.defuse(channel, &quote.show()),
+            (Some(quote), _) => quote.show(),
+            (None, Some(user)) => format!("I don't have any quotes from {} in {}", user, channel),
+            (None, None) => format!("I don't have any quotes in {}", channel),
+        }
+    }
+
+    /// Generates a sentence from a blend of several users' chains in a channel, or says why it can't.
+    pub(super) fn mix(&mut self, channel: &str, users: &[&str]) -> String {
+        if let Some(user) = users.iter().find(|user| self.no_emulate(user)) {
+            return format!("{} has asked not to be emulated", user);
+        }
+        for user in users {
+            self.restore_chain(channel, user);
+        }
+        let blended = {
+            let mut chains = Vec::new();
+            for user in users {
+                match self.find_chain(channel, user) {
+                    Ok(chain) if chain.is_empty() => {
+                        return format!("{} hasn't said anything I can use in {}", user, channel);
+                    }
+                    Ok(chain) => chains.push(chain),
+                    Err(e) => return e,
+                }
+            }
+            generate::blend(&chains)
+        };
+        let generated = match blended {
+            Ok(chain) => self.generate(channel, &chain),
+            Err(e) => return format!("Can't mix {}: {}", users.join(" and "), e),
+        };
+        let source = provenance::Source::Users(users.iter().map(|user| self.canonical_nick(user)).collect());
+        self.note_generated(channel, source, &[], generated).unwrap_or_else(|| {
+            format!("Couldn't come up with anything from {} that's allowed in {}", users.join(" and "), channel)
+        })
+    }
+}
+
+struct Emulate;
+
+impl Command for Emulate {
+    fn name(&self) -> &'static str {
+        "emulate"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<user> [<channel>] [<era>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<user> <channel> [<era>]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        // the era comes last, after the channel if there is one
+        Some(match (ctx.channel, ctx.arg(0), ctx.arg(1)) {
+            (Some(_), Some(user), Some(chan)) if ctx.is_channel(1) => bot.emulate(chan, user, ctx.arg(2)),
+            (Some(channel), Some(user), era) if ctx.args.len() <= 2 => bot.emulate(channel, user, era),
+            (Some(_), _, _) => ctx.usage(),
+            (None, Some(user), Some(chan)) if ctx.is_channel(1) => bot.emulate(chan, user, ctx.arg(2)),
+            (None, _, _) => ctx.usage(),
+        })
+    }
+}
+
+struct Quote;
+
+impl Command for Quote {
+    fn name(&self) -> &'static str {
+        "quote"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<user>] | add <user>"
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let channel = ctx.channel?;
+        Some(match (ctx.arg(0), ctx.arg(1)) {
+            (Some("add"), Some(user)) if ctx.args.len() == 2 => bot.add_quote(channel, user, ctx.sender),
+            (Some("add"), _) => ctx.usage(),
+            (user, None) => bot.random_quote(channel, user),
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Mix;
+
+impl Command for Mix {
+    fn name(&self) -> &'static str {
+        "mix"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<user> <user> [<user>...]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> <user> <user> [<user>...]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match ctx.channel {
+            Some(channel) if ctx.args.len() >= 2 => bot.mix(channel, ctx.args),
+            Some(_) => ctx.usage(),
+            None if ctx.is_channel(0) && ctx.args.len() >= 3 => bot.mix(ctx.args[0], &ctx.args[1..]),
+            None => ctx.usage(),
+        })
+    }
+}
+
+struct Force;
+
+impl Command for Force {
+    fn name(&self) -> &'static str {
+        "force"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let (channel, sender) = (ctx.channel?, ctx.sender);
+        bot.restore_chain(channel, sender);
+        let generated = bot.find_chain(channel, sender)
+            .ok()
+            .filter(|chain| !chain.is_empty())
+            .and_then(|chain| bot.generate(channel, chain));
+        let source = provenance::Source::User(bot.canonical_nick(sender));
+        bot.note_generated(channel, source, &[], generated)
+    }
+}
+
+struct All;
+
+impl Command for All {
+    fn name(&self) -> &'static str {
+        "all"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        bot.generate_all(ctx.channel?)
+    }
+}
+
+struct Global;
+
+impl Command for Global {
+    fn name(&self) -> &'static str {
+        "global"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        bot.generate_global(ctx.channel?)
+    }
+}
+
+struct Topic;
+
+impl Command for Topic {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+
+    fn usage(&self) -> &'static str {
+        " | set"
+    }
+
+    fn subcommands(&self) -> &'static [(&'static str, Privilege)] {
+        &[("set", Privilege::Admin)]
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let channel = ctx.channel?;
+        match ctx.arg(0) {
+            None => Some(bot.generate_topic(channel)
+                .unwrap_or_else(|| format!("Nobody has said anything I can use in {}", channel))),
+            Some("set") => bot.set_topic(channel),
+            Some(_) => Some(ctx.usage()),
+        }
+    }
+}
+
+struct Ignore;
+
+impl Command for Ignore {
+    fn name(&self) -> &'static str {
+        "ignore"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[--all]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> | --all"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        ignore_or_listen(bot, ctx, true)
+    }
+}
+
+struct Listen;
+
+impl Command for Listen {
+    fn name(&self) -> &'static str {
+        "listen"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[--all]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> | --all"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        ignore_or_listen(bot, ctx, false)
+    }
+}
+
+/// Runs `!markov ignore` or `!markov listen`, which only differ in which way they set the ignore flag.
+fn ignore_or_listen(bot: &mut IrcBot, ctx: &Context, ignore: bool) -> Option<String> {
+    Some(match (ctx.channel, ctx.arg(0)) {
+        (_, Some("--all")) => bot.ignore_command(None, ctx.sender, ctx.prefix, ignore),
+        (Some(channel), None) => bot.ignore_command(Some(channel), ctx.sender, ctx.prefix, ignore),
+        (Some(_), Some(_)) => ctx.usage(),
+        (None, Some(chan)) if ctx.is_channel(0) => bot.ignore_command(Some(chan), ctx.sender, ctx.prefix, ignore),
+        (None, _) => ctx.usage(),
+    })
+}
+
+struct Chance;
+
+impl Command for Chance {
+    fn name(&self) -> &'static str {
+        "chance"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<chance>] | <channel> [<chance>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> [<chance>]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match (ctx.channel, ctx.arg(0)) {
+            (Some(_), Some(chan)) if ctx.is_channel(0) => bot.channel_chance_command(chan, ctx.sender, ctx.arg(1)),
+            (Some(channel), value) => bot.chance_command(channel, ctx.sender, value),
+            (None, Some(chan)) if ctx.is_channel(0) => bot.chance_command(chan, ctx.sender, ctx.arg(1)),
+            (None, _) => ctx.usage(),
+        })
+    }
+}
+
+struct Temp;
+
+impl Command for Temp {
+    fn name(&self) -> &'static str {
+        "temp"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<temperature>] | <channel> [<temperature>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> [<temperature>]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match (ctx.channel, ctx.arg(0)) {
+            (Some(_), Some(chan)) if ctx.is_channel(0) => bot.temperature_command(chan, ctx.sender, ctx.arg(1)),
+            (Some(channel), value) => bot.temperature_command(channel, ctx.sender, value),
+            (None, Some(chan)) if ctx.is_channel(0) => bot.temperature_command(chan, ctx.sender, ctx.arg(1)),
+            (None, _) => ctx.usage(),
+        })
+    }
+}
+
+struct Private;
+
+impl Command for Private {
+    fn name(&self) -> &'static str {
+        "private"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(bot.privacy_command(ctx.sender, ctx.prefix, true))
+    }
+}
+
+struct Public;
+
+impl Command for Public {
+    fn name(&self) -> &'static str {
+        "public"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(bot.privacy_command(ctx.sender, ctx.prefix, false))
+    }
+}
+
+struct Forget;
+
+impl Command for Forget {
+    fn name(&self) -> &'static str {
+        "forget"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<user>]"
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let (channel, sender) = (ctx.channel?, ctx.sender);
+        let target = ctx.arg(0).unwrap_or(sender);
+        Some(if !casemap::eq(target, sender) && !bot.is_admin(sender) {
+            "Only admins can make me forget other users".to_string()
+        } else if bot.forget(channel, target) {
+            event!(info, "forget", "forgot {} in {} at the request of {}", target, channel, sender);
+            format!("Forgot everything {} said in {}", target, channel)
+        } else {
+            format!("No chain for user {}", target)
+        })
+    }
+}
+
+struct Scrub;
+
+impl Command for Scrub {
+    fn name(&self) -> &'static str {
+        "scrub"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<user> <minutes>"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<user> <minutes> <channel>"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let channel = match ctx.channel {
+            Some(channel) => Some(channel),
+            None if ctx.is_channel(2) => ctx.arg(2),
+            None => None,
+        };
+        let minutes = ctx.arg(1).and_then(|m| m.parse::<u64>().ok());
+        let (user, minutes, channel) = match (ctx.arg(0), minutes, channel) {
+            (Some(user), Some(minutes), Some(channel)) => (user, minutes, channel),
+            _ => return Some(ctx.usage()),
+        };
+        let window = bot.journal.window().as_secs() / 60;
+        let scrubbed = bot.scrub(channel, user, Duration::from_secs(minutes.saturating_mul(60)));
+        let note = if minutes > window {
+            format!(" (I only remember the last {} minutes)", window)
+        } else {
+            String::new()
+        };
+        Some(match scrubbed {
+            0 => format!("Nothing from {} in the last {} minutes to scrub{}", user, minutes, note),
+            n => format!("Scrubbed {} messages from {} in the last {} minutes{}", n, user, minutes, note),
+        })
+    }
+}
+
+struct Alias;
+
+impl Command for Alias {
+    fn name(&self) -> &'static str {
+        "alias"
+    }
+
+    fn usage(&self) -> &'static str {
+        "add <oldnick> <newnick> | remove <oldnick>"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match (ctx.arg(0), ctx.arg(1), ctx.arg(2)) {
+            (Some("add"), Some(old), Some(new)) => match bot.add_alias(old, new) {
+                Ok(()) => format!("{} is now an alias of {}", old, new),
+                Err(e) => e,
+            },
+            (Some("remove"), Some(old), None) => {
+                if bot.aliases.remove(&casemap::fold(old)).is_some() {
+                    bot.dirty = true;
+                    format!("{} is no longer an alias", old)
+                } else {
+                    format!("{} is not an alias", old)
+                }
+            }
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Status;
+
+impl Command for Status {
+    fn name(&self) -> &'static str {
+        "status"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let channel = ctx.channel?;
+        let user_total = { IrcBot::get_chain_total(bot.user_chain_mut(channel, ctx.sender)) };
+        let all_total = { IrcBot::get_chain_total(bot.allchain(channel)) };
+        let status = ((user_total as f64) / (all_total as f64)) * 100.0;
+        Some(format!("You are worth {:.4}% of the channel", status))
+    }
+}
+
+struct Ignored;
+
+impl Command for Ignored {
+    fn name(&self) -> &'static str {
+        "ignored"
+    }
+
+    fn usage(&self) -> &'static str {
+        " | bot|human|reset <nick>"
+    }
+
+    fn subcommands(&self) -> &'static [(&'static str, Privilege)] {
+        &[("bot", Privilege::Admin), ("human", Privilege::Admin), ("reset", Privilege::Admin)]
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match (ctx.arg(0), ctx.arg(1)) {
+            (None, _) => {
+                let mut bots = bot.detector
+                    .flagged()
+                    .iter()
+                    .filter(|&(nick, _)| bot.bot_overrides.get(nick) != Some(&false))
+                    .map(|(nick, reason)| format!("{} ({})", nick, reason))
+                    .chain(bot.bot_overrides
+                        .iter()
+                        .filter(|&(_, &is_bot)| is_bot)
+                        .map(|(nick, _)| format!("{} (set by an admin)", nick)))
+                    .collect::<Vec<_>>();
+                bots.sort();
+                if bots.is_empty() {
+                    "I'm not ignoring anyone as a bot".to_string()
+                } else {
+                    format!("Ignoring as bots: {}", bots.join(", "))
+                }
+            }
+            (Some(action), Some(nick)) if ["bot", "human", "reset"].contains(&action) => {
+                let key = casemap::fold(nick);
+                bot.dirty = true;
+                match action {
+                    "bot" => {
+                        bot.bot_overrides.insert(key, true);
+                        format!("Treating {} as a bot", nick)
+                    }
+                    "human" => {
+                        bot.bot_overrides.insert(key, false);
+                        format!("Treating {} as a human", nick)
+                    }
+                    _ => {
+                        bot.bot_overrides.remove(&key);
+                        bot.detector.unflag(&key);
+                        format!("Leaving it up to the bot detector whether {} is a bot", nick)
+                    }
+                }
+            }
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Stats;
+
+impl Command for Stats {
+    fn name(&self) -> &'static str {
+        "stats"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<channel> | <user>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "[<channel> | <user> <channel>]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match (ctx.channel, ctx.arg(0), ctx.arg(1)) {
+            (_, None, _) => bot.bot_stats(),
+            (Some(_), Some(chan), _) if ctx.is_channel(0) => bot.channel_stats(chan),
+            (Some(channel), Some(user), _) => bot.user_stats(channel, user),
+            (None, Some(chan), None) if ctx.is_channel(0) => bot.channel_stats(chan),
+            (None, Some(user), Some(chan)) if ctx.is_channel(1) => bot.user_stats(chan, user),
+            (None, _, _) => ctx.usage(),
+        })
+    }
+}
+
+struct Reload;
+
+impl Command for Reload {
+    fn name(&self) -> &'static str {
+        "reload"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn run(&self, bot: &mut IrcBot, _ctx: &Context) -> Option<String> {
+        Some(match bot.reload_from_source() {
+            Ok(()) => "Reloaded the config".to_string(),
+            Err(e) => format!("Could not reload the config: {}", e),
+        })
+    }
+}
+
+struct Save;
+
+impl Command for Save {
+    fn name(&self) -> &'static str {
+        "save"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[every [<duration>]]"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match (ctx.arg(0), ctx.arg(1)) {
+            (None, _) => bot.save_command(None),
+            (Some("every"), value) => bot.save_command(Some(value)),
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Settings;
+
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn usage(&self) -> &'static str {
+        " | set [<channel>] <name> <value> | reset [<channel>] <name>"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let rest = ctx.args.get(1..).unwrap_or(&[]);
+        let (channel, rest) = match rest.first() {
+            Some(channel) if config::is_channel_name(channel) => (Some(*channel), &rest[1..]),
+            _ => (None, rest),
+        };
+        Some(match (ctx.arg(0), rest.split_first()) {
+            (None, _) => bot.runtime_settings(),
+            // values can have spaces in them, like a quit message
+            (Some("set"), Some((name, value))) if !value.is_empty() => {
+                bot.setting_command(ctx.sender, channel, name, Some(&value.join(" ")))
+            }
+            (Some("reset"), Some((name, []))) => {
+                bot.setting_command(ctx.sender, channel, name, None)
+            }
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Shutdown;
+
+impl Command for Shutdown {
+    fn name(&self) -> &'static str {
+        "shutdown"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        bot.request_shutdown(ctx.sender);
+        Some("Saving and shutting down".to_string())
+    }
+}
+
+struct Prune;
+
+impl Command for Prune {
+    fn name(&self) -> &'static str {
+        "prune"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn run(&self, bot: &mut IrcBot, _ctx: &Context) -> Option<String> {
+        let pruned = bot.prune();
+        Some(format!("Pruned {} transitions, {} chains that had nothing left, and settings for {} users not seen in \
+                      too long", pruned.transitions, pruned.chains, pruned.settings))
+    }
+}
+
+struct Snapshot;
+
+impl Command for Snapshot {
+    fn name(&self) -> &'static str {
+        "snapshot"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<name>"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let name = match ctx.arg(0) {
+            Some(name) => name,
+            None => return Some(ctx.usage()),
+        };
+        Some(match bot.take_snapshot(name) {
+            Ok(snapshot) => format!("Saved snapshot {} at {}", snapshot.name, snapshot.when()),
+            Err(e) => format!("Couldn't save a snapshot: {}", e),
+        })
+    }
+}
+
+struct Restore;
+
+impl Command for Restore {
+    fn name(&self) -> &'static str {
+        "restore"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<name>"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let name = match ctx.arg(0) {
+            Some(name) => name,
+            None => return Some(ctx.usage()),
+        };
+        Some(match bot.restore_snapshot(name) {
+            Ok(snapshot) => format!("Rolled the chains back to snapshot {} from {}", snapshot.name, snapshot.when()),
+            Err(e) => format!("Couldn't restore {}: {}", name, e),
+        })
+    }
+}
+
+struct Snapshots;
+
+impl Command for Snapshots {
+    fn name(&self) -> &'static str {
+        "snapshots"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, _ctx: &Context) -> Option<String> {
+        Some(match bot.snapshots() {
+            Ok(ref snapshots) if snapshots.is_empty() => "There are no snapshots yet".to_string(),
+            Ok(snapshots) => {
+                let newest = snapshots
+                    .iter()
+                    .rev()
+                    .take(LISTED_SNAPSHOTS)
+                    .map(|s| format!("{} ({})", s.name, s.when()))
+                    .collect::<Vec<_>>();
+                let more = snapshots.len().saturating_sub(LISTED_SNAPSHOTS);
+                let more = if more > 0 { format!(", and {} older", more) } else { String::new() };
+                format!("Snapshots, newest first: {}{}", newest.join(", "), more)
+            }
+            Err(e) => format!("Couldn't list the snapshots: {}", e),
+        })
+    }
+}
+
+struct Join;
+
+impl Command for Join {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<channel> [<key>]"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match ctx.arg(0) {
+            Some(chan) if ctx.is_channel(0) => bot.join(chan, ctx.arg(1)),
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Part;
+
+impl Command for Part {
+    fn name(&self) -> &'static str {
+        "part"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<channel>"
+    }
+
+    fn privilege(&self) -> Privilege {
+        Privilege::Admin
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(match ctx.arg(0) {
+            Some(chan) if ctx.is_channel(0) => bot.part(chan),
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct MyData;
+
+impl Command for MyData {
+    fn name(&self) -> &'static str {
+        "mydata"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    /// The data always goes to the sender in private, so there's nothing to reply with.
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        bot.send_my_data(ctx.sender, ctx.prefix);
+        None
+    }
+}
+
+struct DeleteMe;
+
+impl Command for DeleteMe {
+    fn name(&self) -> &'static str {
+        "deleteme"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[confirm]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(bot.delete_me_command(ctx.sender, ctx.prefix, ctx.arg(0)))
+    }
+}
+
+struct TopWords;
+
+impl Command for TopWords {
+    fn name(&self) -> &'static str {
+        "topwords"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<user>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> | <user> <channel>"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        topwords_or_vocab(bot, ctx, self.name())
+    }
+}
+
+struct Vocab;
+
+impl Command for Vocab {
+    fn name(&self) -> &'static str {
+        "vocab"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<user>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<channel> | <user> <channel>"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        topwords_or_vocab(bot, ctx, self.name())
+    }
+}
+
+struct Again;
+
+impl Command for Again {
+    fn name(&self) -> &'static str {
+        "again"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(bot.regenerate(ctx.channel?, LengthBias::Same))
+    }
+}
+
+struct Longer;
+
+impl Command for Longer {
+    fn name(&self) -> &'static str {
+        "longer"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(bot.regenerate(ctx.channel?, LengthBias::Longer))
+    }
+}
+
+struct Shorter;
+
+impl Command for Shorter {
+    fn name(&self) -> &'static str {
+        "shorter"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        Some(bot.regenerate(ctx.channel?, LengthBias::Shorter))
+    }
+}
+
+struct Source;
+
+impl Command for Source {
+    fn name(&self) -> &'static str {
+        "source"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<number>]"
+    }
+
+    fn private_usage(&self) -> &'static str {
+        "<number> | <channel>"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let id = ctx.arg(0).and_then(|arg| arg.trim_start_matches('#').parse::<u64>().ok());
+        Some(match (ctx.channel, ctx.arg(0), id) {
+            (_, Some(_), Some(id)) => bot.source(ctx.channel, Some(id)),
+            (Some(channel), None, None) => bot.source(Some(channel), None),
+            (None, Some(chan), None) if ctx.is_channel(0) => bot.source(Some(chan), None),
+            _ => ctx.usage(),
+        })
+    }
+}
+
+/// Runs `!markov topwords` or `!markov vocab`, which look a user or channel up the same way.
+fn topwords_or_vocab(bot: &mut IrcBot, ctx: &Context, name: &str) -> Option<String> {
+    Some(match (ctx.channel, ctx.arg(0), ctx.arg(1)) {
+        (Some(channel), user, _) => bot.word_command(name, channel, user),
+        (None, Some(chan), None) if ctx.is_channel(0) => bot.word_command(name, chan, None),
+        (None, Some(user), Some(chan)) if ctx.is_channel(1) => bot.word_command(name, chan, Some(user)),
+        (None, _, _) => ctx.usage(),
+    })
+}
+
+struct Chat;
+
+impl Command for Chat {
+    fn name(&self) -> &'static str {
+        "chat"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<channel> [<user>] | off"
+    }
+
+    fn public(&self) -> bool {
+        false
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        if !bot.pm_chat {
+            return Some("Chatting in private is turned off".to_string());
+        }
+        let (sender, user) = (ctx.sender, ctx.arg(1));
+        Some(match ctx.arg(0) {
+            Some("off") => {
+                bot.chats.remove(&casemap::fold(sender));
+                "Okay, I'll stop talking".to_string()
+            }
+            Some(chan) if ctx.is_channel(0) => {
+                bot.load_channel(chan);
+                if let Some(user) = user {
+                    bot.restore_chain(chan, user);
+                }
+                if user.map(|user| bot.no_emulate(user)).unwrap_or(false) {
+                    format!("{} has asked not to be emulated", user.unwrap())
+                } else if !bot.chains.contains_key(&casemap::fold(chan)) {
+                    format!("I don't know anything about {}", chan)
+                } else if user.map(|user| bot.find_chain(chan, user).is_err()).unwrap_or(false) {
+                    format!("No chain for user {} in {}", user.unwrap(), chan)
+                } else {
+                    let who = user.map(|user| user.to_string()).unwrap_or(format!("everyone in {}", chan));
+                    bot.chats.insert(casemap::fold(sender), (chan.to_string(), user.map(str::to_string)));
+                    format!("Chatting as {}; say anything, or \"{} chat off\" to stop", who, ctx.prefix)
+                }
+            }
+            _ => ctx.usage(),
+        })
+    }
+}
+
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[<command>]"
+    }
+
+    fn private(&self) -> bool {
+        true
+    }
+
+    fn run(&self, _bot: &mut IrcBot, ctx: &Context) -> Option<String> {
+        let public = ctx.channel.is_some();
+        Some(match ctx.arg(0).map(|name| (name, find(name))) {
+            None => command_list(ctx.prefix, public),
+            Some((_, Some(command))) if available(command, public) => {
+                let admin = if command.privilege() == Privilege::Admin { " (admins only)" } else { "" };
+                format!("{}{}", usage(ctx.prefix, command, public), admin)
+            }
+            Some((_, Some(command))) if public => format!("{} only works in private", command.name()),
+            Some((_, Some(command))) => format!("{} only works in a channel", command.name()),
+            Some((name, None)) => format!("I don't know \"{}\". {}", name, command_list(ctx.prefix, public)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{usage, Emulate, Join};
+    use crate::bot::IrcBot;
+    use crate::bot::testing::{self, ask, say, ADMIN, CHANNEL};
+    use crate::colors;
+    use crate::config::Channel;
+    use futures::FutureExt;
+    use irc::client::prelude::Command as IrcCommand;
+    use crate::shutdown::Shutdown;
+
+    #[test]
+    fn usage_lists_every_form() {
+        assert_eq!(usage("!markov", &Emulate, true), "Usage: !markov emulate <user> [<channel>] [<era>]");
+        assert_eq!(usage("!markov", &Emulate, false), "Usage: !markov emulate <user> <channel> [<era>]");
+        assert_eq!(usage("!mk", &super::Alias, true),
+                   "Usage: !mk alias add <oldnick> <newnick> | !mk alias remove <oldnick>");
+        assert_eq!(usage("!markov", &super::Topic, true), "Usage: !markov topic | !markov topic set (admins only)");
+        assert_eq!(usage("!markov", &super::Ignored, true),
+                   "Usage: !markov ignored | !markov ignored bot|human|reset <nick> (admins only)");
+    }
+
+    #[test]
+    fn bare_prefix_lists_commands() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov");
+        assert!(reply.starts_with("alice: Commands: emulate, mix,"), "{}", reply);
+        assert!(!reply.contains("chat"), "{}", reply);
+    }
+
+    #[test]
+    fn unknown_command_gets_help() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov frobnicate");
+        assert!(reply.starts_with("alice: I don't know \"frobnicate\". Commands:"), "{}", reply);
+    }
+
+    #[test]
+    fn bad_arguments_get_usage() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", "markov", "!markov emulate alice");
+        assert_eq!(reply, "Usage: !markov emulate <user> <channel> [<era>]");
+    }
+
+    #[test]
+    fn admin_commands_need_an_admin() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov join #other");
+        assert_eq!(reply, "alice: Only admins can use !markov join");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov join");
+        assert_eq!(reply, format!("{}: {}", ADMIN, usage("!markov", &Join, true)));
+    }
+
+    #[test]
+    fn scrub_untrains_recent_messages() {
+        // the first message is trained before anything's being journaled, so it's too old to scrub
+        let (mut bot, server) = testing::bot("scrub_window = \"0\"");
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        bot.apply_config(&testing::config("scrub_window = \"3600\"")).unwrap();
+        say(&mut bot, "alice", CHANNEL, "buy cheap watches");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov scrub alice 10");
+        assert_eq!(reply, format!("{}: Scrubbed 1 messages from alice in the last 10 minutes", ADMIN));
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: the quick brown fox");
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov scrub alice 120 #test");
+        assert_eq!(reply, "Nothing from alice in the last 120 minutes to scrub (I only remember the last 60 minutes)");
+    }
+
+    #[test]
+    fn private_users_cant_be_emulated() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        let reply = ask(&mut bot, &server, "alice", "markov", "!markov private");
+        assert_eq!(reply, "Nobody can emulate you now, though I still learn from you. Use !markov public to undo this");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate Alice");
+        assert_eq!(reply, "bob: Alice has asked not to be emulated");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov mix bob alice");
+        assert_eq!(reply, "bob: alice has asked not to be emulated");
+        let export = IrcBot::export(&bot.to_blob());
+        assert!(export.channels[CHANNEL]["alice"].private);
+        assert!(export.channels[CHANNEL]["alice"].transitions.is_empty());
+
+        ask(&mut bot, &server, "alice", CHANNEL, "!markov public");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: the quick brown fox");
+    }
+
+    #[test]
+    fn emulates_users_as_they_were_during_an_era() {
+        let (mut bot, server) = testing::bot("era_buckets = \"quarter\"");
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        // what alice just said goes back to 2019
+        let eras = bot.eras.get_mut(CHANNEL).unwrap().get_mut("alice").unwrap();
+        let chain = eras.values().next().unwrap().clone();
+        eras.clear();
+        eras.insert("2019-Q2".to_string(), chain);
+        say(&mut bot, "alice", CHANNEL, "buy cheap watches");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice 2019");
+        assert_eq!(reply, "bob: the quick brown fox");
+        let reply = ask(&mut bot, &server, "bob", "markov", "!markov emulate alice #test 2019-q2");
+        assert_eq!(reply, "the quick brown fox");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice 2019-Q1");
+        assert_eq!(reply, "bob: alice didn't say anything I can use in #test during 2019-Q1");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice yesterday");
+        assert_eq!(reply, "bob: yesterday isn't an era; try a year like 2019, or a quarter like 2019-Q2");
+
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice 2019");
+        assert_eq!(reply, "bob: I'm not keeping track of eras here; era_buckets isn't set");
+    }
+
+    #[test]
+    fn saves_and_recalls_quotes() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov quote");
+        assert_eq!(reply, "bob: I don't have any quotes in #test");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov quote add Alice");
+        assert_eq!(reply, "bob: Saved that from Alice; #test has 1 quotes now");
+        let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov quote add alice");
+        assert_eq!(reply, "carol: I already have that one from alice");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov quote add carol");
+        assert_eq!(reply, "bob: carol hasn't said anything in #test that I can quote");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov quote alice");
+        assert_eq!(reply, "bob: <alice> the quick brown fox");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov quote carol");
+        assert_eq!(reply, "bob: I don't have any quotes from carol in #test");
+
+        ask(&mut bot, &server, "alice", "markov", "!markov private");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov quote");
+        assert_eq!(reply, "bob: I don't have any quotes in #test");
+        bot.delete_user("alice");
+        assert!(bot.quotes.is_empty());
+    }
+
+    #[test]
+    fn global_leaves_out_private_channels() {
+        let mut config = testing::config("");
+        let mut secret = Channel::new("#secret", None);
+        secret.global = Some(false);
+        config.channels.push(Channel::new("#other", None));
+        config.channels.push(secret);
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", "#other", "the quick brown fox");
+        say(&mut bot, "bob", "#secret", "the plans are in the vault");
+        for _ in 0..5 {
+            let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov global");
+            assert_eq!(reply, "carol: the quick brown fox");
+        }
+    }
+
+    #[test]
+    fn help_describes_a_command() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov help part");
+        assert_eq!(reply, "alice: Usage: !markov part <channel> (admins only)");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov help chat");
+        assert_eq!(reply, "alice: chat only works in private");
+    }
+
+    #[test]
+    fn prefix_can_be_changed() {
+        let mut config = testing::config("command_prefix = \"?m\"");
+        config.channels[0].command_prefix = Some("!mk".to_string());
+        let (mut bot, server) = testing::bot_with(&config);
+        // the old prefix is just another message now, so the only reply is to the new one
+        testing::say(&mut bot, "alice", CHANNEL, "!markov help");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!mk help status");
+        assert_eq!(reply, "alice: Usage: !mk status");
+        let reply = ask(&mut bot, &server, "alice", "markov", "?m help status");
+        assert_eq!(reply, "status only works in a channel");
+    }
+
+    #[test]
+    fn aliases_run_commands() {
+        let mut config = testing::config("");
+        let aliases = vec![(".markov", ""), ("~emulate", "emulate"), ("~help", "help emulate")];
+        config.command_aliases = Some(aliases.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+        config.channels[0].command_aliases = Some(vec![("~help".to_string(), "help vocab".to_string())]
+            .into_iter()
+            .collect());
+        config.validate().unwrap();
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "the cat sat down");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "~emulate alice");
+        assert_eq!(reply, "bob: the cat sat down");
+        // an alias for nothing is a prefix of its own
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, ".markov help status");
+        assert_eq!(reply, "bob: Usage: .markov status");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "~help");
+        assert_eq!(reply, "bob: Usage: !markov vocab [<user>]");
+        let reply = ask(&mut bot, &server, "bob", "markov", "~help");
+        assert_eq!(reply, "Usage: !markov emulate <user> <channel> [<era>]");
+        // commands run by aliases aren't learned from
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov stats bob");
+        assert!(!reply.contains("tokens"), "{}", reply);
+
+        config.command_aliases = Some(vec![("~x".to_string(), "nothing".to_string())].into_iter().collect());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn the_bot_can_train_on_itself() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "the cat sat down");
+        ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate markov");
+        assert_eq!(reply, "bob: No chain for user markov in #test");
+
+        let (mut bot, server) = testing::bot("self_train = \"true\"");
+        say(&mut bot, "alice", CHANNEL, "the cat sat down");
+        ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate markov");
+        assert_eq!(reply, "bob: the cat sat down");
+    }
+
+    #[test]
+    fn channels_can_use_colors() {
+        let mut config = testing::config("");
+        config.channels[0].use_colors = Some(true);
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "the cat sat down");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "\x02bob\x02: the cat sat down");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov vocab alice");
+        assert_eq!(reply, format!("\x02bob\x02: {} has used 4 different words", colors::nick("alice")));
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov stats #test");
+        assert!(reply.starts_with("\x02bob\x02: #test: \x027\x02 tokens, \x027\x02 states"), "{:?}", reply);
+    }
+
+    #[test]
+    fn short_commands_can_be_turned_off() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "the cat sat down");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!impersonate alice");
+        assert_eq!(reply, "bob: the cat sat down");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!chatter");
+        assert_eq!(reply, "bob: the cat sat down");
+
+        let (mut bot, server) = testing::bot("short_commands = \"false\"");
+        say(&mut bot, "alice", CHANNEL, "!emulate alice");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: !emulate alice");
+    }
+
+    #[test]
+    fn shutdown_quits_once_replies_are_out() {
+        let (mut bot, server) = testing::bot("quit_message = \"see you later\"");
+        let shutdown = Shutdown::new();
+        bot.set_shutdown(shutdown.clone());
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov shutdown");
+        assert_eq!(reply, "alice: Only admins can use !markov shutdown");
+        assert!(shutdown.wait().now_or_never().is_none());
+        say(&mut bot, ADMIN, CHANNEL, "!markov shutdown");
+        assert!(shutdown.wait().now_or_never().is_some());
+        bot.quit();
+        let sent = server.wait_for(2);
+        assert_eq!(sent[0], IrcCommand::PRIVMSG(CHANNEL.to_string(), format!("{}: Saving and shutting down", ADMIN)));
+        assert_eq!(sent[1], IrcCommand::QUIT(Some("see you later".to_string())));
+    }
+
+    #[test]
+    fn source_credits_whose_chains_a_sentence_came_from() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "the cat sat down");
+        say(&mut bot, "bob", CHANNEL, "a dog sat down");
+        ask(&mut bot, &server, "alice", CHANNEL, "!markov all");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov source");
+        assert!(reply.starts_with("alice: #1 in #test came from everyone in #test; steps each could have taken: "),
+                "{}", reply);
+        // whichever sentence it was, one of them said all of it and the other only how it ends
+        assert!(reply.ends_with(" 5/5, bob 2/5") || reply.ends_with(" 5/5, alice 2/5"), "{}", reply);
+
+        ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov source #2");
+        assert_eq!(reply, "bob: #2 in #test came from alice's chain; steps each could have taken: alice 5/5");
+        let reply = ask(&mut bot, &server, "bob", "markov", "!markov source 9");
+        assert_eq!(reply, "I don't remember generating #9");
+    }
+
+    #[test]
+    fn regenerating_follows_the_last_sentence() {
+        let (mut bot, server) = testing::bot("generate_attempts = \"50\"");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov again");
+        assert_eq!(reply, "bob: I haven't generated anything in #test lately");
+        say(&mut bot, "alice", CHANNEL, "we like big cats");
+        say(&mut bot, "alice", CHANNEL, "we like big dogs that bark loudly");
+        ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        // longer than either one is impossible, so the longest wins
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov longer");
+        assert_eq!(reply, "bob: we like big dogs that bark loudly");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov shorter");
+        assert_eq!(reply, "bob: we like big cats");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov again");
+        assert!(reply.starts_with("bob: we like big "), "{}", reply);
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov source");
+        assert!(reply.starts_with("bob: #4 in #test came from alice's chain"), "{}", reply);
+    }
+}
@@ -0,0 +1,1498 @@
+use crate::activity::Activity;
+use crate::blobstore::BlobStore;
+use crate::altnick::AltNicks;
+use crate::allchain::AllChains;
+use crate::wordlist::WordList;
+use crate::botdetect::BotDetector;
+use crate::casemap;
+use crate::chat::ChatServer;
+use crate::colors;
+use crate::config::{self, ProgramConfig, Server as ServerConfig};
+use crate::compress::Compression;
+use crate::cooldown::Cooldown;
+use crate::era::{Buckets, EraMap};
+use crate::error::{self, Error};
+use crate::ignore::IgnoreList;
+use crate::journal::Journal;
+use crate::logging;
+use crate::nickserv::NickServ;
+use crate::output;
+use crate::playback::{Delivery, Playback};
+use crate::preprocess::Preprocessor;
+use crate::presence::{NickMode, Presence};
+use crate::provenance::GeneratedLog;
+use crate::prune::PruneOptions;
+use crate::quality::{RecentMessages, SentenceFilter};
+use crate::quiet::Zone;
+use crate::quote::QuoteMap;
+use crate::ratelimit::RateLimiter;
+use crate::reply::{Route, Routes};
+use crate::runtime::RuntimeSettings;
+use crate::sasl::Sasl;
+use crate::schedule::Schedule;
+use crate::sendqueue::{Priority, SendQueue};
+use crate::shutdown::Shutdown;
+use crate::spam::SpamFilter;
+use crate::split::SplitStore;
+use crate::sqlite::SqliteStore;
+use crate::stats::{ChainStats, Counters, LifetimeCounters};
+use crate::tokenize::Tokenizer;
+use irc::client::prelude::*;
+use markov_chain::Chain;
+use rand::{self, Rng};
+use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod commands;
+mod options;
+mod settings;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod training;
+
+pub use self::commands::is_command;
+pub use self::options::{Login, Options};
+pub use self::settings::UserSettings;
+pub use self::storage::BlobFile;
+
+pub type UserSettingsMap = HashMap<String, HashMap<String, UserSettings>>;
+pub type ChainMap = HashMap<String, HashMap<String, Chain<String>>>;
+
+const DEFAULT_CHANCE: f64 = 0.01;
+const DEFAULT_MENTION_CHANCE: f64 = 0.0;
+/// Seconds someone has to answer one of the bot's replies for it to count as talking back.
+const DEFAULT_CONVERSATION_WINDOW: u64 = 60;
+/// Seconds after responding to a trigger before a channel's triggers work again.
+const DEFAULT_TRIGGER_COOLDOWN: u64 = 60;
+/// Seconds the global chain is used for before it's built again.
+const DEFAULT_GLOBAL_MAX_AGE: u64 = 600;
+/// Seconds to wait before rejoining a channel the bot was kicked from.
+const DEFAULT_REJOIN_DELAY: u64 = 30;
+/// How long a user's training cap lasts before it starts over, unless the `training_cap_period` option says otherwise.
+const DEFAULT_TRAINING_CAP_PERIOD: u64 = 24 * 3600;
+/// Seconds between saves, unless the `save_interval` option or `!markov save every` says otherwise.
+pub const DEFAULT_SAVE_INTERVAL: u64 = 3600;
+/// What the bot says as it quits, unless the `quit_message` option says otherwise.
+const DEFAULT_QUIT_MESSAGE: &str = "Shutting down";
+/// Most seconds to wait for queued replies to go out before quitting anyway.
+const QUIT_DRAIN_TIMEOUT: u64 = 10;
+pub const DEFAULT_ORDER: usize = 1;
+pub const DEFAULT_BACKUPS: usize = 1;
+/// The user settings key for settings that apply in every channel, which can't be mistaken for a channel name.
+pub const ALL_CHANNELS: &str = "*";
+
+pub struct IrcBot {
+    chains: ChainMap,
+    /// Each user's training again, kept apart by era, for emulating them as they were back then.
+    eras: EraMap,
+    /// How long each era is, from the `era_buckets` option, or None to not keep eras.
+    era_buckets: Option<Buckets>,
+    /// Each channel's topic chain, trained on the topics people set there.
+    topic_chains: HashMap<String, Chain<String>>,
+    /// What each channel's topic is now, by casefolded channel, as far as we've seen.
+    topics: HashMap<String, String>,
+    /// Quotes saved with `!markov quote add`, by casefolded channel.
+    quotes: QuoteMap,
+    /// The last thing each user said in each channel, for `!markov quote add`, as their nick and what they said,
+    /// keyed by casefolded channel and canonical nick.
+    last_said: HashMap<(String, String), (String, String)>,
+    allchains: AllChains,
+    /// How long the global chain is used for before it's built again, to pick up what's been said since.
+    global_max_age: Duration,
+    user_settings: UserSettingsMap,
+    aliases: HashMap<String, String>,
+    bot_overrides: HashMap<String, bool>,
+    /// Settings admins changed with `!markov settings` and the like, which go on top of the config.
+    runtime: RuntimeSettings,
+    last_pruned: Option<i64>,
+    /// When each user, by canonical nick, last said something or came or went, as a Unix timestamp.
+    last_seen: HashMap<String, i64>,
+    /// Who's in each channel right now.
+    presence: Presence,
+    /// Nicks people have changed to this session, mapped to the nick whose chain they train.
+    session_nicks: HashMap<String, String>,
+    /// Users chatting with the bot in private, mapped to the channel and, optionally, the user whose chain replies.
+    chats: HashMap<String, (String, Option<String>)>,
+    /// Users who've asked to be deleted and still need to confirm, by canonical nick, with when they asked.
+    pending_deletions: HashMap<String, Instant>,
+    ignore: IgnoreList,
+    /// Ignore lists from the channel configs, keyed by casefolded channel.
+    channel_ignores: HashMap<String, IgnoreList>,
+    blacklist: WordList,
+    /// Blacklists for channels that add to the server's, keyed by casefolded channel, with the server's included.
+    channel_blacklists: HashMap<String, WordList>,
+    /// Trigger words for channels that have them, keyed by casefolded channel.
+    triggers: HashMap<String, WordList>,
+    /// When the bot last responded to a trigger in each channel, keyed by casefolded channel.
+    last_triggered: HashMap<String, Instant>,
+    order: usize,
+    chance: f64,
+    mention_chance: f64,
+    mention_from_all: bool,
+    pm_chat: bool,
+    /// Timezone that channels' quiet hours go by, unless they say otherwise.
+    timezone: Zone,
+    /// Whether what the bot generates is trained on, into a chain of its own under its configured nick.
+    self_train: bool,
+    /// Whether what the bot would say unprompted is only logged, in channels that don't say otherwise.
+    shadow: bool,
+    /// Most tokens trained on from one user in a channel each `training_cap_period`, or 0 for no limit.
+    training_cap: u64,
+    training_cap_period: Duration,
+    /// Most lines a single message is split into before the rest is cut off.
+    output_lines: usize,
+    /// What's done with nicks in what's trained on, in channels that don't say otherwise.
+    nick_mentions: NickMode,
+    limiter: RateLimiter,
+    cooldown: Cooldown,
+    activity: Activity,
+    schedule: Schedule,
+    /// Users talking back to the bot's random replies, keyed by casefolded (channel, nick), with when the bot last
+    /// replied to them and how many replies deep the conversation is.
+    conversations: HashMap<(String, String), (Instant, usize)>,
+    /// Most replies the bot gives to someone talking back to it, and how long they have to answer, in seconds.
+    conversation_depth: usize,
+    conversation_window: Duration,
+    routes: Routes,
+    detector: BotDetector,
+    playback: Playback,
+    preprocessor: Preprocessor,
+    filter: SentenceFilter,
+    tokenizer: Tokenizer,
+    prune: PruneOptions,
+    /// Lines recently said in each channel, keyed by casefolded channel.
+    recent: HashMap<String, RecentMessages>,
+    /// What the bot generated lately, and from which chains, for `!markov source`.
+    generated: GeneratedLog,
+    backups: usize,
+    /// How the blob, or a split data directory's files, are compressed when they're saved.
+    compression: Compression,
+    counters: Counters,
+    spam: SpamFilter,
+    /// Messages trained on lately, so they can be scrubbed back out.
+    journal: Journal,
+    lifetime: LifetimeCounters,
+    /// Everything the bot says goes through here, to be sent at a pace the server is happy with.
+    queue: SendQueue,
+    /// Shuts the whole program down when an owner asks.
+    shutdown: Shutdown,
+    /// Whether anything that gets saved has changed since the last save.
+    dirty: bool,
+    /// How long to go between saves, from the `save_interval` option.
+    save_interval: Duration,
+    /// When the chains were last saved, or when the bot started if they haven't been yet.
+    last_saved: Instant,
+    /// How many messages to train on before saving, however long it's been since the last save, or 0 to only save
+    /// on time.
+    save_after: usize,
+    /// How many messages have been trained on since the last save.
+    trained_since_save: usize,
+    /// Chains changed since the last save, as casefolded (channel, user) pairs; only SQLite saves look at these.
+    changed_chains: HashSet<(String, String)>,
+    /// The config file and server name this bot was started from.
+    config_source: Option<(String, String)>,
+    /// Where the blob of chains is saved, and where snapshots of them are kept whatever the storage.
+    blobs: Option<Box<dyn BlobStore>>,
+    /// Where chains are saved when the storage is SQLite, which is also where evicted chains are loaded back from.
+    store: Option<SqliteStore>,
+    /// Most bytes the chains should take up before some are evicted, or 0 for no limit.
+    memory_limit: u64,
+    /// Chains that were evicted to stay under the memory limit, as casefolded (channel, user) pairs. They're still in
+    /// the store, and get loaded back when they're needed.
+    evicted: HashSet<(String, String)>,
+    /// Where chains are saved when the storage is split, which is also where channels are loaded from as they're
+    /// needed.
+    split: Option<SplitStore>,
+    /// Casefolded channels that have chains in the split data directory that haven't been loaded yet.
+    unloaded: HashSet<String>,
+    /// When each chain in memory was last trained or loaded.
+    touched: HashMap<(String, String), Instant>,
+    /// Channels the bot was kicked or removed from, by casefolded name, with when to rejoin them, or None to stay out
+    /// until someone tells it to join again.
+    kicked: HashMap<String, Option<Instant>>,
+    /// How long to wait before rejoining a channel after being kicked, where 0 stays out.
+    rejoin_delay: Duration,
+    /// How long to give NickServ to identify us before joining channels anyway, from the `identify_timeout` option.
+    identify_timeout: Duration,
+    /// Whether allchains are built when the server starts, rather than the first time they're needed.
+    build_allchains: bool,
+    /// Seconds to wait before reconnecting the first time, and most seconds to wait as the waits get longer.
+    reconnect_delay: u64,
+    reconnect_max_delay: u64,
+    /// How each new connection logs in, which `set_server` starts over from.
+    login: Login,
+    /// The SASL login in progress on the current connection, if the server is configured to use one.
+    sasl: Option<Sasl>,
+    /// Identification with NickServ on the current connection, which also keeps track of our nick.
+    nickserv: NickServ,
+    /// Which of the configured nicks we're on, for taking the first one back when we're on an alt nick.
+    alt_nicks: AltNicks,
+    /// The config as it was loaded, before the runtime settings went on top of it.
+    base_config: ServerConfig,
+    /// The config with the runtime settings on top, which is what everything goes by.
+    config: ServerConfig,
+    server: Arc<dyn ChatServer>,
+}
+
+impl IrcBot {
+    pub fn new<S: ChatServer + 'static>(server: S, config: &ServerConfig) -> error::Result<Self> {
+        config.validate().map_err(Error::Config)?;
+        let options = Options::from_config(config)?;
+        let blob = BlobFile::new(options.order.unwrap_or(DEFAULT_ORDER));
+        Ok(Self::with_options(server, config, blob, options))
+    }
+
+    /// Constructs this IrcBot with a pre-saved chain and user settings.
+    ///
+    /// The config is checked first, so that everything read from it afterwards can be counted on to make sense.
+    pub fn from_blob_file<S: ChatServer + 'static>(
+        server: S,
+        config: &ServerConfig,
+        blob: BlobFile,
+    ) -> error::Result<Self> {
+        config.validate().map_err(Error::Config)?;
+        let options = Options::from_config(&blob.runtime_settings.apply(config))?;
+        Ok(Self::with_options(server, config, blob, options))
+    }
+
+    /// Puts a bot together from a saved blob and the options read from its config, with the blob's runtime settings
+    /// already on top.
+    fn with_options<S: ChatServer + 'static>(server: S, config: &ServerConfig, blob: BlobFile, options: Options)
+        -> Self
+    {
+        let server: Arc<dyn ChatServer> = Arc::new(server);
+        let applied = blob.runtime_settings.apply(config);
+        IrcBot {
+            chains: blob.chains,
+            eras: blob.eras,
+            era_buckets: options.era_buckets,
+            topic_chains: blob.topic_chains,
+            topics: HashMap::new(),
+            quotes: blob.quotes,
+            last_said: HashMap::new(),
+            allchains: AllChains::new(),
+            global_max_age: options.global_max_age,
+            user_settings: blob.user_settings,
+            aliases: blob.aliases,
+            bot_overrides: blob.bot_overrides,
+            runtime: blob.runtime_settings,
+            // a blob that's never been pruned starts decaying from now
+            last_pruned: Some(blob.last_pruned.unwrap_or_else(|| Local::now().timestamp())),
+            last_seen: blob.last_seen,
+            presence: Presence::default(),
+            session_nicks: HashMap::new(),
+            chats: HashMap::new(),
+            pending_deletions: HashMap::new(),
+            ignore: options.ignore,
+            channel_ignores: options.channel_ignores,
+            blacklist: options.blacklist,
+            channel_blacklists: options.channel_blacklists,
+            triggers: options.triggers,
+            last_triggered: HashMap::new(),
+            order: options.order.unwrap_or(blob.order),
+            chance: options.chance,
+            mention_chance: options.mention_chance,
+            mention_from_all: options.mention_from_all,
+            pm_chat: options.pm_chat,
+            self_train: options.self_train,
+            shadow: options.shadow,
+            training_cap: options.training_cap,
+            training_cap_period: options.training_cap_period,
+            timezone: options.timezone,
+            output_lines: options.output_lines,
+            nick_mentions: options.nick_mentions,
+            limiter: RateLimiter::new(options.rate_limits),
+            cooldown: options.cooldown,
+            activity: options.activity,
+            schedule: options.schedule,
+            conversations: HashMap::new(),
+            conversation_depth: options.conversation_depth,
+            conversation_window: options.conversation_window,
+            routes: options.routes,
+            preprocessor: options.preprocessor,
+            filter: options.filter,
+            tokenizer: options.tokenizer,
+            prune: options.prune,
+            recent: HashMap::new(),
+            generated: GeneratedLog::new(),
+            backups: options.backups,
+            compression: options.compression,
+            detector: options.detector,
+            playback: options.playback,
+            counters: Counters::new(),
+            spam: options.spam,
+            journal: options.journal,
+            lifetime: blob.lifetime,
+            queue: SendQueue::new(server.clone(), options.pacing),
+            shutdown: Shutdown::new(),
+            dirty: false,
+            save_interval: options.save_interval,
+            last_saved: Instant::now(),
+            save_after: options.save_after,
+            trained_since_save: 0,
+            changed_chains: HashSet::new(),
+            config_source: None,
+            blobs: None,
+            store: None,
+            memory_limit: options.memory_limit,
+            evicted: HashSet::new(),
+            split: None,
+            unloaded: HashSet::new(),
+            touched: HashMap::new(),
+            kicked: HashMap::new(),
+            rejoin_delay: options.rejoin_delay,
+            identify_timeout: options.identify_timeout,
+            build_allchains: options.build_allchains,
+            reconnect_delay: options.reconnect_delay,
+            reconnect_max_delay: options.reconnect_max_delay,
+            sasl: options.login.sasl.clone(),
+            nickserv: options.login.nickserv.clone(),
+            alt_nicks: options.login.alt_nicks.clone(),
+            login: options.login,
+            base_config: config.clone(),
+            config: applied,
+            server,
+        }
+    }
+
+    /// Sets everything that comes from the config, with the runtime settings on top, leaving the chains, settings, and
+    /// session state alone. Nothing changes if the config doesn't check out.
+    fn apply_config(&mut self, config: &ServerConfig) -> error::Result<()> {
+        let options = Options::from_config(&self.runtime.apply(config))?;
+        self.apply_options(config, options);
+        Ok(())
+    }
+
+    /// Sets everything that comes from the config to what was read from it.
+    fn apply_options(&mut self, config: &ServerConfig, options: Options) {
+        self.base_config = config.clone();
+        self.ignore = options.ignore;
+        self.channel_ignores = options.channel_ignores;
+        self.blacklist = options.blacklist;
+        self.channel_blacklists = options.channel_blacklists;
+        self.triggers = options.triggers;
+        self.order = options.order.unwrap_or(self.order);
+        self.chance = options.chance;
+        self.mention_chance = options.mention_chance;
+        self.mention_from_all = options.mention_from_all;
+        self.pm_chat = options.pm_chat;
+        self.timezone = options.timezone;
+        self.self_train = options.self_train;
+        self.shadow = options.shadow;
+        self.training_cap = options.training_cap;
+        self.training_cap_period = options.training_cap_period;
+        self.era_buckets = options.era_buckets;
+        self.output_lines = options.output_lines;
+        self.nick_mentions = options.nick_mentions;
+        self.limiter.set_limits(options.rate_limits);
+        self.cooldown.reconfigure(options.cooldown);
+        self.activity.reconfigure(options.activity);
+        self.schedule.reconfigure(options.schedule);
+        self.spam.reconfigure(options.spam);
+        self.journal.reconfigure(options.journal);
+        self.queue.set_pacing(options.pacing);
+        self.conversation_depth = options.conversation_depth;
+        self.global_max_age = options.global_max_age;
+        self.rejoin_delay = options.rejoin_delay;
+        self.conversation_window = options.conversation_window;
+        self.routes = options.routes;
+        self.preprocessor = options.preprocessor;
+        self.filter = options.filter;
+        self.tokenizer = options.tokenizer;
+        self.prune = options.prune;
+        self.memory_limit = options.memory_limit;
+        self.save_interval = options.save_interval;
+        self.save_after = options.save_after;
+        self.backups = options.backups;
+        self.compression = options.compression;
+        if let Some(split) = self.split.as_mut() {
+            split.set_compression(self.compression);
+        }
+        self.detector.reconfigure(options.detector);
+        self.playback.reconfigure(options.playback);
+        self.identify_timeout = options.identify_timeout;
+        self.build_allchains = options.build_allchains;
+        self.reconnect_delay = options.reconnect_delay;
+        self.reconnect_max_delay = options.reconnect_max_delay;
+        self.login = options.login;
+        self.config = self.runtime.apply(config);
+    }
+
+    /// Remembers which config file and server entry this bot was started from, so `!markov reload` can read them
+    /// again.
+    pub fn set_config_source(&mut self, path: &str, name: &str) {
+        self.config_source = Some((path.to_string(), name.to_string()));
+    }
+
+    /// Hands the bot the program's shutdown signal, so `!markov shutdown` can set it off.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    /// Starts shutting the whole program down, which ends up calling `quit` on every bot.
+    fn request_shutdown(&mut self, nick: &str) {
+        event!(info, "shutdown", "{} asked me to shut down", nick);
+        self.shutdown.trigger();
+    }
+
+    /// Leaves the server properly, once the replies still waiting to go out have been sent.
+    pub fn quit(&mut self) {
+        if !self.queue.drain(Duration::from_secs(QUIT_DRAIN_TIMEOUT)) {
+            warn!("gave up waiting for {} queued lines to be sent before quitting", self.queue.len());
+        }
+        let message = self.config.options
+            .as_ref()
+            .and_then(|o| o.get("quit_message"))
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_QUIT_MESSAGE)
+            .to_string();
+        event!(info, "quit", "quitting {}", self.config.address);
+        if let Err(e) = self.server.send(Command::QUIT(Some(message))) {
+            error!("could not quit {}: {}", self.config.address, e);
+        }
+    }
+
+    /// Gets the config this bot is currently running with, which changes on reload.
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Switches to a reloaded config, joining and parting channels to match it.
+    ///
+    /// Chains and allchains are kept, except for allchains whose channel now has a different order. Changes to the
+    /// address, port, or nick take effect the next time the bot reconnects.
+    pub fn reload(&mut self, config: &ServerConfig) -> error::Result<()> {
+        let options = Options::from_config(&self.runtime.apply(config))?;
+        for channel in &config.channels {
+            if self.config.channel(&channel.name).is_none() {
+                event!(info, "join", "joining {}", channel.name);
+                let joined = match channel.key {
+                    Some(ref key) => self.server.send_join_with_keys(&channel.name, key),
+                    None => self.server.send_join(&channel.name),
+                };
+                if let Err(e) = joined {
+                    error!("{}", e);
+                }
+            }
+        }
+        for channel in &self.config.channels {
+            if config.channel(&channel.name).is_none() {
+                event!(info, "part", "parting {}", channel.name);
+                if let Err(e) = self.server.send_part(&channel.name) {
+                    error!("{}", e);
+                }
+            }
+        }
+
+        self.apply_options(config, options);
+        let (config, order) = (&self.config, self.order);
+        self.allchains.invalidate_stale(|channel| config.channel(channel).and_then(|c| c.order).unwrap_or(order));
+        event!(info, "reload", "reloaded config for {}", self.config.address);
+        Ok(())
+    }
+
+    /// Joins a channel and adds it to the config, returning what to tell whoever asked.
+    fn join(&mut self, channel: &str, key: Option<&str>) -> String {
+        if self.kicked.contains_key(&casemap::fold(channel)) {
+            self.kicked.remove(&casemap::fold(channel));
+            return match self.join_configured(channel) {
+                Ok(()) => format!("Rejoining {}", channel),
+                Err(e) => format!("Could not join {}: {}", channel, e),
+            };
+        }
+        if self.config.channel(channel).is_some() {
+            return format!("I'm already in {}", channel);
+        }
+        let joined = match key {
+            Some(key) => self.server.send_join_with_keys(channel, key),
+            None => self.server.send_join(channel),
+        };
+        if let Err(e) = joined {
+            return format!("Could not join {}: {}", channel, e);
+        }
+        event!(info, "join", "joining {}", channel);
+        let entry = config::Channel::new(channel, key);
+        let saved = match self.config_source {
+            Some((ref path, ref name)) => config::add_channel_to_file(path, name, &entry),
+            None => Err("I don't know which config file I came from".to_string()),
+        };
+        self.base_config.channels.push(entry);
+        self.config = self.runtime.apply(&self.base_config);
+        match saved {
+            Ok(()) => format!("Joined {}", channel),
+            Err(e) => format!("Joined {}, but couldn't add it to the config: {}", channel, e),
+        }
+    }
+
+    /// Leaves a channel and removes it from the config, returning what to tell whoever asked.
+    fn part(&mut self, channel: &str) -> String {
+        if self.config.channel(channel).is_none() {
+            return format!("I'm not in {}", channel);
+        }
+        if let Err(e) = self.server.send_part(channel) {
+            return format!("Could not leave {}: {}", channel, e);
+        }
+        event!(info, "part", "parting {}", channel);
+        self.base_config.channels.retain(|c| !casemap::eq(&c.name, channel));
+        self.config.channels.retain(|c| !casemap::eq(&c.name, channel));
+        let saved = match self.config_source {
+            Some((ref path, ref name)) => config::remove_channel_from_file(path, name, channel),
+            None => Err("I don't know which config file I came from".to_string()),
+        };
+        match saved {
+            Ok(()) => format!("Left {}", channel),
+            Err(e) => format!("Left {}, but couldn't remove it from the config: {}", channel, e),
+        }
+    }
+
+    /// Reloads the config from the file this bot was started from.
+    fn reload_from_source(&mut self) -> Result<(), String> {
+        let (path, name) = self.config_source
+            .clone()
+            .ok_or_else(|| "I don't know which config file I came from".to_string())?;
+        let config = ProgramConfig::load(&path)?;
+        let server = config.servers
+            .get(&name)
+            .ok_or_else(|| format!("there's no server named {} in {} anymore", name, path))?;
+        self.reload(server).map_err(|e| e.to_string())
+    }
+
+    /// Switches over to a new connection after a reconnect.
+    pub fn set_server<S: ChatServer + 'static>(&mut self, server: S) {
+        let server: Arc<dyn ChatServer> = Arc::new(server);
+        self.queue.set_server(server.clone());
+        self.server = server;
+        let login = self.login.clone();
+        self.sasl = login.sasl;
+        self.nickserv = login.nickserv;
+        self.alt_nicks = login.alt_nicks;
+        self.presence.clear();
+        self.counters.reconnects += 1;
+    }
+
+    /// Gets how long to give NickServ to identify us before joining channels anyway, if channels wait on it at all.
+    pub fn identify_timeout(&self) -> Option<Duration> {
+        self.config.nickserv_password.as_ref().map(|_| self.identify_timeout)
+    }
+
+    /// Gets whether the allchains are built as soon as the server starts, rather than the first time they're needed.
+    pub fn builds_allchains(&self) -> bool {
+        self.build_allchains
+    }
+
+    /// Gets how many seconds to wait before reconnecting the first time, and the most to wait as the waits get longer.
+    pub fn reconnect_delays(&self) -> (u64, u64) {
+        (self.reconnect_delay, self.reconnect_max_delay)
+    }
+
+    /// Joins the configured channels if they're still waiting on NickServ, after the identify timeout has run out on
+    /// the given connection.
+    pub fn identify_timed_out(&mut self, connection: u64) {
+        if connection == self.counters.reconnects && self.nickserv.time_out() {
+            self.join_configured_channels();
+        }
+    }
+
+    /// Gets the nick the server knows us by.
+    fn current_nick(&self) -> &str {
+        self.nickserv.current_nick().unwrap_or_else(|| self.server.current_nickname())
+    }
+
+    fn join_configured_channels(&self) {
+        for channel in &self.config.channels {
+            if let Err(e) = self.join_configured(&channel.name) {
+                error!("could not join {}: {}", channel.name, e);
+            }
+        }
+    }
+
+    /// Joins a channel from the config, with its key if it has one.
+    fn join_configured(&self, channel: &str) -> irc::error::Result<()> {
+        match self.config.channel(channel).and_then(|c| c.key.as_ref()) {
+            Some(key) => self.server.send_join_with_keys(channel, key),
+            None => self.server.send_join(channel),
+        }
+    }
+
+    /// Notes that we were kicked or removed from a channel, and decides whether to go back.
+    fn kicked_from(&mut self, channel: &str, by: &str, reason: Option<&str>) {
+        let reason = reason.unwrap_or("no reason given");
+        let rejoin = self.rejoin_delay > Duration::from_secs(0)
+            && self.config.channel(channel).map(|c| c.rejoin.unwrap_or(true)).unwrap_or(false);
+        if rejoin {
+            event!(warn, "kick", "{} kicked us from {} ({}); rejoining in {}s", by, channel, reason,
+                   self.rejoin_delay.as_secs());
+            self.kicked.insert(casemap::fold(channel), Some(Instant::now() + self.rejoin_delay));
+        } else {
+            event!(warn, "kick", "{} kicked us from {} ({}); staying out until told to join again", by, channel,
+                   reason);
+            self.kicked.insert(casemap::fold(channel), None);
+        }
+    }
+
+    /// Rejoins channels we were kicked from whose delay is up, returning how long until the next one is, if there
+    /// are any left to rejoin.
+    pub fn rejoin_kicked(&mut self, now: Instant) -> Option<Duration> {
+        let due = self.kicked
+            .iter()
+            .filter(|&(_, &when)| when.map(|when| when <= now).unwrap_or(false))
+            .map(|(channel, _)| channel.clone())
+            .collect::<Vec<_>>();
+        for channel in due {
+            self.kicked.remove(&channel);
+            // the channel may have been dropped from the config while we waited
+            let name = match self.config.channel(&channel) {
+                Some(c) => c.name.clone(),
+                None => continue,
+            };
+            event!(info, "join", "rejoining {}", name);
+            if let Err(e) = self.join_configured(&name) {
+                error!("could not rejoin {}: {}", name, e);
+            }
+        }
+        self.kicked.values().filter_map(|&when| when).map(|when| when - now).min()
+    }
+
+    /// Asks for the configured nick back if the bot is on an alt nick and it's time to try again, returning how long
+    /// until the next try.
+    pub fn reclaim_nick(&mut self, now: Instant) -> Option<Duration> {
+        self.alt_nicks.reclaim(&*self.server, self.nickserv.current_nick(), now)
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub fn send_queue(&self) -> &SendQueue {
+        &self.queue
+    }
+
+    /// Gets how many users each channel has chains for and how much they've learned, for the chains in memory.
+    pub fn channel_chain_stats(&self) -> Vec<(&str, usize, ChainStats)> {
+        self.chains
+            .iter()
+            .map(|(channel, users)| {
+                let learned = users.values().map(ChainStats::of).fold(ChainStats::default(), |a, b| a + b);
+                (channel.as_str(), users.len(), learned)
+            })
+            .collect()
+    }
+
+    /// Gets how much the bot has learned from each user in a channel, along with their chain's order, for the
+    /// chains in memory.
+    pub fn user_chain_stats(&self, channel: &str) -> Vec<(&str, ChainStats, usize)> {
+        self.chains
+            .get(&casemap::fold(channel))
+            .map(|users| {
+                users.iter()
+                    .map(|(user, chain)| (user.as_str(), ChainStats::of(chain), chain.order()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Generates a sentence from a user's chain, or the whole channel's, without saying it anywhere.
+    pub fn preview(&mut self, channel: &str, user: Option<&str>) -> String {
+        match user {
+            Some(user) => self.emulate(channel, user, None),
+            None => self.generate_all(channel)
+                .unwrap_or_else(|| format!("Nobody has said anything I can use in {}", channel)),
+        }
+    }
+
+    /// Sets a channel's topic to one generated from its topic chain, if the channel lets the bot set its topic. The
+    /// new topic speaks for itself, so there's only something to say if it can't be set.
+    fn set_topic(&mut self, channel: &str) -> Option<String> {
+        if !self.sets_topic(channel) {
+            return Some(format!("I'm not allowed to set the topic in {}", channel));
+        }
+        let topic = match self.generate_topic(channel) {
+            Some(topic) => topic,
+            None => return Some(format!("Nobody has said anything I can use in {}", channel)),
+        };
+        if self.topics.get(&casemap::fold(channel)) == Some(&topic) {
+            return Some(format!("Couldn't come up with anything but the topic {} already has", channel));
+        }
+        event!(info, "topic", "setting the topic in {} to {}", channel, topic);
+        self.server
+            .send(Command::TOPIC(channel.to_string(), Some(topic)))
+            .err()
+            .map(|e| format!("Could not set the topic in {}: {}", channel, e))
+    }
+
+    /// Handles an incoming IRC message.
+    pub fn handle(&mut self, msg: Message) {
+        if self.nickserv.handle(&*self.server, &msg) {
+            self.join_configured_channels();
+        }
+        self.alt_nicks.handle(&*self.server, &msg, self.nickserv.current_nick());
+        if let Some(ref mut sasl) = self.sasl {
+            if sasl.handle(&*self.server, &msg) {
+                return;
+            }
+        }
+        self.track_presence(&msg);
+        // who sent it, as `nick!user@host`, which is what ignore masks are matched against
+        let prefix = msg.prefix.as_ref().map(Prefix::to_string);
+        match msg.command {
+            Command::PRIVMSG(ref target, ref msg_str) => {
+                if let Some(ref prefix) = prefix {
+                    if config::is_channel_name(target) {
+                        match self.playback.classify(target, &msg) {
+                            Delivery::Live => self.channel_message(prefix, target, msg_str, true),
+                            Delivery::TrainOnly => self.channel_message(prefix, target, msg_str, false),
+                            Delivery::Skip => trace!("skipping played back message in {}", target),
+                        }
+                    } else {
+                        let sender = msg.source_nickname().unwrap_or(target);
+                        // commands in played back private messages were for someone else's session
+                        match self.playback.classify(sender, &msg) {
+                            Delivery::Live => self.private_message(prefix, msg_str),
+                            _ => trace!("skipping played back private message from {}", sender),
+                        }
+                    }
+                }
+            }
+            Command::NICK(ref new_nick) => {
+                if let Some(old_nick) = msg.source_nickname() {
+                    self.nick_changed(old_nick, new_nick);
+                }
+            }
+            Command::JOIN(ref channel, _, _) => {
+                // ask about everyone in a channel when we join it, and about everyone who joins after that
+                let target = match msg.source_nickname() {
+                    Some(nick) if casemap::eq(nick, self.current_nick()) => {
+                        self.kicked.remove(&casemap::fold(channel));
+                        channel.clone()
+                    }
+                    Some(nick) => {
+                        self.greet(channel, prefix.as_ref().unwrap());
+                        nick.to_string()
+                    }
+                    None => return,
+                };
+                if self.detector.checks_usermode() {
+                    if let Err(e) = self.server.send(Command::WHO(Some(target), None)) {
+                        error!("{}", e);
+                    }
+                }
+            }
+            Command::KICK(ref channel, ref nick, ref reason) if casemap::eq(nick, self.current_nick()) => {
+                let by = msg.source_nickname().unwrap_or("the server");
+                self.kicked_from(channel, by, reason.as_ref().map(String::as_str));
+            }
+            // a PART we didn't ask for, for a channel we're still supposed to be in, is a server-side REMOVE
+            Command::PART(ref channel, ref reason)
+                if msg.source_nickname().map(|nick| casemap::eq(nick, self.current_nick())).unwrap_or(false)
+                    && self.config.channel(channel).is_some() =>
+            {
+                self.kicked_from(channel, "the server", reason.as_ref().map(String::as_str));
+            }
+            Command::TOPIC(ref channel, Some(ref topic)) => {
+                self.topic_changed(channel, topic, prefix.as_deref());
+            }
+            Command::Response(Response::RPL_TOPIC, ref args) => {
+                // <me> <channel> <topic>; this is the topic as it was when we joined
+                if let (Some(channel), Some(topic)) = (args.get(1), args.get(2)) {
+                    self.topic_changed(channel, topic, None);
+                }
+            }
+            Command::Response(Response::RPL_NOTOPIC, ref args) => {
+                if let Some(channel) = args.get(1) {
+                    self.topics.remove(&casemap::fold(channel));
+                }
+            }
+            Command::Response(Response::RPL_WHOREPLY, ref args) => {
+                // <me> <channel> <user> <host> <server> <nick> <flags>
+                if let (Some(nick), Some(flags)) = (args.get(5), args.get(6)) {
+                    self.detector.record_who_flags(&casemap::fold(nick), flags);
+                }
+            }
+            _ => trace!("not handled: {}", msg),
+        }
+    }
+
+    /// Keeps track of who's in each channel, and when each user was last around.
+    fn track_presence(&mut self, msg: &Message) {
+        let nick = msg.source_nickname().unwrap_or("");
+        let ours = casemap::eq(nick, self.current_nick());
+        match msg.command {
+            // whoever's there is listed again right after we join
+            Command::JOIN(ref channel, _, _) if ours => self.presence.clear_channel(channel),
+            Command::JOIN(ref channel, _, _) => self.presence.joined(channel, nick),
+            Command::PART(ref channel, _) if ours => self.presence.clear_channel(channel),
+            Command::PART(ref channel, _) => self.presence.left(channel, nick),
+            Command::KICK(ref channel, ref kicked, _) if casemap::eq(kicked, self.current_nick()) => {
+                self.presence.clear_channel(channel)
+            }
+            Command::KICK(ref channel, ref kicked, _) => self.presence.left(channel, kicked),
+            Command::QUIT(_) => self.presence.quit(nick),
+            Command::NICK(ref new_nick) => self.presence.renamed(nick, new_nick),
+            Command::Response(Response::RPL_NAMREPLY, ref args) => {
+                // <me> <type> <channel> <names>
+                if let (Some(channel), Some(names)) = (args.get(2), args.get(3)) {
+                    self.presence.names(channel, names);
+                }
+                return;
+            }
+            Command::PRIVMSG(..) => {}
+            _ => return,
+        }
+        if !nick.is_empty() && !ours {
+            let user = self.canonical_nick(nick);
+            self.last_seen.insert(user, Local::now().timestamp());
+        }
+    }
+
+    /// Keeps training a user's existing chain after they change nicks.
+    fn nick_changed(&mut self, old: &str, new: &str) {
+        self.detector.rename(&casemap::fold(old), &casemap::fold(new));
+        if let Some(chat) = self.chats.remove(&casemap::fold(old)) {
+            self.chats.insert(casemap::fold(new), chat);
+        }
+        let canonical = self.canonical_nick(old);
+        self.session_nicks.remove(&casemap::fold(old));
+        if casemap::fold(new) != canonical {
+            debug!("{} is now known as {}, training as {}", old, new, canonical);
+            self.session_nicks.insert(casemap::fold(new), canonical);
+        }
+    }
+
+    /// Handles a channel message from the user with the given `nick!user@host` prefix.
+    ///
+    /// Messages that aren't `live` were played back by a bouncer, and are only trained on.
+    fn channel_message(&mut self, prefix: &str, channel: &str, msg: &str, live: bool) {
+        let _channel = logging::Scope::channel(channel);
+        let sender = prefix.split('!').nth(0).unwrap();
+        // ignore messages from ourself
+        if casemap::eq(sender, self.current_nick()) {
+            return;
+        }
+
+        if live && !self.is_ignored_by_config(channel, sender, prefix) {
+            self.activity.record_message(channel, Instant::now());
+            self.schedule.record_activity(channel, Instant::now());
+        }
+
+        // a split data directory's channels are loaded the first time anything happens in them
+        self.load_channel(channel);
+        let msg_parts = msg.split_whitespace().collect::<Vec<_>>();
+        let command = msg_parts.first().and_then(|word| self.config.command_words(Some(channel), word));
+        if let Some(words) = command {
+            if live {
+                let parts = command_parts(&words, &msg_parts[1..]);
+                self.handle_command(sender, channel, &parts);
+            }
+        } else {
+            let key = casemap::fold(sender);
+            // a burst of playback says nothing about how fast someone talks
+            if live {
+                self.detector.record_message(&key);
+                self.cooldown.record_message(channel);
+            }
+            self.detector.check_nick(&key, sender);
+            // even lines that aren't learned from shouldn't be parroted back
+            self.remember_line(channel, msg);
+            if self.is_ignored(channel, sender, prefix) {
+                return;
+            }
+            let said = (casemap::fold(channel), self.canonical_nick(sender));
+            self.last_said.insert(said, (sender.to_string(), msg.to_string()));
+            self.counters.messages_seen += 1;
+            let chance = self.activity.scale(channel, self.user_chance(channel, sender), Instant::now());
+            let cleaned = self.preprocessor.process(msg);
+            let blacklisted = self.blacklist_for(channel).matches(msg);
+            if blacklisted {
+                debug!("not training on a blacklisted message in {}", channel);
+            }
+            if let (true, false, Some(cleaned)) = (self.trains_in(channel), blacklisted, cleaned) {
+                let tokens = self.mask_nicks(channel, self.tokenizer_for(channel).tokenize(&cleaned));
+                // played back messages weren't said just now, so they can't be told apart from repeats
+                let spam = if live {
+                    self.spam.check(channel, sender, &cleaned, tokens.len(), Instant::now())
+                } else {
+                    self.spam.junk(&cleaned, tokens.len())
+                };
+                match spam {
+                    Some(reason) => debug!("not training on a message from {} in {}: {}", sender, channel, reason),
+                    None if !self.within_training_cap(channel, sender, tokens.len()) => {
+                        debug!("not training on a message from {} in {}: over the training cap", sender, channel)
+                    }
+                    None => self.train_message(channel, sender, tokens),
+                }
+            }
+
+            // trigger words get a response of their own, instead of a random reply
+            if live && self.trigger(channel, sender, msg) {
+                return;
+            }
+
+            // Reply if we feel like it; being addressed by name has its own chance, and answering one of our replies
+            // always gets another, seeded from what they said
+            let mentioned = self.is_mentioned(msg);
+            let conversing = mentioned && self.in_conversation(channel, sender);
+            let reply_chance = if conversing { 1.0 } else if mentioned { self.mention_chance } else { chance };
+            let random = rand::thread_rng().next_f64();
+            let ready = conversing || self.cooldown.ready(channel);
+            if live && self.responds_in(channel) && random < reply_chance && ready {
+                let seeds = if conversing {
+                    self.tokenizer_for(channel)
+                        .tokenize(msg)
+                        .into_iter()
+                        .filter(|word| !self.is_mentioned(word))
+                        .collect()
+                } else {
+                    vec![]
+                };
+                let generated = self.reply_to(channel, sender, mentioned && self.mention_from_all, &seeds);
+                let cost = self.limiter.limits().reply_cost;
+                if let Some(generated) = generated {
+                    if self.allow(channel, sender, cost, false) {
+                        let message = self.address(channel, sender, &generated);
+                        if self.chatter(channel, &message) {
+                            self.lifetime.replies_sent += 1;
+                        }
+                        let key = (casemap::fold(channel), casemap::fold(sender));
+                        if conversing {
+                            if let Some(conversation) = self.conversations.get_mut(&key) {
+                                *conversation = (Instant::now(), conversation.1 + 1);
+                            }
+                        } else {
+                            self.cooldown.replied(channel);
+                            if self.conversation_depth > 0 {
+                                self.conversations.insert(key, (Instant::now(), 0));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a message sent straight to the bot. Nothing said in private is trained on.
+    fn private_message(&mut self, prefix: &str, msg: &str) {
+        let sender = prefix.split('!').nth(0).unwrap();
+        // don't get into a conversation with another bot
+        if self.ignore.matches(sender, prefix) || self.is_bot(sender) {
+            return;
+        }
+
+        let parts = msg.split_whitespace().collect::<Vec<_>>();
+        if let Some(words) = parts.first().and_then(|word| self.config.command_words(None, word)) {
+            let cost = self.limiter.limits().command_cost;
+            if self.allow(sender, sender, cost, true) {
+                self.private_command(sender, &command_parts(&words, &parts[1..]));
+            }
+            return;
+        }
+
+        let (channel, user) = match self.chats.get(&casemap::fold(sender)) {
+            Some(chat) => chat.clone(),
+            None => return,
+        };
+        let cost = self.limiter.limits().reply_cost;
+        if !self.allow(sender, sender, cost, true) {
+            return;
+        }
+        let reply = match user {
+            Some(user) => self.emulate(&channel, &user, None),
+            None => self.generate_all(&channel)
+                .unwrap_or_else(|| format!("Nobody has said anything I can use in {}", channel)),
+        };
+        self.send_privmsg(sender, &reply);
+        self.lifetime.replies_sent += 1;
+    }
+
+    /// Posts in every channel whose scheduled post is due, returning how long until the next one might be.
+    pub fn post_scheduled(&mut self) -> Duration {
+        let now = Instant::now();
+        let channels = self.config.channels
+            .iter()
+            .filter(|c| c.post.unwrap_or(false) && self.responds_in(&c.name))
+            .filter(|c| !self.kicked.contains_key(&casemap::fold(&c.name)))
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+        for channel in self.schedule.due(&channels, now) {
+            match self.generate_all(&channel) {
+                Some(post) => {
+                    event!(info, "post", "posting in {} on schedule", channel);
+                    self.chatter(&channel, &post);
+                }
+                None => debug!("nothing to post in {}, since nobody's said anything I can use", channel),
+            }
+        }
+        self.schedule.until_next(now)
+    }
+
+    /// Maybe greets someone who just joined a channel with something from their own chain, or from the channel's if
+    /// they don't have one there, going by the channel's greet chance.
+    fn greet(&mut self, channel: &str, prefix: &str) {
+        let sender = prefix.split('!').nth(0).unwrap();
+        let chance = self.config.channel(channel).and_then(|c| c.greet_chance).unwrap_or(0.0);
+        if chance <= 0.0 || !self.responds_in(channel) || self.is_ignored_by_config(channel, sender, prefix) {
+            return;
+        }
+        if rand::thread_rng().next_f64() >= chance {
+            return;
+        }
+        let cost = self.limiter.limits().reply_cost;
+        if !self.allow(channel, sender, cost, false) {
+            return;
+        }
+        // someone who asked to be ignored gets greeted like a stranger, rather than with their own words
+        let own = if self.opted_out(channel, sender) || self.opted_out(ALL_CHANNELS, sender) {
+            None
+        } else {
+            self.reply_to(channel, sender, false, &[])
+        };
+        if let Some(greeting) = own.or_else(|| self.generate_all(channel)) {
+            event!(info, "greet", "greeting {} in {}", sender, channel);
+            let message = self.address(channel, sender, &greeting);
+            self.chatter(channel, &message);
+        }
+    }
+
+    /// Responds to a message in a channel if it has one of the channel's trigger words, returning whether it did.
+    fn trigger(&mut self, channel: &str, sender: &str, msg: &str) -> bool {
+        let key = casemap::fold(channel);
+        if !self.responds_in(channel) || !self.triggers.get(&key).map(|t| t.matches(msg)).unwrap_or(false) {
+            return false;
+        }
+        let settings = self.config.channel(channel);
+        let chance = settings.and_then(|c| c.trigger_chance).unwrap_or(1.0);
+        let cooldown = settings.and_then(|c| c.trigger_cooldown).unwrap_or(DEFAULT_TRIGGER_COOLDOWN);
+        let cooled = self.last_triggered
+            .get(&key)
+            .map(|&last| last.elapsed() >= Duration::from_secs(cooldown))
+            .unwrap_or(true);
+        if !cooled || rand::thread_rng().next_f64() >= chance {
+            return false;
+        }
+        let cost = self.limiter.limits().reply_cost;
+        if !self.allow(channel, sender, cost, false) {
+            return false;
+        }
+        match self.generate_all(channel) {
+            Some(generated) => {
+                event!(info, "trigger", "responding to a trigger from {} in {}", sender, channel);
+                if self.chatter(channel, &generated) {
+                    self.lifetime.replies_sent += 1;
+                }
+                self.last_triggered.insert(key, Instant::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gets whether a message mentions the bot's current nick as a whole word.
+    fn is_mentioned(&self, msg: &str) -> bool {
+        let nick = self.current_nick();
+        msg.split(|c: char| !(c.is_alphanumeric() || "[]\\`_^{|}-".contains(c)))
+            .any(|word| casemap::eq(word, nick))
+    }
+
+    /// Sends the reply to a command wherever that command's replies are configured to go.
+    ///
+    /// Replies in the channel are addressed to the sender; notices and private messages don't need to be.
+    fn reply(&mut self, command: &str, channel: &str, sender: &str, message: &str) {
+        match self.routes.get(command) {
+            Route::Channel => {
+                let message = self.address(channel, sender, message);
+                self.send_privmsg(channel, &message);
+            }
+            Route::Private => self.send_privmsg(sender, message),
+            Route::Notice => self.send_lines(sender, message, true, Priority::Reply),
+        }
+    }
+
+    /// Puts the nick a message in a channel is for in front of it, in bold if the channel uses colors.
+    fn address(&self, channel: &str, nick: &str, message: &str) -> String {
+        let nick = if self.uses_colors(channel) { colors::bold(nick) } else { nick.to_string() };
+        format!("{}: {}", nick, message)
+    }
+
+    /// Queues something the bot says in a channel unprompted, or only logs it if the channel is in shadow mode,
+    /// returning whether it was really sent.
+    ///
+    /// Everything leading up to it, like cooldowns and rate limits, happens either way, so shadow mode shows what the
+    /// bot would be saying with its settings as they are.
+    fn chatter(&mut self, channel: &str, message: &str) -> bool {
+        if self.shadows(channel) {
+            event!(info, "shadow", "would have said in {}: {}", channel, message);
+            self.counters.shadowed += 1;
+            return false;
+        }
+        self.send_lines(channel, message, false, Priority::Chatter);
+        true
+    }
+
+    /// Queues a message that someone asked for.
+    fn send_privmsg(&mut self, target: &str, message: &str) {
+        self.send_lines(target, message, false, Priority::Reply);
+    }
+
+    /// Queues a message as a `PRIVMSG`, or a `NOTICE` if `notice` is set, split up or cut short so the server passes
+    /// it on whole.
+    fn send_lines(&mut self, target: &str, message: &str, notice: bool, priority: Priority) {
+        let command = if notice { "NOTICE" } else { "PRIVMSG" };
+        let max_len = output::max_text_len(command, target, self.current_nick());
+        for line in output::lines(message, max_len, self.output_lines) {
+            self.queue.push(priority, notice, target, &line);
+        }
+    }
+
+    /// Checks the rate limiter before saying something on behalf of `user` in `channel`.
+    ///
+    /// When `warn` is set, a throttled user is told (once) how long to wait.
+    fn allow(&mut self, channel: &str, user: &str, cost: f64, warn: bool) -> bool {
+        match self.limiter.acquire(channel, user, cost) {
+            Ok(()) => true,
+            Err(wait) => {
+                event!(debug, "throttle", "throttled {} in {} for {}s", user, channel, wait.as_secs());
+                if warn && self.limiter.should_warn(user) {
+                    let message = format!("Slow down! Try again in {} seconds.", wait.as_secs() + 1);
+                    self.send_lines(user, &message, true, Priority::Reply);
+                }
+                false
+            }
+        }
+    }
+
+}
+
+/// Puts the words a command prefix or alias stands for in front of the rest of a message, to be run as a command.
+fn command_parts<'a>(words: &'a [String], rest: &[&'a str]) -> Vec<&'a str> {
+    words.iter().map(String::as_str).chain(rest.iter().cloned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::IrcBot;
+    use crate::bot::testing::{self, ask, say, ADMIN, CHANNEL, NICK};
+    use crate::chat::mock::MockServer;
+    use chrono::{self, Utc};
+    use irc::client::prelude::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bad_options_keep_the_bot_from_starting() {
+        let config = testing::config("order = \"two\"");
+        let err = IrcBot::new(MockServer::new(NICK), &config).err().unwrap();
+        assert!(err.to_string().starts_with("invalid config: invalid value \"two\" for option order"), "{}", err);
+    }
+
+    #[test]
+    fn replies_at_random_from_the_sender_chain() {
+        let (mut bot, server) = testing::bot("chance = \"1\"");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        assert_eq!(reply, "alice: colorless green ideas sleep furiously");
+        assert_eq!(bot.counters().messages_trained, 1);
+    }
+
+    #[test]
+    fn stays_quiet_where_it_shouldnt_respond() {
+        let mut config = testing::config("chance = \"1\"");
+        config.channels[0].respond = Some(false);
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        // commands still work, and are the only thing said
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "alice: colorless green ideas sleep furiously");
+    }
+
+    #[test]
+    fn doesnt_learn_commands_or_itself() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, NICK, CHANNEL, "something the bot said");
+        ask(&mut bot, &server, "alice", CHANNEL, "!markov status");
+        assert!(bot.find_chain(CHANNEL, NICK).is_err());
+        assert_eq!(bot.counters().messages_trained, 0);
+    }
+
+    #[test]
+    fn keeps_blacklisted_words_out_of_training_and_replies() {
+        let mut config = testing::config("");
+        config.blacklist = Some(vec!["heck".to_string()]);
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "what the heck");
+        assert!(bot.find_chain(CHANNEL, "alice").is_err());
+        say(&mut bot, "alice", CHANNEL, "one two three");
+        assert_eq!(bot.counters().messages_trained, 1);
+
+        config.channels[0].blacklist = Some(vec!["/thr/".to_string()]);
+        bot.apply_config(&config).unwrap();
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: Couldn't come up with anything from alice that's allowed in #test");
+    }
+
+    #[test]
+    fn keeps_the_old_config_when_a_reload_doesnt_check_out() {
+        let (mut bot, _) = testing::bot_with(&testing::config("chance = \"0.5\""));
+        assert!(bot.reload(&testing::config("chance = \"lots\"")).is_err());
+        assert_eq!(bot.chance, 0.5);
+        assert_eq!(bot.config().options.as_ref().unwrap()["chance"], "0.5");
+    }
+
+    fn kick(bot: &mut IrcBot) {
+        bot.handle(Message {
+            tags: None,
+            prefix: Some(Prefix::new_from_str(&format!("{0}!{0}@users.example.com", ADMIN))),
+            command: Command::KICK(CHANNEL.to_string(), NICK.to_string(), Some("out".to_string())),
+        });
+    }
+
+    #[test]
+    fn rejoins_with_the_key_after_a_kick() {
+        let mut config = testing::config("rejoin_delay = \"30\"");
+        config.channels[0].key = Some("hunter2".to_string());
+        let (mut bot, server) = testing::bot_with(&config);
+        kick(&mut bot);
+        let now = Instant::now();
+        assert!(bot.rejoin_kicked(now).unwrap() > Duration::from_secs(25));
+        assert_eq!(bot.rejoin_kicked(now + Duration::from_secs(31)), None);
+        let join = Command::JOIN(CHANNEL.to_string(), Some("hunter2".to_string()), None);
+        assert_eq!(server.wait_for(1), vec![join]);
+    }
+
+    #[test]
+    fn stays_out_when_rejoining_is_off() {
+        let mut config = testing::config("");
+        config.channels[0].rejoin = Some(false);
+        let (mut bot, server) = testing::bot_with(&config);
+        kick(&mut bot);
+        assert_eq!(bot.rejoin_kicked(Instant::now() + Duration::from_secs(3600)), None);
+        say(&mut bot, ADMIN, "#elsewhere", &format!("!markov join {}", CHANNEL));
+        let sent = server.wait_for(2);
+        assert_eq!(sent[0], Command::JOIN(CHANNEL.to_string(), None, None));
+        assert_eq!(sent[1], Command::PRIVMSG("#elsewhere".to_string(), format!("{}: Rejoining {}", ADMIN, CHANNEL)));
+    }
+
+    fn join(bot: &mut IrcBot, nick: &str) {
+        bot.handle(Message {
+            tags: None,
+            prefix: Some(Prefix::new_from_str(&format!("{0}!{0}@users.example.com", nick))),
+            command: Command::JOIN(CHANNEL.to_string(), None, None),
+        });
+    }
+
+    #[test]
+    fn greets_people_who_join() {
+        // no WHOs to get in the way
+        let mut config = testing::config("bot_usermode = \"false\"");
+        config.channels[0].greet_chance = Some(1.0);
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        join(&mut bot, "alice");
+        let greeting = server.wait_for_message().unwrap();
+        assert_eq!(greeting, (CHANNEL.to_string(), "alice: colorless green ideas sleep furiously".to_string()));
+        // someone new gets something from everyone instead
+        join(&mut bot, "bob");
+        let greeting = server.wait_for_message().unwrap();
+        assert_eq!(greeting, (CHANNEL.to_string(), "bob: colorless green ideas sleep furiously".to_string()));
+    }
+
+    #[test]
+    fn learns_topics_people_set_and_sets_one_when_asked() {
+        let mut config = testing::config("");
+        config.channels[0].set_topic = Some(true);
+        let (mut bot, server) = testing::bot_with(&config);
+        let topic = |nick: Option<&str>, topic: &str| Message {
+            tags: None,
+            prefix: nick.map(|nick| Prefix::new_from_str(&format!("{0}!{0}@users.example.com", nick))),
+            command: Command::TOPIC(CHANNEL.to_string(), Some(topic.to_string())),
+        };
+        // the topic from when we joined was set before we got here
+        bot.handle(Message {
+            tags: None,
+            prefix: None,
+            command: Command::Response(Response::RPL_TOPIC,
+                                       vec![NICK.to_string(), CHANNEL.to_string(), "an old topic".to_string()]),
+        });
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov topic");
+        assert_eq!(reply, "bob: Nobody has said anything I can use in #test");
+
+        bot.handle(topic(Some("alice"), "welcome to the pasta party"));
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov topic");
+        assert_eq!(reply, "bob: welcome to the pasta party");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov topic set");
+        assert_eq!(reply, "bob: Only admins can use !markov topic set");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov topic set");
+        assert_eq!(reply, format!("{}: Couldn't come up with anything but the topic #test already has", ADMIN));
+
+        // what we set ourselves isn't learned from
+        bot.handle(topic(Some(NICK), "something else"));
+        say(&mut bot, ADMIN, CHANNEL, "!markov topic set");
+        let set = Command::TOPIC(CHANNEL.to_string(), Some("welcome to the pasta party".to_string()));
+        assert_eq!(server.wait_for(1), vec![set]);
+
+        config.channels[0].set_topic = None;
+        bot.apply_config(&config).unwrap();
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov topic set");
+        assert_eq!(reply, format!("{}: I'm not allowed to set the topic in #test", ADMIN));
+    }
+
+    #[test]
+    fn chances_out_of_range_are_clamped_and_impossible_options_refused() {
+        let mut config = testing::config("mention_chance = \"-2\"");
+        config.options.as_mut().unwrap().insert("chance".to_string(), "5".to_string());
+        let (bot, _) = testing::bot_with(&config);
+        assert_eq!((bot.chance, bot.mention_chance), (1.0, 0.0));
+
+        let impossible = [
+            "order = \"0\"",
+            "mention_chance = \"NaN\"",
+            "mention_chance = \"lots\"",
+            "min_words = \"10\"\nmax_words = \"5\"",
+            "activity_min_chance = \"0.5\"\nactivity_max_chance = \"0.2\"",
+            "post_min_interval = \"7200\"\npost_max_interval = \"60\"",
+            "compression = \"zstd\"\ncompression_level = \"30\"",
+            "urls = \"hide\"",
+            "save_interval = \"0\"",
+            "memory_limit = \"100\"",
+            "dashboard_port = \"8080\"",
+        ];
+        for options in &impossible {
+            assert!(testing::config(options).validate().is_err(), "{}", options);
+        }
+        assert!(testing::config("min_words = \"10\"\nmax_words = \"0\"").validate().is_ok());
+    }
+
+    #[test]
+    fn nicks_of_people_here_dont_highlight_them() {
+        let mut config = testing::config("bot_usermode = \"false\"");
+        let (mut bot, server) = testing::bot_with(&config);
+        join(&mut bot, "bob");
+        say(&mut bot, "alice", CHANNEL, "tell bob and carol hello");
+        let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "carol: tell b\u{200b}ob and carol hello");
+
+        config.channels[0].highlight_safe = Some(false);
+        let (mut bot, server) = testing::bot_with(&config);
+        join(&mut bot, "bob");
+        say(&mut bot, "alice", CHANNEL, "tell bob hello");
+        let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "carol: tell bob hello");
+    }
+
+    #[test]
+    fn nicks_of_people_here_can_be_left_out_of_training() {
+        let mut config = testing::config("bot_usermode = \"false\"\nnick_mentions = \"fill\"");
+        let (mut bot, server) = testing::bot_with(&config);
+        join(&mut bot, "bob");
+        say(&mut bot, "alice", CHANNEL, "thanks bob");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: thanks b\u{200b}ob");
+
+        config.channels[0].nick_mentions = Some("drop".to_string());
+        let (mut bot, server) = testing::bot_with(&config);
+        join(&mut bot, "bob");
+        say(&mut bot, "alice", CHANNEL, "bob is great");
+        let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "carol: is great");
+    }
+
+    #[test]
+    fn responds_to_trigger_words() {
+        let mut config = testing::config("");
+        config.channels[0].triggers = Some(vec!["pizza".to_string()]);
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        // keep the triggering messages out of what the bot can say
+        config.channels[0].train = Some(false);
+        bot.apply_config(&config).unwrap();
+        say(&mut bot, "bob", CHANNEL, "anyone want PIZZA");
+        let response = server.wait_for_message().unwrap();
+        assert_eq!(response, (CHANNEL.to_string(), "colorless green ideas sleep furiously".to_string()));
+        // the cooldown keeps a second mention quiet
+        say(&mut bot, "bob", CHANNEL, "pizza pizza");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: colorless green ideas sleep furiously");
+    }
+
+    #[test]
+    fn keeps_quiet_during_quiet_hours() {
+        let mut config = testing::config("");
+        let now = Utc::now();
+        let (start, end) = (now - chrono::Duration::hours(1), now + chrono::Duration::hours(1));
+        config.channels[0].quiet_hours = Some(format!("{}-{}", start.format("%H:%M"), end.format("%H:%M")));
+        config.channels[0].timezone = Some("UTC".to_string());
+        config.channels[0].triggers = Some(vec!["pizza".to_string()]);
+        let (mut bot, server) = testing::bot_with(&config);
+        assert!(!bot.is_quiet(CHANNEL, now + chrono::Duration::hours(3)));
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        say(&mut bot, "bob", CHANNEL, "anyone want pizza");
+        // commands still work, and what's said is still trained on
+        let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov emulate bob");
+        assert_eq!(reply, "carol: anyone want pizza");
+    }
+
+    #[test]
+    fn shadow_mode_logs_replies_instead_of_sending_them() {
+        let mut config = testing::config("shadow = \"true\"");
+        config.options.as_mut().unwrap().insert("chance".to_string(), "1".to_string());
+        let (mut bot, server) = testing::bot_with(&config);
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        assert_eq!(bot.counters.shadowed, 1);
+        assert_eq!(bot.lifetime.replies_sent, 0);
+        // the would-be reply never went out, so the next thing sent is the command's reply
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: colorless green ideas sleep furiously");
+
+        config.channels[0].shadow = Some(false);
+        bot.apply_config(&config).unwrap();
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        let response = server.wait_for_message().unwrap();
+        assert_eq!(response, (CHANNEL.to_string(), "alice: colorless green ideas sleep furiously".to_string()));
+        assert_eq!(bot.lifetime.replies_sent, 1);
+    }
+
+    #[test]
+    fn chats_in_private() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        let reply = ask(&mut bot, &server, "bob", NICK, "!markov chat #test alice");
+        assert_eq!(reply, "Chatting as alice; say anything, or \"!markov chat off\" to stop");
+        let reply = ask(&mut bot, &server, "bob", NICK, "hello?");
+        assert_eq!(reply, "colorless green ideas sleep furiously");
+        // nothing said in private is learned
+        assert!(bot.find_chain(CHANNEL, "bob").is_err());
+    }
+
+    #[test]
+    fn falls_back_to_an_alt_nick_and_takes_the_nick_back() {
+        let mut config = testing::config("nick_reclaim_interval = \"60\"");
+        config.alt_nicks = Some(vec!["markov_".to_string()]);
+        let (mut bot, server) = testing::bot_with(&config);
+        let from_server = |command| Message { tags: None, prefix: None, command };
+        let taken = vec!["*".to_string(), NICK.to_string()];
+        bot.handle(from_server(Command::Response(Response::ERR_NICKNAMEINUSE, taken)));
+        bot.handle(from_server(Command::Response(Response::RPL_WELCOME, vec!["markov_".to_string()])));
+        // what the bot says on its alt nick isn't learned, while someone else on its nick is
+        say(&mut bot, "markov_", CHANNEL, "something the bot said");
+        say(&mut bot, NICK, CHANNEL, "something someone else said");
+        assert!(bot.find_chain(CHANNEL, "markov_").is_err());
+        assert!(bot.find_chain(CHANNEL, NICK).is_ok());
+
+        let now = Instant::now();
+        assert!(bot.reclaim_nick(now).unwrap() > Duration::from_secs(55));
+        assert_eq!(bot.reclaim_nick(now + Duration::from_secs(61)), Some(Duration::from_secs(60)));
+        assert_eq!(server.wait_for(1), vec![Command::NICK(NICK.to_string())]);
+        // whoever has it quitting gets it asked for right away
+        bot.handle(Message {
+            tags: None,
+            prefix: Some(Prefix::new_from_str(&format!("{0}!{0}@users.example.com", NICK))),
+            command: Command::QUIT(None),
+        });
+        assert_eq!(server.wait_for(1), vec![Command::NICK(NICK.to_string())]);
+        bot.handle(Message {
+            tags: None,
+            prefix: Some(Prefix::new_from_str("markov_!bot@bots.example.com")),
+            command: Command::NICK(NICK.to_string()),
+        });
+        assert_eq!(bot.reclaim_nick(now + Duration::from_secs(600)), None);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::activity::Activity;
+use crate::altnick::AltNicks;
+use crate::botdetect::BotDetector;
+use crate::casemap;
+use crate::compress::Compression;
+use crate::config::{self, Server as ServerConfig};
+use crate::cooldown::Cooldown;
+use crate::duration::HumanDuration;
+use crate::era::Buckets;
+use crate::error::{self, Error};
+use crate::ignore::IgnoreList;
+use crate::journal::Journal;
+use crate::nickserv::{self, NickServ};
+use crate::playback::Playback;
+use crate::preprocess::Preprocessor;
+use crate::presence::NickMode;
+use crate::prune::PruneOptions;
+use crate::quality::SentenceFilter;
+use crate::quiet::Zone;
+use crate::ratelimit::RateLimits;
+use crate::reconnect;
+use crate::reply::Routes;
+use crate::sasl::Sasl;
+use crate::schedule::Schedule;
+use crate::sendqueue::Pacing;
+use crate::spam::SpamFilter;
+use crate::tokenize::Tokenizer;
+use crate::wordlist::WordList;
+use std::collections::HashMap;
+use std::time::Duration;
+use super::{DEFAULT_BACKUPS, DEFAULT_CHANCE, DEFAULT_CONVERSATION_WINDOW, DEFAULT_GLOBAL_MAX_AGE,
+            DEFAULT_MENTION_CHANCE, DEFAULT_REJOIN_DELAY, DEFAULT_SAVE_INTERVAL, DEFAULT_TRAINING_CAP_PERIOD};
+
+/// Everything a bot goes by that comes from a server's config, read once so that a value that doesn't make sense is
+/// caught before any of it is used.
+pub struct Options {
+    /// The `order` option, if it's set; without it, the blob's order is kept.
+    pub order: Option<usize>,
+    pub chance: f64,
+    pub mention_chance: f64,
+    pub mention_from_all: bool,
+    pub pm_chat: bool,
+    pub timezone: Zone,
+    pub self_train: bool,
+    pub shadow: bool,
+    pub training_cap: u64,
+    pub training_cap_period: Duration,
+    pub era_buckets: Option<Buckets>,
+    pub output_lines: usize,
+    /// What's done with nicks in what's trained on, in channels that don't say otherwise.
+    pub nick_mentions: NickMode,
+    pub conversation_depth: usize,
+    pub conversation_window: Duration,
+    pub global_max_age: Duration,
+    pub rejoin_delay: Duration,
+    /// Most bytes the chains should take up, or 0 for no limit.
+    pub memory_limit: u64,
+    pub save_interval: Duration,
+    pub save_after: usize,
+    pub backups: usize,
+    pub compression: Compression,
+    /// How long to give NickServ to identify us before joining channels anyway.
+    pub identify_timeout: Duration,
+    pub build_allchains: bool,
+    /// Seconds to wait before reconnecting the first time, and most seconds to wait as the waits get longer.
+    pub reconnect_delay: u64,
+    pub reconnect_max_delay: u64,
+    pub ignore: IgnoreList,
+    pub channel_ignores: HashMap<String, IgnoreList>,
+    pub blacklist: WordList,
+    pub channel_blacklists: HashMap<String, WordList>,
+    pub triggers: HashMap<String, WordList>,
+    pub rate_limits: RateLimits,
+    pub cooldown: Cooldown,
+    pub activity: Activity,
+    pub schedule: Schedule,
+    pub spam: SpamFilter,
+    pub journal: Journal,
+    pub pacing: Pacing,
+    pub routes: Routes,
+    pub preprocessor: Preprocessor,
+    pub filter: SentenceFilter,
+    pub tokenizer: Tokenizer,
+    pub prune: PruneOptions,
+    pub detector: BotDetector,
+    pub playback: Playback,
+    pub login: Login,
+}
+
+/// How a new connection logs in, from the start: SASL, NickServ, and the nicks to fall back on.
+#[derive(Clone)]
+pub struct Login {
+    pub sasl: Option<Sasl>,
+    pub nickserv: NickServ,
+    pub alt_nicks: AltNicks,
+}
+
+impl Options {
+    /// Reads everything a bot goes by from a server's config.
+    pub fn from_config(config: &ServerConfig) -> error::Result<Self> {
+        Self::parse(config).map_err(Error::Config)
+    }
+
+    /// Same as `from_config`, but with just the message when something doesn't check out, for `Server::validate`.
+    pub fn parse(config: &ServerConfig) -> Result<Self, String> {
+        let options = config.options.clone().unwrap_or_default();
+        let options = &options;
+        let seconds = |key: &str, default: u64| {
+            config::option(options, key).map(|x| Duration::from_secs(x.unwrap_or(default)))
+        };
+        let blacklist = WordList::parse(config.blacklist.as_deref().unwrap_or(&[]))
+            .map_err(|e| format!("blacklist: {}", e))?;
+        let mut channel_ignores = HashMap::new();
+        let mut channel_blacklists = HashMap::new();
+        let mut triggers = HashMap::new();
+        for channel in &config.channels {
+            let name = casemap::fold(&channel.name);
+            if let Some(ref list) = channel.ignore {
+                let ignore = IgnoreList::parse(list).map_err(|e| format!("{}: {}", channel.name, e))?;
+                channel_ignores.insert(name.clone(), ignore);
+            }
+            if let Some(ref list) = channel.blacklist {
+                let mut combined = blacklist.clone();
+                combined.extend(&WordList::parse(list).map_err(|e| format!("{} blacklist: {}", channel.name, e))?);
+                channel_blacklists.insert(name.clone(), combined);
+            }
+            if let Some(ref list) = channel.triggers {
+                let list = WordList::parse(list).map_err(|e| format!("{} triggers: {}", channel.name, e))?;
+                triggers.insert(name, list);
+            }
+        }
+        let order = config::option(options, "order")?;
+        if order == Some(0) {
+            return Err("order must be at least 1".to_string());
+        }
+        let mention_from_all = match options.get("mention_chain").map(String::as_str) {
+            None | Some("user") => false,
+            Some("all") => true,
+            Some(other) => return Err(format!("mention_chain must be \"user\" or \"all\", not {:?}", other)),
+        };
+        let training_cap_period = nonzero_duration(options, "training_cap_period", DEFAULT_TRAINING_CAP_PERIOD)?;
+        let save_interval = nonzero_duration(options, "save_interval", DEFAULT_SAVE_INTERVAL)?;
+        let output_lines = config::option(options, "output_lines")?.unwrap_or(1);
+        if output_lines == 0 {
+            return Err("output_lines must be at least 1".to_string());
+        }
+        Ok(Options {
+            order,
+            chance: config::chance_option(options, "chance", DEFAULT_CHANCE)?,
+            mention_chance: config::chance_option(options, "mention_chance", DEFAULT_MENTION_CHANCE)?,
+            mention_from_all,
+            pm_chat: config::option(options, "pm_chat")?.unwrap_or(true),
+            timezone: config::option(options, "timezone")?.unwrap_or(Zone::Local),
+            self_train: config::option(options, "self_train")?.unwrap_or(false),
+            shadow: config::option(options, "shadow")?.unwrap_or(false),
+            training_cap: config::option(options, "training_cap")?.unwrap_or(0),
+            training_cap_period,
+            era_buckets: config::option(options, "era_buckets")?,
+            output_lines,
+            nick_mentions: config::option(options, "nick_mentions")?.unwrap_or(NickMode::Keep),
+            conversation_depth: config::option(options, "conversation_depth")?.unwrap_or(0),
+            conversation_window: seconds("conversation_window", DEFAULT_CONVERSATION_WINDOW)?,
+            global_max_age: seconds("global_max_age", DEFAULT_GLOBAL_MAX_AGE)?,
+            rejoin_delay: seconds("rejoin_delay", DEFAULT_REJOIN_DELAY)?,
+            memory_limit: config::option::<u64>(options, "memory_limit")?.unwrap_or(0) * 1024 * 1024,
+            save_interval,
+            save_after: config::option(options, "save_after_messages")?.unwrap_or(0),
+            backups: config::option(options, "backups")?.unwrap_or(DEFAULT_BACKUPS),
+            compression: Compression::from_options(options)?,
+            identify_timeout: seconds("identify_timeout", nickserv::DEFAULT_IDENTIFY_TIMEOUT)?,
+            build_allchains: config::option(options, "build_allchains")?.unwrap_or(true),
+            reconnect_delay: config::option(options, "reconnect_delay")?
+                .unwrap_or(reconnect::DEFAULT_RECONNECT_DELAY),
+            reconnect_max_delay: config::option(options, "reconnect_max_delay")?
+                .unwrap_or(reconnect::DEFAULT_RECONNECT_MAX_DELAY),
+            ignore: IgnoreList::parse(&config.ignore_entries())?,
+            channel_ignores,
+            blacklist,
+            channel_blacklists,
+            triggers,
+            rate_limits: RateLimits::from_options(options)?,
+            cooldown: Cooldown::from_options(options)?,
+            activity: Activity::from_options(options)?,
+            schedule: Schedule::from_options(options)?,
+            spam: SpamFilter::from_options(options)?,
+            journal: Journal::from_options(options)?,
+            pacing: Pacing::from_options(options)?,
+            routes: Routes::from_options(options)?,
+            preprocessor: Preprocessor::from_options(options)?,
+            filter: SentenceFilter::from_options(options)?,
+            tokenizer: Tokenizer::from_options(options)?,
+            prune: PruneOptions::from_options(options)?,
+            detector: BotDetector::from_options(options)?,
+            playback: Playback::from_options(options)?,
+            login: Login {
+                sasl: Sasl::from_config(config)?,
+                nickserv: NickServ::from_config(config)?,
+                alt_nicks: AltNicks::from_config(config)?,
+            },
+        })
+    }
+}
+
+/// Reads an option that's a length of time, which has to be longer than nothing.
+fn nonzero_duration(options: &HashMap<String, String>, key: &str, default: u64) -> Result<Duration, String> {
+    match config::option::<HumanDuration>(options, key)? {
+        Some(HumanDuration(duration)) if duration == Duration::from_secs(0) => {
+            Err(format!("{} must be longer than 0s", key))
+        }
+        Some(HumanDuration(duration)) => Ok(duration),
+        None => Ok(Duration::from_secs(default)),
+    }
+}
@@ -0,0 +1,455 @@
+use crate::casemap;
+use crate::config;
+use crate::reply;
+use std::collections::HashMap;
+use std::time::Instant;
+use super::{IrcBot, ALL_CHANNELS};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserSettings {
+    pub ignore: bool,
+    pub chance: f64,
+    /// Whether the user asked not to be emulated. Only the `ALL_CHANNELS` settings use this.
+    #[serde(default)]
+    pub no_emulate: bool,
+    /// Tokens trained on from the user since `cap_started`, which the `training_cap` option limits.
+    #[serde(default)]
+    pub capped_tokens: u64,
+    /// When the user's current training cap period started, as a Unix timestamp.
+    #[serde(default)]
+    pub cap_started: i64,
+}
+
+impl IrcBot {
+    /// Gets a user's settings for changing, creating them if necessary.
+    ///
+    /// This marks the bot dirty, so use `user_chance` and `is_ignored` for reads.
+    pub(super) fn user_settings_mut(&mut self, channel: &str, user: &str) -> &mut UserSettings {
+        self.dirty = true;
+        let (channel, user) = (casemap::fold(channel), self.canonical_nick(user));
+        if !self.user_settings.contains_key(&channel) {
+            self.user_settings
+                .insert(channel.clone(), HashMap::new());
+        }
+        let channel = self.user_settings.get_mut(&channel).unwrap();
+
+        if !channel.contains_key(&user) {
+            channel.insert(
+                user.clone(),
+                UserSettings {
+                    ignore: false,
+                    chance: self.chance,
+                    no_emulate: false,
+                    capped_tokens: 0,
+                    cap_started: 0,
+                },
+            );
+        }
+        channel.get_mut(&user).unwrap()
+    }
+
+    /// Gets the chance of replying to a user at random, which is the default chance until they've set their own.
+    pub(super) fn user_chance(&self, channel: &str, user: &str) -> f64 {
+        let max = self.channel_chance(channel);
+        self.user_settings
+            .get(&casemap::fold(channel))
+            .and_then(|c| c.get(&self.canonical_nick(user)))
+            .map(|u| u.chance.min(max))
+            .unwrap_or(max)
+    }
+
+    /// Gets the most chance anyone has of a random reply in a channel, which is also the chance of anyone who hasn't
+    /// set their own.
+    pub(super) fn channel_chance(&self, channel: &str) -> f64 {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.chance)
+            .unwrap_or(self.chance)
+    }
+
+    /// Gets whether a user on a given channel is ignored.
+    ///
+    /// `prefix` is the user's full `nick!user@host` for matching masks against, or just the nick when that's all
+    /// there is.
+    pub(super) fn is_ignored(&self, channel: &str, user: &str, prefix: &str) -> bool {
+        self.is_ignored_by_config(channel, user, prefix)
+            || self.opted_out(ALL_CHANNELS, user)
+            || self.opted_out(channel, user)
+    }
+
+    /// Gets whether a user is ignored on a channel for reasons they can't change themselves: the ignore lists in
+    /// the config, or looking like a bot.
+    pub(super) fn is_ignored_by_config(&self, channel: &str, user: &str, prefix: &str) -> bool {
+        self.ignore.matches(user, prefix)
+            || self.is_bot(user)
+            || self
+                .channel_ignores
+                .get(&casemap::fold(channel))
+                .map(|list| list.matches(user, prefix))
+                .unwrap_or(false)
+    }
+
+    /// Gets whether a user has asked to be ignored on a channel, or on every channel for `ALL_CHANNELS`.
+    pub(super) fn opted_out(&self, channel: &str, user: &str) -> bool {
+        self.user_settings
+            .get(&casemap::fold(channel))
+            .and_then(|c| c.get(&self.canonical_nick(user)))
+            .map(|u| u.ignore)
+            .unwrap_or(false)
+    }
+
+    /// Gets whether a user has asked not to be emulated, which goes for every channel.
+    pub(super) fn no_emulate(&self, user: &str) -> bool {
+        self.user_settings
+            .get(ALL_CHANNELS)
+            .and_then(|c| c.get(&self.canonical_nick(user)))
+            .map(|u| u.no_emulate)
+            .unwrap_or(false)
+    }
+
+    /// Handles a user asking for their chains to be kept private, so nobody can emulate them, or to be public again.
+    pub(super) fn privacy_command(&mut self, sender: &str, prefix: &str, private: bool) -> String {
+        if self.no_emulate(sender) == private {
+            return format!("You're already {}", if private { "private" } else { "public" });
+        }
+        self.user_settings_mut(ALL_CHANNELS, sender).no_emulate = private;
+        if private {
+            format!("Nobody can emulate you now, though I still learn from you. Use {} public to undo this", prefix)
+        } else {
+            format!("Anyone can emulate you again. Use {} private to undo this", prefix)
+        }
+    }
+
+    /// Handles a user asking to be ignored or listened to again, on one channel or, with no channel, everywhere.
+    ///
+    /// Listening again only undoes what users asked for themselves; it can't get anyone off the config's ignore
+    /// lists.
+    pub(super) fn ignore_command(&mut self, channel: Option<&str>, sender: &str, prefix: &str, ignore: bool) -> String {
+        let (key, flag) = match channel {
+            Some(channel) => (channel, ""),
+            None => (ALL_CHANNELS, " --all"),
+        };
+        let where_ = channel.unwrap_or("every channel");
+        if ignore {
+            if self.opted_out(key, sender) {
+                return format!("I'm already ignoring you in {}", where_);
+            }
+            self.user_settings_mut(key, sender).ignore = true;
+            return format!("You are now being ignored in {}. Use {} listen{} to undo this command", where_, prefix, flag);
+        }
+
+        // listening everywhere also undoes ignoring single channels
+        let user = self.canonical_nick(sender);
+        let mut listened = false;
+        for (chan, users) in &mut self.user_settings {
+            if channel.map(|c| casemap::fold(c) == *chan).unwrap_or(true) {
+                if let Some(settings) = users.get_mut(&user) {
+                    listened = listened || settings.ignore;
+                    settings.ignore = false;
+                }
+            }
+        }
+        self.dirty = self.dirty || listened;
+
+        let by_config = match channel {
+            Some(channel) => self.is_ignored_by_config(channel, sender, sender),
+            None => self.ignore.matches(sender, sender) || self.is_bot(sender),
+        };
+        if by_config {
+            format!("You're on an ignore list the admins set for {}, so I still can't listen to you", where_)
+        } else if channel.is_some() && self.opted_out(ALL_CHANNELS, sender) {
+            format!("You asked to be ignored in every channel; use {} listen --all to undo that", prefix)
+        } else if listened {
+            format!("Markov is now listening to what you say in {}. Use {} ignore{} to undo this command.",
+                    where_, prefix, flag)
+        } else {
+            format!("I'm already listening to you in {}", where_)
+        }
+    }
+
+    /// Gets whether a user is being treated as a bot, either because an admin said so or because the bot detector
+    /// flagged them.
+    pub(super) fn is_bot(&self, user: &str) -> bool {
+        let key = casemap::fold(user);
+        match self.bot_overrides.get(&key) {
+            Some(&bot) => bot,
+            None => self.detector.is_flagged(&key),
+        }
+    }
+
+    /// Gets whether a user is listed as one of the server's owners.
+    pub(super) fn is_admin(&self, user: &str) -> bool {
+        self.config
+            .owners
+            .as_ref()
+            .map(|owners| owners.iter().any(|o| casemap::eq(o, user)))
+            .unwrap_or(false)
+    }
+
+    /// Shows a user's reply chance in a channel, or sets it if `value` is given.
+    pub(super) fn chance_command(&mut self, channel: &str, user: &str, value: Option<&str>) -> String {
+        let value = match value {
+            Some(v) => v,
+            None => {
+                let chance = self.user_chance(channel, user);
+                let scaled = self.activity.scale(channel, chance, Instant::now());
+                return if scaled == chance {
+                    format!("Your markov chance in {} is {}", channel, chance)
+                } else {
+                    format!("Your markov chance in {} is {}, or {:.4} right now going by how busy it is", channel,
+                            chance, scaled)
+                };
+            }
+        };
+        let max = self.channel_chance(channel);
+        match value.parse::<f64>() {
+            Ok(chance) if chance <= max && chance >= 0.0 => {
+                self.user_settings_mut(channel, user).chance = chance;
+                format!("Your chance for getting a random message from markov in {} is {}", channel, chance)
+            }
+            Ok(_) => format!("The chance must be set to a valid number between 0.0 and {}", max),
+            Err(_) => "Invalid number format".to_string(),
+        }
+    }
+
+    /// Shows or, for admins, sets the reply chance for a whole channel, writing it back to the config file.
+    pub(super) fn channel_chance_command(&mut self, channel: &str, sender: &str, value: Option<&str>) -> String {
+        let value = match value {
+            Some(v) => v,
+            None => return format!("The markov chance in {} is {}", channel, self.channel_chance(channel)),
+        };
+        if !self.is_admin(sender) {
+            return "Only admins can change a channel's chance".to_string();
+        }
+        let chance = match value.parse::<f64>() {
+            Ok(chance) if (0.0..=1.0).contains(&chance) => chance,
+            Ok(_) => return "The chance must be set to a valid number between 0.0 and 1.0".to_string(),
+            Err(_) => return "Invalid number format".to_string(),
+        };
+        let name = match self.config.channel(channel) {
+            Some(entry) => entry.name.clone(),
+            None => return format!("I'm not in {}", channel),
+        };
+        if let Err(e) = self.set_runtime_setting(Some(&name), "chance", &chance.to_string()) {
+            return format!("Couldn't change the markov chance in {}: {}", name, e);
+        }
+        event!(info, "chance", "{} set the chance in {} to {}", sender, name, chance);
+        format!("The markov chance in {} is now {}", name, chance)
+    }
+
+    /// Shows how random generated sentences are in a channel, or sets it if `value` is given.
+    pub(super) fn temperature_command(&mut self, channel: &str, sender: &str, value: Option<&str>) -> String {
+        let value = match value {
+            Some(v) => v,
+            None => return format!("The temperature in {} is {}", channel, self.channel_temperature(channel)),
+        };
+        if !self.is_admin(sender) {
+            return "Only admins can change a channel's temperature".to_string();
+        }
+        let temperature = match value.parse::<f64>() {
+            Ok(temperature) => temperature,
+            Err(_) => return "Invalid number format".to_string(),
+        };
+        if let Err(e) = config::check_temperature(temperature) {
+            return format!("The {}", e);
+        }
+        let name = match self.config.channel(channel) {
+            Some(entry) => entry.name.clone(),
+            None => return format!("I'm not in {}", channel),
+        };
+        if let Err(e) = self.set_runtime_setting(Some(&name), "temperature", &temperature.to_string()) {
+            return format!("Couldn't change the temperature in {}: {}", name, e);
+        }
+        event!(info, "temperature", "{} set the temperature in {} to {}", sender, name, temperature);
+        format!("The temperature in {} is now {}", name, temperature)
+    }
+
+    /// Changes a setting for the whole server, or for a channel, on top of the config, and saves it along with the
+    /// chains so it outlasts a restart.
+    pub(super) fn set_runtime_setting(&mut self, channel: Option<&str>, name: &str, value: &str)
+        -> Result<(), String>
+    {
+        let config = self.base_config.clone();
+        self.runtime.set(&config, channel, name, value)?;
+        self.apply_config(&config).map_err(|e| e.to_string())?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Lists the settings changed at runtime.
+    pub(super) fn runtime_settings(&self) -> String {
+        if self.runtime.is_empty() {
+            "Nothing has been changed since the config was loaded".to_string()
+        } else {
+            reply::fit_list("Changed since the config was loaded: ", &self.runtime.list())
+        }
+    }
+
+    /// Changes a setting for the whole server or a channel, or goes back to what the config says if there's no
+    /// `value`.
+    pub(super) fn setting_command(&mut self, sender: &str, channel: Option<&str>, name: &str, value: Option<&str>)
+        -> String
+    {
+        let setting = match channel {
+            Some(channel) => format!("{} in {}", name, channel),
+            None => name.to_string(),
+        };
+        match value {
+            Some(value) => match self.set_runtime_setting(channel, name, value) {
+                Ok(()) => {
+                    event!(info, "settings", "{} set {} to {}", sender, setting, value);
+                    format!("Set {} to {}", setting, value)
+                }
+                Err(e) => format!("Couldn't set {}: {}", setting, e),
+            },
+            None => {
+                if !self.runtime.reset(channel, name) {
+                    return format!("{} hasn't been changed", setting);
+                }
+                let config = self.base_config.clone();
+                if let Err(e) = self.apply_config(&config) {
+                    return format!("Couldn't reset {}: {}", setting, e);
+                }
+                self.dirty = true;
+                event!(info, "settings", "{} reset {}", sender, setting);
+                format!("{} is back to what the config says", setting)
+            }
+        }
+    }
+
+    /// Gets how random generated sentences are in a channel.
+    fn channel_temperature(&self, channel: &str) -> f64 {
+        self.config
+            .channel(channel)
+            .map(|c| self.filter.for_channel(c))
+            .unwrap_or(self.filter)
+            .temperature
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::IrcBot;
+    use crate::bot::testing::{self, ask, say, ADMIN, CHANNEL, NICK};
+    use crate::chat::mock::MockServer;
+
+    #[test]
+    fn user_chance_is_clamped_to_the_channel_chance() {
+        let (mut bot, server) = testing::bot("chance = \"0.5\"");
+        assert_eq!(bot.user_chance(CHANNEL, "alice"), 0.5);
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov chance 0.25");
+        assert!(reply.contains("is 0.25"), "{}", reply);
+        assert_eq!(bot.user_chance(CHANNEL, "alice"), 0.25);
+
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov chance 0.75");
+        assert_eq!(reply, "The chance must be set to a valid number between 0.0 and 0.5");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov chance -1");
+        assert_eq!(reply, "The chance must be set to a valid number between 0.0 and 0.5");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov chance lots");
+        assert_eq!(reply, "Invalid number format");
+
+        // lowering the channel's chance lowers everyone who set theirs higher
+        bot.config.channels[0].chance = Some(0.1);
+        assert_eq!(bot.user_chance(CHANNEL, "alice"), 0.1);
+        assert_eq!(bot.user_chance(CHANNEL, "bob"), 0.1);
+    }
+
+    #[test]
+    fn only_admins_set_the_channel_chance() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, &format!("!markov chance {} 0.5", CHANNEL));
+        assert_eq!(reply, "Only admins can change a channel's chance");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, &format!("!markov chance {} 2", CHANNEL));
+        assert_eq!(reply, "The chance must be set to a valid number between 0.0 and 1.0");
+    }
+
+    #[test]
+    fn config_ignore_lists_apply() {
+        let mut config = testing::config("");
+        config.channels[0].ignore = Some(vec!["carol".to_string()]);
+        let (bot, _server) = testing::bot_with(&config);
+        assert!(bot.is_ignored(CHANNEL, "NickServ", "NickServ!services@services"));
+        assert!(bot.is_ignored(CHANNEL, "dave", "dave!d@bots.example.com"));
+        assert!(bot.is_ignored(CHANNEL, "carol", "carol!c@example.com"));
+        assert!(!bot.is_ignored("#other", "carol", "carol!c@example.com"));
+        assert!(!bot.is_ignored(CHANNEL, "alice", "alice!a@example.com"));
+    }
+
+    #[test]
+    fn users_can_opt_out_and_back_in() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov ignore");
+        assert!(reply.starts_with("You are now being ignored in #test"), "{}", reply);
+        assert!(bot.is_ignored(CHANNEL, "alice", "alice"));
+        say(&mut bot, "alice", CHANNEL, "this should not be learned");
+        assert!(bot.find_chain(CHANNEL, "alice").is_err());
+
+        let reply = ask(&mut bot, &server, "alice", "markov", "!markov ignore --all");
+        assert!(reply.starts_with("You are now being ignored in every channel"), "{}", reply);
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov listen");
+        assert_eq!(reply, "You asked to be ignored in every channel; use !markov listen --all to undo that");
+        assert!(bot.is_ignored(CHANNEL, "alice", "alice"));
+
+        let reply = ask(&mut bot, &server, "alice", "markov", "!markov listen --all");
+        assert!(reply.starts_with("Markov is now listening"), "{}", reply);
+        assert!(!bot.is_ignored(CHANNEL, "alice", "alice"));
+    }
+
+    #[test]
+    fn admins_can_mark_bots() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov ignored bot eve");
+        assert_eq!(reply, "Only admins can use !markov ignored bot");
+        assert!(!bot.is_bot("eve"));
+        ask(&mut bot, &server, ADMIN, CHANNEL, "!markov ignored bot eve");
+        assert!(bot.is_bot("Eve"));
+        assert!(bot.is_ignored(CHANNEL, "eve", "eve"));
+    }
+
+    #[test]
+    fn runtime_settings_outlast_a_restart() {
+        let (mut bot, server) = testing::bot("");
+        let reply = ask(&mut bot, &server, "alice", "markov", "!markov settings");
+        assert_eq!(reply, "Only admins can use !markov settings");
+        ask(&mut bot, &server, ADMIN, CHANNEL, &format!("!markov chance {} 0.25", CHANNEL));
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings set #test respond false");
+        assert_eq!(reply, "Set respond in #test to false");
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings set quit_message see you around");
+        assert_eq!(reply, "Set quit_message to see you around");
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings set #test colour red");
+        assert_eq!(reply, "Couldn't set colour in #test: channels don't have a setting called colour");
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings set chanse 0.5");
+        assert_eq!(reply, "Couldn't set chanse: chanse can't be changed while I'm running");
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings set sasl_password hunter2");
+        assert_eq!(reply, "Couldn't set sasl_password: sasl_password can't be changed while I'm running");
+        assert!(!bot.responds_in(CHANNEL));
+
+        // the config hasn't changed, but the settings are still there when the bot comes back
+        let server = MockServer::new(NICK);
+        let mut bot = IrcBot::from_blob_file(server.clone(), &testing::config(""), bot.to_blob()).unwrap();
+        bot.send_queue().start("test");
+        assert_eq!(bot.channel_chance(CHANNEL), 0.25);
+        assert!(!bot.responds_in(CHANNEL));
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings");
+        assert_eq!(reply, "Changed since the config was loaded: quit_message = see you around, #test chance = 0.25, \
+                           #test respond = false");
+        let reply = ask(&mut bot, &server, ADMIN, "markov", "!markov settings reset #test respond");
+        assert_eq!(reply, "respond in #test is back to what the config says");
+        assert!(bot.responds_in(CHANNEL));
+    }
+
+    #[test]
+    fn temperature_is_set_per_channel() {
+        let (mut bot, server) = testing::bot("temperature = \"1.5\"");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov temp");
+        assert_eq!(reply, "alice: The temperature in #test is 1.5");
+        let reply = ask(&mut bot, &server, "alice", CHANNEL, "!markov temp 3");
+        assert_eq!(reply, "alice: Only admins can change a channel's temperature");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov temp 20");
+        assert_eq!(reply, format!("{}: The temperature must be between 0.1 and 10", ADMIN));
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov temp 0.5");
+        assert!(reply.starts_with(&format!("{}: The temperature in #test is now 0.5", ADMIN)), "{}", reply);
+        let reply = ask(&mut bot, &server, "alice", "markov", "!markov temp #test");
+        assert_eq!(reply, "The temperature in #test is 0.5");
+    }
+}
@@ -0,0 +1,1120 @@
+use crate::atomic;
+use crate::blobstore::BlobStore;
+use crate::casemap;
+use crate::checksum::{self, ChecksumWriter};
+use crate::compress::{self, Compression, Counter};
+use crate::duration::HumanDuration;
+use crate::encrypt::{self, Key};
+use crate::era::EraMap;
+use crate::export::ChainExport;
+use crate::generate;
+use crate::prune::{self, PruneStats};
+use crate::quote::QuoteMap;
+use crate::runtime::RuntimeSettings;
+use crate::snapshot::{self, Snapshot};
+use crate::split::{Index, SplitStore};
+use crate::sqlite::{self, SqliteStore};
+use crate::stats::{self, ChainStats, LifetimeCounters};
+use markov_chain::Chain;
+use chrono::Local;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use super::{ChainMap, IrcBot, UserSettingsMap, ALL_CHANNELS};
+
+const BLOB_VERSION: u32 = 3;
+
+/// Everything that gets saved. Fields added since the first layout all have defaults, so that older blobs, down to the
+/// ones from before blobs carried a version number, decode straight into this in one pass.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlobFile {
+    #[serde(default)]
+    version: u32,
+    pub(super) chains: ChainMap,
+    /// The same training again, kept apart by when it was said, if `era_buckets` is set.
+    #[serde(default)]
+    pub(super) eras: EraMap,
+    /// What each channel's topics have been set to, by casefolded channel.
+    #[serde(default)]
+    pub(super) topic_chains: HashMap<String, Chain<String>>,
+    /// Quotes saved with `!markov quote add`, by casefolded channel.
+    #[serde(default)]
+    pub(super) quotes: QuoteMap,
+    pub(super) user_settings: UserSettingsMap,
+    /// Maps old nicks to the nick whose chain they train, both casefolded.
+    #[serde(default)]
+    pub(super) aliases: HashMap<String, String>,
+    /// Users that admins have said are or aren't bots, by casefolded nick, overriding the bot detector.
+    #[serde(default)]
+    pub(super) bot_overrides: HashMap<String, bool>,
+    /// When chains were last decayed and pruned, as a Unix timestamp.
+    #[serde(default)]
+    pub(super) last_pruned: Option<i64>,
+    /// When each user, by casefolded nick, was last around, as a Unix timestamp.
+    #[serde(default)]
+    pub(super) last_seen: HashMap<String, i64>,
+    /// Counters that add up over every session that's used this blob.
+    #[serde(default)]
+    pub(super) lifetime: LifetimeCounters,
+    /// Settings changed while the bot was running, which go on top of the config.
+    #[serde(default)]
+    pub(super) runtime_settings: RuntimeSettings,
+    pub(super) order: usize,
+}
+
+impl BlobFile {
+    /// Creates an empty blob whose chains will have the given order.
+    pub fn new(order: usize) -> Self {
+        BlobFile {
+            version: BLOB_VERSION,
+            chains: HashMap::new(),
+            eras: HashMap::new(),
+            topic_chains: HashMap::new(),
+            quotes: HashMap::new(),
+            user_settings: HashMap::new(),
+            aliases: HashMap::new(),
+            bot_overrides: HashMap::new(),
+            last_pruned: None,
+            last_seen: HashMap::new(),
+            lifetime: LifetimeCounters::default(),
+            runtime_settings: RuntimeSettings::default(),
+            order,
+        }
+    }
+
+    /// Decodes a blob of any supported version as it's read, migrating it to the current layout.
+    pub fn decode_from<R: Read>(reader: R) -> Result<Self, String> {
+        let mut blob = cbor::from_reader::<BlobFile, _>(reader).map_err(|e| e.to_string())?;
+        if blob.version > BLOB_VERSION {
+            return Err(format!("blob version {} is newer than the supported version {}", blob.version, BLOB_VERSION));
+        }
+        if blob.version < BLOB_VERSION {
+            event!(info, "migrate", "migrating version {} blob to version {}", blob.version, BLOB_VERSION);
+        }
+        // version 2 started storing channels and nicks casefolded
+        if blob.version < 2 {
+            blob.fold_keys();
+        }
+        blob.version = BLOB_VERSION;
+        Ok(blob)
+    }
+
+    /// Builds a blob out of what was loaded from a SQLite database.
+    pub fn from_sqlite(contents: sqlite::Contents) -> Self {
+        BlobFile {
+            version: BLOB_VERSION,
+            chains: contents.chains,
+            eras: HashMap::new(),
+            topic_chains: contents.topic_chains,
+            quotes: contents.quotes,
+            user_settings: contents.user_settings,
+            aliases: contents.aliases,
+            bot_overrides: contents.bot_overrides,
+            last_pruned: contents.last_pruned,
+            last_seen: contents.last_seen,
+            lifetime: contents.lifetime,
+            runtime_settings: contents.runtime_settings,
+            order: contents.order,
+        }
+    }
+
+    /// Writes this blob to a SQLite database, which ends up with exactly this blob's chains.
+    pub fn write_sqlite(&self, store: &mut SqliteStore) -> io::Result<()> {
+        // chains the database has that this blob doesn't are deleted
+        let mut all = store.chain_keys()?;
+        all.extend(self.chains
+            .iter()
+            .flat_map(|(channel, users)| users.keys().map(move |user| (channel.clone(), user.clone()))));
+        store.save(&self.chains, &all, &self.user_settings, &self.aliases, &self.bot_overrides, self.last_pruned,
+                   &self.last_seen, &self.lifetime, &self.runtime_settings, &self.topic_chains,
+                   &self.quotes, self.order)
+    }
+
+    /// Builds a blob out of a split data directory's index and the chains of the channels that were loaded from it.
+    pub fn from_split(index: Index<BlobFile>, chains: ChainMap) -> Self {
+        BlobFile { chains, ..index.rest }
+    }
+
+    /// Writes this blob to a split data directory, which ends up with exactly this blob's channels.
+    pub fn write_split(&self, store: &SplitStore) -> io::Result<()> {
+        let channels = self.chains
+            .iter()
+            .filter(|&(_, users)| !users.is_empty())
+            .map(|(channel, _)| channel.clone())
+            .collect::<BTreeSet<_>>();
+        for channel in &channels {
+            store.save_channel(channel, self.chains.get(channel))?;
+        }
+        store.remove_others(&channels)?;
+        store.save_index(&Index::new(channels, BlobFile { chains: HashMap::new(), ..self.clone() }))
+    }
+
+    /// Gets the counters that have added up over every session that's used this blob.
+    pub fn lifetime(&self) -> &LifetimeCounters {
+        &self.lifetime
+    }
+
+    /// Gets the per-channel, per-user chains stored in this blob.
+    pub fn chains(&self) -> &ChainMap {
+        &self.chains
+    }
+
+    /// Gets whether a user has asked not to be trained on in a channel, or in every channel.
+    pub fn opted_out(&self, channel: &str, user: &str) -> bool {
+        let user = casemap::fold(user);
+        let user = self.aliases.get(&user).cloned().unwrap_or(user);
+        [casemap::fold(channel).as_str(), ALL_CHANNELS].iter().any(|channel| {
+            self.user_settings
+                .get(*channel)
+                .and_then(|users| users.get(&user))
+                .map(|settings| settings.ignore)
+                .unwrap_or(false)
+        })
+    }
+
+    /// Rolls the chains back to a snapshot's, keeping everything else, and leaving out users who've opted out since
+    /// the snapshot was taken.
+    pub fn roll_back_to(&mut self, snapshot: BlobFile) {
+        self.chains = snapshot.chains;
+        let opted_out = self.chains
+            .iter()
+            .flat_map(|(channel, users)| users.keys().map(move |user| (channel.clone(), user.clone())))
+            .filter(|(channel, user)| self.opted_out(channel, user))
+            .collect::<Vec<_>>();
+        for (channel, user) in opted_out {
+            self.chains.get_mut(&channel).unwrap().remove(&user);
+        }
+        self.chains.retain(|_, users| !users.is_empty());
+        self.eras = snapshot.eras;
+        let opted_out = self.eras
+            .iter()
+            .flat_map(|(channel, users)| users.keys().map(move |user| (channel.clone(), user.clone())))
+            .filter(|(channel, user)| self.opted_out(channel, user))
+            .collect::<Vec<_>>();
+        for (channel, user) in opted_out {
+            self.eras.get_mut(&channel).unwrap().remove(&user);
+        }
+        self.eras.retain(|_, users| !users.is_empty());
+        self.topic_chains = snapshot.topic_chains;
+        self.last_pruned = snapshot.last_pruned.or(self.last_pruned);
+    }
+
+    /// Gets the users, by casefolded nick, who've asked not to be emulated.
+    pub fn private_users(&self) -> HashSet<String> {
+        self.user_settings
+            .get(ALL_CHANNELS)
+            .map(|users| users.iter().filter(|&(_, s)| s.no_emulate).map(|(user, _)| user.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Trains a user's chain on the tokens of a single message, creating the chain with the given order if necessary.
+    pub fn train(&mut self, channel: &str, user: &str, tokens: Vec<String>, order: usize) {
+        let user = casemap::fold(user);
+        let user = self.aliases.get(&user).cloned().unwrap_or(user);
+        self.chains
+            .entry(casemap::fold(channel))
+            .or_default()
+            .entry(user)
+            .or_insert_with(|| Chain::new(order))
+            .train(generate::bracket(tokens));
+    }
+
+    /// Merges a chain into a user's, creating theirs if they don't have one yet. Chains of different orders can't be
+    /// merged.
+    pub fn merge(&mut self, channel: &str, user: &str, chain: &Chain<String>) -> Result<(), String> {
+        let user = casemap::fold(user);
+        let user = self.aliases.get(&user).cloned().unwrap_or(user);
+        let theirs = self.chains
+            .entry(casemap::fold(channel))
+            .or_default()
+            .entry(user.clone())
+            .or_insert_with(|| Chain::new(chain.order()));
+        if theirs.order() != chain.order() {
+            return Err(format!("{}'s chain has order {}, but this one has order {}", user, theirs.order(),
+                               chain.order()));
+        }
+        theirs.merge(chain);
+        Ok(())
+    }
+
+    /// Folds every channel and nick key, merging chains and settings that only differed by case.
+    fn fold_keys(&mut self) {
+        let mut chains: ChainMap = HashMap::new();
+        for (channel, users) in self.chains.drain() {
+            let folded = chains.entry(casemap::fold(&channel)).or_default();
+            for (user, chain) in users {
+                match folded.entry(casemap::fold(&user)) {
+                    Entry::Occupied(mut e) => {
+                        debug!("merging chain for {} into {} in {}", user, e.key(), channel);
+                        e.get_mut().merge(&chain);
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert(chain);
+                    }
+                }
+            }
+        }
+        self.chains = chains;
+
+        let mut user_settings: UserSettingsMap = HashMap::new();
+        for (channel, users) in self.user_settings.drain() {
+            let folded = user_settings.entry(casemap::fold(&channel)).or_default();
+            for (user, settings) in users {
+                match folded.entry(casemap::fold(&user)) {
+                    // keep the more conservative of the two settings
+                    Entry::Occupied(mut e) => {
+                        let merged = e.get_mut();
+                        merged.ignore = merged.ignore || settings.ignore;
+                        merged.no_emulate = merged.no_emulate || settings.no_emulate;
+                        merged.chance = merged.chance.min(settings.chance);
+                        merged.capped_tokens += settings.capped_tokens;
+                        merged.cap_started = merged.cap_started.max(settings.cap_started);
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert(settings);
+                    }
+                }
+            }
+        }
+        self.user_settings = user_settings;
+    }
+
+    /// Writes this blob to the given path as CBOR with the given compression, encrypted if there's a key, keeping up
+    /// to `backups` older copies, and returns how big it was before it was compressed.
+    ///
+    /// Unless it's encrypted, the blob is encoded straight into the file as it's written, so it never has to be in
+    /// memory twice over.
+    pub fn write(&self, path: &str, backups: usize, compression: Compression, key: Option<&Key>) -> io::Result<u64> {
+        let mut raw_size = 0;
+        atomic::write_with(path, backups, |file| {
+            raw_size = self.encode_to(file, compression, key)?;
+            Ok(())
+        })?;
+        Ok(raw_size)
+    }
+
+    /// Encodes this blob the same way `write` does, checksum and all, but into memory, for blob stores that send it
+    /// somewhere whole. Returns the data and how big it was before it was compressed.
+    pub fn encode(&self, compression: Compression, key: Option<&Key>) -> io::Result<(Vec<u8>, u64)> {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        let raw_size = self.encode_to(&mut writer, compression, key)?;
+        Ok((writer.finish()?, raw_size))
+    }
+
+    /// Decodes a blob from data `encode` or `write` came up with, checking its checksum first.
+    pub fn decode(data: &[u8], key: Option<&Key>) -> io::Result<Self> {
+        BlobFile::decode_body(checksum::strip(data)?, key)
+    }
+
+    /// Decodes a blob that's had its checksum checked, decrypting it first if it was encrypted.
+    fn decode_body<R: BufRead>(mut reader: R, key: Option<&Key>) -> io::Result<Self> {
+        let decoded = if encrypt::is_sealed(reader.fill_buf()?) {
+            let mut sealed = Vec::new();
+            reader.read_to_end(&mut sealed)?;
+            BlobFile::decode_from(compress::decoder(&encrypt::open(key, &sealed)?[..])?)
+        } else {
+            BlobFile::decode_from(compress::decoder(reader)?)
+        };
+        decoded.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid blob data: {}", e)))
+    }
+
+    /// Writes this blob as compressed CBOR, encrypted if there's a key, returning how big it was before it was
+    /// compressed.
+    fn encode_to(&self, writer: &mut dyn Write, compression: Compression, key: Option<&Key>) -> io::Result<u64> {
+        if let Some(key) = key {
+            let mut data = Vec::new();
+            let raw_size = self.encode_to(&mut data, compression, None)?;
+            writer.write_all(&encrypt::seal(key, &data)?)?;
+            return Ok(raw_size);
+        }
+        let mut counter = Counter::new(compression.encoder(writer)?);
+        cbor::to_writer(&mut counter, self).map_err(|e| io::Error::other(e.to_string()))?;
+        let raw_size = counter.count();
+        counter.into_inner().finish()?;
+        Ok(raw_size)
+    }
+}
+
+impl IrcBot {
+    /// Decays every chain by however long it's been since the last prune, then drops transitions that have fallen
+    /// below the threshold, chains that have nothing left, and the settings of users who haven't been seen in too
+    /// long.
+    pub fn prune(&mut self) -> PruneStats {
+        let now = Local::now().timestamp();
+        let factor = self.prune.decay_factor(now - self.last_pruned.unwrap_or(now));
+        // every chain decays, evicted or not; anything over the memory limit is evicted again on the next save
+        self.restore_where(|_| true);
+        let mut stats = PruneStats::default();
+        for (channel, users) in &mut self.chains {
+            let mut emptied = Vec::new();
+            for (user, chain) in users.iter_mut() {
+                if let Some((pruned, removed)) = prune::prune_chain(chain, factor, self.prune.threshold) {
+                    *chain = pruned;
+                    stats.transitions += removed;
+                    self.changed_chains.insert((channel.clone(), user.clone()));
+                    if chain.is_empty() {
+                        emptied.push(user.clone());
+                    }
+                }
+            }
+            for user in emptied {
+                users.remove(&user);
+                stats.chains += 1;
+            }
+        }
+        // allchains get rebuilt from the pruned chains the next time they're needed
+        self.allchains.clear();
+        stats.settings = self.prune_settings(now);
+        self.last_pruned = Some(now);
+        self.dirty = true;
+        event!(info, "prune", "pruned {} transitions, {} chains, and {} users' settings, decaying weights by {:.3}",
+               stats.transitions, stats.chains, stats.settings, factor);
+        stats
+    }
+
+    /// Drops the settings of users who haven't been seen in longer than the settings max age, returning how many
+    /// users lost settings. Opt-outs are kept, so nobody gets trained on or emulated again without asking.
+    fn prune_settings(&mut self, now: i64) -> u64 {
+        if self.prune.settings_max_age <= 0 {
+            return 0;
+        }
+        // users from before anyone was being seen start the clock now
+        for user in self.user_settings.values().flat_map(|users| users.keys()) {
+            self.last_seen.entry(user.clone()).or_insert(now);
+        }
+        let max_age = self.prune.settings_max_age;
+        let stale = self.last_seen
+            .iter()
+            .filter(|&(_, &seen)| now - seen > max_age)
+            .map(|(user, _)| user.clone())
+            .collect::<HashSet<_>>();
+        let mut pruned = HashSet::new();
+        for users in self.user_settings.values_mut() {
+            users.retain(|user, settings| {
+                let keep = settings.ignore || settings.no_emulate || !stale.contains(user);
+                if !keep {
+                    pruned.insert(user.clone());
+                }
+                keep
+            });
+        }
+        self.user_settings.retain(|_, users| !users.is_empty());
+        self.last_seen.retain(|user, _| !stale.contains(user));
+        pruned.len() as u64
+    }
+
+    /// Prunes the chains if the prune interval has passed since the last time.
+    pub fn prune_if_due(&mut self) {
+        let now = Local::now().timestamp();
+        if self.prune.interval > 0 && now - self.last_pruned.unwrap_or(now) >= self.prune.interval {
+            self.prune();
+        }
+    }
+
+    /// Saves a blob of the chains and user settings, unless nothing has changed since the last save.
+    pub fn save_blob(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            info!("nothing to save");
+            return Ok(());
+        }
+        event!(info, "save", "saving chains");
+        let started = Instant::now();
+        let (size, raw_size) = self.blob_store()?.save(&self.to_blob(), self.compression, self.backups)?;
+        self.dirty = false;
+        self.changed_chains.clear();
+        self.counters.record_save(started.elapsed());
+        self.counters.blob_size = Some(size);
+        self.counters.blob_raw_size = Some(raw_size);
+        Ok(())
+    }
+
+    /// Copies everything that gets saved into a blob.
+    pub(super) fn to_blob(&self) -> BlobFile {
+        BlobFile { chains: self.chains.clone(), eras: self.eras.clone(), ..self.settings_blob() }
+    }
+
+    /// Copies everything that gets saved into a blob, except for the chains.
+    fn settings_blob(&self) -> BlobFile {
+        BlobFile {
+            version: BLOB_VERSION,
+            chains: HashMap::new(),
+            eras: HashMap::new(),
+            topic_chains: self.topic_chains.clone(),
+            quotes: self.quotes.clone(),
+            user_settings: self.user_settings.clone(),
+            aliases: self.aliases.clone(),
+            bot_overrides: self.bot_overrides.clone(),
+            last_pruned: self.last_pruned,
+            last_seen: self.last_seen.clone(),
+            lifetime: self.lifetime.clone(),
+            runtime_settings: self.runtime.clone(),
+            order: self.order,
+        }
+    }
+
+    /// Tells the bot where its blob of chains is saved, which is also where snapshots of them go.
+    pub fn set_blob_store(&mut self, store: Box<dyn BlobStore>) {
+        self.blobs = Some(store);
+    }
+
+    fn blob_store(&self) -> io::Result<&dyn BlobStore> {
+        self.blobs
+            .as_deref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "I don't know where my chains are saved"))
+    }
+
+    /// Saves the chains right away, even if nothing has changed, instead of waiting for the server's task to get to it.
+    /// Returns how big the saved chains are, if that's known.
+    pub fn save_now(&mut self) -> io::Result<Option<u64>> {
+        self.blob_store()?;
+        self.dirty = true;
+        self.save()?;
+        Ok(self.counters.blob_size)
+    }
+
+    /// Gets how long to go between saves, which `!markov save every` can change.
+    pub fn save_interval(&self) -> Duration {
+        self.save_interval
+    }
+
+    /// Gets how long until the next save is due.
+    pub fn next_save_in(&self) -> Duration {
+        self.save_interval().checked_sub(self.last_saved.elapsed()).unwrap_or_default()
+    }
+
+    /// Saves once enough messages have been trained on since the last save, if `save_after_messages` is set, so that
+    /// a busy bot doesn't lose much to a crash.
+    pub(super) fn save_if_trained_enough(&mut self) {
+        if self.save_after == 0 || self.trained_since_save < self.save_after || self.blobs.is_none() {
+            return;
+        }
+        event!(info, "save", "saving after {} messages", self.trained_since_save);
+        if let Err(e) = self.save_now() {
+            error!("could not save: {}", e);
+        }
+    }
+
+    /// Saves the chains right away, or shows or changes how often they're saved if `every` is given.
+    pub(super) fn save_command(&mut self, every: Option<Option<&str>>) -> String {
+        match every {
+            None => {
+                let started = Instant::now();
+                match self.save_now() {
+                    Ok(Some(size)) => format!("Saved {} in {}ms", stats::format_size(size),
+                                              started.elapsed().as_millis()),
+                    Ok(None) => format!("Saved in {}ms", started.elapsed().as_millis()),
+                    Err(e) => format!("Couldn't save: {}", e),
+                }
+            }
+            Some(None) => format!("Saving every {}", HumanDuration(self.save_interval())),
+            Some(Some(value)) => match value.parse::<HumanDuration>() {
+                Ok(HumanDuration(interval)) if interval == Duration::from_secs(0) => {
+                    "Saving has to happen more often than that".to_string()
+                }
+                Ok(interval) => match self.set_runtime_setting(None, "save_interval", &interval.to_string()) {
+                    Ok(()) => {
+                        event!(info, "save", "saving every {}", interval);
+                        format!("Saving every {}", interval)
+                    }
+                    Err(e) => format!("Couldn't change how often I save: {}", e),
+                },
+                Err(e) => format!("Couldn't change how often I save: {}", e),
+            },
+        }
+    }
+
+    /// Saves a copy of the chains and settings as a snapshot with the given name.
+    ///
+    /// Evicted chains are loaded back first so that the snapshot has everything; they're evicted again on the next
+    /// save.
+    pub(super) fn take_snapshot(&mut self, name: &str) -> io::Result<Snapshot> {
+        snapshot::check_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.blob_store()?;
+        self.restore_where(|_| true);
+        let snapshot = self.blob_store()?.save_snapshot(name, &self.to_blob(), self.compression, Local::now())?;
+        event!(info, "snapshot", "saved snapshot {} to {}", name, snapshot.location);
+        Ok(snapshot)
+    }
+
+    /// Lists the snapshots of the chains, oldest first.
+    pub(super) fn snapshots(&self) -> io::Result<Vec<Snapshot>> {
+        self.blob_store()?.snapshots()
+    }
+
+    /// Rolls the chains back to the newest snapshot with the given name, then saves them right away.
+    ///
+    /// Only the chains go back; settings, aliases, and bot overrides stay as they are, and the chains of anyone who
+    /// has opted out since the snapshot was taken are left out, so nobody gets trained on again without asking.
+    pub(super) fn restore_snapshot(&mut self, name: &str) -> io::Result<Snapshot> {
+        let store = self.blob_store()?;
+        let snapshot = store.find_snapshot(name)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("there's no snapshot named {}", name)))?;
+        let saved = store.load_snapshot(&snapshot)?;
+        let mut blob = self.to_blob();
+        blob.roll_back_to(saved);
+        self.replace_chains(blob.chains);
+        self.eras = blob.eras;
+        self.topic_chains = blob.topic_chains;
+        self.last_pruned = blob.last_pruned;
+        event!(info, "snapshot", "restored snapshot {} from {}", name, snapshot.location);
+        self.save()?;
+        Ok(snapshot)
+    }
+
+    /// Swaps every chain, evicted or not, for the given ones.
+    fn replace_chains(&mut self, chains: ChainMap) {
+        // channels that haven't been loaded yet are replaced too, so their old chains have to be known
+        self.load_all_channels();
+        // every chain that was there before or is there now has to be rewritten, or deleted, in the store
+        let keys = |chains: &ChainMap| {
+            chains
+                .iter()
+                .flat_map(|(channel, users)| users.keys().map(move |user| (channel.clone(), user.clone())))
+                .collect::<Vec<_>>()
+        };
+        let old = keys(&self.chains);
+        self.changed_chains.extend(old);
+        self.changed_chains.extend(self.evicted.drain());
+        self.changed_chains.extend(keys(&chains));
+        self.chains = chains;
+        self.touched.clear();
+        self.allchains.clear();
+        self.dirty = true;
+    }
+
+    /// Gives the bot a SQLite database to save to, and to load evicted chains back from.
+    pub fn set_store(&mut self, store: SqliteStore) {
+        self.store = Some(store);
+    }
+
+    /// Gives the bot a split data directory to save to, along with the channels it has files for, which are loaded
+    /// the first time they're needed.
+    pub fn set_split_store(&mut self, store: SplitStore, channels: BTreeSet<String>) {
+        self.unloaded = channels.into_iter().filter(|channel| !self.chains.contains_key(channel)).collect();
+        self.split = Some(store);
+    }
+
+    /// Saves to the bot's database or split data directory if it has one, or to its blob store if it doesn't.
+    ///
+    /// The save timer and the count of messages trained on since the last save only start over once the save works, so
+    /// a failed save is tried again without waiting out another interval.
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.store.is_some() {
+            self.save_sqlite()?;
+        } else if self.split.is_some() {
+            self.save_split()?;
+        } else {
+            self.save_blob()?;
+        }
+        self.last_saved = Instant::now();
+        self.trained_since_save = 0;
+        Ok(())
+    }
+
+    /// Saves the chains that changed since the last save, along with the user settings, to the bot's database, then
+    /// evicts chains if they're over the memory limit.
+    pub(super) fn save_sqlite(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            info!("nothing to save");
+            return Ok(());
+        }
+        event!(info, "save", "saving {} changed chains", self.changed_chains.len());
+        let started = Instant::now();
+        {
+            let store = self.store.as_mut().unwrap();
+            store.save(&self.chains, &self.changed_chains, &self.user_settings, &self.aliases, &self.bot_overrides,
+                       self.last_pruned, &self.last_seen, &self.lifetime, &self.runtime, &self.topic_chains,
+                       &self.quotes, self.order)?;
+            self.counters.blob_size = store.file_size();
+        }
+        self.dirty = false;
+        self.changed_chains.clear();
+        self.counters.record_save(started.elapsed());
+        self.evict_to_limit();
+        Ok(())
+    }
+
+    /// Saves the channels with chains that changed since the last save to the bot's split data directory, then the
+    /// index, which has everything else.
+    fn save_split(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            info!("nothing to save");
+            return Ok(());
+        }
+        let changed = self.changed_chains.iter().map(|(channel, _)| channel.clone()).collect::<BTreeSet<_>>();
+        event!(info, "save", "saving {} changed channels", changed.len());
+        let started = Instant::now();
+        {
+            let split = self.split.as_ref().unwrap();
+            for channel in &changed {
+                split.save_channel(channel, self.chains.get(channel))?;
+            }
+            let mut channels = self.chains
+                .iter()
+                .filter(|&(_, users)| !users.is_empty())
+                .map(|(channel, _)| channel.clone())
+                .collect::<BTreeSet<_>>();
+            channels.extend(self.unloaded.iter().cloned());
+            split.save_index(&Index::new(channels, self.settings_blob()))?;
+            self.counters.blob_size = split.size().ok();
+        }
+        self.dirty = false;
+        self.changed_chains.clear();
+        self.counters.record_save(started.elapsed());
+        Ok(())
+    }
+
+    /// Drops the least recently trained chains until what's left fits in the memory limit.
+    ///
+    /// This has to come right after a save, so that every chain it drops is already in the database.
+    pub(super) fn evict_to_limit(&mut self) {
+        if self.memory_limit == 0 || self.store.is_none() {
+            return;
+        }
+        let mut sizes = self.chains
+            .iter()
+            .flat_map(|(channel, users)| {
+                users.iter().map(move |(user, chain)| ((channel.clone(), user.clone()), ChainStats::of(chain).bytes))
+            })
+            .collect::<Vec<_>>();
+        let allchain_bytes = self.allchains.bytes();
+        let mut total = sizes.iter().map(|&(_, bytes)| bytes).sum::<u64>() + allchain_bytes;
+        if total <= self.memory_limit {
+            return;
+        }
+        // allchains are the cheapest to get back, so they go first
+        self.allchains.clear();
+        total -= allchain_bytes;
+
+        // chains that haven't been touched since startup sort first
+        sizes.sort_by_key(|(key, _)| self.touched.get(key).cloned());
+        let mut count = 0;
+        for (key, bytes) in sizes {
+            if total <= self.memory_limit {
+                break;
+            }
+            if let Some(users) = self.chains.get_mut(&key.0) {
+                users.remove(&key.1);
+            }
+            self.touched.remove(&key);
+            self.evicted.insert(key);
+            total -= bytes;
+            count += 1;
+        }
+        event!(info, "evict", "evicted {} chains to get under the memory limit; {} chains are evicted in all",
+               count, self.evicted.len());
+    }
+
+    /// Loads an evicted chain from the store without putting it back in memory.
+    pub(super) fn load_evicted(&self, channel: &str, user: &str) -> Option<Chain<String>> {
+        let store = self.store.as_ref()?;
+        match store.load_chain(channel, user) {
+            Ok(chain) => chain,
+            Err(e) => {
+                error!("could not load chain for {} in {}: {}", user, channel, e);
+                None
+            }
+        }
+    }
+
+    /// Puts a user's chain for a channel back in memory if it was evicted, or if its channel hasn't been loaded.
+    pub(super) fn restore_chain(&mut self, channel: &str, user: &str) {
+        self.load_channel(channel);
+        let key = (casemap::fold(channel), self.canonical_nick(user));
+        self.restore_evicted(|k| *k == key);
+    }
+
+    /// Puts every evicted chain whose casefolded (channel, user) pair matches back in memory, loading every channel
+    /// that hasn't been loaded yet, since there's no telling which chains they have until they are.
+    pub(super) fn restore_where<F>(&mut self, matches: F)
+        where F: Fn(&(String, String)) -> bool
+    {
+        self.load_all_channels();
+        self.restore_evicted(matches);
+    }
+
+    fn restore_evicted<F>(&mut self, matches: F)
+        where F: Fn(&(String, String)) -> bool
+    {
+        let keys = self.evicted.iter().filter(|k| matches(k)).cloned().collect::<Vec<_>>();
+        for key in keys {
+            self.evicted.remove(&key);
+            if let Some(chain) = self.load_evicted(&key.0, &key.1) {
+                debug!("loaded evicted chain for {} in {}", key.1, key.0);
+                self.chains
+                    .entry(key.0.clone())
+                    .or_default()
+                    .insert(key.1.clone(), chain);
+                self.touched.insert(key, Instant::now());
+            }
+        }
+    }
+
+    /// Loads a channel's chains from the split data directory, if it has any there that haven't been loaded yet.
+    ///
+    /// A channel whose file can't be read starts over empty, so that one bad file doesn't take the rest down with it.
+    pub(super) fn load_channel(&mut self, channel: &str) {
+        let channel = casemap::fold(channel);
+        if !self.unloaded.remove(&channel) {
+            return;
+        }
+        let started = Instant::now();
+        let loaded = match self.split.as_ref().unwrap().load_channel(&channel) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                error!("could not load chains for {}: {}", channel, e);
+                return;
+            }
+        };
+        event!(info, "load", "loaded {} chains for {} in {:?}", loaded.len(), channel, started.elapsed());
+        let users = self.chains.entry(channel.clone()).or_default();
+        for (user, chain) in loaded {
+            match users.entry(user) {
+                Entry::Occupied(mut e) => {
+                    e.get_mut().merge(&chain);
+                }
+                Entry::Vacant(e) => {
+                    e.insert(chain);
+                }
+            }
+        }
+        self.allchains.invalidate(&channel);
+    }
+
+    /// Loads every channel that hasn't been loaded from the split data directory yet.
+    pub(super) fn load_all_channels(&mut self) {
+        for channel in self.unloaded.iter().cloned().collect::<Vec<_>>() {
+            self.load_channel(&channel);
+        }
+    }
+
+    /// Builds a human-readable dump of the chains in a blob.
+    pub fn export(blob: &BlobFile) -> ChainExport {
+        ChainExport::new(blob.order, &blob.chains, &blob.lifetime, &blob.private_users())
+    }
+
+    /// Reads a blob of chains and user settings, falling back to the newest backup that reads cleanly if the blob is
+    /// corrupt. The corrupt blob is moved aside to `<path>.corrupt`, so the next save doesn't rotate it into the
+    /// backups.
+    pub fn read_blob_or_backup(path: &str, key: Option<&Key>) -> io::Result<BlobFile> {
+        let err = match IrcBot::read_blob(path, key) {
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => io::Error::new(e.kind(), e.to_string()),
+            read => return read,
+        };
+        error!("{} is corrupt: {}", path, err);
+        let mut i = 1;
+        loop {
+            let backup = format!("{}.{}", path, i);
+            if !Path::new(&backup).exists() {
+                return Err(err);
+            }
+            match IrcBot::read_blob(&backup, key) {
+                Ok(blob) => {
+                    let aside = format!("{}.corrupt", path);
+                    fs::rename(path, &aside)?;
+                    event!(warn, "load", "loaded chains from backup {} instead of {}, which was moved to {}",
+                           backup, path, aside);
+                    return Ok(blob);
+                }
+                Err(e) => error!("backup {} is no good either: {}", backup, e),
+            }
+            i += 1;
+        }
+    }
+
+    /// Reads a blob of chains and user settings, which needs the key it was encrypted with if it was.
+    pub fn read_blob(path: &str, key: Option<&Key>) -> io::Result<BlobFile> {
+        debug!("reading from {}", path);
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        let len = checksum::verify(&mut file)?;
+        let read_data = BlobFile::decode_body(BufReader::new(file.take(len)), key)?;
+        trace!("Read data: {:?}", &read_data);
+        Ok(read_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlobFile, BLOB_VERSION};
+    use crate::blobstore::{BlobStore, FileStore};
+    use crate::bot::{ChainMap, IrcBot, UserSettingsMap};
+    use crate::bot::testing::{self, ask, say, ADMIN, CHANNEL, NICK};
+    use crate::chat::mock::MockServer;
+    use chrono::Local;
+    use crate::compress::Compression;
+    use crate::encrypt::Key;
+    use markov_chain::Chain;
+    use crate::split::SplitStore;
+    use std::collections::{BTreeSet, HashMap};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn blob_round_trips() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "Alice", CHANNEL, "the quick brown fox");
+        say(&mut bot, "bob", CHANNEL, "jumps over the lazy dog");
+        ask(&mut bot, &server, "bob", CHANNEL, "!markov ignore");
+        bot.add_alias("alice_", "alice").unwrap();
+
+        let path = env::temp_dir().join(format!("markov-bot-test-{}.cbor", process::id()));
+        let path = path.to_str().unwrap();
+        bot.set_blob_store(Box::new(FileStore::new(path, None)));
+        bot.save_blob().unwrap();
+        let blob = IrcBot::read_blob(path, None).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(blob.version, BLOB_VERSION);
+        for (channel, users) in &bot.chains {
+            for (user, chain) in users {
+                assert_eq!(blob.chains[channel][user].chain(), chain.chain());
+            }
+        }
+        assert_eq!(blob.chains[CHANNEL].len(), 2);
+        assert_eq!(blob.aliases, bot.aliases);
+        assert!(blob.opted_out(CHANNEL, "bob"));
+        assert!(!blob.opted_out(CHANNEL, "alice"));
+        assert_eq!(blob.lifetime, bot.lifetime);
+        assert_eq!(blob.lifetime.trained_from(CHANNEL, "alice"), 1);
+        assert_eq!(blob.lifetime.commands_handled, 1);
+
+        // a bot started from the blob picks up right where the old one left off
+        let (mut restored, server) = testing::bot_with(&testing::config(""));
+        restored.chains = blob.chains;
+        let reply = ask(&mut restored, &server, "alice_", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "alice_: the quick brown fox");
+    }
+
+    #[test]
+    fn compressed_blobs_still_load() {
+        let (mut bot, server) = testing::bot("compression = \"gzip\"");
+        say(&mut bot, "alice", CHANNEL, &"the quick brown fox ".repeat(10));
+        let path = env::temp_dir().join(format!("markov-bot-gzip-{}.cbor", process::id()));
+        let path = path.to_str().unwrap();
+        bot.set_blob_store(Box::new(FileStore::new(path, None)));
+        bot.save_blob().unwrap();
+        let blob = IrcBot::read_blob(path, None).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(blob.chains[CHANNEL]["alice"].chain(), bot.chains[CHANNEL]["alice"].chain());
+        assert!(bot.counters.blob_size.unwrap() < bot.counters.blob_raw_size.unwrap());
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov stats");
+        assert!(reply.contains(" uncompressed), last saved "), "{}", reply);
+    }
+
+    #[test]
+    fn corrupt_blobs_fall_back_to_a_backup() {
+        let (mut bot, _) = testing::bot("");
+        let path = env::temp_dir().join(format!("markov-bot-corrupt-{}.cbor", process::id()));
+        let path = path.to_str().unwrap();
+        bot.set_blob_store(Box::new(FileStore::new(path, None)));
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        bot.save_blob().unwrap();
+        say(&mut bot, "bob", CHANNEL, "jumps over the lazy dog");
+        bot.save_blob().unwrap();
+
+        let mut data = fs::read(path).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        fs::write(path, data).unwrap();
+        let err = IrcBot::read_blob(path, None).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{}", err);
+
+        let blob = IrcBot::read_blob_or_backup(path, None).unwrap();
+        assert_eq!(blob.chains[CHANNEL].keys().collect::<Vec<_>>(), vec!["alice"]);
+        let aside = format!("{}.corrupt", path);
+        assert!(!Path::new(path).exists());
+        fs::remove_file(aside).unwrap();
+        fs::remove_file(format!("{}.1", path)).unwrap();
+    }
+
+    #[test]
+    fn encrypted_blobs_are_told_apart_from_plain_ones() {
+        let (mut bot, _) = testing::bot("compression = \"zstd\"");
+        let key = Key::parse("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap();
+        let path = env::temp_dir().join(format!("markov-bot-encrypted-{}.cbor", process::id()));
+        let path = path.to_str().unwrap();
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        bot.set_blob_store(Box::new(FileStore::new(path, None)));
+        bot.save_blob().unwrap();
+        // turning encryption on still reads the plain blob saved before it
+        let store = FileStore::new(path, Some(key.clone()));
+        assert_eq!(store.load().unwrap().chains[CHANNEL].len(), 1);
+
+        bot.set_blob_store(Box::new(store));
+        bot.save_now().unwrap();
+        assert!(!String::from_utf8_lossy(&fs::read(path).unwrap()).contains("alice"));
+        assert_eq!(IrcBot::read_blob(path, Some(&key)).unwrap().chains[CHANNEL].len(), 1);
+        let err = IrcBot::read_blob_or_backup(path, None).unwrap_err();
+        assert!(err.to_string().contains("no encryption_key"), "{}", err);
+        let wrong = Key::parse("ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=").unwrap();
+        assert!(IrcBot::read_blob_or_backup(path, Some(&wrong)).is_err());
+        // the wrong key isn't mistaken for corruption
+        assert!(Path::new(path).exists());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(format!("{}.1", path)).unwrap();
+    }
+
+    #[test]
+    fn prunes_settings_of_users_gone_too_long() {
+        let (mut bot, server) = testing::bot("");
+        ask(&mut bot, &server, "alice", CHANNEL, "!markov chance 0");
+        ask(&mut bot, &server, "bob", CHANNEL, "!markov chance 0");
+        ask(&mut bot, &server, "carol", CHANNEL, "!markov ignore");
+        let long_ago = Local::now().timestamp() - 2 * 365 * 24 * 3600;
+        bot.last_seen.insert("bob".to_string(), long_ago);
+        bot.last_seen.insert("carol".to_string(), long_ago);
+
+        assert_eq!(bot.prune().settings, 1);
+        assert!(bot.user_settings[CHANNEL].contains_key("alice"));
+        assert!(!bot.user_settings[CHANNEL].contains_key("bob"));
+        assert!(bot.opted_out(CHANNEL, "carol"));
+    }
+
+    #[test]
+    fn restores_snapshots() {
+        let (mut bot, server) = testing::bot("");
+        let path = env::temp_dir().join(format!("markov-bot-snapshot-{}.cbor", process::id()));
+        bot.set_blob_store(Box::new(FileStore::new(path.to_str().unwrap(), None)));
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov snapshot clean");
+        assert!(reply.starts_with(&format!("{}: Saved snapshot clean at ", ADMIN)), "{}", reply);
+
+        say(&mut bot, "spammer", CHANNEL, "buy cheap watches");
+        say(&mut bot, "alice", CHANNEL, "jumps over the lazy dog");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov restore clean");
+        assert!(reply.starts_with(&format!("{}: Rolled the chains back to snapshot clean", ADMIN)), "{}", reply);
+        assert_eq!(bot.chains[CHANNEL].len(), 1);
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "bob: the quick brown fox");
+
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov snapshots");
+        assert!(reply.starts_with(&format!("{}: Snapshots, newest first: clean (", ADMIN)), "{}", reply);
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov restore dirty");
+        assert_eq!(reply, format!("{}: Couldn't restore dirty: there's no snapshot named dirty", ADMIN));
+        fs::remove_dir_all(format!("{}.snapshots", path.display())).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_command_saves_now_and_changes_the_interval() {
+        let (mut bot, server) = testing::bot("save_interval = \"3600\"");
+        let path = env::temp_dir().join(format!("markov-bot-save-{}.cbor", process::id()));
+        bot.set_blob_store(Box::new(FileStore::new(path.to_str().unwrap(), None)));
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov save");
+        assert!(reply.starts_with(&format!("{}: Saved {} B in ", ADMIN, fs::metadata(&path).unwrap().len())),
+                "{}", reply);
+        assert!(bot.next_save_in() > Duration::from_secs(3590));
+
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov save every 30m");
+        assert_eq!(reply, format!("{}: Saving every 30m", ADMIN));
+        // the change outlives a reload, and a restart
+        bot.apply_config(&testing::config("save_interval = \"3600\"")).unwrap();
+        assert_eq!(bot.save_interval(), Duration::from_secs(1800));
+        let restarted = IrcBot::from_blob_file(MockServer::new(NICK), &testing::config("save_interval = \"3600\""),
+                                               bot.to_blob()).unwrap();
+        assert_eq!(restarted.save_interval(), Duration::from_secs(1800));
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov save every soon");
+        assert_eq!(reply, format!("{}: Couldn't change how often I save: \"soon\" isn't a duration, like 90s, 30m, 2h, \
+                                   or 1d", ADMIN));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saves_after_enough_messages() {
+        let (mut bot, _server) = testing::bot("save_interval = \"1h\"\nsave_after_messages = \"2\"");
+        let path = env::temp_dir().join(format!("markov-bot-save-after-{}.cbor", process::id()));
+        bot.set_blob_store(Box::new(FileStore::new(path.to_str().unwrap(), None)));
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        assert!(!path.exists());
+        say(&mut bot, "bob", CHANNEL, "jumps over the lazy dog");
+        assert!(path.exists());
+        assert_eq!(IrcBot::read_blob(path.to_str().unwrap(), None).unwrap().chains[CHANNEL].len(), 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_saves_keep_counting_toward_the_next() {
+        let (mut bot, _server) = testing::bot("save_interval = \"1h\"\nsave_after_messages = \"2\"");
+        let dir = env::temp_dir().join(format!("markov-bot-save-failed-{}", process::id()));
+        let path = dir.join("chains.cbor");
+        bot.set_blob_store(Box::new(FileStore::new(path.to_str().unwrap(), None)));
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        say(&mut bot, "bob", CHANNEL, "jumps over the lazy dog");
+        assert!(!path.exists());
+        // the directory turning up means the very next message saves, instead of two more
+        fs::create_dir(&dir).unwrap();
+        say(&mut bot, "carol", CHANNEL, "and then it naps");
+        assert_eq!(IrcBot::read_blob(path.to_str().unwrap(), None).unwrap().chains[CHANNEL].len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_storage_loads_channels_lazily() {
+        let (mut bot, _) = testing::bot("");
+        let dir = env::temp_dir().join(format!("markov-bot-split-{}.data", process::id()));
+        let dir = dir.to_str().unwrap();
+        bot.set_split_store(SplitStore::open(dir, 0, Compression::Zstd(3)).unwrap(), BTreeSet::new());
+        say(&mut bot, "alice", CHANNEL, "the quick brown fox");
+        say(&mut bot, "bob", "#other", "jumps over the lazy dog");
+        bot.save().unwrap();
+
+        let split = SplitStore::open(dir, 0, Compression::Zstd(3)).unwrap();
+        let channels = split.load_index::<BlobFile>().unwrap().channels;
+        assert_eq!(channels.len(), 2);
+        let (mut restored, server) = testing::bot_with(&testing::config(""));
+        restored.set_split_store(split.clone(), channels.clone());
+        assert!(restored.chains.is_empty());
+        let reply = ask(&mut restored, &server, "carol", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "carol: the quick brown fox");
+        assert!(!restored.chains.contains_key("#other"));
+
+        // channels that were never loaded are still there after a save
+        say(&mut restored, "alice", CHANNEL, "over the hill");
+        restored.save().unwrap();
+        assert_eq!(split.load_index::<BlobFile>().unwrap().channels, channels);
+        assert!(split.load_channel("#other").unwrap().contains_key("bob"));
+        assert_eq!(split.load_channel(CHANNEL).unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unversioned_blobs_are_migrated_as_theyre_read() {
+        #[derive(Serialize)]
+        struct OldBlob {
+            chains: ChainMap,
+            user_settings: UserSettingsMap,
+            order: usize,
+        }
+        let mut chain = Chain::new(1);
+        chain.train(vec!["hello".to_string(), "there".to_string()]);
+        let mut users = HashMap::new();
+        users.insert("Alice".to_string(), chain.clone());
+        let mut chains = HashMap::new();
+        chains.insert("#Test".to_string(), users);
+        let old = OldBlob { chains, user_settings: HashMap::new(), order: 1 };
+        let data = Compression::Zstd(3).compress(cbor::to_vec(&old).unwrap()).unwrap();
+
+        let path = env::temp_dir().join(format!("markov-bot-v0-{}.cbor", process::id()));
+        fs::write(&path, data).unwrap();
+        let blob = IrcBot::read_blob(path.to_str().unwrap(), None).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(blob.version, BLOB_VERSION);
+        assert_eq!(blob.chains[CHANNEL]["alice"].chain(), chain.chain());
+    }
+
+    #[test]
+    fn bad_blobs_are_rejected() {
+        assert!(BlobFile::decode_from(&b"not a blob"[..]).is_err());
+        let mut newer = BlobFile::new(1);
+        newer.version = BLOB_VERSION + 1;
+        let err = BlobFile::decode_from(&cbor::to_vec(&newer).unwrap()[..]).unwrap_err();
+        assert!(err.contains("newer than the supported version"), "{}", err);
+    }
+}
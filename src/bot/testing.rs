@@ -0,0 +1,65 @@
+//! Helpers for testing the bot against a mock server.
+
+use crate::chat::mock::MockServer;
+use crate::config::Server as ServerConfig;
+use irc::client::prelude::*;
+use super::IrcBot;
+
+/// The nick the bot goes by in tests.
+pub const NICK: &str = "markov";
+/// The channel the bot is in for tests.
+pub const CHANNEL: &str = "#test";
+/// The nick the bot treats as an admin in tests.
+pub const ADMIN: &str = "admin";
+
+/// Parses a server config for tests, with whatever's in `extra` added to the `[options]` table.
+///
+/// Lines are sent as soon as they're queued, nothing is rate limited, and nobody gets a random reply unless a test
+/// asks for one.
+pub fn config(extra: &str) -> ServerConfig {
+    let source = format!(r#"
+        address = "irc.example.com"
+        nick = "{}"
+        owners = ["{}"]
+        ignore = ["NickServ", "*!*@bots.example.com"]
+
+        [[channels]]
+        name = "{}"
+
+        [options]
+        send_delay = "0"
+        channel_rate = "0"
+        user_rate = "0"
+        chance = "0"
+        {}
+    "#, NICK, ADMIN, CHANNEL, extra);
+    toml::from_str(&source).unwrap()
+}
+
+/// Creates a bot with the given config, along with the mock server it talks to.
+pub fn bot_with(config: &ServerConfig) -> (IrcBot, MockServer) {
+    let server = MockServer::new(NICK);
+    let bot = IrcBot::new(server.clone(), config).unwrap();
+    bot.send_queue().start("test");
+    (bot, server)
+}
+
+/// Creates a bot with the given options, along with the mock server it talks to.
+pub fn bot(options: &str) -> (IrcBot, MockServer) {
+    bot_with(&config(options))
+}
+
+/// Has `nick` say something to `target`, which is either a channel or the bot.
+pub fn say(bot: &mut IrcBot, nick: &str, target: &str, text: &str) {
+    bot.handle(Message {
+        tags: None,
+        prefix: Some(Prefix::new_from_str(&format!("{0}!{0}@users.example.com", nick))),
+        command: Command::PRIVMSG(target.to_string(), text.to_string()),
+    });
+}
+
+/// Has `nick` say something to `target`, and waits for the bot's reply.
+pub fn ask(bot: &mut IrcBot, server: &MockServer, nick: &str, target: &str, text: &str) -> String {
+    say(bot, nick, target, text);
+    server.wait_for_message().map(|(_, text)| text).expect("the bot didn't reply")
+}
@@ -0,0 +1,701 @@
+use crate::wordlist::WordList;
+use crate::casemap;
+use crate::generate;
+use crate::journal;
+use crate::provenance::Source;
+use crate::presence::NickMode;
+use crate::quality::{RecentMessages, SentenceFilter};
+use crate::quiet::{QuietHours, Zone};
+use crate::quote;
+use crate::tokenize::Tokenizer;
+use markov_chain::Chain;
+use chrono::{DateTime, Local, Utc};
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+use super::IrcBot;
+
+impl IrcBot {
+    /// Gets the casefolded nick whose chain and settings belong to a user, following nick changes and aliases.
+    pub(super) fn canonical_nick(&self, nick: &str) -> String {
+        let nick = casemap::fold(nick);
+        self.session_nicks
+            .get(&nick)
+            .or_else(|| self.aliases.get(&nick))
+            .cloned()
+            .unwrap_or(nick)
+    }
+
+    /// Makes `old` an alias of `new`, merging everything stored under `old` into `new`.
+    pub(super) fn add_alias(&mut self, old: &str, new: &str) -> Result<(), String> {
+        let old_key = casemap::fold(old);
+        let new_key = self.canonical_nick(new);
+        if old_key == new_key {
+            return Err(format!("{} and {} are already the same person", old, new));
+        }
+
+        // anything that pointed at the old nick follows it to the new one
+        for target in self.aliases.values_mut().chain(self.session_nicks.values_mut()) {
+            if *target == old_key {
+                *target = new_key.clone();
+            }
+        }
+        self.aliases.insert(old_key.clone(), new_key.clone());
+
+        self.restore_where(|(_, user)| *user == old_key || *user == new_key);
+        for (channel, users) in self.chains.iter_mut() {
+            if let Some(chain) = users.remove(&old_key) {
+                self.changed_chains.insert((channel.clone(), old_key.clone()));
+                self.changed_chains.insert((channel.clone(), new_key.clone()));
+                // merged chains of different orders would no longer add up to the allchain
+                self.allchains.invalidate(channel);
+                users
+                    .entry(new_key.clone())
+                    .or_insert_with(|| Chain::new(chain.order()))
+                    .merge(&chain);
+            }
+        }
+        for users in self.eras.values_mut() {
+            if let Some(old_eras) = users.remove(&old_key) {
+                let new_eras = users.entry(new_key.clone()).or_insert_with(BTreeMap::new);
+                for (era, chain) in old_eras {
+                    new_eras
+                        .entry(era)
+                        .or_insert_with(|| Chain::new(chain.order()))
+                        .merge(&chain);
+                }
+            }
+        }
+        for users in self.user_settings.values_mut() {
+            if let Some(settings) = users.remove(&old_key) {
+                users.entry(new_key.clone()).or_insert(settings);
+            }
+        }
+        quote::rename_user(&mut self.quotes, &old_key, &new_key);
+        self.lifetime.merge_user(&old_key, &new_key);
+        self.journal.merge_user(&old_key, &new_key);
+        if let Some(seen) = self.last_seen.remove(&old_key) {
+            let latest = self.last_seen.entry(new_key.clone()).or_insert(seen);
+            *latest = (*latest).max(seen);
+        }
+        self.dirty = true;
+        event!(info, "alias", "{} is now an alias of {}", old_key, new_key);
+        Ok(())
+    }
+
+    /// Trains a user's chain, and the channel's allchain if it's built, on a message that's been cleaned up and split
+    /// into tokens.
+    pub(super) fn train_message(&mut self, channel: &str, user: &str, tokens: Vec<String>) {
+        let tokens = generate::bracket(tokens);
+        // the allchain only learns the message if it's already built; otherwise it gets it from the user chain when
+        // it is
+        let order = {
+            let chain = self.user_chain_mut(channel, user);
+            chain.train(tokens.clone());
+            chain.order()
+        };
+        let user = self.canonical_nick(user);
+        if let Some(buckets) = self.era_buckets {
+            self.eras
+                .entry(casemap::fold(channel))
+                .or_default()
+                .entry(user.clone())
+                .or_default()
+                .entry(buckets.era(Local::now()))
+                .or_insert_with(|| Chain::new(order))
+                .train(tokens.clone());
+        }
+        self.journal.record(channel, &user, tokens.clone(), Instant::now());
+        self.allchains.train(channel, &user, tokens, order);
+        let key = (casemap::fold(channel), user);
+        self.lifetime.record_trained(&key.0, &key.1);
+        self.changed_chains.insert(key);
+        self.counters.messages_trained += 1;
+        self.dirty = true;
+        self.trained_since_save += 1;
+        self.save_if_trained_enough();
+    }
+
+    /// Counts a message toward a user's training cap in a channel, returning whether it fits under the cap and can be
+    /// trained on. The count starts over once the cap's period has passed since it last did.
+    pub(super) fn within_training_cap(&mut self, channel: &str, user: &str, tokens: usize) -> bool {
+        if self.training_cap == 0 {
+            return true;
+        }
+        let (cap, period) = (self.training_cap, self.training_cap_period.as_secs() as i64);
+        let now = Local::now().timestamp();
+        let settings = self.user_settings_mut(channel, user);
+        if now - settings.cap_started >= period {
+            settings.capped_tokens = 0;
+            settings.cap_started = now;
+        }
+        if settings.capped_tokens + tokens as u64 > cap {
+            return false;
+        }
+        settings.capped_tokens += tokens as u64;
+        true
+    }
+
+    /// Gets the order that new chains in a channel are created with.
+    pub(super) fn order_for(&self, channel: &str) -> usize {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.order)
+            .unwrap_or(self.order)
+    }
+
+    /// Remembers a line said in a channel, so generated sentences there don't just repeat it.
+    pub(super) fn remember_line(&mut self, channel: &str, line: &str) {
+        let line = self.preprocessor.process(line).unwrap_or_else(|| line.to_string());
+        self.recent
+            .entry(casemap::fold(channel))
+            .or_insert_with(RecentMessages::new)
+            .push(&line, self.filter.recent_lines);
+    }
+
+    /// Generates a sentence from one of a channel's chains, held to that channel's sentence filter, or None if
+    /// everything it came up with was blacklisted there.
+    pub(super) fn generate(&self, channel: &str, chain: &Chain<String>) -> Option<String> {
+        self.generate_from(channel, chain, &[])
+    }
+
+    /// Generates a sentence that picks up from one of the seed words where it can, and from the start otherwise.
+    pub(super) fn generate_from(&self, channel: &str, chain: &Chain<String>, seeds: &[String]) -> Option<String> {
+        self.generate_filtered(channel, chain, seeds, self.filter_for(channel))
+    }
+
+    /// Gets what generated sentences should look like in a channel.
+    pub(super) fn filter_for(&self, channel: &str) -> SentenceFilter {
+        self.config
+            .channel(channel)
+            .map(|c| self.filter.for_channel(c))
+            .unwrap_or(self.filter)
+    }
+
+    /// Same as `generate_from`, but held to the given filter instead of the channel's.
+    pub(super) fn generate_filtered(&self, channel: &str, chain: &Chain<String>, seeds: &[String],
+                                    filter: SentenceFilter) -> Option<String> {
+        let recent = self.recent.get(&casemap::fold(channel));
+        let tokenizer = self.tokenizer_for(channel);
+        let blacklist = self.blacklist_for(channel);
+        if seeds.is_empty() {
+            filter.generate(chain, tokenizer, recent, blacklist)
+        } else {
+            let sampler = filter.sampler_for(chain);
+            let make = || {
+                let tokens = generate::seeded(chain, seeds, &sampler);
+                tokenizer.join(&tokens.unwrap_or_else(|| generate::sentence(chain, &sampler)))
+            };
+            filter.generate_with(make, tokenizer, recent, blacklist)
+        }
+    }
+
+    /// Gets the blacklist for a channel, which is the server's along with whatever the channel adds to it.
+    pub(super) fn blacklist_for(&self, channel: &str) -> &WordList {
+        self.channel_blacklists.get(&casemap::fold(channel)).unwrap_or(&self.blacklist)
+    }
+
+    /// Gets how messages in a channel are split into tokens.
+    pub(super) fn tokenizer_for(&self, channel: &str) -> Tokenizer {
+        self.tokenizer.for_channel(self.config.channel(channel))
+    }
+
+    /// Generates a reply to a user in a channel from their chain, or from everyone's if `from_all` is set.
+    pub(super) fn reply_to(&mut self, channel: &str, user: &str, from_all: bool, seeds: &[String]) -> Option<String> {
+        if from_all {
+            self.build_allchain(channel);
+            let generated = {
+                let chain = self.allchains.get(channel).unwrap();
+                if chain.is_empty() {
+                    return None;
+                }
+                self.generate_from(channel, chain, seeds)
+            };
+            return self.note_generated(channel, Source::Channel, seeds, generated);
+        }
+        self.restore_chain(channel, user);
+        let user = self.canonical_nick(user);
+        let generated = self.chains
+            .get(&casemap::fold(channel))
+            .and_then(|c| c.get(&user))
+            .filter(|chain| !chain.is_empty())
+            .and_then(|chain| self.generate_from(channel, chain, seeds));
+        self.note_generated(channel, Source::User(user), seeds, generated)
+    }
+
+    /// Remembers where a sentence generated for a channel came from, and what it was seeded with, passing it along
+    /// with the nicks of anyone in the channel defused, if it's highlight safe. With `self_train` on, it's trained on
+    /// too.
+    pub(super) fn note_generated(&mut self, channel: &str, source: Source, seeds: &[String],
+                                 generated: Option<String>) -> Option<String> {
+        generated.map(|text| {
+            let id = self.generated.record(channel, source, seeds, &text);
+            debug!("generated #{} for {}", id, channel);
+            let fill = self.nick_mentions(channel) == NickMode::Fill;
+            let text = self.presence.fill(channel, &text, fill, self.current_nick());
+            if self.self_train && self.trains_in(channel) {
+                let tokens = self.mask_nicks(channel, self.tokenizer_for(channel).tokenize(&text));
+                let nick = self.config.nick.clone();
+                self.train_message(channel, &nick, tokens);
+            }
+            if self.highlight_safe(channel) { self.presence.defuse(channel, &text) } else { text }
+        })
+    }
+
+    /// Gets whether a user is talking back to one of the bot's replies, and the conversation can go on.
+    pub(super) fn in_conversation(&self, channel: &str, user: &str) -> bool {
+        self.conversations
+            .get(&(casemap::fold(channel), casemap::fold(user)))
+            .map(|&(when, depth)| when.elapsed() <= self.conversation_window && depth < self.conversation_depth)
+            .unwrap_or(false)
+    }
+
+    /// Generates a sentence from a channel's allchain, or None if nobody there has said anything, or nothing that
+    /// isn't blacklisted.
+    pub(super) fn generate_all(&mut self, channel: &str) -> Option<String> {
+        self.build_allchain(channel);
+        let generated = {
+            let chain = self.allchains.get(channel).unwrap();
+            if chain.is_empty() {
+                return None;
+            }
+            self.generate(channel, chain)
+        };
+        self.note_generated(channel, Source::Channel, &[], generated)
+    }
+
+    /// Generates a topic for a channel from the topics people have set there, or from its allchain if nobody has set
+    /// one while the bot was around, or None if there's nothing to generate from.
+    pub(super) fn generate_topic(&mut self, channel: &str) -> Option<String> {
+        let generated = match self.topic_chains.get(&casemap::fold(channel)).filter(|chain| !chain.is_empty()) {
+            Some(chain) => self.generate(channel, chain),
+            None => return self.generate_all(channel),
+        };
+        self.note_generated(channel, Source::Topics, &[], generated)
+    }
+
+    /// Keeps track of a channel's topic, and trains the channel's topic chain on it when someone changes it.
+    ///
+    /// `prefix` is who changed it, or None for the topic the server tells us about when we join, which isn't trained
+    /// on since it'd be trained on again every time we rejoin. Topics we set ourselves aren't trained on either.
+    pub(super) fn topic_changed(&mut self, channel: &str, topic: &str, prefix: Option<&str>) {
+        let key = casemap::fold(channel);
+        if topic.is_empty() {
+            self.topics.remove(&key);
+            return;
+        }
+        self.topics.insert(key.clone(), topic.to_string());
+        let prefix = match prefix {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        let setter = prefix.split('!').nth(0).unwrap();
+        if casemap::eq(setter, self.current_nick()) || self.is_ignored(channel, setter, prefix)
+            || !self.trains_in(channel) || self.blacklist_for(channel).matches(topic)
+        {
+            return;
+        }
+        let tokens = match self.preprocessor.process(topic) {
+            Some(cleaned) => self.mask_nicks(channel, self.tokenizer_for(channel).tokenize(&cleaned)),
+            None => return,
+        };
+        if tokens.is_empty() {
+            return;
+        }
+        let order = self.order_for(channel);
+        self.topic_chains
+            .entry(key)
+            .or_insert_with(|| Chain::new(order))
+            .train(generate::bracket(tokens));
+        debug!("trained the topic chain for {} on a topic set by {}", channel, setter);
+        self.dirty = true;
+    }
+
+    /// Generates a sentence, held to a channel's filter and blacklist, from the global chain of everything said in
+    /// every channel that shares its chains, or None if there's nothing to generate from.
+    pub(super) fn generate_global(&mut self, channel: &str) -> Option<String> {
+        self.build_global();
+        let generated = {
+            let chain = self.allchains.global(self.global_max_age, Instant::now()).unwrap();
+            if chain.is_empty() {
+                return None;
+            }
+            self.generate(channel, chain)
+        };
+        self.note_generated(channel, Source::Global, &[], generated)
+    }
+
+    /// Builds the global chain, unless it's already built and not too old.
+    pub(super) fn build_global(&mut self) {
+        if self.allchains.global(self.global_max_age, Instant::now()).is_none() {
+            let started = Instant::now();
+            let mut channels = self.chain_channels();
+            channels.extend(self.evicted.iter().map(|(channel, _)| channel.clone()));
+            channels.extend(self.unloaded.iter().cloned());
+            channels.sort();
+            channels.dedup();
+            channels.retain(|channel| self.shares_globally(channel));
+            let mut global = Chain::new(self.order);
+            for channel in &channels {
+                let allchain = self.allchain(channel);
+                // channels configured with a different order would have to be left out as a whole anyway
+                if allchain.order() == global.order() {
+                    global.merge(allchain);
+                }
+            }
+            event!(info, "global", "built the global chain from {} channels in {:?}", channels.len(),
+                   started.elapsed());
+            self.allchains.set_global(global, Instant::now());
+        }
+    }
+
+    /// Gets whether a channel's chains go into the global chain.
+    pub(super) fn shares_globally(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.global)
+            .unwrap_or(true)
+    }
+
+    /// Gets whether messages in a channel should be trained on.
+    pub(super) fn trains_in(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.train)
+            .unwrap_or(true)
+    }
+
+    /// Gets whether nicks are kept from highlighting anyone in what's generated for a channel.
+    pub(super) fn highlight_safe(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.highlight_safe)
+            .unwrap_or(true)
+    }
+
+    /// Gets whether replies in a channel use mIRC formatting.
+    pub(super) fn uses_colors(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.use_colors)
+            .unwrap_or(false)
+    }
+
+    /// Gets whether a channel is in shadow mode, where what the bot would say unprompted is logged instead of sent.
+    pub(super) fn shadows(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.shadow)
+            .unwrap_or(self.shadow)
+    }
+
+    /// Gets whether admins can have the bot set a channel's topic, which channels have to opt in to.
+    pub(super) fn sets_topic(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.set_topic)
+            .unwrap_or(false)
+    }
+
+    /// Gets what's done with the nicks of people in a channel in what's trained on there.
+    pub(super) fn nick_mentions(&self, channel: &str) -> NickMode {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.nick_mentions.as_ref())
+            .map(|x| x.parse::<NickMode>().unwrap())
+            .unwrap_or(self.nick_mentions)
+    }
+
+    /// Replaces the nicks of people in a channel with `NICK_TOKEN` in tokens about to be trained on there, unless
+    /// the channel keeps them.
+    pub(super) fn mask_nicks(&self, channel: &str, tokens: Vec<String>) -> Vec<String> {
+        if self.nick_mentions(channel) == NickMode::Keep {
+            return tokens;
+        }
+        tokens.iter().map(|token| self.presence.mask(channel, token)).collect()
+    }
+
+    /// Gets whether the bot may send random replies in a channel right now, which it can't during its quiet hours.
+    pub(super) fn responds_in(&self, channel: &str) -> bool {
+        self.config
+            .channel(channel)
+            .and_then(|c| c.respond)
+            .unwrap_or(true)
+            && !self.is_quiet(channel, Utc::now())
+    }
+
+    /// Gets whether it's during a channel's quiet hours at a moment.
+    pub(super) fn is_quiet(&self, channel: &str, now: DateTime<Utc>) -> bool {
+        let channel = match self.config.channel(channel) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        let hours = match channel.quiet_hours {
+            Some(ref hours) => hours.parse::<QuietHours>().unwrap(),
+            None => return false,
+        };
+        let zone = channel.timezone.as_ref().map(|zone| zone.parse::<Zone>().unwrap()).unwrap_or(self.timezone);
+        hours.contains(zone.time_of_day(now))
+    }
+
+    /// Gets the chain of everything said in a channel, building it from the user chains if necessary.
+    pub(super) fn allchain(&mut self, channel: &str) -> &Chain<String> {
+        self.build_allchain(channel);
+        self.allchains.get(channel).unwrap()
+    }
+
+    /// Builds a channel's allchain by merging its user chains, unless it's already built.
+    pub(super) fn build_allchain(&mut self, channel: &str) {
+        while !self.build_allchain_step(channel) {}
+    }
+
+    /// Merges one more user chain into a channel's allchain, starting it if it hasn't been started, and returns
+    /// whether the allchain is built. Building it a step at a time keeps the bot from being tied up for long.
+    ///
+    /// The allchain has the channel's configured order, so user chains left over from a different order are left out.
+    pub fn build_allchain_step(&mut self, channel: &str) -> bool {
+        if self.allchains.get(channel).is_some() {
+            return true;
+        }
+        let channel = casemap::fold(channel);
+        if !self.allchains.is_pending(&channel) {
+            self.load_channel(&channel);
+            debug!("building allchain for {}", channel);
+            let order = self.order_for(&channel);
+            let mut users = self.chains
+                .entry(channel.clone())
+                .or_default()
+                .keys()
+                .cloned()
+                .collect::<HashSet<_>>();
+            // evicted chains are merged in straight from the store, without keeping them around
+            users.extend(self.evicted.iter().filter(|&(c, _)| *c == channel).map(|(_, user)| user.clone()));
+            self.allchains.begin(&channel, order, users);
+        }
+        match self.allchains.next_user(&channel) {
+            Some(user) => {
+                let evicted;
+                let chain = match self.chains.get(&channel).and_then(|users| users.get(&user)) {
+                    Some(chain) => Some(chain),
+                    None => {
+                        evicted = self.load_evicted(&channel, &user);
+                        evicted.as_ref()
+                    }
+                };
+                self.allchains.merge(&channel, &user, chain);
+                false
+            }
+            None => self.allchains.finish(&channel),
+        }
+    }
+
+    /// Gets every channel the bot has chains loaded for.
+    pub fn chain_channels(&self) -> Vec<String> {
+        self.chains.keys().cloned().collect()
+    }
+
+    /// Finds a user's chain in a channel.
+    pub(super) fn find_chain(&self, channel: &str, user: &str) -> Result<&Chain<String>, String> {
+        self.chains
+            .get(&casemap::fold(channel))
+            .ok_or_else(|| format!("No chains for channel {}", channel))?
+            .get(&self.canonical_nick(user))
+            .ok_or_else(|| format!("No chain for user {} in {}", user, channel))
+    }
+
+    pub(super) fn user_chain_mut(&mut self, channel: &str, user: &str) -> &mut Chain<String> {
+        self.restore_chain(channel, user);
+        let order = self.order_for(channel);
+        let user = self.canonical_nick(user);
+        self.touched.insert((casemap::fold(channel), user.clone()), Instant::now());
+        self.chains
+            .entry(casemap::fold(channel))
+            .or_default()
+            .entry(user)
+            .or_insert_with(|| Chain::new(order))
+    }
+
+    /// Removes a user's chain for a channel, returning whether there was one to remove.
+    ///
+    /// The channel's allchain is dropped so that it gets rebuilt without the user the next time it's needed.
+    pub(super) fn forget(&mut self, channel: &str, user: &str) -> bool {
+        let (channel, user) = (casemap::fold(channel), self.canonical_nick(user));
+        let key = (channel.clone(), user.clone());
+        let removed = self
+            .chains
+            .get_mut(&channel)
+            .and_then(|c| c.remove(&user))
+            .is_some()
+            || self.evicted.remove(&key);
+        self.touched.remove(&key);
+        let removed_eras = self.eras.get_mut(&channel).and_then(|users| users.remove(&user)).is_some();
+        self.eras.retain(|_, users| !users.is_empty());
+        self.lifetime.forget(&channel, &user);
+        self.journal.forget(&channel, &user);
+        if removed {
+            self.allchains.invalidate(&channel);
+            self.changed_chains.insert((channel, user));
+        }
+        if removed || removed_eras {
+            self.dirty = true;
+        }
+        removed
+    }
+
+    /// Takes the messages a user was trained on in a channel within the last `since` back out of their chain,
+    /// returning how many were taken out. Only messages still in the journal can be.
+    pub(super) fn scrub(&mut self, channel: &str, user: &str, since: Duration) -> usize {
+        let key = (casemap::fold(channel), self.canonical_nick(user));
+        let messages = self.journal.take(&key.0, &key.1, since, Instant::now());
+        if messages.is_empty() {
+            return 0;
+        }
+        self.restore_chain(channel, user);
+        let scrubbed = match self.chains.get(&key.0).and_then(|users| users.get(&key.1)) {
+            Some(chain) => journal::untrain(chain, &messages),
+            None => return 0,
+        };
+        let users = self.chains.get_mut(&key.0).unwrap();
+        match scrubbed {
+            Ok(ref chain) if chain.is_empty() => {
+                users.remove(&key.1);
+            }
+            Ok(chain) => {
+                users.insert(key.1.clone(), chain);
+            }
+            Err(e) => {
+                error!("could not scrub the chain for {} in {}: {}", key.1, key.0, e);
+                return 0;
+            }
+        }
+        self.scrub_eras(&key.0, &key.1, &messages);
+        event!(info, "scrub", "scrubbed {} messages from {} in {}", messages.len(), key.1, key.0);
+        self.allchains.invalidate(&key.0);
+        self.changed_chains.insert(key);
+        self.dirty = true;
+        messages.len()
+    }
+
+    /// Takes scrubbed messages back out of a user's chain for the newest era, which is where anything still in the
+    /// journal was trained.
+    fn scrub_eras(&mut self, channel: &str, user: &str, messages: &[Vec<String>]) {
+        let eras = match self.eras.get_mut(channel).and_then(|users| users.get_mut(user)) {
+            Some(eras) => eras,
+            None => return,
+        };
+        let newest = match eras.keys().next_back() {
+            Some(era) => era.clone(),
+            None => return,
+        };
+        match journal::untrain(&eras[&newest], messages) {
+            Ok(ref chain) if chain.is_empty() => {
+                eras.remove(&newest);
+            }
+            Ok(chain) => {
+                eras.insert(newest, chain);
+            }
+            Err(e) => error!("could not scrub the {} chain for {} in {}: {}", newest, user, channel, e),
+        }
+    }
+
+    /// Removes a user's chains from every channel along with their settings, aliases, bot override, and quotes,
+    /// returning how many channels they had chains in. The allchains they were part of get rebuilt without them.
+    ///
+    /// `user` is a canonical nick.
+    pub(super) fn delete_user(&mut self, user: &str) -> usize {
+        self.restore_where(|(_, u)| u == user);
+        let channels = self.chains
+            .iter()
+            .filter(|&(_, users)| users.contains_key(user))
+            .map(|(channel, _)| channel.clone())
+            .collect::<Vec<_>>();
+        for channel in &channels {
+            self.forget(channel, user);
+        }
+        for users in self.eras.values_mut() {
+            users.remove(user);
+        }
+        self.eras.retain(|_, users| !users.is_empty());
+        for users in self.user_settings.values_mut() {
+            users.remove(user);
+        }
+        for users in self.lifetime.trained.values_mut() {
+            users.remove(user);
+        }
+        self.aliases.retain(|alias, nick| alias != user && nick != user);
+        self.bot_overrides.remove(user);
+        self.last_seen.remove(user);
+        quote::remove_user(&mut self.quotes, user);
+        self.last_said.retain(|(_, u), _| u != user);
+        self.dirty = true;
+        channels.len()
+    }
+
+    pub fn get_chain_total(chain: &Chain<String>) -> u32 {
+        chain
+            .chain()
+            .values()
+            .map(|link| link.values().sum::<u32>())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::IrcBot;
+    use crate::bot::testing::{self, ask, say, ADMIN, CHANNEL};
+
+    #[test]
+    fn generates_from_users_and_channels() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "colorless green ideas sleep furiously");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate Alice");
+        assert_eq!(reply, "bob: colorless green ideas sleep furiously");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov emulate carol");
+        assert_eq!(reply, "bob: No chain for user carol in #test");
+        let reply = ask(&mut bot, &server, "bob", CHANNEL, "!markov all");
+        assert_eq!(reply, "bob: colorless green ideas sleep furiously");
+        assert!(bot.generate_all("#elsewhere").is_none());
+    }
+
+    #[test]
+    fn allchain_keeps_up_with_training() {
+        let (mut bot, _server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "one two three");
+        assert_eq!(bot.generate_all(CHANNEL).unwrap(), "one two three");
+        say(&mut bot, "bob", CHANNEL, "four five six");
+        // six transitions a message: into the start token, into each of the three words, into the end token, and out
+        assert_eq!(IrcBot::get_chain_total(bot.allchain(CHANNEL)), 12);
+
+        // forgetting someone throws the allchain out, and it's built again without them
+        assert!(bot.forget(CHANNEL, "alice"));
+        assert_eq!(bot.generate_all(CHANNEL).unwrap(), "four five six");
+    }
+
+    #[test]
+    fn stops_training_users_over_the_cap_until_it_starts_over() {
+        let (mut bot, server) = testing::bot("training_cap = \"5\"\ntraining_cap_period = \"1d\"");
+        say(&mut bot, "alice", CHANNEL, "one two three");
+        say(&mut bot, "alice", CHANNEL, "four five six");
+        say(&mut bot, "bob", CHANNEL, "seven eight");
+        let reply = ask(&mut bot, &server, "carol", CHANNEL, "!markov emulate alice");
+        assert_eq!(reply, "carol: one two three");
+        assert_eq!(bot.user_settings[CHANNEL]["alice"].capped_tokens, 3);
+
+        // a day later, alice can be trained on again
+        bot.user_settings_mut(CHANNEL, "alice").cap_started -= 24 * 3600;
+        say(&mut bot, "alice", CHANNEL, "nine ten eleven");
+        assert_eq!(bot.user_settings[CHANNEL]["alice"].capped_tokens, 3);
+        assert_eq!(IrcBot::get_chain_total(bot.find_chain(CHANNEL, "alice").unwrap()), 12);
+    }
+
+    #[test]
+    fn aliases_train_the_old_chain() {
+        let (mut bot, server) = testing::bot("");
+        say(&mut bot, "alice", CHANNEL, "one two three");
+        let reply = ask(&mut bot, &server, ADMIN, CHANNEL, "!markov alias add alice_ alice");
+        assert_eq!(reply, format!("{}: alice_ is now an alias of alice", ADMIN));
+        say(&mut bot, "alice_", CHANNEL, "four five six");
+        assert!(bot.find_chain(CHANNEL, "alice_").is_ok());
+        assert_eq!(bot.chains[CHANNEL].len(), 1);
+    }
+}
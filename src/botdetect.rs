@@ -0,0 +1,130 @@
+use crate::config;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How many recent messages per user are kept for working out their message rate.
+const RATE_WINDOW: usize = 10;
+
+/// Message histories are dropped for users who've gone quiet once this many are being tracked.
+const MAX_TRACKED_USERS: usize = 1000;
+
+/// Guesses which users are other bots, so their output doesn't get trained on.
+///
+/// Users are flagged by nick (ending in `bot_nick_suffix`), by the `+B` user mode that many networks give bots, or
+/// by talking faster than `bot_message_rate` messages a minute. Flags only last for the session; admins can
+/// override the guess either way, which is kept in the blob.
+pub struct BotDetector {
+    nick_suffix: Option<String>,
+    usermode: bool,
+    /// Messages per minute, or None if the rate isn't checked.
+    max_rate: Option<f64>,
+    /// Flagged users, by casefolded nick, along with why they were flagged.
+    flagged: HashMap<String, String>,
+    recent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl BotDetector {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(BotDetector {
+            nick_suffix: options
+                .get("bot_nick_suffix")
+                .cloned()
+                .or(Some("bot".to_string()))
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_lowercase()),
+            usermode: config::option(options, "bot_usermode")?.unwrap_or(true),
+            max_rate: config::option(options, "bot_message_rate")?
+                .or(Some(30.0))
+                .filter(|&r| r > 0.0),
+            flagged: HashMap::new(),
+            recent: HashMap::new(),
+        })
+    }
+
+    /// Takes the heuristics from a freshly built detector, keeping everyone who's already been flagged.
+    pub fn reconfigure(&mut self, fresh: BotDetector) {
+        self.nick_suffix = fresh.nick_suffix;
+        self.usermode = fresh.usermode;
+        self.max_rate = fresh.max_rate;
+    }
+
+    /// Flags a user whose nick says they're a bot.
+    ///
+    /// `key` is the casefolded nick that flags are kept under, and `nick` is what they're going by now.
+    pub fn check_nick(&mut self, key: &str, nick: &str) {
+        if self.flagged.contains_key(key) {
+            return;
+        }
+        if let Some(ref suffix) = self.nick_suffix {
+            if nick.to_lowercase().ends_with(suffix.as_str()) {
+                self.flagged.insert(key.to_string(), format!("nick ends in {}", suffix));
+            }
+        }
+    }
+
+    pub fn is_flagged(&self, key: &str) -> bool {
+        self.flagged.contains_key(key)
+    }
+
+    /// Gets whether user modes are being checked, which means asking the server about everyone who joins.
+    pub fn checks_usermode(&self) -> bool {
+        self.usermode
+    }
+
+    /// Records a message from a user, flagging them if they're talking faster than anyone should.
+    pub fn record_message(&mut self, key: &str) {
+        let max_rate = match self.max_rate {
+            Some(r) => r,
+            None => return,
+        };
+        let now = Instant::now();
+        if self.recent.len() > MAX_TRACKED_USERS {
+            self.recent.retain(|_, times| {
+                times.back().map(|&t| now.duration_since(t) < Duration::from_secs(60)).unwrap_or(false)
+            });
+        }
+
+        let times = self.recent.entry(key.to_string()).or_default();
+        times.push_back(now);
+        if times.len() > RATE_WINDOW {
+            times.pop_front();
+        }
+        if times.len() < RATE_WINDOW || self.flagged.contains_key(key) {
+            return;
+        }
+        let elapsed = now.duration_since(times[0]);
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+        let rate = (RATE_WINDOW - 1) as f64 * 60.0 / secs.max(0.001);
+        if rate > max_rate {
+            let reason = format!("sent {} messages in {:.1}s", RATE_WINDOW, secs);
+            event!(info, "bot_flagged", "flagging {} as a bot: {}", key, reason);
+            self.flagged.insert(key.to_string(), reason);
+        }
+    }
+
+    /// Looks at the flags of a WHO reply for the `+B` bot mode.
+    pub fn record_who_flags(&mut self, key: &str, flags: &str) {
+        if self.usermode && flags.contains('B') && !self.flagged.contains_key(key) {
+            event!(info, "bot_flagged", "flagging {} as a bot: has user mode +B", key);
+            self.flagged.insert(key.to_string(), "has user mode +B".to_string());
+        }
+    }
+
+    /// Follows a flag over to a user's new nick.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(reason) = self.flagged.remove(old) {
+            self.flagged.insert(new.to_string(), reason);
+        }
+        self.recent.remove(old);
+    }
+
+    /// Gets every flagged user and why they were flagged.
+    pub fn flagged(&self) -> &HashMap<String, String> {
+        &self.flagged
+    }
+
+    pub fn unflag(&mut self, key: &str) {
+        self.flagged.remove(key);
+        self.recent.remove(key);
+    }
+}
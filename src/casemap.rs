@@ -0,0 +1,19 @@
+/// Folds a nick or channel name to lowercase using the rfc1459 casemapping, which most networks use.
+///
+/// Under rfc1459, `[`, `]`, `\`, and `~` are the uppercase forms of `{`, `}`, `|`, and `^`.
+pub fn fold(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '[' => '{',
+            ']' => '}',
+            '\\' => '|',
+            '~' => '^',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Compares two nicks or channel names under the rfc1459 casemapping.
+pub fn eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && fold(a) == fold(b)
+}
@@ -0,0 +1,133 @@
+use crate::config::Server as ServerConfig;
+use irc::client::prelude::*;
+use irc::error::Result;
+
+/// A connection the bot talks to a chat server through.
+///
+/// The bot only ever sends through this, so it can run against something other than a live IRC server, like the
+/// mock the tests use.
+pub trait ChatServer: Send + Sync {
+    fn send(&self, command: Command) -> Result<()>;
+
+    /// Gets the nick the server knows us by, as far as the connection knows.
+    fn current_nickname(&self) -> &str;
+
+    /// Gets the address of the server, for logging.
+    fn address(&self) -> &str;
+
+    fn send_privmsg(&self, target: &str, message: &str) -> Result<()> {
+        self.send(Command::PRIVMSG(target.to_string(), message.to_string()))
+    }
+
+    fn send_notice(&self, target: &str, message: &str) -> Result<()> {
+        self.send(Command::NOTICE(target.to_string(), message.to_string()))
+    }
+
+    fn send_join(&self, channel: &str) -> Result<()> {
+        self.send(Command::JOIN(channel.to_string(), None, None))
+    }
+
+    fn send_join_with_keys(&self, channel: &str, key: &str) -> Result<()> {
+        self.send(Command::JOIN(channel.to_string(), Some(key.to_string()), None))
+    }
+
+    fn send_part(&self, channel: &str) -> Result<()> {
+        self.send(Command::PART(channel.to_string(), None))
+    }
+}
+
+/// The sending half of a connection to an IRC server. What comes in is read by the server's task, which owns the
+/// client itself.
+#[derive(Clone)]
+pub struct IrcConnection {
+    sender: Sender,
+    nick: String,
+    address: String,
+}
+
+impl IrcConnection {
+    pub fn new(client: &Client, config: &ServerConfig) -> Self {
+        IrcConnection {
+            sender: client.sender(),
+            nick: config.nick.clone(),
+            address: config.address.clone(),
+        }
+    }
+}
+
+impl ChatServer for IrcConnection {
+    fn send(&self, command: Command) -> Result<()> {
+        self.sender.send(command)
+    }
+
+    /// Gets the configured nick; the nick we actually ended up with is followed from what the server says instead.
+    fn current_nickname(&self) -> &str {
+        &self.nick
+    }
+
+    fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::ChatServer;
+    use irc::client::prelude::*;
+    use irc::error::Result;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, Instant};
+
+    /// A chat server that keeps everything sent to it instead of sending it anywhere.
+    ///
+    /// Clones share what's been sent, so a test can hand one to the bot and look at what it said through another.
+    #[derive(Clone)]
+    pub struct MockServer {
+        nick: String,
+        sent: Arc<(Mutex<Vec<Command>>, Condvar)>,
+    }
+
+    impl MockServer {
+        pub fn new(nick: &str) -> Self {
+            MockServer {
+                nick: nick.to_string(),
+                sent: Arc::new((Mutex::new(Vec::new()), Condvar::new())),
+            }
+        }
+
+        /// Waits for at least `count` commands to be sent, since messages go out from the send queue's thread, then
+        /// takes everything sent so far. Gives up after a few seconds.
+        pub fn wait_for(&self, count: usize) -> Vec<Command> {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut sent = self.sent.0.lock().unwrap();
+            while sent.len() < count && Instant::now() < deadline {
+                sent = self.sent.1.wait_timeout(sent, deadline - Instant::now()).unwrap().0;
+            }
+            sent.drain(..).collect()
+        }
+
+        /// Waits for one message to be sent, returning its target and text.
+        pub fn wait_for_message(&self) -> Option<(String, String)> {
+            self.wait_for(1).into_iter().filter_map(|command| match command {
+                Command::PRIVMSG(target, text) | Command::NOTICE(target, text) => Some((target, text)),
+                _ => None,
+            }).next()
+        }
+    }
+
+    impl ChatServer for MockServer {
+        fn send(&self, command: Command) -> Result<()> {
+            self.sent.0.lock().unwrap().push(command);
+            self.sent.1.notify_all();
+            Ok(())
+        }
+
+        fn current_nickname(&self) -> &str {
+            &self.nick
+        }
+
+        fn address(&self) -> &str {
+            "irc.example.com"
+        }
+    }
+}
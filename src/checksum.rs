@@ -0,0 +1,117 @@
+use crc32fast::Hasher;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// What a checksum trailer starts with, so that files saved before there were checksums can be told apart.
+const TRAILER_MAGIC: &[u8; 4] = b"MKCK";
+/// How long a checksum trailer is: the magic, then the CRC32 of everything before it, little-endian.
+pub const TRAILER_LEN: usize = 8;
+
+/// A writer that keeps a CRC32 of everything written through it, to be appended as a trailer when it's finished.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter { inner, hasher: Hasher::new() }
+    }
+
+    /// Writes the trailer and hands back the writer underneath.
+    pub fn finish(mut self) -> io::Result<W> {
+        let crc = self.hasher.finalize();
+        self.inner.write_all(TRAILER_MAGIC)?;
+        self.inner.write_all(&crc_bytes(crc))?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Checks the trailer of a file that's been read into memory, giving back the data without it.
+///
+/// Data without a trailer is taken as it is, since it was saved before there were checksums.
+pub fn strip(data: &[u8]) -> io::Result<&[u8]> {
+    if data.len() < TRAILER_LEN || !data[data.len() - TRAILER_LEN..].starts_with(TRAILER_MAGIC) {
+        return Ok(data);
+    }
+    let (body, trailer) = data.split_at(data.len() - TRAILER_LEN);
+    let mut hasher = Hasher::new();
+    hasher.update(body);
+    check(hasher.finalize(), &trailer[TRAILER_MAGIC.len()..])?;
+    Ok(body)
+}
+
+/// Checks the trailer of a file without reading all of it into memory at once, returning how much of the file is
+/// data. The file is left at the start, ready for the data to be read.
+///
+/// A file without a trailer is taken as it is, since it was saved before there were checksums.
+pub fn verify(file: &mut File) -> io::Result<u64> {
+    let len = file.metadata()?.len();
+    if len < TRAILER_LEN as u64 {
+        return Ok(len);
+    }
+    let mut trailer = [0; TRAILER_LEN];
+    file.seek(SeekFrom::Start(len - TRAILER_LEN as u64))?;
+    file.read_exact(&mut trailer)?;
+    file.seek(SeekFrom::Start(0))?;
+    if !trailer.starts_with(TRAILER_MAGIC) {
+        return Ok(len);
+    }
+    let data_len = len - TRAILER_LEN as u64;
+    let mut hasher = Hasher::new();
+    let mut body = (&mut *file).take(data_len);
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let read = body.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    check(hasher.finalize(), &trailer[TRAILER_MAGIC.len()..])?;
+    Ok(data_len)
+}
+
+fn check(crc: u32, expected: &[u8]) -> io::Result<()> {
+    if crc_bytes(crc) == expected {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "checksum doesn't match; the file is corrupt"))
+    }
+}
+
+fn crc_bytes(crc: u32) -> [u8; 4] {
+    [crc as u8, (crc >> 8) as u8, (crc >> 16) as u8, (crc >> 24) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip, ChecksumWriter, TRAILER_LEN};
+    use std::io::Write;
+
+    #[test]
+    fn catches_flipped_bits() {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        writer.write_all(b"the quick brown fox").unwrap();
+        let mut data = writer.finish().unwrap();
+        assert_eq!(data.len(), 19 + TRAILER_LEN);
+        assert_eq!(strip(&data).unwrap(), b"the quick brown fox");
+        data[4] ^= 1;
+        assert!(strip(&data).is_err());
+        // files from before checksums are taken as they are
+        assert_eq!(strip(b"no trailer here").unwrap(), b"no trailer here");
+    }
+}
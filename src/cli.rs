@@ -0,0 +1,118 @@
+use clap::{App, Arg, SubCommand};
+
+/// Builds the command line interface for the bot.
+pub fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("markov-bot")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("PATH")
+            .takes_value(true)
+            .global(true)
+            .help("Path to the config file [default: markov-bot.toml]"))
+        .arg(Arg::with_name("server")
+            .short("s")
+            .long("server")
+            .value_name("NAME")
+            .takes_value(true)
+            .global(true)
+            .help("Only use the named server from the config file"))
+        .arg(Arg::with_name("chain-file")
+            .long("chain-file")
+            .value_name("PATH")
+            .takes_value(true)
+            .global(true)
+            .help("Path to the chain file, overriding the chain_file option"))
+        .arg(Arg::with_name("log-level")
+            .short("l")
+            .long("log-level")
+            .value_name("LEVEL")
+            .takes_value(true)
+            .global(true)
+            .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+            .help("Minimum level of log messages to print; RUST_LOG takes precedence"))
+        .arg(Arg::with_name("log-format")
+            .long("log-format")
+            .value_name("FORMAT")
+            .takes_value(true)
+            .global(true)
+            .possible_values(&["text", "json"])
+            .help("How to write log messages, overriding the log_format setting [default: text]"))
+        .subcommand(SubCommand::with_name("run")
+            .about("Connects to the configured servers (the default)"))
+        .subcommand(SubCommand::with_name("stats")
+            .about("Prints statistics about the chain file of each selected server"))
+        .subcommand(SubCommand::with_name("snapshot")
+            .about("Saves a copy of the chain file of each selected server as a named snapshot")
+            .arg(Arg::with_name("NAME")
+                .required(true)
+                .help("Name to restore the snapshot by; letters, digits, - and _ only")))
+        .subcommand(SubCommand::with_name("restore")
+            .about("Rolls a chain file back to its newest snapshot with the given name; stop the bot first, or use \
+                    !markov restore instead")
+            .arg(Arg::with_name("NAME")
+                .required(true)
+                .help("Name of the snapshot to restore")))
+        .subcommand(SubCommand::with_name("snapshots")
+            .about("Lists the snapshots of the chain file of each selected server"))
+        .subcommand(SubCommand::with_name("export")
+            .about("Dumps a chain file as JSON")
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("File to write the JSON to instead of stdout"))
+            .arg(Arg::with_name("corpus")
+                .long("corpus")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Also write a text file of generated sentences for each user to DIR/<channel>/<user>.txt"))
+            .arg(Arg::with_name("sentences")
+                .long("sentences")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("100")
+                .help("Number of sentences to generate per user for --corpus")))
+        .subcommand(SubCommand::with_name("repl")
+            .about("Loads a chain file and talks to the bot at a prompt instead of connecting, leaving the file alone")
+            .arg(Arg::with_name("nick")
+                .long("nick")
+                .value_name("NICK")
+                .takes_value(true)
+                .default_value("you")
+                .help("Nick to talk to the bot as; /nick changes it"))
+            .arg(Arg::with_name("channel")
+                .long("channel")
+                .value_name("CHANNEL")
+                .takes_value(true)
+                .help("Channel to talk in; /join changes it [default: the first configured channel]")))
+        .subcommand(SubCommand::with_name("import")
+            .about("Trains a channel's chains from an IRC log, a plain text file, or another markov bot's corpus")
+            .arg(Arg::with_name("FILE")
+                .required(true)
+                .help("File to read messages from"))
+            .arg(Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(&["plain", "weechat", "irssi", "znc", "markovify", "hailo"])
+                .default_value("plain")
+                .help("Format of the file; plain files have one message per line, markovify is markovify's JSON, and \
+                       hailo is a Hailo brain"))
+            .arg(Arg::with_name("channel")
+                .long("channel")
+                .value_name("CHANNEL")
+                .takes_value(true)
+                .required(true)
+                .help("Channel to train"))
+            .arg(Arg::with_name("user")
+                .long("user")
+                .value_name("NICK")
+                .takes_value(true)
+                .help("Only import this user's messages; required for plain files and other bots' corpora")))
+}
@@ -0,0 +1,42 @@
+use crate::casemap;
+
+/// Turns bold on or off.
+const BOLD: char = '\x02';
+
+/// Starts a color, given as two digits after it, or ends one when it isn't followed by any.
+const COLOR: char = '\x03';
+
+/// Colors nicks are given, leaving out white, black, and the greys, which disappear on some backgrounds.
+const NICK_COLORS: &[u8] = &[2, 3, 4, 5, 6, 7, 9, 10, 11, 12, 13];
+
+/// Makes text bold.
+pub fn bold(text: &str) -> String {
+    format!("{}{}{}", BOLD, text, BOLD)
+}
+
+/// Colors text with one of the 16 mIRC colors. The color is always written as two digits, so text starting with a
+/// digit isn't taken as part of it.
+pub fn color(text: &str, color: u8) -> String {
+    format!("{}{:02}{}{}", COLOR, color, text, COLOR)
+}
+
+/// Colors a nick, picking the same color for it every time however it's capitalized.
+pub fn nick(nick: &str) -> String {
+    let hash = casemap::fold(nick)
+        .bytes()
+        .fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+    color(nick, NICK_COLORS[hash % NICK_COLORS.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bold, color, nick};
+
+    #[test]
+    fn formats_with_mirc_codes() {
+        assert_eq!(bold("alice"), "\x02alice\x02");
+        assert_eq!(color("1st", 4), "\x03041st\x03");
+        assert_eq!(nick("Alice")[..3], nick("ALICE")[..3]);
+        assert!(nick("alice").starts_with('\x03') && nick("alice").ends_with("alice\x03"));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::checksum;
+use crate::config;
+use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// Compression level gzip uses unless another is configured, from 0 to 9.
+pub const DEFAULT_GZIP_LEVEL: u32 = 6;
+/// Compression level zstd uses unless another is configured, from 1 to 22.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// How chain files are compressed when they're written.
+///
+/// Reading doesn't need to be told: compressed files are recognized by their magic bytes, which a CBOR map never
+/// starts with, so files written before compression was turned on, or with another kind, still load.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip(u32),
+    Zstd(i32),
+}
+
+impl Compression {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let level = config::option::<i32>(options, "compression_level")?;
+        match (options.get("compression").map(String::as_str), level) {
+            (None, _) | (Some("none"), _) => Ok(Compression::None),
+            (Some("gzip"), Some(level)) if !(0..=9).contains(&level) => {
+                Err("compression_level must be between 0 and 9 for gzip".to_string())
+            }
+            (Some("zstd"), Some(level)) if !(1..=22).contains(&level) => {
+                Err("compression_level must be between 1 and 22 for zstd".to_string())
+            }
+            (Some("gzip"), level) => Ok(Compression::Gzip(level.map(|l| l as u32).unwrap_or(DEFAULT_GZIP_LEVEL))),
+            (Some("zstd"), level) => Ok(Compression::Zstd(level.unwrap_or(DEFAULT_ZSTD_LEVEL))),
+            (Some(other), _) => Err(format!("compression must be \"none\", \"gzip\", or \"zstd\", not {:?}", other)),
+        }
+    }
+
+    /// Wraps a writer so that whatever's written to it is compressed on the way through.
+    pub fn encoder<W: Write>(self, inner: W) -> io::Result<Encoder<W>> {
+        Ok(match self {
+            Compression::None => Encoder::None(inner),
+            Compression::Gzip(level) => Encoder::Gzip(GzEncoder::new(inner, flate2::Compression::new(level))),
+            Compression::Zstd(level) => Encoder::Zstd(zstd::Encoder::new(inner, level)?),
+        })
+    }
+
+    /// Compresses data that's about to be written.
+    pub fn compress(self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        if self == Compression::None {
+            return Ok(data);
+        }
+        let mut encoder = self.encoder(Vec::new())?;
+        encoder.write_all(&data)?;
+        encoder.finish()
+    }
+}
+
+/// A writer that compresses what's written to it before passing it on. It has to be finished once everything's been
+/// written, or the end of the data is lost.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes out whatever's left and hands back the writer underneath.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(inner) => Ok(inner),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Encoder::None(ref mut inner) => inner.write(buf),
+            Encoder::Gzip(ref mut encoder) => encoder.write(buf),
+            Encoder::Zstd(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Encoder::None(ref mut inner) => inner.flush(),
+            Encoder::Gzip(ref mut encoder) => encoder.flush(),
+            Encoder::Zstd(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Wraps a reader so that what comes out of it is decompressed, if its first few bytes say it's compressed.
+pub fn decoder<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let (gzip, zstd) = {
+        let start = reader.fill_buf()?;
+        (start.starts_with(GZIP_MAGIC), start.starts_with(ZSTD_MAGIC))
+    };
+    Ok(if gzip {
+        Box::new(GzDecoder::new(reader))
+    } else if zstd {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Decompresses data that was just read, if it was compressed.
+pub fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !data.starts_with(GZIP_MAGIC) && !data.starts_with(ZSTD_MAGIC) {
+        return Ok(data);
+    }
+    let mut out = Vec::new();
+    decoder(&data[..])?.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Gets how big a file is on disk, and how big its data is once it's decompressed, without holding all of it in
+/// memory.
+pub fn file_sizes(path: &Path) -> io::Result<(u64, u64)> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let len = checksum::verify(&mut file)?;
+    let raw = io::copy(&mut decoder(BufReader::new(file.take(len)))?, &mut io::sink())?;
+    Ok((size, raw))
+}
+
+/// A writer that keeps count of how many bytes have gone through it.
+pub struct Counter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Counter<W> {
+    pub fn new(inner: W) -> Self {
+        Counter { inner, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, Compression};
+    use std::collections::HashMap;
+
+    #[test]
+    fn compressed_data_is_recognized_on_read() {
+        let mut map = HashMap::new();
+        map.insert("the".to_string(), "quick brown fox ".repeat(50));
+        let data = cbor::to_vec(&map).unwrap();
+        for &compression in &[Compression::None, Compression::Gzip(6), Compression::Zstd(3)] {
+            let compressed = compression.compress(data.clone()).unwrap();
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len(), "{:?}", compression);
+            }
+            assert_eq!(decompress(compressed).unwrap(), data);
+        }
+    }
+}
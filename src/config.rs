@@ -1,16 +1,87 @@
-use toml;
-use std::fs::File;
-use std::io::Read;
+use crate::blobstore;
+use crate::bot;
+use crate::casemap;
+use crate::dashboard::Dashboard;
+use crate::generate::{MAX_TEMPERATURE, MIN_TEMPERATURE};
+use crate::environment;
+use crate::logging;
+use crate::metrics;
+use crate::presence::NickMode;
+use crate::quiet::{QuietHours, Zone};
+use crate::tokenize::Tokenizer;
+use toml_edit::{self, ArrayOfTables, Document, Item, Table};
+use irc::client::prelude::Config;
+use std::fs;
+use std::env;
+use std::io::ErrorKind;
 use std::result;
-use std::collections::HashMap;
+use std::str::FromStr;
+use std::fmt::Display;
+use std::collections::{HashMap, HashSet};
 
 type Result<T> = result::Result<T, String>;
 
+/// What commands start with, unless the `command_prefix` option or a channel says otherwise.
+pub const DEFAULT_COMMAND_PREFIX: &str = "!markov";
+
+/// Command aliases there are without configuring any, unless the `short_commands` option is off. Configured aliases
+/// with the same names replace them.
+const SHORT_COMMANDS: &[(&str, &str)] = &[
+    ("!emulate", "emulate"),
+    ("!impersonate", "emulate"),
+    ("!chatter", "all"),
+];
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ProgramConfig {
+    /// "text" for colored log lines, or "json" for one JSON object per line.
+    pub log_format: Option<String>,
     pub servers: HashMap<String, Server>,
 }
 
+impl ProgramConfig {
+    /// Loads the program configuration from a TOML file, with any `MARKOV_*` environment variables applied on top.
+    ///
+    /// The file doesn't have to be there if the environment variables set up a server on their own; see
+    /// `environment::overlay`.
+    pub fn load(path: &str) -> Result<Self> {
+        Self::load_with(path, &env::vars().collect())
+    }
+
+    /// Same as `load`, but with the given environment variables instead of the process's.
+    pub fn load_with(path: &str, vars: &HashMap<String, String>) -> Result<Self> {
+        let configured = environment::configures(vars);
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == ErrorKind::NotFound && configured => {
+                info!("{} isn't there, so the bot is configured from the environment", path);
+                String::new()
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let mut value = contents.parse::<toml::Value>().map_err(|e| e.to_string())?;
+        environment::overlay(&mut value, vars)?;
+        let config: ProgramConfig = value.try_into().map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the configuration for values that would keep a server from starting.
+    pub fn validate(&self) -> Result<()> {
+        if self.servers.is_empty() {
+            return Err("no servers configured".to_string());
+        }
+        if let Some(ref format) = self.log_format {
+            format.parse::<logging::Format>()?;
+        }
+        for (name, server) in &self.servers {
+            server.validate()
+                .map_err(|e| format!("server {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Server {
     pub address: String,
@@ -18,16 +89,448 @@ pub struct Server {
     pub ssl: Option<bool>,
     pub accept_invalid_certs: Option<bool>,
     pub nick: String,
+    /// Nicks to try, in order, when `nick` is taken.
+    pub alt_nicks: Option<Vec<String>>,
     pub user: Option<String>,
+    /// Account to log in to with SASL while connecting. Setting this turns SASL on.
+    pub sasl_username: Option<String>,
+    pub sasl_password: Option<String>,
+    /// "PLAIN" to log in with the username and password, or "EXTERNAL" to log in with a client certificate.
+    pub sasl_mechanism: Option<String>,
+    /// Password to identify to NickServ with. While it's set, channels are joined once NickServ says we're
+    /// identified.
+    pub nickserv_password: Option<String>,
+    /// "regain" or "ghost": how to get the nick back from NickServ when someone else has it. Defaults to "regain".
+    pub nickserv_recover: Option<String>,
+    pub owners: Option<Vec<String>>,
+    /// Nicks, `nick!user@host` masks, and `/regex/`es that are never trained on.
     pub ignore: Option<Vec<String>>,
+    /// Words and `/regex/`es that keep a message from being trained on, and a generated sentence from being said.
+    pub blacklist: Option<Vec<String>>,
+    /// Words that run a command when a message starts with them, mapped to the words they stand for after the command
+    /// prefix; an alias that stands for nothing is another command prefix.
+    pub command_aliases: Option<HashMap<String, String>>,
     pub channels: Vec<Channel>,
+    pub options: Option<HashMap<String, String>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Channel {
     pub name: String,
     pub key: Option<String>,
+    /// Same as the server's ignore list, but only for this channel.
     pub ignore: Option<Vec<String>>,
+    /// Added to the server's blacklist, for this channel only.
+    pub blacklist: Option<Vec<String>>,
+    /// Whether messages in this channel are trained on. Defaults to true.
+    pub train: Option<bool>,
+    /// Whether the bot sends random replies in this channel. Defaults to true.
+    pub respond: Option<bool>,
+    /// Markov order for new chains in this channel, overriding the `order` option.
+    pub order: Option<usize>,
+    /// Word limits for generated sentences in this channel, overriding the `min_words` and `max_words` options.
+    pub min_words: Option<usize>,
+    pub max_words: Option<usize>,
+    /// How random generated sentences are here, overriding the `temperature` option.
+    pub temperature: Option<f64>,
+    /// Chance of a random reply for anyone in this channel, overriding the `chance` option. Users can only set theirs
+    /// lower than this.
+    pub chance: Option<f64>,
+    /// How messages here are split into tokens, overriding the `tokenizer` option.
+    pub tokenizer: Option<String>,
+    /// What commands start with here, overriding the `command_prefix` option.
+    pub command_prefix: Option<String>,
+    /// Added to the server's command aliases, for this channel only, replacing any with the same names.
+    pub command_aliases: Option<HashMap<String, String>>,
+    /// Whether the bot posts something from the allchain here every so often, unprompted. Defaults to false.
+    pub post: Option<bool>,
+    /// Whether the bot comes back after `rejoin_delay` when it's kicked from here. Defaults to true.
+    pub rejoin: Option<bool>,
+    /// Chance of greeting someone who joins here with something from their own chain, or the channel's if they're
+    /// new. Defaults to 0, which never greets anyone.
+    pub greet_chance: Option<f64>,
+    /// Whether nicks of people in this channel are broken up with a zero-width space in generated sentences, so they
+    /// don't get highlighted. Defaults to true.
+    pub highlight_safe: Option<bool>,
+    /// Whether replies here use mIRC bold and colors, like the nick they're addressed to in bold. Defaults to false,
+    /// since some channels don't allow them.
+    pub use_colors: Option<bool>,
+    /// Time of day, like `23:00-08:00`, when the bot still trains here but sends nothing unprompted: no random
+    /// replies, greetings, trigger responses, or scheduled posts.
+    pub quiet_hours: Option<String>,
+    /// Timezone the quiet hours go by, overriding the `timezone` option.
+    pub timezone: Option<String>,
+    /// Whether the bot only logs what it would have said here unprompted, instead of saying it, overriding the
+    /// `shadow` option.
+    pub shadow: Option<bool>,
+    /// Whether admins can have the bot set the topic here with `!markov topic set`. Defaults to false.
+    pub set_topic: Option<bool>,
+    /// What's done with the nicks of people here in what's trained on, overriding the `nick_mentions` option.
+    pub nick_mentions: Option<String>,
+    /// Whether this channel's chains go into the global chain that `!markov global` generates from. Defaults to true.
+    pub global: Option<bool>,
+    /// Words and `/regex/`es that get a response from the channel's chain whenever someone says them, apart from the
+    /// random replies.
+    pub triggers: Option<Vec<String>>,
+    /// Chance of responding to a trigger. Defaults to 1.
+    pub trigger_chance: Option<f64>,
+    /// Seconds after responding to a trigger before responding to another one here. Defaults to 60.
+    pub trigger_cooldown: Option<u64>,
+}
+
+impl Channel {
+    /// Creates a channel entry with nothing but a name and maybe a key, like the ones added by `!markov join`.
+    pub fn new(name: &str, key: Option<&str>) -> Self {
+        Channel {
+            name: name.to_string(),
+            key: key.map(str::to_string),
+            ignore: None,
+            blacklist: None,
+            train: None,
+            respond: None,
+            order: None,
+            min_words: None,
+            max_words: None,
+            temperature: None,
+            chance: None,
+            tokenizer: None,
+            command_prefix: None,
+            command_aliases: None,
+            post: None,
+            rejoin: None,
+            greet_chance: None,
+            highlight_safe: None,
+            use_colors: None,
+            quiet_hours: None,
+            timezone: None,
+            shadow: None,
+            set_topic: None,
+            nick_mentions: None,
+            global: None,
+            triggers: None,
+            trigger_chance: None,
+            trigger_cooldown: None,
+        }
+    }
+}
+
+impl Channel {
+    /// Checks a channel's entry for values that would keep the server from starting, naming the channel.
+    ///
+    /// The ignore list, blacklist, and triggers are checked along with the server's, in `bot::Options`.
+    fn validate(&self) -> Result<()> {
+        if !is_channel_name(&self.name) {
+            return Err(format!("invalid channel name {:?}", self.name));
+        }
+        if self.order == Some(0) {
+            return Err(format!("order for {} must be at least 1", self.name));
+        }
+        if let (Some(min), Some(max)) = (self.min_words, self.max_words) {
+            if max != 0 && min > max {
+                return Err(format!("min_words for {} is more than its max_words", self.name));
+            }
+        }
+        let chances = [("chance", self.chance), ("greet_chance", self.greet_chance),
+                       ("trigger_chance", self.trigger_chance)];
+        for &(key, chance) in &chances {
+            if chance.map(|chance| !(0.0..=1.0).contains(&chance)).unwrap_or(false) {
+                return Err(format!("{} for {} must be between 0.0 and 1.0", key, self.name));
+            }
+        }
+        let in_channel = |e: String| format!("{}: {}", self.name, e);
+        if let Some(temperature) = self.temperature {
+            check_temperature(temperature).map_err(in_channel)?;
+        }
+        if let Some(ref tokenizer) = self.tokenizer {
+            tokenizer.parse::<Tokenizer>().map_err(in_channel)?;
+        }
+        if let Some(ref hours) = self.quiet_hours {
+            hours.parse::<QuietHours>().map_err(in_channel)?;
+        }
+        if let Some(ref zone) = self.timezone {
+            zone.parse::<Zone>().map_err(in_channel)?;
+        }
+        if let Some(ref mode) = self.nick_mentions {
+            mode.parse::<NickMode>().map_err(in_channel)?;
+        }
+        if let Some(ref prefix) = self.command_prefix {
+            check_command_prefix(prefix).map_err(in_channel)?;
+        }
+        if let Some(ref aliases) = self.command_aliases {
+            check_command_aliases(aliases).map_err(in_channel)?;
+        }
+        Ok(())
+    }
 }
 
+impl Server {
+    /// Checks the server's config for values that would keep it from starting, naming the option or field that's
+    /// wrong.
+    ///
+    /// Each feature checks its own options as it's built from them, so most of this is reading them the way the bot
+    /// does.
+    pub fn validate(&self) -> Result<()> {
+        if self.address.trim().is_empty() {
+            return Err("address must not be empty".to_string());
+        }
+        if self.nick.is_empty() || self.nick.contains(char::is_whitespace) {
+            return Err(format!("invalid nick {:?}", self.nick));
+        }
+        for nick in self.alt_nicks.iter().flat_map(|nicks| nicks.iter()) {
+            if nick.is_empty() || nick.contains(char::is_whitespace) {
+                return Err(format!("invalid alt nick {:?}", nick));
+            }
+            if casemap::eq(nick, &self.nick) {
+                return Err(format!("alt nick {} is the same as the nick", nick));
+            }
+        }
+        let mut seen = HashSet::new();
+        for channel in &self.channels {
+            channel.validate()?;
+            if !seen.insert(casemap::fold(&channel.name)) {
+                return Err(format!("channel {} is listed more than once", channel.name));
+            }
+        }
+        if let Some(ref aliases) = self.command_aliases {
+            check_command_aliases(aliases)?;
+        }
+        let options = self.options.clone().unwrap_or_default();
+        option::<bool>(&options, "short_commands")?;
+        if let Some(prefix) = options.get("command_prefix") {
+            check_command_prefix(prefix)?;
+        }
+        blobstore::check_options(&options)?;
+        metrics::address(&options)?;
+        Dashboard::from_options(&options)?;
+        bot::Options::parse(self)?;
+        Ok(())
+    }
+
+    /// Gets the server-wide ignore list, combining the `ignore` field and the comma-separated `ignore` option.
+    pub fn ignore_entries(&self) -> Vec<String> {
+        let mut ignore = self.ignore.clone().unwrap_or(vec![]);
+        if let Some(list) = self.options.as_ref().and_then(|o| o.get("ignore")) {
+            ignore.extend(list.split(',').map(str::to_string));
+        }
+        ignore
+    }
+
+    /// Gets the config entry for the given channel, if there is one.
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels
+            .iter()
+            .find(|c| casemap::eq(&c.name, name))
+    }
+
+    /// Gets what commands start with in a channel, or in private messages if there's no channel.
+    pub fn command_prefix(&self, channel: Option<&str>) -> &str {
+        channel
+            .and_then(|name| self.channel(name))
+            .and_then(|c| c.command_prefix.as_ref())
+            .or_else(|| self.options.as_ref().and_then(|o| o.get("command_prefix")))
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_COMMAND_PREFIX)
+    }
+
+    /// Gets the words a message starting with `word` runs a command with in a channel, or in private messages if
+    /// there's no channel, starting with the prefix to describe the command with, or None if it doesn't run one.
+    ///
+    /// A command alias stands for the command prefix followed by whatever it's mapped to, unless it's mapped to
+    /// nothing, in which case it's a prefix of its own. The short commands are aliases too.
+    pub fn command_words(&self, channel: Option<&str>, word: &str) -> Option<Vec<String>> {
+        let prefix = self.command_prefix(channel);
+        if word == prefix {
+            return Some(vec![word.to_string()]);
+        }
+        let alias = channel
+            .and_then(|name| self.channel(name))
+            .and_then(|c| c.command_aliases.as_ref())
+            .and_then(|aliases| aliases.get(word))
+            .or_else(|| self.command_aliases.as_ref().and_then(|aliases| aliases.get(word)))
+            .map(String::as_str)
+            .or_else(|| self.short_command(word))?;
+        let mut words = alias.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+        words.insert(0, if words.is_empty() { word } else { prefix }.to_string());
+        Some(words)
+    }
+
+    /// Gets what one of the short commands stands for, if they're on.
+    fn short_command(&self, word: &str) -> Option<&'static str> {
+        let on = self.options
+            .as_ref()
+            .and_then(|o| o.get("short_commands"))
+            .and_then(|x| x.parse::<bool>().ok())
+            .unwrap_or(true);
+        SHORT_COMMANDS.iter().find(|&&(alias, _)| on && alias == word).map(|&(_, words)| words)
+    }
+
+    /// Gets the nicks to try when the configured one is taken. Without any configured, there's `<nick>_` when
+    /// there's a NickServ password, so the nick can be taken back once we're connected.
+    pub fn alt_nicks(&self) -> Vec<String> {
+        match (&self.alt_nicks, &self.nickserv_password) {
+            (Some(nicks), _) => nicks.clone(),
+            (&None, &Some(_)) => vec![format!("{}_", self.nick)],
+            (&None, &None) => vec![],
+        }
+    }
+
+    /// Builds the connection config used by the irc crate for this server.
+    ///
+    /// When there's a NickServ password, the channels are left out for the bot to join itself once it's identified.
+    pub fn irc_config(&self) -> Config {
+        let channel_keys = self.channels
+            .iter()
+            .filter_map(|c| c.key.as_ref().map(|k| (c.name.clone(), k.clone())))
+            .collect::<HashMap<_, _>>();
+        Config {
+            owners: self.owners.clone().unwrap_or_default(),
+            nickname: Some(self.nick.clone()),
+            alt_nicks: self.alt_nicks(),
+            username: self.user.clone(),
+            realname: self.user.clone(),
+            server: Some(self.address.clone()),
+            port: self.port,
+            use_tls: self.ssl,
+            dangerously_accept_invalid_certs: self.accept_invalid_certs,
+            channels: if self.nickserv_password.is_some() {
+                Vec::new()
+            } else {
+                self.channels.iter().map(|c| c.name.clone()).collect()
+            },
+            channel_keys,
+            options: self.options.clone().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
 
+/// Reads an option, failing with a message that names it if its value doesn't parse.
+pub fn option<T>(options: &HashMap<String, String>, key: &str) -> Result<Option<T>>
+    where T: FromStr,
+          T::Err: Display
+{
+    match options.get(key) {
+        Some(value) => value.parse::<T>()
+            .map(Some)
+            .map_err(|e| format!("invalid value {:?} for option {}: {}", value, key, e)),
+        None => Ok(None),
+    }
+}
+
+/// Reads an option that's a chance, bringing it back to between 0 and 1 with a warning if it's outside of that, since
+/// the chance of anything can't be more than certain or less than never.
+pub fn chance_option(options: &HashMap<String, String>, key: &str, default: f64) -> Result<f64> {
+    let chance = match option::<f64>(options, key)? {
+        Some(chance) if chance.is_nan() => return Err(format!("{} must be a number", key)),
+        Some(chance) => chance,
+        None => return Ok(default),
+    };
+    let clamped = chance.clamp(0.0, 1.0);
+    if clamped != chance {
+        warn!("option {} is {}, but chances go from 0.0 to 1.0; using {}", key, chance, clamped);
+    }
+    Ok(clamped)
+}
+
+/// Makes sure a temperature is one generation can work with.
+pub fn check_temperature(temperature: f64) -> Result<()> {
+    if (MIN_TEMPERATURE..=MAX_TEMPERATURE).contains(&temperature) {
+        Ok(())
+    } else {
+        Err(format!("temperature must be between {} and {}", MIN_TEMPERATURE, MAX_TEMPERATURE))
+    }
+}
+
+/// Makes sure a command prefix is a single word, since commands are split on whitespace.
+fn check_command_prefix(prefix: &str) -> Result<()> {
+    if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+        Err(format!("command prefix {:?} must be a single word", prefix))
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes sure command aliases are single words that stand for commands the bot knows.
+fn check_command_aliases(aliases: &HashMap<String, String>) -> Result<()> {
+    for (alias, words) in aliases {
+        check_command_prefix(alias).map_err(|_| format!("command alias {:?} must be a single word", alias))?;
+        if let Some(command) = words.split_whitespace().next() {
+            if !bot::is_command(command) {
+                return Err(format!("command alias {} is for {:?}, which isn't a command", alias, command));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Gets whether a name has one of the prefixes that mark a channel rather than a nick.
+pub fn is_channel_name(name: &str) -> bool {
+    name.starts_with(|c| "#&+!".contains(c))
+}
+
+/// Adds a channel to a server's channel list in a config file.
+pub fn add_channel_to_file(path: &str, server: &str, channel: &Channel) -> Result<()> {
+    edit_channels(path, server, |channels| {
+        let mut table = Table::new();
+        table["name"] = toml_edit::value(channel.name.as_str());
+        if let Some(ref key) = channel.key {
+            table["key"] = toml_edit::value(key.as_str());
+        }
+        channels.push(table);
+        None
+    })
+}
+
+/// Removes a channel from a server's channel list in a config file.
+pub fn remove_channel_from_file(path: &str, server: &str, name: &str) -> Result<()> {
+    edit_channels(path, server, |channels| {
+        let index = channels.iter().position(|table| {
+            table.get("name")
+                .and_then(Item::as_str)
+                .map(|n| casemap::eq(n, name))
+                .unwrap_or(false)
+        })?;
+        // comments above a table are part of it, and they usually aren't about the channel
+        let comments = channels.get(index)
+            .and_then(|table| table.decor().prefix())
+            .and_then(|prefix| prefix.as_str())
+            .map(str::to_string);
+        channels.remove(index);
+        match (comments, channels.get_mut(index)) {
+            (Some(comments), Some(next)) => {
+                next.decor_mut().set_prefix(comments);
+                None
+            }
+            (comments, _) => comments,
+        }
+    })
+}
+
+/// Changes the `[[servers.<server>.channels]]` tables of a config file, leaving the rest of the file, comments and
+/// all, the way it was.
+///
+/// Whatever text `edit` returns is kept at the end of the file, for comments that had nowhere else to go.
+fn edit_channels<F>(path: &str, server: &str, edit: F) -> Result<()>
+    where F: FnOnce(&mut ArrayOfTables) -> Option<String>
+{
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut doc = contents.parse::<Document>().map_err(|e| e.to_string())?;
+    let leftover = {
+        let channels = doc.get_mut("servers")
+            .and_then(|servers| servers.get_mut(server))
+            .and_then(Item::as_table_mut)
+            .ok_or_else(|| format!("no server named {} in {}", server, path))?
+            .entry("channels")
+            .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+            .as_array_of_tables_mut()
+            .ok_or_else(|| format!("channels for {} aren't written as [[servers.{}.channels]] tables", server, server))?;
+        edit(channels)
+    };
+    if let Some(leftover) = leftover {
+        let trailing = format!("{}{}", leftover, doc.trailing().as_str().unwrap_or(""));
+        doc.set_trailing(trailing);
+    }
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, doc.to_string()).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
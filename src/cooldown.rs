@@ -0,0 +1,54 @@
+use crate::casemap;
+use crate::config;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Keeps random replies in a channel from coming one right after another.
+///
+/// After a random reply, a channel has to go `seconds` seconds and `messages` messages before it gets another one.
+/// Either can be 0 to leave it out.
+#[derive(Clone, Debug)]
+pub struct Cooldown {
+    seconds: u64,
+    messages: usize,
+    /// When each channel, casefolded, last got a random reply, and how many messages it's seen since.
+    last: HashMap<String, (Instant, usize)>,
+}
+
+impl Cooldown {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(Cooldown {
+            seconds: config::option(options, "reply_cooldown")?.unwrap_or(0),
+            messages: config::option(options, "reply_cooldown_messages")?.unwrap_or(0),
+            last: HashMap::new(),
+        })
+    }
+
+    /// Takes the settings from a freshly built cooldown, keeping track of when each channel last got a reply.
+    pub fn reconfigure(&mut self, fresh: Cooldown) {
+        self.seconds = fresh.seconds;
+        self.messages = fresh.messages;
+    }
+
+    /// Counts a message sent to a channel.
+    pub fn record_message(&mut self, channel: &str) {
+        if let Some(&mut (_, ref mut count)) = self.last.get_mut(&casemap::fold(channel)) {
+            *count += 1;
+        }
+    }
+
+    /// Gets whether a channel is out of its cooldown.
+    pub fn ready(&self, channel: &str) -> bool {
+        match self.last.get(&casemap::fold(channel)) {
+            Some(&(when, count)) => {
+                when.elapsed() >= Duration::from_secs(self.seconds) && count >= self.messages
+            }
+            None => true,
+        }
+    }
+
+    /// Starts a channel's cooldown over after a random reply.
+    pub fn replied(&mut self, channel: &str) {
+        self.last.insert(casemap::fold(channel), (Instant::now(), 0));
+    }
+}
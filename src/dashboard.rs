@@ -0,0 +1,291 @@
+use crate::bot::IrcBot;
+use crate::config;
+use crate::logging;
+use crate::server::BotHandle;
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{Cursor, Read};
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Where the dashboard listens, unless the `dashboard_address` option says otherwise.
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+/// The cookie a browser keeps the token in once it's logged in.
+const COOKIE: &str = "markov_dashboard";
+/// Most bytes of a login form that are read.
+const MAX_FORM: u64 = 4096;
+
+type Page = Response<Cursor<Vec<u8>>>;
+
+/// Where the dashboard for one server listens, and what it needs to get in.
+pub struct Dashboard {
+    pub address: String,
+    pub token: String,
+}
+
+impl Dashboard {
+    /// Gets where the dashboard listens from the `dashboard_*` options, or None if it's not turned on with
+    /// `dashboard_port`.
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Option<Self>, String> {
+        let port = match config::option::<u16>(options, "dashboard_port")? {
+            Some(port) => port,
+            None => return Ok(None),
+        };
+        let token = match options.get("dashboard_token") {
+            Some(token) if !token.is_empty() => token.clone(),
+            _ => return Err("dashboard_port is set, but there's no dashboard_token to log in with".to_string()),
+        };
+        let address = options.get("dashboard_address").map(String::as_str).unwrap_or(DEFAULT_ADDRESS);
+        Ok(Some(Dashboard { address: format!("{}:{}", address, port), token }))
+    }
+
+    /// Starts serving the dashboard on a thread of its own.
+    ///
+    /// Every page needs the token, either as `Authorization: Bearer <token>` or in a cookie, which a browser gets by
+    /// logging in with the token once. It never goes in a URL, where it would end up in history and logs.
+    pub fn serve(self, name: String, bot: BotHandle) -> Result<(), String> {
+        let server = Server::http(self.address.as_str())
+            .map_err(|e| format!("could not serve dashboard on {}: {}", self.address, e))?;
+        info!("serving dashboard for {} at http://{}/", name, self.address);
+        thread::spawn(move || {
+            logging::set_server(&name);
+            for mut request in server.incoming_requests() {
+                let response = self.respond(&name, &bot, &mut request);
+                if let Err(e) = request.respond(response) {
+                    warn!("could not send dashboard page for {}: {}", name, e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Works out the response to a request.
+    fn respond(&self, name: &str, bot: &BotHandle, request: &mut Request) -> Page {
+        let url = request.url().to_string();
+        let (path, query) = match url.find('?') {
+            Some(i) => (url[..i].to_string(), parse_query(&url[i + 1..])),
+            None => (url.clone(), Vec::new()),
+        };
+
+        if let (&Method::Post, "/login") = (request.method(), path.as_str()) {
+            let mut form = String::new();
+            if request.as_reader().take(MAX_FORM).read_to_string(&mut form).is_err() {
+                return html(400, page(name, "Bad request", "<p>That login form couldn't be read.</p>"));
+            }
+            let form = parse_query(&form);
+            let token = form.iter().find(|&(k, _)| k == "token").map(|(_, v)| v.as_str());
+            return match token {
+                Some(token) if same_token(token, &self.token) => {
+                    let cookie = format!("{}={}; HttpOnly; SameSite=Strict; Path=/", COOKIE, encode(token));
+                    Response::from_data(Vec::new())
+                        .with_status_code(303)
+                        .with_header(header("Location", "/"))
+                        .with_header(header("Set-Cookie", &cookie))
+                }
+                _ => html(401, page(name, "Unauthorized", &format!("<p>That isn't the token.</p>{}", LOGIN_FORM))),
+            };
+        }
+        if !self.authorized(request) {
+            let body = format!("<p>This dashboard needs a token.</p>{}", LOGIN_FORM);
+            return html(401, page(name, "Unauthorized", &body));
+        }
+
+        // the rest is built on the server's task, which is the only thing that can get at the bot
+        let (method, page_name) = (request.method().clone(), name.to_string());
+        bot.call(move |bot| bot_page(&page_name, bot, &method, &path, &query))
+            .unwrap_or_else(|| html(503, page(name, "Unavailable", "<p>The bot is shutting down.</p>")))
+    }
+
+    /// Gets whether a request carries the token, as a bearer token or in the cookie logging in sets.
+    fn authorized(&self, request: &Request) -> bool {
+        let header = |name: &'static str| request.headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str());
+        let bearer = header("Authorization").and_then(|value| {
+            let mut parts = value.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("Bearer") => Some(token.to_string()),
+                _ => None,
+            }
+        });
+        let cookie = header("Cookie").and_then(|value| cookie(value, COOKIE));
+        bearer.into_iter().chain(cookie).any(|token| same_token(&token, &self.token))
+    }
+}
+
+/// Builds a page that needs the bot, for a request that's been authorized already.
+fn bot_page(name: &str, bot: &mut IrcBot, method: &Method, path: &str, query: &[(String, String)]) -> Page {
+    let param = |key: &str| query.iter().find(|&(k, _)| k == key).map(|(_, v)| v.as_str());
+    let link = |path: &str, params: &[(&str, &str)]| {
+        let mut link = path.to_string();
+        for (i, &(key, value)) in params.iter().enumerate() {
+            write!(link, "{}{}={}", if i == 0 { "?" } else { "&amp;" }, key, encode(value)).unwrap();
+        }
+        link
+    };
+    match (method, path, param("channel")) {
+        (&Method::Get, "/", _) => {
+            let mut body = String::from("<table><tr><th>Channel</th><th>Users</th><th>Tokens</th><th>States</th></tr>");
+            let mut channels = bot.channel_chain_stats();
+            channels.sort_by(|a, b| a.0.cmp(b.0));
+            for (channel, users, learned) in channels {
+                write!(body, "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                       link("/channel", &[("channel", channel)]), escape(channel), users, learned.tokens,
+                       learned.states).unwrap();
+            }
+            write!(body, "</table><form method=\"post\" action=\"{}\"><button>Save now</button></form>",
+                   link("/save", &[])).unwrap();
+            html(200, page(name, "Channels", &body))
+        }
+        (&Method::Get, "/channel", Some(channel)) => {
+            let mut body = format!("<p><a href=\"{}\">Generate from everyone</a></p>\
+                                    <table><tr><th>User</th><th>Tokens</th><th>States</th><th>Order</th></tr>",
+                                   link("/generate", &[("channel", channel)]));
+            let mut users = bot.user_chain_stats(channel);
+            users.sort_by_key(|user| Reverse(user.1.tokens));
+            for (user, learned, order) in users {
+                write!(body, "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                       link("/generate", &[("channel", channel), ("user", user)]), escape(user),
+                       learned.tokens, learned.states, order).unwrap();
+            }
+            body.push_str("</table>");
+            html(200, page(name, channel, &body))
+        }
+        (&Method::Get, "/generate", Some(channel)) => {
+            let user = param("user");
+            let sentence = bot.preview(channel, user);
+            let again = match user {
+                Some(user) => link("/generate", &[("channel", channel), ("user", user)]),
+                None => link("/generate", &[("channel", channel)]),
+            };
+            let body = format!("<blockquote>{}</blockquote><p><a href=\"{}\">Again</a> \
+                                <a href=\"{}\">Back to {}</a></p>",
+                               escape(&sentence), again, link("/channel", &[("channel", channel)]),
+                               escape(channel));
+            html(200, page(name, &format!("{} in {}", user.unwrap_or("Everyone"), channel), &body))
+        }
+        (&Method::Post, "/save", _) => {
+            info!("saving {} from the dashboard", name);
+            let body = match bot.save() {
+                Ok(()) => "<p>Saved.</p>".to_string(),
+                Err(e) => format!("<p>Could not save: {}</p>", escape(&e.to_string())),
+            };
+            html(200, page(name, "Save", &format!("{}<p><a href=\"{}\">Back</a></p>", body, link("/", &[]))))
+        }
+        _ => html(404, page(name, "Not found", "<p>There's nothing here.</p>")),
+    }
+}
+
+/// Where to put the token to log in with a browser.
+const LOGIN_FORM: &str = "<form method=\"post\" action=\"/login\"><input type=\"password\" name=\"token\" \
+                          placeholder=\"Token\"> <button>Log in</button></form>";
+
+/// Compares a token someone gave with the real one in the same time however much of it matches, so the time a
+/// request takes doesn't give away how close it got. Both are hashed first so their lengths don't matter either.
+fn same_token(given: &str, token: &str) -> bool {
+    let (given, token) = (Sha256::digest(given.as_bytes()), Sha256::digest(token.as_bytes()));
+    given.iter().zip(token.iter()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Finds a cookie's decoded value in a `Cookie` header.
+fn cookie(header: &str, name: &str) -> Option<String> {
+    header.split(';')
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, '=');
+            Some((parts.next()?, parts.next()?))
+        })
+        .find(|&(key, _)| key == name)
+        .map(|(_, value)| decode(value))
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/// Makes a response out of a page.
+fn html(status: u16, body: String) -> Page {
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+/// Wraps the body of a page in the rest of the HTML.
+fn page(name: &str, title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{0} - {1}</title></head>\
+             <body><h1>{0}: {1}</h1>{2}</body></html>",
+            escape(name), escape(title), body)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Percent-encodes a query parameter value.
+fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+/// Splits a query string into decoded key and value pairs.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = decode(parts.next().unwrap());
+            let value = decode(parts.next().unwrap_or(""));
+            (key, value)
+        })
+        .collect()
+}
+
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            hex(bytes[i + 1]).and_then(|high| hex(bytes[i + 2]).map(|low| high * 16 + low))
+        } else {
+            None
+        };
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 2;
+            }
+            (None, b'+') => decoded.push(b' '),
+            (None, byte) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cookie, same_token};
+
+    #[test]
+    fn tokens_are_found_and_checked() {
+        assert!(same_token("hunter2", "hunter2"));
+        assert!(!same_token("hunter", "hunter2") && !same_token("", "hunter2"));
+        let header = "theme=dark; markov_dashboard=a%20b; other=x";
+        assert_eq!(cookie(header, "markov_dashboard"), Some("a b".to_string()));
+        assert_eq!(cookie(header, "missing"), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A duration written the way people write them, like `90s`, `30m`, `2h`, `1d`, or `1h30m`. A bare number is taken as
+/// seconds, which is how durations were written before units were allowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HumanDuration(pub Duration);
+
+impl FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if let Ok(secs) = s.parse::<u64>() {
+            return Ok(HumanDuration(Duration::from_secs(secs)));
+        }
+        let invalid = || format!("{:?} isn't a duration, like 90s, 30m, 2h, or 1d", s);
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        let mut secs = 0u64;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+            if digits == 0 {
+                return Err(invalid());
+            }
+            let count = rest[..digits].parse::<u64>().map_err(|_| invalid())?;
+            let unit = match rest[digits..].chars().next() {
+                Some('s') => 1,
+                Some('m') => 60,
+                Some('h') => 3600,
+                Some('d') => 86400,
+                _ => return Err(invalid()),
+            };
+            secs = count.checked_mul(unit).and_then(|n| secs.checked_add(n)).ok_or_else(invalid)?;
+            rest = &rest[digits + 1..];
+        }
+        Ok(HumanDuration(Duration::from_secs(secs)))
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let parts = [(secs / 86400, "d"), (secs / 3600 % 24, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")];
+        let mut wrote = false;
+        for &(count, unit) in parts.iter().filter(|&&(count, _)| count > 0) {
+            write!(f, "{}{}", count, unit)?;
+            wrote = true;
+        }
+        if !wrote {
+            write!(f, "0s")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HumanDuration;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_units_and_bare_seconds() {
+        let parse = |s: &str| s.parse::<HumanDuration>().map(|d| d.0.as_secs());
+        assert_eq!(parse("3600"), Ok(3600));
+        assert_eq!(parse("90s"), Ok(90));
+        assert_eq!(parse("30m"), Ok(1800));
+        assert_eq!(parse("2h"), Ok(7200));
+        assert_eq!(parse("1d"), Ok(86400));
+        assert_eq!(parse("1h30m"), Ok(5400));
+        assert!(parse("").is_err());
+        assert!(parse("soon").is_err());
+        assert!(parse("30").is_ok());
+        assert!(parse("30x").is_err());
+        assert!(parse("m30").is_err());
+        assert_eq!(HumanDuration(Duration::from_secs(5400)).to_string(), "1h30m");
+        assert_eq!(HumanDuration(Duration::from_secs(0)).to_string(), "0s");
+    }
+}
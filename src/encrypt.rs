@@ -0,0 +1,93 @@
+use chacha20poly1305::{self, ChaCha20Poly1305, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use std::collections::HashMap;
+use std::env;
+use std::io;
+
+/// What encrypted data starts with, so that it can be told apart from plain data, which is CBOR or compressed CBOR
+/// and never starts with this. The last byte is the version of the layout that follows.
+const MAGIC: &[u8] = b"MKEN\x01";
+/// How long the nonce after the magic is.
+const NONCE_LEN: usize = 12;
+/// Where the key is read from if the `encryption_key` option isn't set.
+pub const KEY_VAR: &str = "MARKOV_BOT_ENCRYPTION_KEY";
+
+/// A key for encrypting blobs with ChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct Key(chacha20poly1305::Key);
+
+impl Key {
+    /// Reads a key written as base64 of 32 bytes, like `openssl rand -base64 32` gives.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match base64::decode(s.trim()) {
+            Ok(ref bytes) if bytes.len() == 32 => Ok(Key(*chacha20poly1305::Key::from_slice(bytes))),
+            Ok(bytes) => Err(format!("encryption keys are 32 bytes, not {}", bytes.len())),
+            Err(e) => Err(format!("encryption keys are written in base64: {}", e)),
+        }
+    }
+
+    /// Gets the key from the `encryption_key` option, or from `MARKOV_BOT_ENCRYPTION_KEY` if that isn't set, or none
+    /// if neither is.
+    pub fn from_options(options: &HashMap<String, String>) -> io::Result<Option<Self>> {
+        let key = match options.get("encryption_key").cloned().or_else(|| env::var(KEY_VAR).ok()) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        Key::parse(&key).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+/// Gets whether data was encrypted by `seal`.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts data with a fresh nonce, which is kept along with it.
+pub fn seal(key: &Key, data: &[u8]) -> io::Result<Vec<u8>> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = ChaCha20Poly1305::new(&key.0)
+        .encrypt(&nonce, data)
+        .map_err(|_| io::Error::other("could not encrypt"))?;
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend(sealed);
+    Ok(out)
+}
+
+/// Decrypts data `seal` came up with.
+///
+/// Failing to decrypt isn't reported as `InvalidData`, since it's most likely down to the wrong key, and that
+/// shouldn't get the file moved aside as corrupt; damage to the file is caught by its checksum before this.
+pub fn open(key: Option<&Key>, data: &[u8]) -> io::Result<Vec<u8>> {
+    let key = key.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "the chains are encrypted, but no encryption_key is set")
+    })?;
+    if data.len() < MAGIC.len() + NONCE_LEN || !is_sealed(data) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "encrypted data is cut short"));
+    }
+    let (nonce, sealed) = data[MAGIC.len()..].split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(&key.0)
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| {
+            io::Error::new(io::ErrorKind::PermissionDenied, "couldn't decrypt the chains; is the encryption_key right?")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_sealed, open, seal, Key};
+
+    #[test]
+    fn sealed_data_only_opens_with_its_key() {
+        let key = Key::parse("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap();
+        let other = Key::parse("ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=").unwrap();
+        let sealed = seal(&key, b"the quick brown fox").unwrap();
+        assert!(is_sealed(&sealed) && !is_sealed(b"the quick brown fox"));
+        assert_ne!(seal(&key, b"the quick brown fox").unwrap(), sealed);
+        assert_eq!(open(Some(&key), &sealed).unwrap(), b"the quick brown fox");
+        assert!(open(Some(&other), &sealed).is_err());
+        assert!(open(None, &sealed).is_err());
+        assert!(Key::parse("c2hvcnQ=").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::casemap;
+use crate::encrypt;
+use toml::value::{Table, Value};
+use std::collections::HashMap;
+
+/// What every environment variable the bot reads starts with.
+const PREFIX: &str = "MARKOV_";
+
+/// What a server configured only from the environment is called, unless `MARKOV_SERVER` names it.
+const DEFAULT_SERVER: &str = "default";
+
+/// Gets whether any `MARKOV_*` variables are set.
+pub fn configures(vars: &HashMap<String, String>) -> bool {
+    vars.keys().any(|key| is_config_var(key))
+}
+
+/// Gets whether a variable is one of the `MARKOV_*` ones that configure a server. The encryption key's variable isn't,
+/// since it's read for every server when its blobs are loaded, rather than going into one server's config.
+fn is_config_var(key: &str) -> bool {
+    key.starts_with(PREFIX) && key != encrypt::KEY_VAR
+}
+
+/// Applies `MARKOV_*` environment variables on top of a parsed config file, so the bot can be set up without one, like
+/// in a container:
+///
+/// * `MARKOV_ADDRESS`, `MARKOV_PORT`, `MARKOV_SSL`, and `MARKOV_NICK` set the server's fields of the same names.
+/// * `MARKOV_CHANNELS` replaces the channel list with a comma-separated one, where a channel's key follows a colon,
+///   like `#public,#secret:hunter2`. Channels that were already in the file keep the rest of their settings.
+/// * `MARKOV_OWNERS` replaces the owners with a comma-separated list.
+/// * `MARKOV_CHANCE` and `MARKOV_DATA_DIR` set the `chance` and `data_dir` options, and `MARKOV_OPTION_<NAME>` sets any
+///   other option, like `MARKOV_OPTION_SAVE_INTERVAL` for `save_interval`.
+///
+/// These all go to one server: the one named by `MARKOV_SERVER`, the only one in the file, or one named "default" if
+/// the file has none. Other `MARKOV_*` variables are ignored with a warning, except for `MARKOV_BOT_ENCRYPTION_KEY`,
+/// which is left for `encrypt::Key` to read.
+pub fn overlay(config: &mut Value, vars: &HashMap<String, String>) -> Result<(), String> {
+    let mut vars = vars.iter()
+        .filter(|&(key, _)| is_config_var(key))
+        .map(|(key, value)| (&key[PREFIX.len()..], value.as_str()))
+        .collect::<Vec<_>>();
+    // so that any warnings come out in the same order every time
+    vars.sort();
+    let named = vars.iter().find(|&&(key, _)| key == "SERVER").map(|&(_, name)| name.to_string());
+    vars.retain(|&(key, _)| key != "SERVER");
+    if vars.is_empty() {
+        return Ok(());
+    }
+    let servers = table(config, "config")?
+        .entry("servers".to_string())
+        .or_insert_with(|| Value::Table(Table::new()));
+    let servers = table(servers, "servers")?;
+    let name = match (named, servers.len()) {
+        (Some(name), _) => name,
+        (None, 0) => DEFAULT_SERVER.to_string(),
+        (None, 1) => servers.keys().next().unwrap().clone(),
+        (None, _) => return Err("MARKOV_* variables are set, but there's more than one server to apply them to; pick \
+                                 one with MARKOV_SERVER".to_string()),
+    };
+    let server = servers.entry(name.clone()).or_insert_with(|| Value::Table(Table::new()));
+    let server = table(server, &name)?;
+    for (key, value) in vars {
+        match key {
+            "ADDRESS" => set(server, "address", Value::String(value.to_string())),
+            "NICK" => set(server, "nick", Value::String(value.to_string())),
+            "PORT" => {
+                let port = value.parse::<u16>().map_err(|e| format!("invalid MARKOV_PORT {:?}: {}", value, e))?;
+                set(server, "port", Value::Integer(i64::from(port)));
+            }
+            "SSL" => {
+                let ssl = value.parse::<bool>().map_err(|e| format!("invalid MARKOV_SSL {:?}: {}", value, e))?;
+                set(server, "ssl", Value::Boolean(ssl));
+            }
+            "OWNERS" => set(server, "owners", Value::Array(list(value).into_iter().map(Value::String).collect())),
+            "CHANNELS" => set_channels(server, value)?,
+            "CHANCE" => set_option(server, "chance", value)?,
+            "DATA_DIR" => set_option(server, "data_dir", value)?,
+            _ if key.starts_with("OPTION_") && key.len() > "OPTION_".len() => {
+                set_option(server, &key["OPTION_".len()..].to_lowercase(), value)?;
+            }
+            _ => warn!("ignoring unknown environment variable {}{}", PREFIX, key),
+        }
+    }
+    Ok(())
+}
+
+/// Gets a value as a table, or says which one wasn't.
+fn table<'a>(value: &'a mut Value, what: &str) -> Result<&'a mut Table, String> {
+    value.as_table_mut().ok_or_else(|| format!("{} isn't a table", what))
+}
+
+fn set(server: &mut Table, key: &str, value: Value) {
+    server.insert(key.to_string(), value);
+}
+
+fn set_option(server: &mut Table, key: &str, value: &str) -> Result<(), String> {
+    let options = server.entry("options".to_string()).or_insert_with(|| Value::Table(Table::new()));
+    table(options, "options")?.insert(key.to_string(), Value::String(value.to_string()));
+    Ok(())
+}
+
+/// Replaces a server's channels, keeping the settings of those that were already there.
+fn set_channels(server: &mut Table, value: &str) -> Result<(), String> {
+    let mut old = match server.remove("channels") {
+        Some(Value::Array(channels)) => channels,
+        Some(_) => return Err("channels isn't a list of tables".to_string()),
+        None => vec![],
+    };
+    let mut channels = Vec::new();
+    for entry in list(value) {
+        let mut parts = entry.splitn(2, ':');
+        let name = parts.next().unwrap().to_string();
+        let position = old.iter().position(|channel| {
+            channel.get("name").and_then(Value::as_str).map(|n| casemap::eq(n, &name)).unwrap_or(false)
+        });
+        let mut channel = match position {
+            Some(i) => old.remove(i),
+            None => Value::Table(Table::new()),
+        };
+        {
+            let channel = table(&mut channel, &name)?;
+            channel.insert("name".to_string(), Value::String(name.clone()));
+            if let Some(key) = parts.next() {
+                channel.insert("key".to_string(), Value::String(key.to_string()));
+            }
+        }
+        channels.push(channel);
+    }
+    server.insert("channels".to_string(), Value::Array(channels));
+    Ok(())
+}
+
+/// Splits a comma-separated list, leaving out empty entries.
+fn list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ProgramConfig;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn variables_configure_a_server_with_or_without_a_file() {
+        let missing = env::temp_dir().join(format!("markov-bot-env-test-missing-{}.toml", ::std::process::id()));
+        let config = ProgramConfig::load_with(missing.to_str().unwrap(), &vars(&[
+            ("MARKOV_ADDRESS", "irc.example.com"),
+            ("MARKOV_NICK", "markov"),
+            ("MARKOV_CHANNELS", "#one, #two:hunter2"),
+            ("MARKOV_CHANCE", "0.1"),
+            ("MARKOV_DATA_DIR", "/data"),
+        ])).unwrap();
+        let server = &config.servers["default"];
+        assert_eq!(server.address, "irc.example.com");
+        assert_eq!(server.channels.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["#one", "#two"]);
+        assert_eq!(server.channels[1].key.as_deref(), Some("hunter2"));
+        let options = server.options.as_ref().unwrap();
+        assert_eq!(options["chance"], "0.1");
+        assert_eq!(options["data_dir"], "/data");
+
+        let path = env::temp_dir().join(format!("markov-bot-env-test-{}.toml", ::std::process::id()));
+        fs::write(&path, r##"
+            [servers.net]
+            address = "irc.example.net"
+            nick = "bot"
+            [[servers.net.channels]]
+            name = "#one"
+            respond = false
+        "##).unwrap();
+        let config = ProgramConfig::load_with(path.to_str().unwrap(), &vars(&[
+            ("MARKOV_NICK", "other"),
+            ("MARKOV_CHANNELS", "#ONE,#three"),
+            ("MARKOV_OPTION_SAVE_INTERVAL", "30m"),
+        ]));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        let server = &config.servers["net"];
+        assert_eq!((server.address.as_str(), server.nick.as_str()), ("irc.example.net", "other"));
+        assert_eq!(server.channels.len(), 2);
+        assert_eq!(server.channels[0].respond, Some(false));
+        assert_eq!(server.options.as_ref().unwrap()["save_interval"], "30m");
+        assert!(ProgramConfig::load_with(missing.to_str().unwrap(), &vars(&[])).is_err());
+    }
+
+    #[test]
+    fn the_encryption_key_variable_isnt_for_one_server() {
+        let key = ("MARKOV_BOT_ENCRYPTION_KEY", "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        let missing = env::temp_dir().join(format!("markov-bot-env-test-key-missing-{}.toml", ::std::process::id()));
+        assert!(ProgramConfig::load_with(missing.to_str().unwrap(), &vars(&[key])).is_err());
+
+        let path = env::temp_dir().join(format!("markov-bot-env-test-key-{}.toml", ::std::process::id()));
+        fs::write(&path, r##"
+            [servers.one]
+            address = "irc.example.com"
+            nick = "bot"
+            channels = []
+            [servers.two]
+            address = "irc.example.net"
+            nick = "bot"
+            channels = []
+        "##).unwrap();
+        let config = ProgramConfig::load_with(path.to_str().unwrap(), &vars(&[key]));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.servers.len(), 2);
+        assert!(config.servers.values().all(|server| server.options.is_none()));
+    }
+}
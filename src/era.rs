@@ -0,0 +1,81 @@
+use chrono::{DateTime, Datelike, Local};
+use markov_chain::Chain;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+/// Chains trained on only what was said during each era, by channel, then user, then era, like `2019` or `2019-Q2`.
+pub type EraMap = HashMap<String, HashMap<String, BTreeMap<String, Chain<String>>>>;
+
+/// How long each era that chains are kept for is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Buckets {
+    Year,
+    Quarter,
+}
+
+impl Buckets {
+    /// Names the era a moment falls in.
+    pub fn era(&self, at: DateTime<Local>) -> String {
+        match *self {
+            Buckets::Year => at.year().to_string(),
+            Buckets::Quarter => format!("{}-Q{}", at.year(), at.month0() / 3 + 1),
+        }
+    }
+}
+
+impl FromStr for Buckets {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "year" => Ok(Buckets::Year),
+            "quarter" => Ok(Buckets::Quarter),
+            _ => Err(format!("era_buckets must be \"year\" or \"quarter\", not {:?}", s)),
+        }
+    }
+}
+
+/// Gets whether something someone asked for looks like an era: a year, or a year and a quarter, like `2019-Q2`.
+pub fn is_era(s: &str) -> bool {
+    let mut parts = s.splitn(2, '-');
+    let year = parts.next().unwrap_or("");
+    let year_ok = year.len() == 4 && year.chars().all(|c| c.is_ascii_digit());
+    match parts.next() {
+        None => year_ok,
+        Some(quarter) => year_ok && ["Q1", "Q2", "Q3", "Q4"].contains(&quarter.to_uppercase().as_str()),
+    }
+}
+
+/// Gets whether an era chains were kept for falls within an era someone asked for, so that asking for a year takes in
+/// each of its quarters.
+pub fn within(era: &str, asked: &str) -> bool {
+    let asked = asked.to_uppercase();
+    era == asked || era.starts_with(&format!("{}-", asked))
+}
+
+/// Merges a user's chains for every era within the one asked for into one chain to generate from.
+pub fn merged(eras: &BTreeMap<String, Chain<String>>, asked: &str) -> Option<Chain<String>> {
+    let mut within_asked = eras.iter().filter(|&(era, _)| within(era, asked)).map(|(_, chain)| chain);
+    let mut merged = within_asked.next()?.clone();
+    for chain in within_asked {
+        merged.merge(chain);
+    }
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_era, within, Buckets};
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn eras_are_named_and_matched() {
+        let at = Local.with_ymd_and_hms(2019, 5, 4, 12, 0, 0).unwrap();
+        assert_eq!(Buckets::Year.era(at), "2019");
+        assert_eq!(Buckets::Quarter.era(at), "2019-Q2");
+        assert!(is_era("2019") && is_era("2019-q4"));
+        assert!(!is_era("#2019") && !is_era("2019-Q5") && !is_era("19"));
+        assert!(within("2019-Q2", "2019") && within("2019-Q2", "2019-q2") && within("2019", "2019"));
+        assert!(!within("2019-Q2", "2019-Q1") && !within("2019", "2019-Q1") && !within("20190", "2019"));
+    }
+}
@@ -0,0 +1,45 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Something that keeps the bot from starting or running, with enough said about it to go and fix it.
+#[derive(Debug)]
+pub enum Error {
+    /// The config has a value the bot can't use; the message names the option or field.
+    Config(String),
+    /// Reading or writing a file went wrong.
+    Io { what: String, err: io::Error },
+    /// The server couldn't be reached or refused the connection.
+    Connect(String),
+    /// Something the bot sets up for itself, like logging, signal handlers, or an HTTP listener, couldn't be set up.
+    Setup(String),
+}
+
+impl Error {
+    /// Wraps an I/O error with what was being done when it happened, like "could not read blob file foo.cbor".
+    pub fn io<S: Into<String>>(what: S, err: io::Error) -> Self {
+        Error::Io { what: what.into(), err }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Config(ref msg) => write!(f, "invalid config: {}", msg),
+            Error::Io { ref what, ref err } => write!(f, "{}: {}", what, err),
+            Error::Connect(ref msg) | Error::Setup(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io { ref err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
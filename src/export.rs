@@ -0,0 +1,120 @@
+use crate::bot::ChainMap;
+use crate::generate::{self, Sampler};
+use markov_chain::Chain;
+use crate::stats::LifetimeCounters;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use crate::tokenize::Tokenizer;
+
+/// A human-readable dump of every chain in a blob.
+#[derive(Serialize, Debug)]
+pub struct ChainExport {
+    pub order: usize,
+    /// Chains keyed by channel, then by user.
+    pub channels: BTreeMap<String, BTreeMap<String, UserExport>>,
+    pub replies_sent: u64,
+    pub commands_handled: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserExport {
+    /// The chain's own order, which differs from the blob's when the channel has its own order configured.
+    pub order: usize,
+    pub total_weight: u32,
+    /// Messages trained on over the chain's lifetime, which pruning doesn't bring down like it does the weights.
+    pub messages_trained: u64,
+    /// Whether the user asked not to be emulated, in which case their transitions are left out.
+    pub private: bool,
+    /// Transitions ordered from heaviest to lightest.
+    pub transitions: Vec<Transition>,
+}
+
+/// A single weighted link in a chain. `null` in `from` or `to` marks the start or end of a sentence.
+#[derive(Serialize, Debug)]
+pub struct Transition {
+    pub from: Vec<Option<String>>,
+    pub to: Option<String>,
+    pub weight: u32,
+}
+
+impl ChainExport {
+    /// Dumps the given chains, leaving out the transitions of the `private` users, by casefolded nick.
+    pub fn new(order: usize, chains: &ChainMap, lifetime: &LifetimeCounters, private: &HashSet<String>) -> Self {
+        let channels = chains
+            .iter()
+            .map(|(channel, users)| {
+                let users = users
+                    .iter()
+                    .map(|(user, chain)| {
+                        let trained = lifetime.trained_from(channel, user);
+                        (user.clone(), UserExport::new(chain, trained, private.contains(user)))
+                    })
+                    .collect();
+                (channel.clone(), users)
+            })
+            .collect();
+        ChainExport {
+            order,
+            channels,
+            replies_sent: lifetime.replies_sent,
+            commands_handled: lifetime.commands_handled,
+        }
+    }
+}
+
+impl UserExport {
+    fn new(chain: &Chain<String>, messages_trained: u64, private: bool) -> Self {
+        let mut transitions = chain
+            .chain()
+            .iter()
+            .flat_map(|(from, link)| {
+                link.iter().map(move |(to, weight)| Transition {
+                    from: from.clone(),
+                    to: to.clone(),
+                    weight: *weight,
+                })
+            })
+            .collect::<Vec<_>>();
+        transitions.sort_by_key(|t| Reverse(t.weight));
+        let total_weight = transitions.iter().fold(0, |a, t| a + t.weight);
+        if private {
+            transitions.clear();
+        }
+        UserExport {
+            order: chain.order(),
+            total_weight,
+            messages_trained,
+            private,
+            transitions,
+        }
+    }
+}
+
+/// Writes a text corpus for every user under `dir`, as `dir/<channel>/<user>.txt`, except the `private` users, who
+/// asked not to be emulated.
+///
+/// The original messages aren't stored anywhere, so each file is `sentences` lines sampled from the user's chain,
+/// put together the way `tokenizer_for` says the channel's messages are split up.
+pub fn write_corpus<F>(dir: &Path, chains: &ChainMap, private: &HashSet<String>, sentences: usize, tokenizer_for: F)
+    -> io::Result<()>
+    where F: Fn(&str) -> Tokenizer
+{
+    for (channel, users) in chains {
+        let tokenizer = tokenizer_for(channel);
+        let channel_dir = dir.join(channel);
+        fs::create_dir_all(&channel_dir)?;
+        for (user, chain) in users {
+            if chain.is_empty() || private.contains(user) {
+                continue;
+            }
+            let mut file = File::create(channel_dir.join(format!("{}.txt", user)))?;
+            for _ in 0..sentences {
+                writeln!(file, "{}", tokenizer.join(&generate::sentence(chain, &Sampler::plain())))?;
+            }
+        }
+    }
+    Ok(())
+}
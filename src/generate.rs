@@ -0,0 +1,321 @@
+use markov_chain::Chain;
+use rand::{self, Rng};
+use crate::rawchain::RawChain;
+use std::collections::HashMap;
+
+/// Most words a walk through a chain will go before giving up on reaching the end of a sentence.
+const MAX_WALK: usize = 100;
+
+/// The token every message is trained to start with, so that generating can start from where sentences start no
+/// matter the chain's order, instead of from the middle of one. It's a private use character, so nobody types it.
+pub const START: &str = "\u{e000}";
+/// The token every message is trained to end with, so that generating stops where sentences stopped.
+pub const END: &str = "\u{e001}";
+
+/// Lowest temperature generation takes; anything lower would only ever go the likeliest way.
+pub const MIN_TEMPERATURE: f64 = 0.1;
+/// Highest temperature generation takes; anything higher would go every way about as often.
+pub const MAX_TEMPERATURE: f64 = 10.0;
+
+/// How a walk through a chain picks where to go next.
+pub struct Sampler {
+    /// Lower orders to back off to, if backing off is turned on.
+    pub backoff: Option<Backoff>,
+    /// How far to flatten or sharpen the odds of each way the chain can go. At 1 they're as the chain learned them;
+    /// higher evens them out, for more chaotic sentences, and lower favors the likeliest ways, for more predictable
+    /// ones.
+    pub temperature: f64,
+}
+
+impl Sampler {
+    /// Goes by the odds as the chain learned them, without backing off.
+    pub fn plain() -> Self {
+        Sampler { backoff: None, temperature: 1.0 }
+    }
+}
+
+/// Gets whether a token marks where a sentence starts or ends, rather than being something someone said.
+pub fn is_boundary(token: &str) -> bool {
+    token == START || token == END
+}
+
+/// Puts the start and end tokens around a message's tokens, for training on. Any boundary tokens already in the
+/// message are dropped, so nobody can make the bot start or end a sentence in the middle of one.
+pub fn bracket(tokens: Vec<String>) -> Vec<String> {
+    if tokens.is_empty() {
+        return tokens;
+    }
+    let mut bracketed = Vec::with_capacity(tokens.len() + 2);
+    bracketed.push(START.to_string());
+    bracketed.extend(tokens.into_iter().filter(|token| !is_boundary(token)));
+    bracketed.push(END.to_string());
+    bracketed
+}
+
+/// Generates the tokens of a sentence that begins and ends where the sentences the chain learned from did.
+///
+/// Chains trained before there were boundary tokens don't have any, so those are left to `Chain::generate`. A chain
+/// with some of each only ever starts from the newer messages, and stops wherever an older one did.
+pub fn sentence(chain: &Chain<String>, sampler: &Sampler) -> Vec<String> {
+    let starts = chain.chain()
+        .keys()
+        .filter(|state| state.last() == Some(&Some(START.to_string())))
+        .collect::<Vec<_>>();
+    if starts.is_empty() {
+        return chain.generate();
+    }
+    let mut rng = rand::thread_rng();
+    let state = starts[rng.gen_range(0, starts.len())].clone();
+    walk(chain, state, sampler, &mut rng)
+}
+
+/// Generates the tokens of a sentence that picks up from one of the given words, or None if the chain has never seen
+/// any of them.
+///
+/// The sentence starts from a state ending in one of the seed words, chosen at random among all of them, so it reads
+/// like the rest of a thought that went through that word rather than a sentence made up from nothing.
+pub fn seeded(chain: &Chain<String>, seeds: &[String], sampler: &Sampler) -> Option<Vec<String>> {
+    let mut rng = rand::thread_rng();
+    let starts = chain.chain()
+        .keys()
+        .filter(|state| match state.last() {
+            Some(Some(word)) => seeds.iter().any(|seed| seed == word),
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    if starts.is_empty() {
+        return None;
+    }
+    let state = starts[rng.gen_range(0, starts.len())].clone();
+    Some(walk(chain, state, sampler, &mut rng))
+}
+
+/// The links out of each state of a chain, with how many times each was seen.
+type Links = HashMap<Vec<Option<String>>, HashMap<Option<String>, u32>>;
+
+/// The lower orders of a chain, for backing off to when the chain itself hasn't seen enough to say where to go next.
+///
+/// A chain trained at some order holds everything a chain trained at a lower order on the same messages would, since
+/// the lower order's states are just the ends of its states. So rather than every user having a chain at each order,
+/// the lower orders are worked out from the one chain when they're needed, which takes a pass over the whole chain.
+pub struct Backoff {
+    /// The links of each lower order, from order 1 up, keyed by the end of the chain's states.
+    orders: Vec<Links>,
+}
+
+impl Backoff {
+    /// Works out the lower orders of a chain, or None if it's order 1 and has none.
+    pub fn of(chain: &Chain<String>) -> Option<Self> {
+        let order = chain.order();
+        if order < 2 {
+            return None;
+        }
+        let mut orders = vec![HashMap::new(); order - 1];
+        for (state, link) in chain.chain() {
+            for (i, lower) in orders.iter_mut().enumerate() {
+                let merged = lower.entry(state[order - i - 1..].to_vec()).or_insert_with(HashMap::new);
+                for (next, &weight) in link {
+                    let entry = merged.entry(next.clone()).or_insert(0u32);
+                    *entry = entry.saturating_add(weight);
+                }
+            }
+        }
+        Some(Backoff { orders })
+    }
+
+    /// Picks where to go from a state, using the chain's own order as long as it's seen the state often enough to
+    /// trust, and backing off to lower orders otherwise.
+    ///
+    /// How often each order backs off follows Witten-Bell smoothing: a state that went one way the one time it was
+    /// seen backs off half the time, while one seen a hundred times that always went the same way hardly ever does.
+    /// That keeps sentences mostly as coherent as the chain's order, with the lower orders mixing in something new
+    /// where the chain would otherwise just repeat one message word for word.
+    fn pick<R: Rng>(&self, chain: &Chain<String>, state: &[Option<String>], temperature: f64, rng: &mut R)
+        -> Option<Option<String>>
+    {
+        let mut links = Some(chain.chain().get(state))
+            .into_iter()
+            .chain(self.orders.iter().rev().enumerate().map(|(i, lower)| lower.get(&state[i + 1..])))
+            .peekable();
+        while let Some(link) = links.next() {
+            let link = match link {
+                Some(link) if !link.is_empty() => link,
+                _ => continue,
+            };
+            let total = link.values().map(|&weight| u64::from(weight)).sum::<u64>();
+            let ways = link.len() as u64;
+            if links.peek().is_some() && rng.gen_range(0, total + ways) < ways {
+                continue;
+            }
+            return pick(link, temperature, rng).cloned();
+        }
+        None
+    }
+}
+
+/// Walks a chain from a state until a sentence ends, giving back the words along the way, including the ones in the
+/// state it started from, without any boundary tokens.
+fn walk<R: Rng>(chain: &Chain<String>, mut state: Vec<Option<String>>, sampler: &Sampler, rng: &mut R) -> Vec<String> {
+    let mut words = state.iter()
+        .filter_map(|word| word.clone())
+        .filter(|word| !is_boundary(word))
+        .collect::<Vec<_>>();
+    while words.len() < MAX_WALK {
+        let next = match sampler.backoff {
+            Some(ref backoff) => backoff.pick(chain, &state, sampler.temperature, rng),
+            None => chain.chain().get(&state).and_then(|link| pick(link, sampler.temperature, rng)).cloned(),
+        };
+        let next = match next {
+            Some(Some(word)) => {
+                if word == END {
+                    break;
+                }
+                word
+            }
+            _ => break,
+        };
+        state.remove(0);
+        state.push(Some(next.clone()));
+        if !is_boundary(&next) {
+            words.push(next);
+        }
+    }
+    words
+}
+
+/// Merges chains into one that sounds like all of them at once, leaving the originals alone.
+///
+/// Each chain's weights are scaled so that it counts as much as the biggest one, so someone who's said a lot doesn't
+/// drown out someone who's said a little. The chains must all have the same order.
+pub fn blend(chains: &[&Chain<String>]) -> Result<Chain<String>, String> {
+    let order = match chains.first() {
+        Some(chain) => chain.order(),
+        None => return Err("there's nothing to blend".to_string()),
+    };
+    if chains.iter().any(|chain| chain.order() != order) {
+        return Err("chains with different orders can't be blended".to_string());
+    }
+    let totals = chains.iter()
+        .map(|chain| chain.chain().values().flat_map(|link| link.values()).map(|&w| u64::from(w)).sum::<u64>())
+        .collect::<Vec<_>>();
+    let biggest = totals.iter().cloned().max().unwrap_or(0);
+    let mut blended = RawChain::new(order);
+    for (chain, &total) in chains.iter().zip(&totals) {
+        if total == 0 {
+            continue;
+        }
+        let scale = biggest as f64 / total as f64;
+        for (state, link) in chain.chain() {
+            let merged = blended.chain.entry(state.clone()).or_default();
+            for (next, &weight) in link {
+                let scaled = (f64::from(weight) * scale).round().min(f64::from(u32::MAX)) as u32;
+                let entry = merged.entry(next.clone()).or_insert(0);
+                *entry = entry.saturating_add(scaled.max(1));
+            }
+        }
+    }
+    blended.into_chain()
+}
+
+/// Picks where a chain goes next from a link, weighted by how often each way was seen, with the weights raised to the
+/// power of one over the temperature.
+fn pick<'a, R: Rng>(link: &'a HashMap<Option<String>, u32>, temperature: f64, rng: &mut R)
+    -> Option<&'a Option<String>>
+{
+    if temperature != 1.0 {
+        return pick_tempered(link, temperature, rng);
+    }
+    let total = link.values().map(|&weight| u64::from(weight)).sum::<u64>();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = rng.gen_range(0, total);
+    for (next, &weight) in link {
+        if roll < u64::from(weight) {
+            return Some(next);
+        }
+        roll -= u64::from(weight);
+    }
+    None
+}
+
+fn pick_tempered<'a, R: Rng>(link: &'a HashMap<Option<String>, u32>, temperature: f64, rng: &mut R)
+    -> Option<&'a Option<String>>
+{
+    // scaling by the heaviest weight first keeps low temperatures from overflowing to infinity
+    let heaviest = f64::from(link.values().cloned().max().unwrap_or(0));
+    if heaviest == 0.0 {
+        return None;
+    }
+    let tempered = link.iter()
+        .filter(|&(_, &weight)| weight > 0)
+        .map(|(next, &weight)| (next, (f64::from(weight) / heaviest).powf(1.0 / temperature)))
+        .collect::<Vec<_>>();
+    let total = tempered.iter().map(|&(_, weight)| weight).sum::<f64>();
+    let mut roll = rng.next_f64() * total;
+    for &(next, weight) in &tempered {
+        if roll < weight {
+            return Some(next);
+        }
+        roll -= weight;
+    }
+    // rounding can leave a sliver of the roll over at the end
+    tempered.last().map(|&(next, _)| next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bracket, sentence, Backoff, Sampler, END, START};
+    use std::collections::HashSet;
+    use markov_chain::Chain;
+
+    fn tokens(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn higher_orders_start_and_end_with_sentences() {
+        let mut chain = Chain::new(3);
+        chain.train(bracket(tokens("the quick brown fox jumps")));
+        chain.train(bracket(tokens("a quick brown fox sleeps")));
+        for _ in 0..20 {
+            let words = sentence(&chain, &Sampler::plain());
+            assert!(words[0] == "the" || words[0] == "a", "{:?}", words);
+            assert!(words.last().unwrap() == "jumps" || words.last().unwrap() == "sleeps", "{:?}", words);
+        }
+        // nobody gets to end a sentence early by saying the end token
+        assert_eq!(bracket(tokens(&format!("hi {} there", END))), vec![START, "hi", "there", END]);
+    }
+
+    #[test]
+    fn backoff_mixes_in_lower_orders() {
+        let mut chain = Chain::new(2);
+        chain.train(bracket(tokens("a b c")));
+        chain.train(bracket(tokens("x b d")));
+        let plain = (0..200).map(|_| sentence(&chain, &Sampler::plain()).join(" ")).collect::<HashSet<_>>();
+        assert_eq!(plain, ["a b c", "x b d"].iter().map(|s| s.to_string()).collect());
+        // backing off to order 1 at "b" lets either message's ending follow either start
+        let sampler = Sampler { backoff: Backoff::of(&chain), temperature: 1.0 };
+        let blended = (0..200).map(|_| sentence(&chain, &sampler).join(" ")).collect::<HashSet<_>>();
+        assert!(blended.contains("a b d"), "{:?}", blended);
+        assert!(blended.iter().all(|s| s.ends_with(" c") || s.ends_with(" d")), "{:?}", blended);
+        assert!(Backoff::of(&Chain::new(1)).is_none());
+    }
+
+    #[test]
+    fn temperature_sharpens_and_flattens() {
+        let mut chain = Chain::new(1);
+        for _ in 0..9 {
+            chain.train(bracket(tokens("hello there")));
+        }
+        chain.train(bracket(tokens("hello world")));
+        let count = |temperature| {
+            let sampler = Sampler { backoff: None, temperature };
+            (0..1000).filter(|_| sentence(&chain, &sampler).join(" ") == "hello world").count()
+        };
+        // at 0.1 "world" has (1/9)^10 of the odds of "there", which never comes up; at 10 the two are nearly even
+        assert_eq!(count(0.1), 0);
+        let flat = count(10.0);
+        assert!(flat > 350 && flat < 650, "{}", flat);
+    }
+}
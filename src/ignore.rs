@@ -0,0 +1,100 @@
+use crate::casemap;
+use regex::{self, Regex};
+
+/// A single entry in an ignore list.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// A plain nick.
+    Nick(String),
+    /// A `nick!user@host` glob, where `*` matches anything and `?` matches any one character.
+    Mask(Regex),
+    /// A regex written between slashes, like `/^\w+bot!/`, searched for anywhere in the prefix.
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.len() > 1 && s.starts_with('/') && s.ends_with('/') {
+            Regex::new(&s[1..s.len() - 1])
+                .map(Pattern::Regex)
+                .map_err(|e| format!("invalid ignore regex {}: {}", s, e))
+        } else if s.contains(|c| "!@*?".contains(c)) {
+            let mut re = String::from("^");
+            for c in casemap::fold(s).chars() {
+                match c {
+                    '*' => re.push_str(".*"),
+                    '?' => re.push('.'),
+                    c => re.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            re.push('$');
+            Regex::new(&re)
+                .map(Pattern::Mask)
+                .map_err(|e| format!("invalid ignore mask {}: {}", s, e))
+        } else {
+            Ok(Pattern::Nick(s.to_string()))
+        }
+    }
+
+    /// Checks a user against this pattern. `prefix` is the full `nick!user@host`, or just the nick if that's all
+    /// that's known, in which case masks that look at the host can't match.
+    pub fn matches(&self, nick: &str, prefix: &str) -> bool {
+        match *self {
+            Pattern::Nick(ref n) => casemap::eq(n, nick),
+            Pattern::Mask(ref re) => re.is_match(&casemap::fold(prefix)),
+            Pattern::Regex(ref re) => re.is_match(prefix),
+        }
+    }
+}
+
+/// A list of nicks, masks, and regexes to ignore.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreList {
+    /// Parses every entry of a list, failing on the first bad one.
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let patterns = entries
+            .iter()
+            .map(|s| Pattern::parse(s.as_ref().trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(IgnoreList { patterns })
+    }
+
+    pub fn matches(&self, nick: &str, prefix: &str) -> bool {
+        self.patterns.iter().any(|p| p.matches(nick, prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IgnoreList;
+
+    #[test]
+    fn nicks_match_case_insensitively() {
+        let list = IgnoreList::parse(&["SomeBot"]).unwrap();
+        assert!(list.matches("somebot", "somebot!bot@example.com"));
+        assert!(list.matches("SOMEBOT", "SOMEBOT"));
+        assert!(!list.matches("somebot_", "somebot_!bot@example.com"));
+    }
+
+    #[test]
+    fn masks_match_the_whole_prefix() {
+        let list = IgnoreList::parse(&["*!*@*.bots.example.com", "relay?!*@*"]).unwrap();
+        assert!(list.matches("alice", "alice!a@one.bots.example.com"));
+        assert!(list.matches("relay1", "Relay1!r@example.com"));
+        assert!(!list.matches("relay12", "relay12!r@example.com"));
+        // without a host, a mask that needs one can't match
+        assert!(!list.matches("alice", "alice"));
+    }
+
+    #[test]
+    fn regexes_search_the_prefix() {
+        let list = IgnoreList::parse(&[r"/^\w+bot!/"]).unwrap();
+        assert!(list.matches("chatbot", "chatbot!x@example.com"));
+        assert!(!list.matches("bottom", "bottom!x@example.com"));
+        assert!(IgnoreList::parse(&["/(/"]).is_err());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::generate::{END, START};
+use markov_chain::Chain;
+use crate::rawchain::RawChain;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, NO_PARAMS};
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+/// What markovify starts and ends its states with.
+const MARKOVIFY_BEGIN: &str = "___BEGIN__";
+const MARKOVIFY_END: &str = "___END__";
+
+/// Formats of log files that can be imported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// One message per line, with no timestamp or nick.
+    Plain,
+    /// WeeChat's logger plugin: `2017-06-01 12:34:56\t@nick\tmessage`.
+    Weechat,
+    /// irssi: `12:34 <@nick> message`.
+    Irssi,
+    /// ZNC's log module: `[12:34:56] <nick> message`.
+    Znc,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "plain" => Ok(LogFormat::Plain),
+            "weechat" => Ok(LogFormat::Weechat),
+            "irssi" => Ok(LogFormat::Irssi),
+            "znc" => Ok(LogFormat::Znc),
+            _ => Err(format!("unknown log format {}", s)),
+        }
+    }
+}
+
+/// Formats of other markov bots' corpora that can be imported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CorpusFormat {
+    /// Python markovify's JSON, from `Text.to_json()` or `Chain.to_json()`.
+    Markovify,
+    /// A Hailo brain, which is a SQLite database.
+    Hailo,
+}
+
+impl FromStr for CorpusFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "markovify" => Ok(CorpusFormat::Markovify),
+            "hailo" => Ok(CorpusFormat::Hailo),
+            _ => Err(format!("unknown corpus format {}", s)),
+        }
+    }
+}
+
+/// Anything that can be imported: an IRC log, or another bot's corpus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Log(LogFormat),
+    Corpus(CorpusFormat),
+}
+
+impl Format {
+    /// Gets whether what's imported has no nicks of its own, so it all has to go to one user.
+    pub fn needs_user(&self) -> bool {
+        match *self {
+            Format::Log(LogFormat::Plain) | Format::Corpus(_) => true,
+            Format::Log(_) => false,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        s.parse::<LogFormat>()
+            .map(Format::Log)
+            .or_else(|_| s.parse::<CorpusFormat>().map(Format::Corpus))
+            .map_err(|_| format!("unknown format {}", s))
+    }
+}
+
+/// What was read out of another bot's corpus.
+pub enum Corpus {
+    /// The sentences it learned from, which are trained on the way lines from a log are.
+    Sentences(Vec<String>),
+    /// Its transitions, for corpora that didn't keep their sentences, already laid out the way this bot's chains are.
+    /// These can only be read into chains of the order the other bot used.
+    Chain(Chain<String>),
+}
+
+/// Reads another bot's corpus, for chains of the given order.
+pub fn read_corpus(format: CorpusFormat, path: &str, order: usize) -> Result<Corpus, String> {
+    match format {
+        CorpusFormat::Markovify => {
+            let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            read_markovify(&json, order)
+        }
+        CorpusFormat::Hailo => {
+            let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(|e| e.to_string())?;
+            read_hailo(&conn, order).map(Corpus::Chain)
+        }
+    }
+}
+
+/// Reads markovify's JSON, which is either a whole `Text` with its `state_size`, its `chain` (as a JSON string of its
+/// own), and usually the `parsed_sentences` it was built from, or just a `Chain`'s list of `[state, {next: count}]`.
+fn read_markovify(json: &str, order: usize) -> Result<Corpus, String> {
+    let mut value = serde_json::from_str::<Value>(json).map_err(|e| e.to_string())?;
+    if let Some(sentences) = value.get("parsed_sentences").and_then(Value::as_array) {
+        return sentences
+            .iter()
+            .map(|words| {
+                let words = words.as_array().ok_or("a parsed sentence isn't a list of words")?;
+                let words = words.iter().map(Value::as_str).collect::<Option<Vec<_>>>()
+                    .ok_or("a parsed sentence has something besides words in it")?;
+                Ok(words.join(" "))
+            })
+            .collect::<Result<Vec<_>, &str>>()
+            .map(Corpus::Sentences)
+            .map_err(str::to_string);
+    }
+    if let Some(chain) = value.get("chain").cloned() {
+        value = match chain {
+            Value::String(chain) => serde_json::from_str(&chain).map_err(|e| e.to_string())?,
+            chain => chain,
+        };
+    }
+    let model = value.as_array().ok_or("this doesn't look like markovify's JSON")?;
+    let mut transitions = Vec::new();
+    for entry in model {
+        let (state, links) = match entry.as_array().map(Vec::as_slice) {
+            Some(&[Value::Array(ref state), Value::Object(ref links)]) => (state, links),
+            _ => return Err("a transition in the chain isn't a [state, {next: count}] pair".to_string()),
+        };
+        let state = state
+            .iter()
+            .map(|word| match word.as_str() {
+                Some(MARKOVIFY_BEGIN) => Ok(None),
+                Some(word) => Ok(Some(word.to_string())),
+                None => Err("a state has something besides words in it".to_string()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        check_order("markovify model", state.len(), order)?;
+        for (next, count) in links {
+            let count = count.as_u64().ok_or("a transition's count isn't a number")?;
+            let next = if next == MARKOVIFY_END { None } else { Some(next.clone()) };
+            transitions.push((state.clone(), next, count as u32));
+        }
+    }
+    from_transitions(order, transitions).map(Corpus::Chain)
+}
+
+/// Reads a Hailo brain's transitions.
+///
+/// Hailo only remembers the state each sentence first fills up, with the boundary token where the sentence started,
+/// so the states before that, which start with more of the boundary, are worked out from those.
+fn read_hailo(conn: &Connection, order: usize) -> Result<Chain<String>, String> {
+    let hailo_order = conn
+        .query_row("SELECT text FROM info WHERE attribute = 'markov_order'", NO_PARAMS, |row| row.get::<_, String>(0))
+        .map_err(|e| format!("this doesn't look like a Hailo brain: {}", e))?;
+    let hailo_order = hailo_order.parse::<usize>().map_err(|e| e.to_string())?;
+    check_order("Hailo brain", hailo_order, order)?;
+    let boundary = conn
+        .query_row("SELECT id FROM token WHERE spacing = 0 AND text = ''", NO_PARAMS, |row| row.get::<_, i64>(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    let mut tokens = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, text FROM token").map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?))).map_err(|e| e.to_string())?;
+        for row in rows {
+            let (id, text): (i64, String) = row.map_err(|e| e.to_string())?;
+            tokens.insert(id, if Some(id) == boundary { None } else { Some(text) });
+        }
+    }
+    let token = |id: i64| tokens.get(&id).cloned().ok_or_else(|| format!("there's no token {}", id));
+
+    let columns = (0..order).map(|i| format!("e.token{}_id", i)).collect::<Vec<_>>().join(", ");
+    let mut stmt = conn
+        .prepare(&format!("SELECT {}, n.token_id, n.count FROM next_token n JOIN expr e ON e.id = n.expr_id", columns))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(NO_PARAMS, |row| {
+            let state = (0..order).map(|i| row.get::<_, i64>(i)).collect::<Result<Vec<_>, _>>()?;
+            Ok((state, row.get::<_, i64>(order)?, row.get::<_, i64>(order + 1)?))
+        })
+        .map_err(|e| e.to_string())?;
+    let mut transitions = Vec::new();
+    for row in rows {
+        let (state, next, count) = row.map_err(|e| e.to_string())?;
+        let state = state.into_iter().map(&token).collect::<Result<Vec<_>, _>>()?;
+        let (next, count) = (token(next)?, count as u32);
+        if state[0].is_none() {
+            // the states before this one in the same sentence, back to one that's all boundary
+            for filled in 0..order - 1 {
+                let mut earlier = vec![None; order - filled];
+                earlier.extend(state[1..=filled].iter().cloned());
+                transitions.push((earlier, state[filled + 1].clone(), count));
+            }
+        }
+        transitions.push((state, next, count));
+    }
+    from_transitions(order, transitions)
+}
+
+/// Checks that a corpus without its sentences has the same order as the chains it's going into.
+fn check_order(what: &str, theirs: usize, order: usize) -> Result<(), String> {
+    if theirs == order {
+        Ok(())
+    } else {
+        Err(format!("the {} has order {}, but the chains here have order {}; without the sentences it learned \
+                     from, it can only be imported into chains of order {}", what, theirs, order, theirs))
+    }
+}
+
+/// Builds a chain out of another bot's transitions, laid out the way markovify's are: every state is `order` words
+/// long with `None`s standing in for the start of the sentence, and a `None` next word is where it ends.
+///
+/// This bot's chains are trained on sentences with start and end tokens around them, so each state's last `None`
+/// becomes the start token, the end of each sentence becomes the end token, and the steps on either side of those
+/// that the other bot has no need for are filled in, leaving what training on the same sentences would have.
+fn from_transitions(order: usize, transitions: Vec<(Vec<Option<String>>, Option<String>, u32)>)
+    -> Result<Chain<String>, String>
+{
+    let mut raw = RawChain::new(order);
+    {
+        let mut add = |state: Vec<Option<String>>, next: Option<String>, weight: u32| {
+            *raw.chain.entry(state).or_default().entry(next).or_insert(0) += weight;
+        };
+        for (state, next, weight) in transitions {
+            let started = state.iter().take_while(|word| word.is_none()).count();
+            let mut ours = state;
+            if started > 0 {
+                ours[started - 1] = Some(START.to_string());
+            }
+            if started == order {
+                add(vec![None; order], Some(START.to_string()), weight);
+            }
+            if next.is_none() {
+                let mut ended = ours[1..].to_vec();
+                ended.push(Some(END.to_string()));
+                add(ended, None, weight);
+            }
+            add(ours, Some(next.unwrap_or_else(|| END.to_string())), weight);
+        }
+    }
+    raw.into_chain()
+}
+
+pub struct LogLine<'a> {
+    /// The nick that sent the message, if the format records one.
+    pub nick: Option<&'a str>,
+    pub message: &'a str,
+}
+
+impl LogFormat {
+    /// Parses a single line of a log.
+    ///
+    /// Anything that isn't a plain chat message, like joins, parts, actions, and day-change markers, is skipped by
+    /// returning None.
+    pub fn parse_line<'a>(&self, line: &'a str) -> Option<LogLine<'a>> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (nick, message) = match *self {
+            LogFormat::Plain => (None, line),
+            LogFormat::Weechat => {
+                let mut fields = line.splitn(3, '\t');
+                let _timestamp = fields.next()?;
+                let nick = fields.next()?;
+                let message = fields.next()?;
+                (Some(nick), message)
+            }
+            LogFormat::Irssi => {
+                let start = line.find(' ')?;
+                let (nick, message) = bracketed(&line[start + 1..])?;
+                (Some(nick), message)
+            }
+            LogFormat::Znc => {
+                if !line.starts_with('[') {
+                    return None;
+                }
+                let start = line.find("] ")?;
+                let (nick, message) = bracketed(&line[start + 2..])?;
+                (Some(nick), message)
+            }
+        };
+
+        let nick = match nick.map(strip_mode) {
+            Some(nick) if !is_nick(nick) => return None,
+            nick => nick,
+        };
+        if message.trim().is_empty() {
+            None
+        } else {
+            Some(LogLine { nick, message })
+        }
+    }
+}
+
+/// Splits `<@nick> message` into the nick and the message.
+fn bracketed(s: &str) -> Option<(&str, &str)> {
+    if !s.starts_with('<') {
+        return None;
+    }
+    let end = s.find("> ")?;
+    Some((&s[1..end], &s[end + 2..]))
+}
+
+/// Strips channel mode prefixes like `@` and `+`, along with irssi's padding, from the front of a nick.
+fn strip_mode(nick: &str) -> &str {
+    nick.trim_start_matches(|c| "~&@%+ ".contains(c))
+}
+
+/// Gets whether a string could be an IRC nick, which is how WeeChat's `-->` and `--` event markers get told apart
+/// from messages.
+fn is_nick(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+        && s.chars().all(|c| c.is_alphanumeric() || "[]\\`_^{|}-".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_hailo, read_markovify, Corpus};
+    use crate::generate::bracket;
+    use markov_chain::Chain;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    /// What training a chain on the same sentence would have left.
+    fn trained(order: usize) -> Chain<String> {
+        let mut chain = Chain::new(order);
+        chain.train(bracket(vec!["the".to_string(), "cat".to_string(), "sat".to_string()]));
+        chain
+    }
+
+    #[test]
+    fn markovify_chains_read_as_if_trained_here() {
+        let model = r#"[[["___BEGIN__", "___BEGIN__"], {"the": 1}], [["___BEGIN__", "the"], {"cat": 1}],
+                        [["the", "cat"], {"sat": 1}], [["cat", "sat"], {"___END__": 1}]]"#;
+        let text = format!(r#"{{"state_size": 2, "chain": {:?}}}"#, model);
+        match read_markovify(&text, 2).unwrap() {
+            Corpus::Chain(chain) => assert_eq!(chain.chain(), trained(2).chain()),
+            Corpus::Sentences(_) => panic!("read sentences out of a bare chain"),
+        }
+        assert!(read_markovify(model, 3).is_err());
+
+        let text = r#"{"state_size": 2, "chain": "[]", "parsed_sentences": [["the", "cat", "sat"], ["hi"]]}"#;
+        match read_markovify(text, 3).unwrap() {
+            Corpus::Sentences(sentences) => assert_eq!(sentences, vec!["the cat sat", "hi"]),
+            Corpus::Chain(_) => panic!("read a chain when there were sentences"),
+        }
+    }
+
+    #[test]
+    fn hailo_brains_read_as_if_trained_here() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE info (attribute TEXT, text TEXT);
+             CREATE TABLE token (id INTEGER, spacing INTEGER, text TEXT, count INTEGER);
+             CREATE TABLE expr (id INTEGER, token0_id INTEGER, token1_id INTEGER);
+             CREATE TABLE next_token (id INTEGER, expr_id INTEGER, token_id INTEGER, count INTEGER);
+             INSERT INTO info VALUES ('markov_order', '2');
+             INSERT INTO token VALUES (1, 0, '', 0), (2, 0, 'the', 1), (3, 0, 'cat', 1), (4, 0, 'sat', 1);
+             INSERT INTO expr VALUES (1, 1, 2), (2, 2, 3), (3, 3, 4);
+             INSERT INTO next_token VALUES (1, 1, 3, 1), (2, 2, 4, 1), (3, 3, 1, 1);",
+        ).unwrap();
+        assert_eq!(read_hailo(&conn, 2).unwrap().chain(), trained(2).chain());
+        assert!(read_hailo(&conn, 3).is_err());
+        conn.execute("DELETE FROM info", NO_PARAMS).unwrap();
+        assert!(read_hailo(&conn, 2).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::casemap;
+use crate::config;
+use markov_chain::Chain;
+use crate::rawchain::RawChain;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Seconds that trained messages are remembered for, so they can be scrubbed.
+pub const DEFAULT_SCRUB_WINDOW: u64 = 3600;
+
+/// When a message was trained on, and its tokens.
+type Entry = (Instant, Vec<String>);
+
+/// The tokens of every message trained on lately, so that a user's recent messages can be taken back out of their
+/// chain after a spam incident.
+///
+/// Nothing here is saved; messages older than the scrub window are forgotten, and a window of 0 keeps nothing.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    window: Duration,
+    /// When each message was trained on and its tokens, oldest first, keyed by casefolded (channel, nick).
+    entries: HashMap<(String, String), VecDeque<Entry>>,
+}
+
+impl Journal {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(Journal {
+            window: Duration::from_secs(config::option(options, "scrub_window")?.unwrap_or(DEFAULT_SCRUB_WINDOW)),
+            entries: HashMap::new(),
+        })
+    }
+
+    /// Takes the window from a freshly built journal, keeping what's been recorded that's still inside it.
+    pub fn reconfigure(&mut self, fresh: Journal) {
+        self.window = fresh.window;
+        self.expire(Instant::now());
+    }
+
+    /// Gets how far back messages are remembered.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Remembers the tokens of a message a user was just trained on.
+    pub fn record(&mut self, channel: &str, user: &str, tokens: Vec<String>, now: Instant) {
+        if self.window.as_secs() == 0 {
+            return;
+        }
+        self.expire(now);
+        self.entries
+            .entry((casemap::fold(channel), casemap::fold(user)))
+            .or_default()
+            .push_back((now, tokens));
+    }
+
+    /// Takes out the messages a user was trained on in a channel within `since` of now, newest last.
+    pub fn take(&mut self, channel: &str, user: &str, since: Duration, now: Instant) -> Vec<Vec<String>> {
+        self.expire(now);
+        let key = (casemap::fold(channel), casemap::fold(user));
+        let entries = match self.entries.get_mut(&key) {
+            Some(entries) => entries,
+            None => return Vec::new(),
+        };
+        let keep = entries.iter().take_while(|&&(when, _)| now.duration_since(when) > since).count();
+        let taken = entries.split_off(keep).into_iter().map(|(_, tokens)| tokens).collect();
+        if entries.is_empty() {
+            self.entries.remove(&key);
+        }
+        taken
+    }
+
+    /// Moves everything recorded for one nick over to another, for when they turn out to be the same person.
+    pub fn merge_user(&mut self, old: &str, new: &str) {
+        let (old, new) = (casemap::fold(old), casemap::fold(new));
+        let moved = self.entries
+            .keys()
+            .filter(|&(_, user)| *user == old)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in moved {
+            let mut entries = self.entries.remove(&key).unwrap().into_iter().collect::<Vec<_>>();
+            let merged = self.entries.entry((key.0, new.clone())).or_default();
+            entries.extend(merged.drain(..));
+            entries.sort_by_key(|&(when, _)| when);
+            merged.extend(entries);
+        }
+    }
+
+    /// Forgets everything recorded for a user in a channel.
+    pub fn forget(&mut self, channel: &str, user: &str) {
+        self.entries.remove(&(casemap::fold(channel), casemap::fold(user)));
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let window = self.window;
+        for entries in self.entries.values_mut() {
+            while entries.front().map(|&(when, _)| now.duration_since(when) > window).unwrap_or(false) {
+                entries.pop_front();
+            }
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+    }
+}
+
+/// Takes messages back out of a chain, lowering the weight of every transition training on them added, and dropping
+/// transitions that get to nothing.
+///
+/// This walks each message the same way training does, so untraining a message that was trained leaves the chain as
+/// it was before.
+pub fn untrain(chain: &Chain<String>, messages: &[Vec<String>]) -> Result<Chain<String>, String> {
+    let mut raw = RawChain::new(chain.order());
+    raw.chain = chain.chain().clone();
+    let mut lower = |state: &[Option<String>], next: Option<String>| {
+        if let Some(link) = raw.chain.get_mut(state) {
+            let gone = match link.get_mut(&next) {
+                Some(weight) => {
+                    *weight = weight.saturating_sub(1);
+                    *weight == 0
+                }
+                None => false,
+            };
+            if gone {
+                link.remove(&next);
+            }
+        }
+    };
+    for tokens in messages.iter().filter(|tokens| !tokens.is_empty()) {
+        let mut state = vec![None; chain.order()];
+        for token in tokens {
+            lower(&state, Some(token.clone()));
+            state.remove(0);
+            state.push(Some(token.clone()));
+        }
+        lower(&state, None);
+    }
+    raw.chain.retain(|_, link| !link.is_empty());
+    raw.into_chain()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{untrain, Journal};
+    use markov_chain::Chain;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn tokens(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn takes_only_recent_messages() {
+        let options = [("scrub_window".to_string(), "600".to_string())].iter().cloned().collect::<HashMap<_, _>>();
+        let mut journal = Journal::from_options(&options).unwrap();
+        let start = Instant::now();
+        journal.record("#a", "alice", tokens("old news"), start);
+        journal.record("#a", "Alice", tokens("buy now"), start + Duration::from_secs(300));
+        journal.record("#b", "alice", tokens("elsewhere"), start + Duration::from_secs(300));
+        let now = start + Duration::from_secs(360);
+        assert_eq!(journal.take("#A", "alice", Duration::from_secs(120), now), vec![tokens("buy now")]);
+        assert!(journal.take("#a", "alice", Duration::from_secs(120), now).is_empty());
+        // past the window, there's nothing left to take
+        assert!(journal.take("#a", "alice", Duration::from_secs(3600), start + Duration::from_secs(700)).is_empty());
+    }
+
+    #[test]
+    fn untraining_undoes_training() {
+        let mut chain = Chain::new(2);
+        chain.train(tokens("the quick brown fox"));
+        let before = chain.clone();
+        chain.train(tokens("the quick spam"));
+        chain.train(tokens("buy cheap watches"));
+        let after = untrain(&chain, &[tokens("the quick spam"), tokens("buy cheap watches")]).unwrap();
+        assert_eq!(after.chain(), before.chain());
+    }
+}
@@ -0,0 +1,167 @@
+use ansi_term::{Colour, Style};
+use chrono::Local;
+use env_logger::LogBuilder;
+use crate::error::{self, Error};
+use log::{LogLevel, LogLevelFilter, LogRecord};
+use std::cell::RefCell;
+use std::env;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Logs a message along with the kind of event it's about, which gets a field of its own in JSON logs.
+///
+/// `event!(info, "forget", "forgot {} in {}", user, channel)` is `info!("forgot {} in {}", user, channel)` with an
+/// event type of "forget".
+macro_rules! event {
+    ($level:ident, $event:expr, $($arg:tt)+) => {{
+        let _event = crate::logging::Scope::event($event);
+        $level!($($arg)+);
+    }};
+}
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Colored lines for reading in a terminal.
+    Text,
+    /// One JSON object per line, for shipping somewhere that can search them.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("log format must be \"text\" or \"json\", not {:?}", s)),
+        }
+    }
+}
+
+/// Whether log lines are written as JSON, which can change after the logger starts, once the config is loaded.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Fields attached to every line logged on a thread while they're set.
+#[derive(Clone, Default)]
+struct Fields {
+    server: Option<String>,
+    channel: Option<String>,
+    event: Option<&'static str>,
+}
+
+thread_local! {
+    static FIELDS: RefCell<Fields> = RefCell::new(Fields::default());
+}
+
+tokio::task_local! {
+    /// The server a task is for, since a task can move between threads whenever it waits on something.
+    static SERVER: String;
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: String,
+    module: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'static str>,
+}
+
+/// Initializes the global logger.
+///
+/// `level` sets the default filter; RUST_LOG, if present, is applied on top of it.
+pub fn init(level: Option<&str>, format: Format) -> error::Result<()> {
+    set_format(format);
+    let mut builder = LogBuilder::new();
+    builder.filter(None, level
+        .and_then(|l| l.parse::<LogLevelFilter>().ok())
+        .unwrap_or(LogLevelFilter::Warn));
+    if let Ok(env_var) = env::var("RUST_LOG") {
+        builder.parse(env_var.as_str());
+    }
+    builder.format(|record| if JSON.load(Ordering::Relaxed) { json_line(record) } else { text_line(record) })
+        .init()
+        .map_err(|e| Error::Setup(format!("could not set up logging: {}", e)))
+}
+
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// Tags everything this thread logs from now on with a server name.
+pub fn set_server(name: &str) {
+    FIELDS.with(|f| f.borrow_mut().server = Some(name.to_string()));
+}
+
+/// Tags everything a task logs with a server name; `set_server` is for threads.
+pub fn in_server<F: Future>(name: &str, future: F) -> impl Future<Output = F::Output> {
+    SERVER.scope(name.to_string(), future)
+}
+
+/// Sets a field for as long as it's alive, putting back what was there before when it's dropped.
+pub struct Scope {
+    previous: Fields,
+}
+
+impl Scope {
+    /// Tags everything logged in this scope with a channel.
+    pub fn channel(channel: &str) -> Self {
+        Scope::with(|f| f.channel = Some(channel.to_string()))
+    }
+
+    /// Tags everything logged in this scope with an event type; see `event!`.
+    pub fn event(event: &'static str) -> Self {
+        Scope::with(|f| f.event = Some(event))
+    }
+
+    fn with<F: FnOnce(&mut Fields)>(set: F) -> Self {
+        FIELDS.with(|f| {
+            let previous = f.borrow().clone();
+            set(&mut f.borrow_mut());
+            Scope { previous }
+        })
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.clone();
+        FIELDS.with(|f| *f.borrow_mut() = previous);
+    }
+}
+
+fn text_line(record: &LogRecord) -> String {
+    let color = match record.level() {
+        LogLevel::Error => Colour::Red.bold(),
+        LogLevel::Warn => Style::new().fg(Colour::Yellow),
+        LogLevel::Info => Style::new().fg(Colour::Green),
+        LogLevel::Debug => Style::new().fg(Colour::Blue),
+        _ => Colour::White.dimmed(),
+    };
+    let now = Local::now();
+    format!("{}", color.paint(format!("{time} {level} [{location}] {msg}", time=now.format("%Y-%m-%d %H:%M:%S"),
+                                      location=record.location().module_path(), level=record.level(),
+                                      msg=record.args())))
+}
+
+fn json_line(record: &LogRecord) -> String {
+    let fields = FIELDS.with(|f| f.borrow().clone());
+    let line = JsonLine {
+        timestamp: Local::now().to_rfc3339(),
+        level: record.level().to_string(),
+        module: record.location().module_path(),
+        message: record.args().to_string(),
+        server: fields.server.or_else(|| SERVER.try_with(String::clone).ok()),
+        channel: fields.channel,
+        event: fields.event,
+    };
+    serde_json::to_string(&line).unwrap()
+}
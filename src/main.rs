@@ -4,183 +4,795 @@ extern crate log;
 extern crate env_logger;
 extern crate ansi_term;
 extern crate irc;
-extern crate ctrlc;
+extern crate tokio;
+extern crate futures;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_cbor as cbor;
+extern crate serde_json;
 extern crate rand;
 extern crate chrono;
+extern crate chrono_tz;
+extern crate toml;
+extern crate toml_edit;
+extern crate regex;
+extern crate tiny_http;
+extern crate unicode_segmentation;
+extern crate base64;
+extern crate crc32fast;
+extern crate flate2;
+extern crate zstd;
+extern crate ureq;
+extern crate hmac;
+extern crate sha2;
+extern crate hex;
+extern crate chacha20poly1305;
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate rusqlite;
 
+#[macro_use]
+mod logging;
+mod activity;
+mod allchain;
+mod altnick;
+mod atomic;
+mod blobstore;
 mod bot;
+mod botdetect;
+mod casemap;
+mod checksum;
+mod chat;
+mod cli;
+mod colors;
+mod compress;
+mod dashboard;
+mod duration;
+mod encrypt;
+mod error;
+mod environment;
+mod era;
+mod config;
+mod cooldown;
+mod export;
+mod generate;
+mod ignore;
+mod import;
+mod journal;
+mod metrics;
+mod nickserv;
+mod output;
+mod playback;
+mod preprocess;
+mod presence;
+mod provenance;
+mod prune;
+mod quality;
+mod quiet;
+mod quote;
+mod rawchain;
+mod ratelimit;
+mod reconnect;
+mod repl;
+mod reply;
+mod runtime;
+mod s3;
+mod sasl;
+mod schedule;
+mod sendqueue;
+mod server;
+mod shutdown;
+mod signals;
+mod snapshot;
+mod spam;
+mod split;
+mod systemd;
+mod sqlite;
+mod stats;
+mod tokenize;
+mod wordlist;
 
-use bot::IrcBot;
+use crate::blobstore::BlobStore;
+use crate::bot::{IrcBot, BlobFile};
+use crate::compress::Compression;
+use crate::error::Error;
+use crate::config::{ProgramConfig, Server as ServerConfig};
+use crate::dashboard::Dashboard;
+use crate::encrypt::Key;
+use crate::import::{Corpus, Format};
+use crate::server::{BotHandle, Server};
+use crate::shutdown::Shutdown;
+use crate::signals::{Signal, Signals};
+use crate::split::SplitStore;
+use crate::sqlite::SqliteStore;
 
-use env_logger::LogBuilder;
-use log::{LogRecord, LogLevelFilter, LogLevel};
-use ansi_term::{Style, Colour};
-use irc::client::prelude::*;
 use chrono::Local;
-
+use futures::future;
 use std::time::Duration;
-use std::thread;
-use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
 use std::process;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::collections::HashMap;
-
-const DEFAULT_CONFIG: &str = "markov-bot.json";
-
-/// Initializes the global logger.
-fn init_logger() {
-    let logger_format = |record: &LogRecord| {
-        let color = match record.level() {
-            LogLevel::Error => Colour::Red.bold(),
-            LogLevel::Warn => Style::new().fg(Colour::Yellow),
-            LogLevel::Info => Style::new().fg(Colour::Green),
-            LogLevel::Debug => Style::new().fg(Colour::Blue),
-            _ => Colour::White.dimmed(),
-        };
-        let now = Local::now();
-        format!("{}", color.paint(format!("{time} {level} [{location}] {msg}", time=now.format("%Y-%m-%d %H:%M:%S"),
-                                          location=record.location().module_path(), level=record.level(),
-                                          msg=record.args())))
-    };
-    let mut builder = LogBuilder::new();
-    builder.filter(None, LogLevelFilter::Warn);
-    if let Ok(env_var) = env::var("RUST_LOG") {
-        builder.parse(env_var.as_str());
-    }
-    builder.format(logger_format)
-        .init()
-        .unwrap();
-}
+use std::collections::{BTreeSet, HashMap};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Interval};
+
+const DEFAULT_CONFIG: &str = "markov-bot.toml";
 
 macro_rules! exit_error {
+    ($msg:expr) => {{
+        error!($msg);
+        process::exit(1)
+    }};
     ($fmt:expr, $($item:expr),*) => {{
         error!($fmt, $($item),*);
         process::exit(1)
     }};
 }
 
-fn run(config: Config) {
-    //let mut threads = Vec::new();
-    debug!("starting server {}", config.server.as_ref().unwrap());
-    let options = config.options
-        .as_ref()
-        .map(|x| x.clone())
-        .unwrap_or(HashMap::new());
-    let save_interval = options
-        .get("save_interval")
-        .map(|s| s.parse::<usize>().unwrap())
-        .unwrap_or(3600);
-    let chain_file = format!("{}.cbor", options.get("chain_file")
-        .map(String::clone)
-        .unwrap_or(config.server.clone().unwrap()));
-    let server = IrcServer::from_config(config).unwrap();
-    let running = Arc::new(AtomicBool::new(true));
-    let save_thread;
-
-    // start the server connection and handler thread
-    server.identify().unwrap();
-    {
-        debug!("attempting to read blob file at {}", &chain_file);
-        let bot = Arc::new(Mutex::new(
-            match IrcBot::read_blob(&chain_file) {
-                Ok(blob_file) => {
-                    info!("using blob file {}", &chain_file);
-                    IrcBot::from_blob_file(server.clone(), options, blob_file)
-                },
-                Err(e) => {
-                    info!("could not read blob file {}: {}", &chain_file, e);
-                    info!("one will be created instead");
-                    IrcBot::new(server.clone(), options)
-                },
+/// A server that's up and running.
+struct RunningServer {
+    name: String,
+    bot: BotHandle,
+    /// The server's task, which finishes once the bot has quit and saved one last time.
+    task: JoinHandle<()>,
+}
+
+/// Connects to a single server and starts its task.
+async fn start_server(name: String, config: ServerConfig, config_path: &str, chain_file: String, shutdown: Shutdown)
+    -> error::Result<RunningServer>
+{
+    debug!("starting server {} ({})", name, config.address);
+    let options = config.options.clone().unwrap_or_default();
+    if let Some(dir) = options.get("data_dir") {
+        fs::create_dir_all(dir).map_err(|e| Error::io(format!("could not create data_dir {}", dir), e))?;
+    }
+
+    let bot_options = bot::Options::from_config(&config)?;
+    let split = if uses_split(&config) {
+        let split = SplitStore::open(&chain_file, bot_options.backups, bot_options.compression)
+            .map_err(|e| Error::io(format!("could not open data directory {}", chain_file), e))?;
+        Some(split)
+    } else {
+        None
+    };
+
+    debug!("attempting to read blob file at {}", &chain_file);
+    let mut split_channels = BTreeSet::new();
+    let read = match split {
+        // only the index is read now; each channel's chains are read the first time something happens there
+        Some(ref split) => split.load_index::<BlobFile>().map(|index| {
+            split_channels = index.channels.clone();
+            BlobFile::from_split(index, HashMap::new())
+        }),
+        None => read_chains(&config, &chain_file),
+    };
+    let blob_file = match read {
+        Ok(blob_file) => {
+            info!("using blob file {}", &chain_file);
+            Some(blob_file)
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("could not read blob file {}: {}", &chain_file, e);
+            info!("one will be created instead");
+            None
+        },
+        Err(e) => return Err(Error::io(format!("could not read blob file {}", chain_file), e)),
+    };
+
+    let store = if uses_sqlite(&config) {
+        let store = SqliteStore::open(&chain_file)
+            .map_err(|e| Error::io(format!("could not open database {}", chain_file), e))?;
+        Some(store)
+    } else {
+        None
+    };
+
+    let (connection, stream) = server::connect(config.clone()).await?;
+    let mut bot = match blob_file {
+        Some(blob_file) => IrcBot::from_blob_file(connection, &config, blob_file)?,
+        None => IrcBot::new(connection, &config)?,
+    };
+    bot.set_config_source(config_path, &name);
+    bot.set_blob_store(blobstore::open(&config, &chain_file)
+        .map_err(|e| Error::io(format!("could not set up storage for {}", chain_file), e))?);
+    bot.send_queue().start(&name);
+    if let Some(store) = store {
+        bot.set_store(store);
+    }
+    if let Some(split) = split {
+        bot.set_split_store(split, split_channels);
+    }
+    bot.set_shutdown(shutdown.clone());
+    let (server, handle) = Server::new(name.clone(), bot, stream, chain_file, shutdown);
+    if let Some(address) = metrics::address(&options).map_err(Error::Config)? {
+        metrics::serve(&address, name.clone(), handle.clone()).map_err(Error::Setup)?;
+    }
+    if let Some(dashboard) = Dashboard::from_options(&options).map_err(Error::Config)? {
+        dashboard.serve(name.clone(), handle.clone()).map_err(Error::Setup)?;
+    }
+    let task = tokio::spawn(logging::in_server(&name, server.run()));
+    Ok(RunningServer { name, bot: handle, task })
+}
+
+/// Reads the config file again and hands each running server its new settings.
+///
+/// Servers that were added to the file aren't started, and servers that were removed from it keep running.
+async fn reload(config_path: &str, servers: &[RunningServer]) {
+    event!(info, "reload", "reloading {}", config_path);
+    let config = match ProgramConfig::load(config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("could not reload config {}: {}", config_path, e);
+            return;
+        }
+    };
+    for server in servers {
+        match config.servers.get(&server.name) {
+            Some(server_config) => {
+                let server_config = server_config.clone();
+                if let Some(Err(e)) = server.bot.ask(move |bot| bot.reload(&server_config)).await {
+                    error!("could not reload {}: {}", server.name, e);
+                }
             }
-        ));
-        // Set up the handler thread
+            None => warn!("server {} is no longer in {}, but it will keep running", server.name, config_path),
+        }
+    }
+    for name in config.servers.keys() {
+        if !servers.iter().any(|s| &s.name == name) {
+            warn!("server {} was added to {}, but it won't be started until a restart", name, config_path);
+        }
+    }
+}
+
+/// Saves every server's chains right away, without waiting for their tasks to get around to it.
+async fn save_now(servers: &[RunningServer]) {
+    for server in servers {
+        event!(info, "save", "saving {} now", server.name);
+        if let Some(Err(e)) = server.bot.ask(IrcBot::save_now).await {
+            error!("could not save {}: {}", server.name, e);
+        }
+    }
+}
+
+fn run(servers: HashMap<String, ServerConfig>, config_path: &str, chain_file: Option<&str>) -> error::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| Error::Setup(format!("could not start the runtime: {}", e)))?;
+    runtime.block_on(run_servers(servers, config_path, chain_file))
+}
+
+async fn run_servers(servers: HashMap<String, ServerConfig>, config_path: &str, chain_file: Option<&str>)
+    -> error::Result<()>
+{
+    let shutdown = Shutdown::new();
+    let mut started = Vec::new();
+    for (name, server) in servers {
+        let chain_file = chain_file_path(&name, &server, chain_file);
+        match start_server(name.clone(), server, config_path, chain_file, shutdown.clone()).await {
+            Ok(server) => started.push(server),
+            Err(e) => error!("could not start server {}: {}", name, e),
+        }
+    }
+    if started.is_empty() {
+        return Err(Error::Setup("no servers could be started".to_string()));
+    }
+
+    debug!("setting signal handlers");
+    let mut signals = Signals::listen()?;
+
+    systemd::notify(&format!("READY=1\nSTATUS=connected to {} server(s)", started.len()));
+
+    // this waits until there's something to do, waking up to ping the watchdog if systemd wants it
+    let watchdog = systemd::watchdog_interval();
+    let mut pings = watchdog.map(time::interval);
+    info!("main loop");
+    loop {
+        tokio::select! {
+            // signals and !markov shutdown both end up setting this off
+            _ = shutdown.wait() => break,
+            signal = signals.next() => match signal {
+                Signal::Shutdown => break,
+                Signal::Reload => {
+                    systemd::notify("RELOADING=1");
+                    reload(config_path, &started).await;
+                    systemd::notify("READY=1");
+                }
+                Signal::Save => save_now(&started).await,
+            },
+            _ = next_ping(&mut pings) => ping_watchdog(&started, watchdog.unwrap()).await,
+        }
+    }
+    systemd::notify("STOPPING=1\nSTATUS=saving chains");
+    shutdown.trigger();
+    info!("waiting for servers to finish");
+    for server in started {
+        if server.task.await.is_err() {
+            error!("the task for {} panicked", server.name);
+        }
+    }
+    Ok(())
+}
+
+/// Waits until it's time to ping the watchdog, which is never if systemd doesn't want pings.
+async fn next_ping(pings: &mut Option<Interval>) {
+    match *pings {
+        Some(ref mut pings) => {
+            pings.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Tells the systemd watchdog the bot is still alive. Each server's task has to answer first, so one that's stuck
+/// stops the pings and gets the service restarted.
+async fn ping_watchdog(servers: &[RunningServer], interval: Duration) {
+    for server in servers {
+        if time::timeout(interval, server.bot.ask(|_| ())).await.is_err() {
+            warn!("{} didn't answer in {:?}, so the watchdog isn't being pinged", server.name, interval);
+            return;
+        }
+    }
+    systemd::notify("WATCHDOG=1");
+}
+
+/// Gets the chain file for a server, preferring the path given on the command line. Otherwise, it's in the
+/// `data_dir` option's directory, if there is one, and the current directory if not.
+fn chain_file_path(name: &str, config: &ServerConfig, chain_file: Option<&str>) -> String {
+    let options = config.options.as_ref();
+    match chain_file {
+        Some(path) => path.to_string(),
+        None => {
+            let file = format!("{}.{}", options
+                .and_then(|o| o.get("chain_file"))
+                .map(String::as_str)
+                .unwrap_or(name), if uses_sqlite(config) {
+                    "sqlite"
+                } else if uses_split(config) {
+                    "data"
+                } else {
+                    "cbor"
+                });
+            match options.and_then(|o| o.get("data_dir")) {
+                Some(dir) => Path::new(dir).join(file).to_string_lossy().into_owned(),
+                None => file,
+            }
+        }
+    }
+}
+
+/// Gets whether a server keeps its chains in SQLite rather than a CBOR blob.
+fn uses_sqlite(config: &ServerConfig) -> bool {
+    config.options
+        .as_ref()
+        .and_then(|o| o.get("storage"))
+        .map(|s| s == "sqlite")
+        .unwrap_or(false)
+}
+
+/// Gets whether a server keeps each channel's chains in a file of its own under a data directory.
+fn uses_split(config: &ServerConfig) -> bool {
+    config.options
+        .as_ref()
+        .and_then(|o| o.get("storage"))
+        .map(|s| s == "split")
+        .unwrap_or(false)
+}
+
+/// Gets whether a server keeps its chains in an S3 bucket.
+fn uses_s3(config: &ServerConfig) -> bool {
+    config.options
+        .as_ref()
+        .and_then(|o| o.get("storage"))
+        .map(|s| s == "s3")
+        .unwrap_or(false)
+}
+
+/// Reads a server's options, for working with its chains without starting it.
+fn read_options(config: &ServerConfig) -> io::Result<bot::Options> {
+    bot::Options::from_config(config).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Reads a server's chains from whichever kind of storage it uses.
+///
+/// Like reading a blob, this fails with `NotFound` if nothing has been saved yet.
+fn read_chains(config: &ServerConfig, chain_file: &str) -> io::Result<BlobFile> {
+    if uses_split(config) {
+        if !Path::new(chain_file).exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "data directory does not exist"));
+        }
+        let split = SplitStore::open(chain_file, 0, Compression::None)?;
+        let index = split.load_index::<BlobFile>()?;
+        let mut chains = HashMap::new();
+        for channel in &index.channels {
+            chains.insert(channel.clone(), split.load_channel(channel)?);
+        }
+        return Ok(BlobFile::from_split(index, chains));
+    }
+    if !uses_sqlite(config) {
+        return blobstore::open(config, chain_file)?.load();
+    }
+    if !Path::new(chain_file).exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "database does not exist"));
+    }
+    match SqliteStore::open(chain_file)?.load()? {
+        Some(contents) => Ok(BlobFile::from_sqlite(contents)),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "database is empty")),
+    }
+}
+
+/// Writes a whole set of chains to whichever kind of storage a server uses.
+fn write_chains(config: &ServerConfig, chain_file: &str, blob: &BlobFile) -> io::Result<()> {
+    if uses_sqlite(config) {
+        let mut store = SqliteStore::open(chain_file)?;
+        blob.write_sqlite(&mut store)
+    } else if uses_split(config) {
+        let options = read_options(config)?;
+        blob.write_split(&SplitStore::open(chain_file, options.backups, options.compression)?)
+    } else {
+        let options = read_options(config)?;
+        blobstore::open(config, chain_file)?.save(blob, options.compression, options.backups).map(|_| ())
+    }
+}
+
+/// Gets how much space a server's chains take up on disk, and how much they would without compression.
+fn chain_file_sizes(config: &ServerConfig, chain_file: &str) -> io::Result<(u64, u64)> {
+    if uses_split(config) {
+        SplitStore::open(chain_file, 0, Compression::None)?.sizes()
+    } else if uses_sqlite(config) {
+        let size = fs::metadata(chain_file)?.len();
+        Ok((size, size))
+    } else if uses_s3(config) {
+        Err(io::Error::other("sizes aren't known for chains kept in S3"))
+    } else if Key::from_options(&config.options.clone().unwrap_or_default())?.is_some() {
+        // how big encrypted chains are before compression isn't known without decrypting all of them
+        let size = fs::metadata(chain_file)?.len();
+        Ok((size, size))
+    } else {
+        compress::file_sizes(Path::new(chain_file))
+    }
+}
+
+/// Prints a summary of the chains stored in a server's chain file.
+fn stats(name: &str, config: &ServerConfig, chain_file: &str) {
+    let blob = match read_chains(config, chain_file) {
+        Ok(b) => b,
+        Err(e) => exit_error!("could not read blob file {}: {}", chain_file, e),
+    };
+    println!("{} ({})", name, chain_file);
+    match chain_file_sizes(config, chain_file) {
+        Ok((size, raw)) if raw != size => {
+            println!("  {} on disk, {} uncompressed", stats::format_size(size), stats::format_size(raw))
+        }
+        Ok((size, _)) => println!("  {} on disk", stats::format_size(size)),
+        Err(e) => warn!("could not get the size of {}: {}", chain_file, e),
+    }
+    let lifetime = blob.lifetime();
+    println!("  all time: trained on {} messages, sent {} replies, handled {} commands",
+             lifetime.total_trained(), lifetime.replies_sent, lifetime.commands_handled);
+    let mut channels = blob.chains().iter().collect::<Vec<_>>();
+    channels.sort_by_key(|&(name, _)| name);
+    for (channel, users) in channels {
+        let total = users.values()
+            .map(IrcBot::get_chain_total)
+            .sum::<u32>();
+        println!("  {}: {} users, {} transitions", channel, users.len(), total);
+    }
+}
+
+/// Trains chains for a channel from a log file, or from another markov bot's corpus.
+///
+/// If `user` is given, only that user's messages are imported; plain files and corpora have no nicks, so they need
+/// one.
+fn import(config: &ServerConfig, chain_file: &str, path: &str, format: Format, channel: &str, user: Option<&str>) {
+    if format.needs_user() && user.is_none() {
+        exit_error!("importing a plain text file or another bot's corpus needs --user");
+    }
+    let options = match read_options(config) {
+        Ok(o) => o,
+        Err(e) => exit_error!("{}", e),
+    };
+    let order = options.order.unwrap_or(bot::DEFAULT_ORDER);
+    let mut blob = match read_chains(config, chain_file) {
+        Ok(b) => b,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => BlobFile::new(order),
+        Err(e) => exit_error!("could not read blob file {}: {}", chain_file, e),
+    };
+    let order = config.channel(channel)
+        .and_then(|c| c.order)
+        .unwrap_or(order);
+    // logs only have nicks, so masks that look at hosts won't match anything here
+    let folded = casemap::fold(channel);
+    let mut ignore = vec![options.ignore];
+    ignore.extend(options.channel_ignores.get(&folded).cloned());
+    let blacklist = options.channel_blacklists.get(&folded).cloned().unwrap_or(options.blacklist);
+    let preprocessor = options.preprocessor;
+    let tokenizer = options.tokenizer.for_channel(config.channel(channel));
+    // logs don't say much about how fast people repeat themselves, so only the content is checked
+    let spam = options.spam;
+    // trains on a message unless it's one that wouldn't have been trained on had the bot seen it said
+    let train = |blob: &mut BlobFile, nick: &str, message: &str| -> bool {
+        let first_word = message.split_whitespace().next().unwrap_or("");
+        if casemap::eq(nick, &config.nick)
+            || ignore.iter().any(|i| i.matches(nick, nick))
+            || blob.opted_out(channel, nick)
+            || config.command_words(Some(channel), first_word).is_some()
+            || blacklist.matches(message)
         {
-            let bot = bot.clone();
-            thread::spawn(move || {
-                debug!("starting bot thread");
-                for msg in server.iter() {
-                    match msg {
-                        Ok(msg) => {
-                            let mut bot = bot.lock()
-                                .unwrap();
-                            bot.handle(msg)
-                        },
-                        Err(e) => {
-                            error!("{}", e);
-                            break;
-                        }
+            return false;
+        }
+        let tokens = preprocessor.process(message).map(|cleaned| {
+            let tokens = tokenizer.tokenize(&cleaned);
+            (spam.junk(&cleaned, tokens.len()), tokens)
+        });
+        match tokens {
+            Some((None, tokens)) => {
+                blob.train(channel, nick, tokens, order);
+                true
+            }
+            _ => false,
+        }
+    };
+
+    let mut count = 0;
+    let mut skipped = 0;
+    let imported = match format {
+        Format::Log(format) => {
+            let file = match File::open(path) {
+                Ok(f) => f,
+                Err(e) => exit_error!("could not open {}: {}", path, e),
+            };
+            for line in BufReader::new(file).lines() {
+                let line = match line {
+                    Ok(l) => l,
+                    Err(e) => exit_error!("error reading {}: {}", path, e),
+                };
+                let parsed = match format.parse_line(&line) {
+                    Some(p) => p,
+                    None => {
+                        skipped += 1;
+                        continue;
                     }
-                }
-            });
-        }
-        //threads.push(bot_thread);
-
-        let running = running.clone();
-        save_thread = thread::spawn(move || {
-            // save every hour
-            let bot = bot.clone();
-            let ref chain_file = chain_file;
-            debug!("starting save thread");
-            'outer: while running.load(Ordering::SeqCst) {
-                let mut count = 0;
-                while count < save_interval * 10 {
-                    thread::sleep(Duration::from_millis(100));
+                };
+                let nick = match (parsed.nick, user) {
+                    (Some(nick), Some(user)) if !casemap::eq(nick, user) => continue,
+                    (Some(nick), _) => nick,
+                    (None, user) => user.unwrap(),
+                };
+                if train(&mut blob, nick, parsed.message) {
                     count += 1;
-                    if !running.load(Ordering::SeqCst) {
-                        break 'outer;
-                    }
+                } else {
+                    skipped += 1;
                 }
-                // special bot lock block
-                {
-                    let mut bot = bot.lock().unwrap();
-                    if let Err(write_err) = bot.save_blob(chain_file) {
-                        error!("error writing {}: {}", chain_file, write_err);
+            }
+            "messages"
+        }
+        Format::Corpus(format) => {
+            let user = user.unwrap();
+            match import::read_corpus(format, path, order) {
+                Ok(Corpus::Sentences(sentences)) => {
+                    for sentence in sentences {
+                        if train(&mut blob, user, &sentence) {
+                            count += 1;
+                        } else {
+                            skipped += 1;
+                        }
                     }
+                    "sentences"
                 }
-            }
-            info!("saving one last time");
-            // special bot lock block
-            {
-                let mut bot = bot.lock().unwrap();
-                if let Err(write_err) = bot.save_blob(chain_file) {
-                    error!("error writing {}: {}", chain_file, write_err);
+                Ok(Corpus::Chain(chain)) => {
+                    if blob.opted_out(channel, user) {
+                        exit_error!("{} has asked not to be trained on in {}", user, channel);
+                    }
+                    if let Err(e) = blob.merge(channel, user, &chain) {
+                        exit_error!("could not import {}: {}", path, e);
+                    }
+                    count = chain.chain().values().map(|link| link.len()).sum();
+                    "transitions"
                 }
+                Err(e) => exit_error!("could not read {}: {}", path, e),
             }
-        });
+        }
+    };
+    debug!("skipped {} of what was in {}", skipped, path);
+    if let Err(e) = write_chains(config, chain_file, &blob) {
+        exit_error!("error writing {}: {}", chain_file, e);
     }
+    println!("imported {} {} from {} into {}", count, imported, path, chain_file);
+}
 
-    debug!("setting ctrlc handler");
-    {
-        let running = running.clone();
-        ctrlc::set_handler(move || {
-            info!("ctrl-c caught");
-            running.store(false, Ordering::SeqCst);
-        }).unwrap();
+/// Opens where a server's chains are kept, for commands that work on them without connecting.
+fn open_blob_store(config: &ServerConfig, chain_file: &str) -> Box<dyn BlobStore> {
+    match blobstore::open(config, chain_file) {
+        Ok(store) => store,
+        Err(e) => exit_error!("could not set up storage for {}: {}", chain_file, e),
     }
+}
 
-    info!("main loop");
-    while running.load(Ordering::SeqCst) { thread::sleep(Duration::from_millis(1)); }
-    info!("joining save thread");
-    save_thread.join()
-        .unwrap();
+/// Saves a copy of a server's chain file as a snapshot with the given name.
+fn take_snapshot(config: &ServerConfig, chain_file: &str, name: &str) {
+    let blob = match read_chains(config, chain_file) {
+        Ok(b) => b,
+        Err(e) => exit_error!("could not read blob file {}: {}", chain_file, e),
+    };
+    let options = match read_options(config) {
+        Ok(o) => o,
+        Err(e) => exit_error!("{}", e),
+    };
+    let store = open_blob_store(config, chain_file);
+    let snapshot = match store.save_snapshot(name, &blob, options.compression, Local::now()) {
+        Ok(s) => s,
+        Err(e) => exit_error!("could not save a snapshot of {}: {}", store.describe(), e),
+    };
+    println!("saved snapshot {} of {} to {}", name, store.describe(), snapshot.location);
+}
+
+/// Rolls a server's chain file back to the newest snapshot with the given name.
+///
+/// Like `!markov restore`, only the chains go back, and users who've opted out since are left out.
+fn restore_snapshot(config: &ServerConfig, chain_file: &str, name: &str) {
+    let store = open_blob_store(config, chain_file);
+    let snapshot = match store.find_snapshot(name) {
+        Ok(Some(s)) => s,
+        Ok(None) => exit_error!("there's no snapshot of {} named {}", store.describe(), name),
+        Err(e) => exit_error!("could not list the snapshots of {}: {}", store.describe(), e),
+    };
+    let saved = match store.load_snapshot(&snapshot) {
+        Ok(b) => b,
+        Err(e) => exit_error!("could not read snapshot {}: {}", snapshot.location, e),
+    };
+    let mut blob = match read_chains(config, chain_file) {
+        Ok(b) => b,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => saved.clone(),
+        Err(e) => exit_error!("could not read blob file {}: {}", chain_file, e),
+    };
+    blob.roll_back_to(saved);
+    if let Err(e) = write_chains(config, chain_file, &blob) {
+        exit_error!("error writing {}: {}", store.describe(), e);
+    }
+    println!("restored {} from snapshot {} taken {}", store.describe(), name, snapshot.when());
+}
+
+/// Lists the snapshots of a server's chain file, oldest first.
+fn list_snapshots(name: &str, config: &ServerConfig, chain_file: &str) {
+    let store = open_blob_store(config, chain_file);
+    let snapshots = match store.snapshots() {
+        Ok(s) => s,
+        Err(e) => exit_error!("could not list the snapshots of {}: {}", store.describe(), e),
+    };
+    println!("{} ({})", name, store.describe());
+    if snapshots.is_empty() {
+        println!("  no snapshots");
+    }
+    for snapshot in snapshots {
+        println!("  {}: {} ({})", snapshot.name, snapshot.when(), snapshot.location);
+    }
+}
+
+/// Dumps a chain file as JSON, and optionally as a text corpus for each user.
+fn export(config: &ServerConfig, chain_file: &str, output: Option<&str>, corpus: Option<&str>, sentences: usize) {
+    let blob = match read_chains(config, chain_file) {
+        Ok(b) => b,
+        Err(e) => exit_error!("could not read blob file {}: {}", chain_file, e),
+    };
+    let json = serde_json::to_string_pretty(&IrcBot::export(&blob)).unwrap();
+    match output {
+        Some(path) => {
+            let written = File::create(path).and_then(|mut f| f.write_all(json.as_bytes()));
+            if let Err(e) = written {
+                exit_error!("error writing {}: {}", path, e);
+            }
+        }
+        None => println!("{}", json),
+    }
+    if let Some(dir) = corpus {
+        let tokenizer = match read_options(config) {
+            Ok(o) => o.tokenizer,
+            Err(e) => exit_error!("{}", e),
+        };
+        let tokenizer_for = |channel: &str| tokenizer.for_channel(config.channel(channel));
+        if let Err(e) = export::write_corpus(Path::new(dir), blob.chains(), &blob.private_users(), sentences,
+                                                tokenizer_for) {
+            exit_error!("error writing corpus to {}: {}", dir, e);
+        }
+    }
 }
 
 fn main() {
-    init_logger();
-    let config_path = DEFAULT_CONFIG;
+    let matches = cli::app().get_matches();
+    let log_format = matches.value_of("log-format").map(|f| f.parse::<logging::Format>().unwrap());
+    if let Err(e) = logging::init(matches.value_of("log-level"), log_format.unwrap_or(logging::Format::Text)) {
+        // there's no logger to say it with
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    let config_path = matches.value_of("config").unwrap_or(DEFAULT_CONFIG);
     trace!("Loading {}", config_path);
-    let config = match Config::load(config_path) {
+    let config = match ProgramConfig::load(config_path) {
         Ok(c) => c,
         Err(e) => exit_error!("could not load config {}: {}", config_path, e),
     };
-
-    trace!("Starting main server");
     trace!("Config: {:?}", config);
-    run(config);
+    if let (None, Some(format)) = (log_format, config.log_format.as_ref()) {
+        logging::set_format(format.parse().unwrap());
+    }
+
+    let mut servers = config.servers;
+    if let Some(name) = matches.value_of("server") {
+        servers = match servers.remove(name) {
+            Some(server) => vec![(name.to_string(), server)].into_iter().collect(),
+            None => exit_error!("no server named {} in {}", name, config_path),
+        };
+    }
+    let chain_file = matches.value_of("chain-file");
+    if chain_file.is_some() && servers.len() > 1 {
+        exit_error!("--chain-file needs a single server; pick one with --server");
+    }
+
+    match matches.subcommand() {
+        ("stats", Some(_)) => {
+            for (name, server) in &servers {
+                stats(name, server, &chain_file_path(name, server, chain_file));
+            }
+        }
+        ("export", Some(sub)) => {
+            if servers.len() > 1 {
+                exit_error!("export needs a single server; pick one with --server");
+            }
+            let (name, server) = servers.iter().next().unwrap();
+            let sentences = value_t!(sub, "sentences", usize).unwrap_or_else(|e| e.exit());
+            export(server, &chain_file_path(name, server, chain_file), sub.value_of("output"),
+                   sub.value_of("corpus"), sentences);
+        }
+        ("snapshot", Some(sub)) => {
+            for (name, server) in &servers {
+                take_snapshot(server, &chain_file_path(name, server, chain_file), sub.value_of("NAME").unwrap());
+            }
+        }
+        ("restore", Some(sub)) => {
+            if servers.len() > 1 {
+                exit_error!("restore needs a single server; pick one with --server");
+            }
+            let (name, server) = servers.iter().next().unwrap();
+            restore_snapshot(server, &chain_file_path(name, server, chain_file), sub.value_of("NAME").unwrap());
+        }
+        ("snapshots", Some(_)) => {
+            for (name, server) in &servers {
+                list_snapshots(name, server, &chain_file_path(name, server, chain_file));
+            }
+        }
+        ("repl", Some(sub)) => {
+            if servers.len() > 1 {
+                exit_error!("repl needs a single server; pick one with --server");
+            }
+            let (name, server) = servers.iter().next().unwrap();
+            let chain_file = chain_file_path(name, server, chain_file);
+            let blob = match read_chains(server, &chain_file) {
+                Ok(blob) => Some(blob),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => exit_error!("could not read blob file {}: {}", chain_file, e),
+            };
+            if let Err(e) = repl::run(server, blob, sub.value_of("nick").unwrap(), sub.value_of("channel")) {
+                exit_error!("{}", e);
+            }
+        }
+        ("import", Some(sub)) => {
+            if servers.len() > 1 {
+                exit_error!("import needs a single server; pick one with --server");
+            }
+            let (name, server) = servers.iter().next().unwrap();
+            let format = value_t!(sub, "format", Format).unwrap_or_else(|e| e.exit());
+            import(server, &chain_file_path(name, server, chain_file), sub.value_of("FILE").unwrap(), format,
+                   sub.value_of("channel").unwrap(), sub.value_of("user"));
+        }
+        _ => {
+            trace!("Starting servers");
+            if let Err(e) = run(servers, config_path, chain_file) {
+                exit_error!("{}", e);
+            }
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use crate::bot::IrcBot;
+use crate::config;
+use crate::logging;
+use crate::server::BotHandle;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::thread;
+use tiny_http::{Header, Response, Server};
+
+/// Where metrics are served, unless the `metrics_address` option says otherwise.
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+
+/// Gets the address to serve metrics at from the `metrics_*` options, or None if they're not turned on with
+/// `metrics_port`.
+pub fn address(options: &HashMap<String, String>) -> Result<Option<String>, String> {
+    let address = options.get("metrics_address").map(String::as_str).unwrap_or(DEFAULT_ADDRESS);
+    Ok(config::option::<u16>(options, "metrics_port")?.map(|port| format!("{}:{}", address, port)))
+}
+
+/// Starts serving a bot's metrics in the Prometheus text format at `http://<address>/metrics`, on a thread of its
+/// own.
+pub fn serve(address: &str, name: String, bot: BotHandle) -> Result<(), String> {
+    let server = Server::http(address).map_err(|e| format!("could not serve metrics on {}: {}", address, e))?;
+    info!("serving metrics for {} at http://{}/metrics", name, address);
+    thread::spawn(move || {
+        logging::set_server(&name);
+        let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).unwrap();
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let server = name.clone();
+                match bot.call(move |bot| render(&server, bot)) {
+                    Some(body) => Response::from_string(body).with_header(content_type.clone()),
+                    None => Response::from_string("shutting down\n").with_status_code(503),
+                }
+            } else {
+                Response::from_string("not found\n").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                warn!("could not send metrics for {}: {}", name, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Writes out everything there is to know about a bot as Prometheus metrics.
+fn render(name: &str, bot: &IrcBot) -> String {
+    let server = format!("server=\"{}\"", escape(name));
+    let counters = bot.counters();
+    let mut out = String::new();
+    let mut metric = |metric: &str, kind: &str, help: &str, values: &[(String, f64)]| {
+        writeln!(out, "# HELP {} {}", metric, help).unwrap();
+        writeln!(out, "# TYPE {} {}", metric, kind).unwrap();
+        for &(ref labels, value) in values {
+            writeln!(out, "{}{{{}}} {}", metric, labels, value).unwrap();
+        }
+    };
+
+    metric("markov_uptime_seconds", "gauge", "Seconds since the bot started.",
+           &[(server.clone(), counters.uptime().as_secs() as f64)]);
+    metric("markov_messages_seen_total", "counter", "Channel messages seen, trained on or not.",
+           &[(server.clone(), counters.messages_seen as f64)]);
+    metric("markov_messages_trained_total", "counter", "Channel messages trained on.",
+           &[(server.clone(), counters.messages_trained as f64)]);
+    let queue = bot.send_queue();
+    metric("markov_messages_sent_total", "counter", "Lines sent, random replies and command replies alike.",
+           &[(server.clone(), queue.sent() as f64)]);
+    metric("markov_messages_shadowed_total", "counter", "Messages logged instead of sent in shadow mode.",
+           &[(server.clone(), counters.shadowed as f64)]);
+    metric("markov_messages_dropped_total", "counter", "Lines dropped from the send queue instead of being sent.",
+           &[(server.clone(), queue.dropped() as f64)]);
+    metric("markov_send_queue_length", "gauge", "Lines waiting to be sent.",
+           &[(server.clone(), queue.len() as f64)]);
+    metric("markov_saves_total", "counter", "Saves that actually wrote something.",
+           &[(server.clone(), counters.saves as f64)]);
+    if let Some(duration) = counters.last_save_duration {
+        let secs = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0;
+        metric("markov_last_save_duration_seconds", "gauge", "How long the last save took.",
+               &[(server.clone(), secs)]);
+    }
+    if let Some(size) = counters.blob_size {
+        metric("markov_blob_bytes", "gauge", "Size of the chain file as of the last save.",
+               &[(server.clone(), size as f64)]);
+    }
+    if let Some(size) = counters.blob_raw_size {
+        metric("markov_blob_uncompressed_bytes", "gauge",
+               "Size of the chain file before it was compressed as of the last save.",
+               &[(server.clone(), size as f64)]);
+    }
+    metric("markov_reconnects_total", "counter", "Times the bot has reconnected to the server.",
+           &[(server.clone(), counters.reconnects as f64)]);
+
+    let (mut users, mut tokens, mut states) = (Vec::new(), Vec::new(), Vec::new());
+    for (channel, count, learned) in bot.channel_chain_stats() {
+        let labels = format!("{},channel=\"{}\"", server, escape(channel));
+        users.push((labels.clone(), count as f64));
+        tokens.push((labels.clone(), learned.tokens as f64));
+        states.push((labels, learned.states as f64));
+    }
+    metric("markov_chain_users", "gauge", "Users with a chain in each channel.", &users);
+    metric("markov_chain_tokens", "gauge", "Tokens learned in each channel.", &tokens);
+    metric("markov_chain_states", "gauge", "Distinct chain states in each channel.", &states);
+    out
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,199 @@
+use crate::casemap;
+use crate::chat::ChatServer;
+use crate::config::Server as ServerConfig;
+use irc::client::prelude::*;
+use std::str::FromStr;
+
+/// How long to wait for NickServ to identify us before joining channels anyway, in seconds.
+pub const DEFAULT_IDENTIFY_TIMEOUT: u64 = 30;
+
+/// How the bot takes its nick back from whoever's using it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Recover {
+    /// `REGAIN`, which kills the other user and hands the nick over in one step.
+    Regain,
+    /// `GHOST`, which only kills the other user, followed by a NICK once NickServ says it's done.
+    Ghost,
+}
+
+impl FromStr for Recover {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "regain" => Ok(Recover::Regain),
+            "ghost" => Ok(Recover::Ghost),
+            _ => Err(format!("nickserv_recover must be \"regain\" or \"ghost\", not {:?}", s)),
+        }
+    }
+}
+
+/// Identifies with NickServ once the connection registers, taking the configured nick back first if someone else
+/// has it.
+///
+/// While there's a password to identify with, channels aren't joined by the irc crate; they're joined once services
+/// say we're identified, so that channels set to +r don't turn the bot away. If that never happens, they're joined
+/// anyway after the identify timeout.
+#[derive(Clone, Debug)]
+pub struct NickServ {
+    /// The nick from the config.
+    wanted: String,
+    password: Option<String>,
+    recover: Recover,
+    /// The nick the server knows us by, once it's told us.
+    current: Option<String>,
+    /// Whether the server's done registering us, which channels can't be joined before.
+    registered: bool,
+    /// Whether the server says we're logged in to an account, whether it was NickServ or SASL that did it.
+    identified: bool,
+    /// Whether GHOST has been sent and we're waiting on NickServ before changing nicks.
+    ghosting: bool,
+    /// Whether the configured channels are waiting to be joined.
+    joins_waiting: bool,
+}
+
+impl NickServ {
+    pub fn from_config(config: &ServerConfig) -> Result<Self, String> {
+        Ok(NickServ {
+            wanted: config.nick.clone(),
+            password: config.nickserv_password.clone(),
+            recover: match config.nickserv_recover {
+                Some(ref recover) => recover.parse()?,
+                None => Recover::Regain,
+            },
+            current: None,
+            registered: false,
+            identified: false,
+            ghosting: false,
+            joins_waiting: config.nickserv_password.is_some(),
+        })
+    }
+
+    /// Gets the nick the server knows us by, if it's said.
+    pub fn current_nick(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// Follows a message through identification, returning whether it's time to join the configured channels.
+    pub fn handle(&mut self, server: &dyn ChatServer, msg: &Message) -> bool {
+        match msg.command {
+            Command::Response(Response::RPL_WELCOME, ref args) => {
+                self.current = args.first().cloned();
+            }
+            Command::Response(Response::RPL_ENDOFMOTD, _) |
+            Command::Response(Response::ERR_NOMOTD, _) => return self.on_registered(server),
+            Command::Response(Response::RPL_LOGGEDIN, ref args) => {
+                // <me> <nick!user@host> <account>
+                debug!("logged in as {}", args.get(2).map(String::as_str).unwrap_or("?"));
+                return self.identified();
+            }
+            Command::UserMODE(ref target, ref modes) if self.is_me(target) && adds_registered(modes) => {
+                return self.identified();
+            }
+            Command::NICK(ref new_nick) if msg.source_nickname().map(|n| self.is_me(n)).unwrap_or(false) => {
+                self.current = Some(new_nick.clone());
+                if casemap::eq(new_nick, &self.wanted) {
+                    event!(info, "nickserv", "got nick {} back", new_nick);
+                    if !self.identified {
+                        self.identify(server);
+                    }
+                }
+            }
+            Command::NOTICE(_, ref text) if self.ghosting && is_nickserv(msg) => {
+                // whatever NickServ said, the ghost is gone or it isn't going to be
+                debug!("NickServ says: {}", text);
+                self.ghosting = false;
+                self.send(server, Command::NICK(self.wanted.clone()));
+            }
+            Command::Response(Response::ERR_NICKNAMEINUSE, _) if self.current.is_some() => {
+                warn!("could not take back nick {}; it's still in use", self.wanted);
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Gets whether channels are still waiting on identification, and stops them waiting if they are.
+    ///
+    /// Called once the identify timeout runs out.
+    pub fn time_out(&mut self) -> bool {
+        if self.joins_waiting {
+            warn!("NickServ hasn't identified {} yet; joining channels anyway", self.wanted);
+            self.joins_waiting = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Identifies, or recovers our nick first, once the server's done registering us.
+    fn on_registered(&mut self, server: &dyn ChatServer) -> bool {
+        let password = match self.password {
+            Some(ref password) => password.clone(),
+            None => return false,
+        };
+        self.registered = true;
+        let on_wanted = self.current.as_ref().map(|n| casemap::eq(n, &self.wanted)).unwrap_or(true);
+        if !on_wanted {
+            event!(info, "nickserv", "{} is taken; asking NickServ for it back with {:?}", self.wanted, self.recover);
+            match self.recover {
+                Recover::Regain => self.nickserv(server, &format!("REGAIN {} {}", self.wanted, password)),
+                Recover::Ghost => {
+                    self.ghosting = true;
+                    self.nickserv(server, &format!("GHOST {} {}", self.wanted, password));
+                }
+            }
+        } else if !self.identified {
+            self.identify(server);
+        }
+        // SASL may have logged us in before registration finished
+        self.identified && self.take_joins()
+    }
+
+    fn identified(&mut self) -> bool {
+        if !self.identified {
+            event!(info, "nickserv", "identified as {}", self.wanted);
+            self.identified = true;
+        }
+        self.registered && self.take_joins()
+    }
+
+    fn take_joins(&mut self) -> bool {
+        let waiting = self.joins_waiting;
+        self.joins_waiting = false;
+        waiting
+    }
+
+    fn identify(&self, server: &dyn ChatServer) {
+        if let Some(ref password) = self.password {
+            debug!("identifying with NickServ as {}", self.wanted);
+            self.nickserv(server, &format!("IDENTIFY {}", password));
+        }
+    }
+
+    fn nickserv(&self, server: &dyn ChatServer, message: &str) {
+        self.send(server, Command::PRIVMSG("NickServ".to_string(), message.to_string()));
+    }
+
+    fn send(&self, server: &dyn ChatServer, command: Command) {
+        if let Err(e) = server.send(command) {
+            error!("could not send to NickServ: {}", e);
+        }
+    }
+
+    fn is_me(&self, nick: &str) -> bool {
+        self.current.as_ref().map(|n| casemap::eq(n, nick)).unwrap_or(false)
+    }
+}
+
+/// Gets whether a message came from NickServ.
+fn is_nickserv(msg: &Message) -> bool {
+    msg.source_nickname().map(|n| casemap::eq(n, "NickServ")).unwrap_or(false)
+}
+
+/// Gets whether a user mode change turns on +r, the mode services set on identified users.
+///
+/// The irc crate knows +r by its RFC 2812 name, restricted, which no network that runs services uses it for.
+fn adds_registered(modes: &[Mode<UserMode>]) -> bool {
+    modes.iter().any(|mode| matches!(*mode, Mode::Plus(UserMode::Restricted, _)))
+}
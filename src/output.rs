@@ -0,0 +1,57 @@
+/// Longest line an IRC server will pass on, counting the CRLF at the end.
+const IRC_LINE_LEN: usize = 512;
+
+/// Room left for the user and host in the `:nick!user@host` prefix the server puts in front of what we send, since
+/// we don't know ours. Usernames are cut off at 10 characters and hostnames at 63 on most servers.
+const USER_HOST_LEN: usize = 10 + 63;
+
+/// What's put on the end of the last line when there's more that couldn't be sent.
+const ELLIPSIS: &str = "...";
+
+/// Gets the most bytes of text that fit in one `PRIVMSG` or `NOTICE` to `target`, as relayed by the server.
+pub fn max_text_len(command: &str, target: &str, nick: &str) -> usize {
+    // :nick!user@host COMMAND target :text\r\n
+    let overhead = 1 + nick.len() + 1 + USER_HOST_LEN + 1 + command.len() + 1 + target.len() + 2 + 2;
+    IRC_LINE_LEN.saturating_sub(overhead).max(ELLIPSIS.len() + 1)
+}
+
+/// Makes a message safe to send as up to `max_lines` lines of at most `max_len` bytes each.
+///
+/// Line breaks become spaces, lines are broken between words where possible, and if there's still more left after
+/// the last line, it's cut off with an ellipsis.
+pub fn lines(message: &str, max_len: usize, max_lines: usize) -> Vec<String> {
+    let message = message
+        .split(['\r', '\n', '\0'])
+        .filter(|part| !part.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut lines = Vec::new();
+    let mut rest = message.as_str();
+    while !rest.is_empty() && lines.len() < max_lines.max(1) {
+        let last = lines.len() + 1 == max_lines.max(1);
+        if rest.len() <= max_len {
+            lines.push(rest.to_string());
+            break;
+        }
+        let room = if last { max_len - ELLIPSIS.len() } else { max_len };
+        let cut = word_boundary(rest, room);
+        let line = rest[..cut].trim_end();
+        lines.push(if last { format!("{}{}", line, ELLIPSIS) } else { line.to_string() });
+        rest = rest[cut..].trim_start();
+    }
+    lines
+}
+
+/// Finds where to break a string so that the first part is at most `max` bytes, preferring the last space before
+/// that and falling back on the last character boundary for words too long to fit on a line at all.
+fn word_boundary(s: &str, max: usize) -> usize {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    match s[..end].rfind(' ') {
+        Some(space) if space > 0 => space,
+        _ => end,
+    }
+}
@@ -0,0 +1,101 @@
+use crate::casemap;
+use crate::config;
+use chrono::{DateTime, Duration, Utc};
+use irc::client::prelude::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Messages stamped more than this many seconds ago are taken to be playback.
+pub const DEFAULT_PLAYBACK_CUTOFF: i64 = 60;
+
+/// What to do with messages that a bouncer plays back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackMode {
+    /// Train on played back messages that weren't trained on already, but don't answer them.
+    Train,
+    /// Ignore played back messages entirely.
+    Skip,
+}
+
+impl FromStr for PlaybackMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "train" => Ok(PlaybackMode::Train),
+            "skip" => Ok(PlaybackMode::Skip),
+            _ => Err(format!("playback must be \"train\" or \"skip\", not {:?}", s)),
+        }
+    }
+}
+
+/// How a message should be handled, going by when it was sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delivery {
+    /// Sent just now; handle it like any other message.
+    Live,
+    /// Played back, but new to us; train on it and nothing else.
+    TrainOnly,
+    /// Played back and either already seen or not wanted.
+    Skip,
+}
+
+/// Tells messages that just happened apart from ones a bouncer like ZNC plays back on connect.
+///
+/// This relies on the IRCv3 `server-time` tag, which the bot asks for when it connects. Messages without it are
+/// always live.
+#[derive(Clone, Debug)]
+pub struct Playback {
+    mode: PlaybackMode,
+    cutoff: Duration,
+    /// The newest message time seen in each channel or query, casefolded, so that playback after a reconnect isn't
+    /// trained on twice.
+    newest: HashMap<String, DateTime<Utc>>,
+}
+
+impl Playback {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(Playback {
+            mode: config::option(options, "playback")?.unwrap_or(PlaybackMode::Train),
+            cutoff: Duration::seconds(config::option(options, "playback_cutoff")?.unwrap_or(DEFAULT_PLAYBACK_CUTOFF)),
+            newest: HashMap::new(),
+        })
+    }
+
+    /// Takes the settings from a freshly built playback, keeping track of what's been seen.
+    pub fn reconfigure(&mut self, fresh: Playback) {
+        self.mode = fresh.mode;
+        self.cutoff = fresh.cutoff;
+    }
+
+    /// Works out how to handle a message sent to a channel or query.
+    pub fn classify(&mut self, target: &str, msg: &Message) -> Delivery {
+        let time = match server_time(msg) {
+            Some(time) => time,
+            None => return Delivery::Live,
+        };
+        let key = casemap::fold(target);
+        let seen = self.newest.get(&key).map(|&newest| time <= newest).unwrap_or(false);
+        if !seen {
+            self.newest.insert(key, time);
+        }
+        if Utc::now().signed_duration_since(time) <= self.cutoff {
+            Delivery::Live
+        } else if seen || self.mode == PlaybackMode::Skip {
+            Delivery::Skip
+        } else {
+            Delivery::TrainOnly
+        }
+    }
+}
+
+/// Gets the time a message was sent from its `server-time` tag.
+fn server_time(msg: &Message) -> Option<DateTime<Utc>> {
+    msg.tags
+        .as_ref()?
+        .iter()
+        .find(|tag| tag.0 == "time")
+        .and_then(|tag| tag.1.as_ref())
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc))
+}
@@ -0,0 +1,124 @@
+use crate::config;
+use std::collections::HashMap;
+
+/// Stands in for URLs when they're tokenized rather than stripped.
+pub const URL_TOKEN: &str = "<url>";
+
+/// What to do with URLs in messages that are about to be trained on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UrlMode {
+    Keep,
+    Strip,
+    /// Replace every URL with `URL_TOKEN`, so the chain learns where links go without learning the links.
+    Token,
+}
+
+/// Cleans up messages before they're trained on, so links, color codes, and "nick:" prefixes don't end up as
+/// tokens in the chains.
+#[derive(Clone, Debug)]
+pub struct Preprocessor {
+    strip_formatting: bool,
+    urls: UrlMode,
+    strip_address: bool,
+}
+
+impl Preprocessor {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(Preprocessor {
+            strip_formatting: config::option(options, "strip_formatting")?.unwrap_or(true),
+            urls: match options.get("urls").map(String::as_str) {
+                Some("keep") => UrlMode::Keep,
+                Some("token") => UrlMode::Token,
+                None | Some("strip") => UrlMode::Strip,
+                Some(other) => return Err(format!("urls must be \"keep\", \"strip\", or \"token\", not {:?}", other)),
+            },
+            strip_address: config::option(options, "strip_address")?.unwrap_or(true),
+        })
+    }
+
+    /// Runs a message through the pipeline, returning None if there's nothing left worth training on.
+    pub fn process(&self, msg: &str) -> Option<String> {
+        let msg = if self.strip_formatting {
+            strip_formatting(msg)
+        } else {
+            msg.to_string()
+        };
+        let mut words = msg.split_whitespace().collect::<Vec<_>>();
+        if self.strip_address && words.first().map(|w| is_address(w)).unwrap_or(false) {
+            words.remove(0);
+        }
+        let words = words
+            .into_iter()
+            .filter_map(|w| match self.urls {
+                UrlMode::Keep => Some(w),
+                UrlMode::Strip if is_url(w) => None,
+                UrlMode::Token if is_url(w) => Some(URL_TOKEN),
+                _ => Some(w),
+            })
+            .collect::<Vec<_>>();
+        if words.is_empty() {
+            None
+        } else {
+            Some(words.join(" "))
+        }
+    }
+}
+
+/// Removes mIRC bold, italic, underline, strikethrough, monospace, reverse, reset, and color codes from a message.
+pub fn strip_formatting(msg: &str) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut chars = msg.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x02' | '\x1d' | '\x1f' | '\x1e' | '\x11' | '\x16' | '\x0f' => {}
+            // \x03 is followed by up to two digits of foreground and optionally a comma and two digits of background
+            '\x03' => {
+                skip_while_max(&mut chars, 2, |c| c.is_ascii_digit());
+                if chars.peek() == Some(&',') {
+                    chars.next();
+                    skip_while_max(&mut chars, 2, |c| c.is_ascii_digit());
+                }
+            }
+            // \x04 is the same thing with six-digit hex colors
+            '\x04' => {
+                skip_while_max(&mut chars, 6, |c| c.is_ascii_hexdigit());
+                if chars.peek() == Some(&',') {
+                    chars.next();
+                    skip_while_max(&mut chars, 6, |c| c.is_ascii_hexdigit());
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn skip_while_max<I, F>(chars: &mut ::std::iter::Peekable<I>, max: usize, pred: F)
+    where I: Iterator<Item = char>,
+          F: Fn(char) -> bool
+{
+    for _ in 0..max {
+        match chars.peek() {
+            Some(&c) if pred(c) => {
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+}
+
+fn is_url(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    ["http://", "https://", "ftp://", "www."]
+        .iter()
+        .any(|prefix| lower.starts_with(prefix))
+}
+
+/// Gets whether a word looks like the `nick:` someone uses to address a message.
+fn is_address(word: &str) -> bool {
+    word.len() > 1
+        && word.ends_with(':')
+        && word[..word.len() - 1]
+            .chars()
+            .all(|c| c.is_alphanumeric() || "[]\\`_^{|}-".contains(c))
+}
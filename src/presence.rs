@@ -0,0 +1,234 @@
+use crate::casemap;
+use rand::{self, Rng};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Characters servers put in front of nicks in NAMES replies for their channel modes, like `@` for ops.
+const MODE_PREFIXES: &str = "~&@%+";
+
+/// Characters that can be in a nick besides letters and digits.
+const NICK_SPECIALS: &str = "[]\\`_^{|}-";
+
+/// What's put in a nick to keep it from highlighting anyone.
+const ZERO_WIDTH_SPACE: char = '\u{200b}';
+
+/// Stands in for the nicks of people in a channel when they're replaced rather than trained on.
+pub const NICK_TOKEN: &str = "<nick>";
+
+/// What to do with the nicks of people in a channel in messages that are about to be trained on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NickMode {
+    Keep,
+    /// Replace them with `NICK_TOKEN`, which is filled in with the nick of someone who's there when it's generated.
+    Fill,
+    /// Replace them with `NICK_TOKEN`, which is left out when it's generated.
+    Drop,
+}
+
+impl FromStr for NickMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "keep" => Ok(NickMode::Keep),
+            "fill" => Ok(NickMode::Fill),
+            "drop" => Ok(NickMode::Drop),
+            _ => Err(format!("nick_mentions must be \"keep\", \"fill\", or \"drop\", not {:?}", s)),
+        }
+    }
+}
+
+/// Who's in each channel the bot is in, kept up to date from NAMES replies and JOIN, PART, KICK, QUIT, and NICK.
+#[derive(Clone, Debug, Default)]
+pub struct Presence {
+    /// The nicks in each channel, keyed by casefolded channel and then by casefolded nick.
+    members: HashMap<String, HashMap<String, String>>,
+}
+
+impl Presence {
+    /// Adds the nicks from a NAMES reply for a channel, which lists them separated by spaces.
+    pub fn names(&mut self, channel: &str, names: &str) {
+        let members = self.members.entry(casemap::fold(channel)).or_default();
+        for name in names.split_whitespace() {
+            let nick = name.trim_start_matches(|c| MODE_PREFIXES.contains(c));
+            if !nick.is_empty() {
+                members.insert(casemap::fold(nick), nick.to_string());
+            }
+        }
+    }
+
+    pub fn joined(&mut self, channel: &str, nick: &str) {
+        self.members
+            .entry(casemap::fold(channel))
+            .or_default()
+            .insert(casemap::fold(nick), nick.to_string());
+    }
+
+    /// Removes someone who parted or was kicked from a channel.
+    pub fn left(&mut self, channel: &str, nick: &str) {
+        if let Some(members) = self.members.get_mut(&casemap::fold(channel)) {
+            members.remove(&casemap::fold(nick));
+        }
+    }
+
+    /// Removes someone who quit from every channel.
+    pub fn quit(&mut self, nick: &str) {
+        let nick = casemap::fold(nick);
+        for members in self.members.values_mut() {
+            members.remove(&nick);
+        }
+    }
+
+    /// Follows someone to their new nick in every channel they're in.
+    pub fn renamed(&mut self, old: &str, new: &str) {
+        let old = casemap::fold(old);
+        for members in self.members.values_mut() {
+            if members.remove(&old).is_some() {
+                members.insert(casemap::fold(new), new.to_string());
+            }
+        }
+    }
+
+    /// Forgets everyone in a channel, for when the bot leaves it, or is about to hear who's in it all over again.
+    pub fn clear_channel(&mut self, channel: &str) {
+        self.members.remove(&casemap::fold(channel));
+    }
+
+    /// Forgets everyone everywhere, for when the connection is lost.
+    pub fn clear(&mut self) {
+        self.members.clear();
+    }
+
+    /// Gets how many people are in a channel, or None if the bot doesn't know who's there.
+    pub fn count(&self, channel: &str) -> Option<usize> {
+        self.members.get(&casemap::fold(channel)).map(HashMap::len)
+    }
+
+    /// Puts a zero-width space after the first character of every nick in some text that belongs to someone in the
+    /// channel, so saying it there doesn't highlight them.
+    pub fn defuse(&self, channel: &str, text: &str) -> String {
+        self.replace_nicks(channel, text, |nick| {
+            let first = nick.chars().next().unwrap().len_utf8();
+            format!("{}{}{}", &nick[..first], ZERO_WIDTH_SPACE, &nick[first..])
+        })
+    }
+
+    /// Replaces the nick of everyone in the channel in a token with `NICK_TOKEN`.
+    pub fn mask(&self, channel: &str, token: &str) -> String {
+        self.replace_nicks(channel, token, |_| NICK_TOKEN.to_string())
+    }
+
+    /// Fills in every `NICK_TOKEN` in a generated sentence with the nick of someone in the channel other than us, or
+    /// leaves them out if `fill` isn't set or there's nobody to fill them in with. A word that's nothing but
+    /// punctuation without it goes too.
+    pub fn fill(&self, channel: &str, text: &str, fill: bool, us: &str) -> String {
+        if !text.contains(NICK_TOKEN) {
+            return text.to_string();
+        }
+        let nicks = self.members
+            .get(&casemap::fold(channel))
+            .map(|members| members.values().filter(|nick| !casemap::eq(nick, us)).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let mut rng = rand::thread_rng();
+        text.split_whitespace()
+            .filter_map(|word| {
+                if !word.contains(NICK_TOKEN) {
+                    return Some(word.to_string());
+                }
+                let parts = word.split(NICK_TOKEN).collect::<Vec<_>>();
+                let mut filled = parts[0].to_string();
+                for part in &parts[1..] {
+                    if fill {
+                        if let Some(nick) = rng.choose(&nicks) {
+                            filled.push_str(nick);
+                        }
+                    }
+                    filled.push_str(part);
+                }
+                Some(filled).filter(|filled| filled.chars().any(char::is_alphanumeric))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Replaces every nick in some text that belongs to someone in the channel with whatever `replace` makes of it.
+    fn replace_nicks<F>(&self, channel: &str, text: &str, replace: F) -> String
+        where F: Fn(&str) -> String
+    {
+        let members = match self.members.get(&casemap::fold(channel)) {
+            Some(members) => members,
+            None => return text.to_string(),
+        };
+        let mut replaced = String::with_capacity(text.len());
+        let mut rest = text;
+        while !rest.is_empty() {
+            // a nick, or whatever's between nicks
+            let is_nick = is_nick_char(rest.chars().next().unwrap());
+            let end = rest.find(|c| is_nick_char(c) != is_nick).unwrap_or(rest.len());
+            let (word, after) = rest.split_at(end);
+            if is_nick && members.contains_key(&casemap::fold(word)) {
+                replaced.push_str(&replace(word));
+            } else {
+                replaced.push_str(word);
+            }
+            rest = after;
+        }
+        replaced
+    }
+}
+
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || NICK_SPECIALS.contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Presence;
+    use crate::casemap;
+
+    fn here(presence: &Presence, channel: &str, nick: &str) -> bool {
+        presence.members.get(&casemap::fold(channel)).map(|m| m.contains_key(&casemap::fold(nick))).unwrap_or(false)
+    }
+
+    #[test]
+    fn follows_people_in_and_out() {
+        let mut presence = Presence::default();
+        presence.names("#a", "@Alice +bob carol");
+        presence.names("#b", "alice");
+        assert!(here(&presence, "#A", "alice"));
+        assert!(here(&presence, "#a", "BOB"));
+        assert_eq!(presence.count("#a"), Some(3));
+
+        presence.joined("#a", "dave");
+        presence.left("#a", "carol");
+        presence.renamed("alice", "alice_");
+        assert!(here(&presence, "#a", "dave"));
+        assert!(!here(&presence, "#a", "carol"));
+        assert!(here(&presence, "#b", "alice_"));
+        assert!(!here(&presence, "#a", "alice"));
+
+        presence.quit("alice_");
+        assert_eq!(presence.count("#b"), Some(0));
+        presence.clear_channel("#a");
+        assert_eq!(presence.count("#a"), None);
+    }
+
+    #[test]
+    fn nicks_of_people_here_are_defused() {
+        let mut presence = Presence::default();
+        presence.names("#a", "alice [bob] élodie");
+        assert_eq!(presence.defuse("#A", "Alice, ask [bob]: is élodie alice's? alicex"),
+                   "A\u{200b}lice, ask [\u{200b}bob]: is é\u{200b}lodie a\u{200b}lice's? alicex");
+        assert_eq!(presence.defuse("#b", "alice"), "alice");
+    }
+
+    #[test]
+    fn nicks_are_masked_and_filled_back_in() {
+        let mut presence = Presence::default();
+        presence.names("#a", "Alice markov");
+        assert_eq!(presence.mask("#a", "alice:"), "<nick>:");
+        assert_eq!(presence.fill("#a", "<nick>: hi <nick>", true, "MARKOV"), "Alice: hi Alice");
+        assert_eq!(presence.fill("#a", "<nick>: hi <nick>", false, "markov"), "hi");
+        assert_eq!(presence.fill("#b", "hi <nick>!", true, "markov"), "hi");
+    }
+}
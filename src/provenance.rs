@@ -0,0 +1,141 @@
+use crate::casemap;
+use crate::generate;
+use markov_chain::Chain;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// How many generated sentences are remembered for `!markov source`.
+const KEPT: usize = 100;
+
+/// Which chains a sentence was generated from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// One user's chain, by canonical nick.
+    User(String),
+    /// A blend of several users' chains.
+    Users(Vec<String>),
+    /// Everyone's chains in the channel.
+    Channel,
+    /// Everyone's chains in every channel that shares them.
+    Global,
+    /// The chain of topics set in the channel.
+    Topics,
+}
+
+/// A sentence the bot generated, and where it came from.
+#[derive(Clone, Debug)]
+pub struct Generated {
+    pub id: u64,
+    /// The casefolded channel it was generated for.
+    pub channel: String,
+    pub source: Source,
+    /// The words it was meant to pick up from, if it was a reply to something.
+    pub seeds: Vec<String>,
+    pub text: String,
+}
+
+/// The last few sentences the bot generated, numbered in the order they were made.
+pub struct GeneratedLog {
+    next_id: u64,
+    entries: VecDeque<Generated>,
+}
+
+impl GeneratedLog {
+    pub fn new() -> Self {
+        GeneratedLog {
+            next_id: 1,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Remembers a generated sentence, returning the number it was given.
+    pub fn record(&mut self, channel: &str, source: Source, seeds: &[String], text: &str) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push_back(Generated {
+            id,
+            channel: casemap::fold(channel),
+            source,
+            seeds: seeds.to_vec(),
+            text: text.to_string(),
+        });
+        if self.entries.len() > KEPT {
+            self.entries.pop_front();
+        }
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Generated> {
+        self.entries.iter().find(|generated| generated.id == id)
+    }
+
+    /// Gets the last sentence generated for a channel.
+    pub fn last_in(&self, channel: &str) -> Option<&Generated> {
+        let channel = casemap::fold(channel);
+        self.entries.iter().rev().find(|generated| generated.channel == channel)
+    }
+}
+
+/// Works out who could have said each step of a sentence, going by whose chains have that step in them, returning how
+/// many steps there are and how many of them each name could have taken, most first.
+///
+/// Steps are taken from each chain at its own order. The same name can come with more than one chain, which counts
+/// a step for the name if any of them have it. Since it's only known what was said and not which chain each word
+/// was picked from, this is an approximation: a step anyone could have taken counts for everyone who could have.
+pub fn contributions<'a, I>(tokens: Vec<String>, chains: I) -> (usize, Vec<(String, usize)>)
+    where I: IntoIterator<Item = (&'a str, &'a Chain<String>)>
+{
+    let tokens = generate::bracket(tokens).into_iter().map(Some).collect::<Vec<_>>();
+    // the first step, onto the start token, is one every chain takes
+    let steps = tokens.len().saturating_sub(1);
+    let mut taken: HashMap<&str, HashSet<usize>> = HashMap::new();
+    for (name, chain) in chains {
+        let order = chain.order();
+        let mut padded = vec![None; order];
+        padded.extend(tokens.iter().cloned());
+        let taken = taken.entry(name).or_default();
+        for i in 1..tokens.len() {
+            let state = &padded[i..i + order];
+            let next = &padded[i + order];
+            if chain.chain().get(state).map(|link| link.contains_key(next)).unwrap_or(false) {
+                taken.insert(i);
+            }
+        }
+    }
+    let mut counts = taken
+        .into_iter()
+        .map(|(name, taken)| (name.to_string(), taken.len()))
+        .filter(|&(_, count)| count > 0)
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    (steps, counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contributions, GeneratedLog, Source};
+    use crate::generate;
+    use markov_chain::Chain;
+
+    fn tokens(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn steps_are_credited_to_whoever_could_take_them() {
+        let mut alice = Chain::new(1);
+        alice.train(generate::bracket(tokens("the cat sat down")));
+        let mut bob = Chain::new(1);
+        bob.train(generate::bracket(tokens("a dog sat down")));
+        let chains = vec![("alice", &alice), ("bob", &bob)];
+        let (steps, counts) = contributions(tokens("the cat sat down"), chains);
+        assert_eq!(steps, 5);
+        assert_eq!(counts, vec![("alice".to_string(), 5), ("bob".to_string(), 2)]);
+
+        let mut log = GeneratedLog::new();
+        let first = log.record("#Test", Source::Channel, &[], "the cat sat down");
+        let second = log.record("#other", Source::User("alice".to_string()), &[], "hi");
+        assert_eq!(log.last_in("#test").map(|generated| generated.id), Some(first));
+        assert_eq!(log.get(second).map(|generated| generated.text.as_str()), Some("hi"));
+        assert!(log.get(second + 1).is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::config;
+use markov_chain::Chain;
+use rand::{self, Rng};
+use crate::rawchain::RawChain;
+use std::collections::HashMap;
+
+/// A year, in seconds: how long a user can go unseen before their settings are dropped.
+pub const DEFAULT_SETTINGS_MAX_AGE: i64 = 365 * 24 * 3600;
+
+/// How chains decay and get pruned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PruneOptions {
+    /// Seconds between scheduled prunes, or 0 to only prune on command.
+    pub interval: i64,
+    /// Seconds it takes a transition's weight to halve, or 0 for no decay.
+    pub half_life: i64,
+    /// Transitions with less weight than this are removed.
+    pub threshold: u32,
+    /// Seconds a user can go unseen before their settings are dropped, or 0 to keep them forever.
+    pub settings_max_age: i64,
+}
+
+impl PruneOptions {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(PruneOptions {
+            interval: config::option::<u64>(options, "prune_interval")?.unwrap_or(0) as i64,
+            half_life: config::option::<u64>(options, "decay_half_life")?.unwrap_or(0) as i64,
+            threshold: config::option(options, "prune_threshold")?.unwrap_or(1),
+            settings_max_age: config::option(options, "settings_max_age")?.unwrap_or(DEFAULT_SETTINGS_MAX_AGE),
+        })
+    }
+
+    /// Gets how much weights should be scaled by after the given number of seconds.
+    pub fn decay_factor(&self, elapsed: i64) -> f64 {
+        if self.half_life <= 0 || elapsed <= 0 {
+            1.0
+        } else {
+            0.5f64.powf(elapsed as f64 / self.half_life as f64)
+        }
+    }
+}
+
+/// What a prune got rid of.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PruneStats {
+    pub transitions: u64,
+    /// Chains that had nothing left and were removed entirely.
+    pub chains: u64,
+    /// Users whose settings were dropped for not being seen in too long.
+    pub settings: u64,
+}
+
+/// Scales every weight in a chain by `factor` and drops transitions that end up below `threshold`, returning the new
+/// chain and how many transitions were dropped, or None if nothing changed.
+///
+/// Weights are rounded up or down at random in proportion to the fraction, so that a transition seen once still
+/// fades out over many small decays instead of rounding back to 1 every time.
+pub fn prune_chain(chain: &Chain<String>, factor: f64, threshold: u32) -> Option<(Chain<String>, u64)> {
+    let mut rng = rand::thread_rng();
+    let mut raw = RawChain::new(chain.order());
+    let mut changed = false;
+    let mut removed = 0;
+    for (state, link) in chain.chain() {
+        let mut pruned = HashMap::new();
+        for (next, &weight) in link {
+            let scaled = f64::from(weight) * factor;
+            let decayed = (scaled.floor() + if rng.next_f64() < scaled.fract() { 1.0 } else { 0.0 }) as u32;
+            if decayed != weight {
+                changed = true;
+            }
+            if decayed == 0 || decayed < threshold {
+                removed += 1;
+            } else {
+                pruned.insert(next.clone(), decayed);
+            }
+        }
+        if !pruned.is_empty() {
+            raw.chain.insert(state.clone(), pruned);
+        }
+    }
+    if !changed && removed == 0 {
+        return None;
+    }
+    match raw.into_chain() {
+        Ok(pruned) => Some((pruned, removed)),
+        Err(e) => {
+            error!("could not rebuild a pruned chain: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use crate::wordlist::WordList;
+use crate::config::{self, Channel};
+use crate::generate::{self, Backoff, Sampler};
+use markov_chain::Chain;
+use crate::tokenize::Tokenizer;
+use std::collections::{HashMap, VecDeque};
+
+/// How many lines per channel are remembered by default for catching generated sentences that just repeat one.
+const RECENT_LINES: usize = 1000;
+
+/// Added to the penalty of a sentence that repeats a recent message, so that one of any length loses to one that's
+/// merely too long or too short.
+const REPEAT_PENALTY: usize = 1000;
+
+/// What generated sentences should look like.
+///
+/// Sentences are generated up to `attempts` times until one has between `min_words` and `max_words` words and
+/// isn't something someone said recently, or close to it. When none of them get there, the closest one is used
+/// anyway, as long as it's not blacklisted; blacklisted sentences are never used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SentenceFilter {
+    pub min_words: usize,
+    /// The most words a sentence should have, or 0 for no limit.
+    pub max_words: usize,
+    pub attempts: usize,
+    /// Whether chains above order 1 back off to lower orders when they haven't seen enough to go on.
+    pub backoff: bool,
+    /// How random sentences are; see `Sampler::temperature`.
+    pub temperature: f64,
+    /// How many of the lines last said in a channel generated sentences are checked against.
+    pub recent_lines: usize,
+    /// How alike a sentence and a recent line can be, from 0 to 1, before the sentence counts as a repeat. At 1, only
+    /// exact repeats do.
+    pub similarity: f64,
+}
+
+impl SentenceFilter {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let min_words = config::option(options, "min_words")?;
+        let max_words = config::option(options, "max_words")?;
+        if let (Some(min), Some(max)) = (min_words, max_words) {
+            if max != 0 && min > max {
+                return Err("min_words is more than max_words".to_string());
+            }
+        }
+        let temperature = config::option(options, "temperature")?.unwrap_or(1.0);
+        config::check_temperature(temperature)?;
+        let similarity = config::option(options, "repeat_similarity")?.unwrap_or(0.9);
+        if !(0.0..=1.0).contains(&similarity) {
+            return Err("repeat_similarity must be between 0 and 1".to_string());
+        }
+        Ok(SentenceFilter {
+            min_words: min_words.unwrap_or(3),
+            max_words: max_words.unwrap_or(0),
+            attempts: config::option(options, "generate_attempts")?.unwrap_or(10),
+            backoff: config::option(options, "backoff")?.unwrap_or(false),
+            temperature,
+            recent_lines: config::option(options, "recent_lines")?.unwrap_or(RECENT_LINES),
+            similarity,
+        })
+    }
+
+    /// Applies a channel's overrides to these limits.
+    pub fn for_channel(&self, channel: &Channel) -> Self {
+        SentenceFilter {
+            min_words: channel.min_words.unwrap_or(self.min_words),
+            max_words: channel.max_words.unwrap_or(self.max_words),
+            attempts: self.attempts,
+            backoff: self.backoff,
+            temperature: channel.temperature.unwrap_or(self.temperature),
+            recent_lines: self.recent_lines,
+            similarity: self.similarity,
+        }
+    }
+
+    /// Changes the word limits to get a sentence longer or shorter than one with `words` words, or None if it can't
+    /// get any shorter.
+    pub fn biased(&self, bias: LengthBias, words: usize) -> Option<Self> {
+        let mut filter = *self;
+        match bias {
+            LengthBias::Same => {}
+            LengthBias::Longer => {
+                filter.min_words = words + 1;
+                filter.max_words = 0;
+            }
+            LengthBias::Shorter => {
+                if words <= 1 {
+                    return None;
+                }
+                filter.max_words = words - 1;
+                filter.min_words = filter.min_words.min(filter.max_words);
+            }
+        }
+        Some(filter)
+    }
+
+    /// Generates the best sentence a chain can come up with in the allowed number of attempts, or None if every one
+    /// of them was blacklisted.
+    ///
+    /// The chain must not be empty.
+    pub fn generate(&self, chain: &Chain<String>, tokenizer: Tokenizer, recent: Option<&RecentMessages>,
+                    blacklist: &WordList) -> Option<String> {
+        let sampler = self.sampler_for(chain);
+        self.generate_with(|| tokenizer.join(&generate::sentence(chain, &sampler)), tokenizer, recent, blacklist)
+    }
+
+    /// Gets how to walk a chain, working out its lower orders to back off to if backing off is turned on.
+    pub fn sampler_for(&self, chain: &Chain<String>) -> Sampler {
+        Sampler {
+            backoff: if self.backoff { Backoff::of(chain) } else { None },
+            temperature: self.temperature,
+        }
+    }
+
+    /// Same as `generate`, but with sentences coming from `make` instead of straight from a chain.
+    pub fn generate_with<F>(&self, mut make: F, tokenizer: Tokenizer, recent: Option<&RecentMessages>,
+                            blacklist: &WordList) -> Option<String>
+        where F: FnMut() -> String
+    {
+        let mut best: Option<(usize, String)> = None;
+        for _ in 0..self.attempts.max(1) {
+            let sentence = make();
+            if blacklist.matches(&sentence) {
+                continue;
+            }
+            let penalty = self.penalty(&sentence, tokenizer, recent);
+            if penalty == 0 {
+                return Some(sentence);
+            }
+            if best.as_ref().map(|&(p, _)| penalty < p).unwrap_or(true) {
+                best = Some((penalty, sentence));
+            }
+        }
+        debug!("no generated sentence passed the filter in {} attempts", self.attempts);
+        best.map(|(_, sentence)| sentence)
+    }
+
+    /// Scores how far a sentence is from what's wanted, where 0 is a sentence that passes.
+    fn penalty(&self, sentence: &str, tokenizer: Tokenizer, recent: Option<&RecentMessages>) -> usize {
+        let words = tokenizer.count_words(sentence);
+        let mut penalty = if words < self.min_words {
+            self.min_words - words
+        } else if self.max_words > 0 && words > self.max_words {
+            words - self.max_words
+        } else {
+            0
+        };
+        if recent.map(|r| r.repeats(sentence, self.similarity)).unwrap_or(false) {
+            penalty += REPEAT_PENALTY;
+        }
+        penalty
+    }
+}
+
+/// How a sentence that's generated again should compare to the one before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthBias {
+    Same,
+    Longer,
+    Shorter,
+}
+
+/// The last few lines said in a channel.
+pub struct RecentMessages {
+    messages: VecDeque<String>,
+    /// How many times each line appears in `messages`.
+    counts: HashMap<String, usize>,
+}
+
+impl RecentMessages {
+    pub fn new() -> Self {
+        RecentMessages {
+            messages: VecDeque::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Remembers a line, forgetting the oldest ones past the last `limit`.
+    pub fn push(&mut self, message: &str, limit: usize) {
+        let message = normalize(message);
+        *self.counts.entry(message.clone()).or_insert(0) += 1;
+        self.messages.push_back(message);
+        while self.messages.len() > limit {
+            let oldest = self.messages.pop_front().unwrap();
+            let gone = {
+                let count = self.counts.get_mut(&oldest).unwrap();
+                *count -= 1;
+                *count == 0
+            };
+            if gone {
+                self.counts.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn contains(&self, message: &str) -> bool {
+        self.counts.contains_key(&normalize(message))
+    }
+
+    /// Checks whether a sentence is one of the recent lines, or at least `threshold` similar to one.
+    pub fn repeats(&self, sentence: &str, threshold: f64) -> bool {
+        if self.contains(sentence) {
+            return true;
+        }
+        if threshold >= 1.0 {
+            return false;
+        }
+        let sentence = normalize(sentence);
+        let words = sentence.split(' ').collect::<Vec<_>>();
+        self.counts.keys().any(|line| {
+            let other = line.split(' ').collect::<Vec<_>>();
+            similarity(&words, &other) >= threshold
+        })
+    }
+}
+
+/// Puts a message in the same shape as a generated sentence, which is its words joined by single spaces, and lowercases
+/// it so that repeats aren't missed over capitalization.
+fn normalize(message: &str) -> String {
+    message.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// How alike two lists of words are, from 0 for nothing in common to 1 for the same words in the same order, going by
+/// how many words have to be added, removed, or swapped to turn one into the other.
+fn similarity(a: &[&str], b: &[&str]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // lists this different in length can't be more alike than that, and aren't worth the full comparison
+    let shortest = a.len().min(b.len());
+    if shortest * 2 < longest {
+        return shortest as f64 / longest as f64;
+    }
+    let mut previous = (0..b.len() + 1).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, x) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let swap = previous[j] + if x == y { 0 } else { 1 };
+            current[j + 1] = swap.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        ::std::mem::swap(&mut previous, &mut current);
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{similarity, RecentMessages};
+
+    #[test]
+    fn repeats_catch_near_misses() {
+        let mut recent = RecentMessages::new();
+        recent.push("the quick brown fox jumps over the lazy dog today", 10);
+        assert!(recent.repeats("The quick  brown fox jumps over the lazy dog today", 0.9));
+        assert!(recent.repeats("the quick brown fox jumps over the lazy cat today", 0.9));
+        assert!(!recent.repeats("the quick brown fox jumps over the lazy cat today", 1.0));
+        assert!(!recent.repeats("the quick brown fox naps", 0.9));
+        assert_eq!(similarity(&["a", "b"], &["a", "b"]), 1.0);
+        assert_eq!(similarity(&["a", "b", "c", "d"], &["a", "c", "d"]), 0.75);
+
+        // only the last few lines are remembered
+        for i in 0..10 {
+            recent.push(&format!("line number {}", i), 10);
+        }
+        assert!(!recent.contains("the quick brown fox jumps over the lazy dog today"));
+        assert!(recent.contains("line number 0"));
+    }
+}
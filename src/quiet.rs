@@ -0,0 +1,86 @@
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+/// A stretch of the day, like `23:00-08:00`, that can run past midnight, during which a channel gets no random
+/// replies or scheduled posts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Gets whether a time of day falls within the hours, which take in their start but not their end.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(2, '-');
+        let time = |part: Option<&str>| part.and_then(|part| NaiveTime::parse_from_str(part.trim(), "%H:%M").ok());
+        match (time(parts.next()), time(parts.next())) {
+            (Some(start), Some(end)) if start != end => Ok(QuietHours { start, end }),
+            (Some(_), Some(_)) => Err(format!("quiet hours {:?} start and end at the same time", s)),
+            _ => Err(format!("quiet hours must be written like \"23:00-08:00\", not {:?}", s)),
+        }
+    }
+}
+
+/// The timezone quiet hours go by: the system's, or one from the tz database, like `Europe/Berlin`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    /// Gets what time of day it is in the zone at a moment.
+    pub fn time_of_day(&self, now: DateTime<Utc>) -> NaiveTime {
+        match *self {
+            Zone::Local => now.with_timezone(&Local).time(),
+            Zone::Named(tz) => now.with_timezone(&tz).time(),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "local" => Ok(Zone::Local),
+            _ => s.parse::<Tz>().map(Zone::Named).map_err(|_| format!("unknown timezone {:?}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuietHours, Zone};
+    use chrono::{NaiveTime, TimeZone, Utc};
+
+    #[test]
+    fn hours_can_run_past_midnight() {
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let night = "23:00-08:00".parse::<QuietHours>().unwrap();
+        assert!(night.contains(at(23, 0)) && night.contains(at(2, 30)));
+        assert!(!night.contains(at(8, 0)) && !night.contains(at(12, 0)));
+        let lunch = "12:00 - 13:30".parse::<QuietHours>().unwrap();
+        assert!(lunch.contains(at(13, 0)) && !lunch.contains(at(14, 0)));
+        assert!("12:00-12:00".parse::<QuietHours>().is_err());
+        assert!("noon-1pm".parse::<QuietHours>().is_err());
+
+        let zone = "Europe/Berlin".parse::<Zone>().unwrap();
+        assert_eq!(zone.time_of_day(Utc.with_ymd_and_hms(2020, 1, 1, 22, 30, 0).unwrap()), at(23, 30));
+        assert!("Mars/Olympus_Mons".parse::<Zone>().is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use rand::{self, Rng};
+use std::collections::HashMap;
+
+/// Saved quotes, by casefolded channel, oldest first.
+pub type QuoteMap = HashMap<String, Vec<Quote>>;
+
+/// Something someone said, saved word for word with `!markov quote add`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Quote {
+    /// The canonical nick of whoever said it.
+    pub user: String,
+    /// Their nick as it was when they said it.
+    pub nick: String,
+    pub text: String,
+    /// Who saved it.
+    pub added_by: String,
+    /// When it was saved, as a Unix timestamp.
+    pub added: i64,
+}
+
+impl Quote {
+    /// Writes a quote out the way IRC clients show a message.
+    pub fn show(&self) -> String {
+        format!("<{}> {}", self.nick, self.text)
+    }
+}
+
+/// Adds a quote to a channel's, returning how many the channel has now, or None if it already had that one.
+pub fn add(quotes: &mut QuoteMap, channel: &str, quote: Quote) -> Option<usize> {
+    let saved = quotes.entry(channel.to_string()).or_default();
+    if saved.iter().any(|q| q.user == quote.user && q.text == quote.text) {
+        return None;
+    }
+    saved.push(quote);
+    Some(saved.len())
+}
+
+/// Picks one of a channel's quotes at random, only from the users `include` picks.
+pub fn random<'a, F>(quotes: &'a QuoteMap, channel: &str, include: F) -> Option<&'a Quote>
+    where F: Fn(&str) -> bool
+{
+    let candidates = quotes.get(channel)?
+        .iter()
+        .filter(|quote| include(&quote.user))
+        .collect::<Vec<_>>();
+    rand::thread_rng().choose(&candidates).cloned()
+}
+
+/// Removes every quote of a user, in every channel, returning how many there were.
+pub fn remove_user(quotes: &mut QuoteMap, user: &str) -> usize {
+    let mut removed = 0;
+    for saved in quotes.values_mut() {
+        let before = saved.len();
+        saved.retain(|quote| quote.user != user);
+        removed += before - saved.len();
+    }
+    quotes.retain(|_, saved| !saved.is_empty());
+    removed
+}
+
+/// Moves every quote of one user over to another, for when the two turn out to be the same person.
+pub fn rename_user(quotes: &mut QuoteMap, old: &str, new: &str) {
+    for quote in quotes.values_mut().flat_map(|saved| saved.iter_mut()) {
+        if quote.user == old {
+            quote.user = new.to_string();
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Buckets that haven't been touched are dropped once this many users are being tracked.
+const MAX_TRACKED_USERS: usize = 1000;
+
+/// Limits on how fast the bot will talk, configured through options.
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    /// Messages a channel can get in a burst, and how many per minute it regains. A rate of 0 disables the limit.
+    pub channel_burst: f64,
+    pub channel_rate: f64,
+    /// Same as above, but for everything a single user makes the bot say.
+    pub user_burst: f64,
+    pub user_rate: f64,
+    /// How many tokens a command reply costs.
+    pub command_cost: f64,
+    /// How many tokens a random reply costs.
+    pub reply_cost: f64,
+}
+
+impl RateLimits {
+    /// Reads the limits, refusing any a bucket could never pay for, since everything it limits would be dropped.
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let get = |key: &str, default: f64| match config::option(options, key)?.unwrap_or(default) {
+            x if x < 0.0 => Err(format!("{} can't be negative", key)),
+            x => Ok(x),
+        };
+        let limits = RateLimits {
+            channel_burst: get("channel_burst", 5.0)?,
+            channel_rate: get("channel_rate", 10.0)?,
+            user_burst: get("user_burst", 3.0)?,
+            user_rate: get("user_rate", 4.0)?,
+            command_cost: get("command_cost", 1.0)?,
+            reply_cost: get("reply_cost", 1.0)?,
+        };
+        // a limit that's on has to have room for the dearest thing it's charged for
+        let cost = limits.command_cost.max(limits.reply_cost);
+        let cost_key = if limits.command_cost >= limits.reply_cost { "command_cost" } else { "reply_cost" };
+        for &(burst_key, burst, rate) in &[
+            ("channel_burst", limits.channel_burst, limits.channel_rate),
+            ("user_burst", limits.user_burst, limits.user_rate),
+        ] {
+            if rate > 0.0 && cost > burst {
+                return Err(format!("{} ({}) is more than {} ({}), so nothing would ever be let through", cost_key,
+                                   cost, burst_key, burst));
+            }
+        }
+        Ok(limits)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    warned: bool,
+}
+
+impl Bucket {
+    fn new(burst: f64) -> Self {
+        Bucket {
+            tokens: burst,
+            last: Instant::now(),
+            warned: false,
+        }
+    }
+
+    fn refill(&mut self, burst: f64, per_minute: f64, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_minute / 60.0).min(burst);
+        self.last = now;
+    }
+
+    /// Gets how long until this bucket has `cost` tokens, assuming it was just refilled.
+    fn wait_for(&self, cost: f64, per_minute: f64) -> Duration {
+        if self.tokens >= cost {
+            Duration::from_secs(0)
+        } else {
+            let secs = (cost - self.tokens) * 60.0 / per_minute;
+            Duration::from_millis((secs * 1000.0).ceil() as u64)
+        }
+    }
+}
+
+/// Token buckets for every channel and user the bot has talked to.
+pub struct RateLimiter {
+    limits: RateLimits,
+    channels: HashMap<String, Bucket>,
+    users: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits,
+            channels: HashMap::new(),
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Changes the limits, keeping the buckets as they are.
+    pub fn set_limits(&mut self, limits: RateLimits) {
+        self.limits = limits;
+    }
+
+    /// Takes `cost` tokens from both the channel's and the user's bucket.
+    ///
+    /// If either bucket is short, nothing is taken and the time until both could pay is returned instead.
+    pub fn acquire(&mut self, channel: &str, user: &str, cost: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        if self.users.len() > MAX_TRACKED_USERS {
+            let limits = &self.limits;
+            self.users.retain(|_, b| {
+                b.refill(limits.user_burst, limits.user_rate, now);
+                b.tokens < limits.user_burst
+            });
+        }
+
+        let limits = &self.limits;
+        let mut wait = Duration::from_secs(0);
+        if limits.channel_rate > 0.0 {
+            let bucket = self.channels
+                .entry(channel.to_string())
+                .or_insert_with(|| Bucket::new(limits.channel_burst));
+            bucket.refill(limits.channel_burst, limits.channel_rate, now);
+            wait = wait.max(bucket.wait_for(cost, limits.channel_rate));
+        }
+        if limits.user_rate > 0.0 {
+            let bucket = self.users
+                .entry(user.to_string())
+                .or_insert_with(|| Bucket::new(limits.user_burst));
+            bucket.refill(limits.user_burst, limits.user_rate, now);
+            wait = wait.max(bucket.wait_for(cost, limits.user_rate));
+        }
+        if wait > Duration::from_secs(0) {
+            return Err(wait);
+        }
+
+        if let Some(bucket) = self.channels.get_mut(channel) {
+            bucket.tokens -= cost;
+        }
+        if let Some(bucket) = self.users.get_mut(user) {
+            bucket.tokens -= cost;
+            bucket.warned = false;
+        }
+        Ok(())
+    }
+
+    /// Gets whether a throttled user should be told so, which happens once until they're let through again.
+    pub fn should_warn(&mut self, user: &str) -> bool {
+        match self.users.get_mut(user) {
+            Some(bucket) if !bucket.warned => {
+                bucket.warned = true;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimits;
+    use std::collections::HashMap;
+
+    fn limits(pairs: &[(&str, &str)]) -> Result<RateLimits, String> {
+        RateLimits::from_options(&pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>())
+    }
+
+    #[test]
+    fn costs_have_to_fit_in_the_bursts() {
+        assert!(limits(&[]).is_ok());
+        assert!(limits(&[("command_cost", "-1")]).is_err());
+        assert!(limits(&[("user_rate", "-4")]).is_err());
+        assert_eq!(limits(&[("command_cost", "4")]).unwrap_err(),
+                   "command_cost (4) is more than user_burst (3), so nothing would ever be let through");
+        assert!(limits(&[("reply_cost", "6"), ("user_burst", "10")]).is_err());
+        // a cost only has to fit in limits that are on
+        assert!(limits(&[("command_cost", "4"), ("user_rate", "0")]).is_ok());
+        assert!(limits(&[("command_cost", "6"), ("user_rate", "0"), ("channel_rate", "0")]).is_ok());
+    }
+}
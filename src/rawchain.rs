@@ -0,0 +1,21 @@
+use markov_chain::Chain;
+use std::collections::HashMap;
+
+/// The same shape as `Chain`, which has no way to set transitions directly, so chains that are built up a
+/// transition at a time go through serde instead.
+#[derive(Serialize)]
+pub struct RawChain {
+    pub chain: HashMap<Vec<Option<String>>, HashMap<Option<String>, u32>>,
+    pub order: usize,
+}
+
+impl RawChain {
+    pub fn new(order: usize) -> Self {
+        RawChain { chain: HashMap::new(), order }
+    }
+
+    pub fn into_chain(self) -> Result<Chain<String>, String> {
+        let bytes = cbor::to_vec(&self).map_err(|e| e.to_string())?;
+        cbor::from_slice(&bytes).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,41 @@
+use rand::{self, Rng};
+use std::cmp;
+use std::time::Duration;
+
+pub const DEFAULT_RECONNECT_DELAY: u64 = 5;
+pub const DEFAULT_RECONNECT_MAX_DELAY: u64 = 300;
+
+/// Exponential backoff with jitter between reconnect attempts.
+pub struct Backoff {
+    initial: u64,
+    max: u64,
+    current: u64,
+}
+
+impl Backoff {
+    /// Creates a backoff starting at `initial` seconds and never going above `max` seconds.
+    pub fn new(initial: u64, max: u64) -> Self {
+        let initial = cmp::max(initial, 1);
+        Backoff {
+            initial,
+            max: cmp::max(max, initial),
+            current: initial,
+        }
+    }
+
+    /// Gets how long to wait before the next attempt, and doubles the delay for the attempt after that.
+    ///
+    /// The delay is randomly shortened by up to half so that several bots dropped at once don't all come back in
+    /// lockstep.
+    pub fn next_delay(&mut self) -> Duration {
+        let jitter = 0.5 + rand::thread_rng().next_f64() / 2.0;
+        let delay = Duration::from_millis((self.current as f64 * jitter * 1000.0) as u64);
+        self.current = cmp::min(self.current * 2, self.max);
+        delay
+    }
+
+    /// Goes back to the initial delay, once a connection has proven to be healthy.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
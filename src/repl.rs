@@ -0,0 +1,114 @@
+use crate::bot::{BlobFile, IrcBot};
+use crate::chat::ChatServer;
+use crate::config::{self, Server as ServerConfig};
+use crate::error;
+use irc::client::prelude::*;
+use irc::error::Result;
+use std::io::{self, BufRead, Write};
+
+/// A chat server that's really the terminal: whatever the bot says is printed instead of sent anywhere.
+struct Console {
+    nick: String,
+}
+
+impl ChatServer for Console {
+    fn send(&self, command: Command) -> Result<()> {
+        match command {
+            Command::PRIVMSG(ref target, ref text) if config::is_channel_name(target) => {
+                println!("{} <{}> {}", target, self.nick, text)
+            }
+            Command::PRIVMSG(ref target, ref text) => println!("(to {}) <{}> {}", target, self.nick, text),
+            Command::NOTICE(ref target, ref text) => println!("(to {}) -{}- {}", target, self.nick, text),
+            command => debug!("not sending {:?}", command),
+        }
+        Ok(())
+    }
+
+    fn current_nickname(&self) -> &str {
+        &self.nick
+    }
+
+    fn address(&self) -> &str {
+        "the console"
+    }
+}
+
+/// Talks to a bot at a prompt, as a made up user, without connecting to anything.
+///
+/// Lines are said in the current channel, except for ones starting with `/`, which are commands for the REPL itself.
+/// Whatever the bot learns is thrown away at the end, so the chain file is never changed.
+pub fn run(config: &ServerConfig, blob: Option<BlobFile>, nick: &str, channel: Option<&str>) -> error::Result<()> {
+    let mut config = config.clone();
+    // there's no server to flood, and nothing to be slowed down for
+    {
+        let options = config.options.get_or_insert_with(Default::default);
+        options.insert("channel_rate".to_string(), "0".to_string());
+        options.insert("user_rate".to_string(), "0".to_string());
+    }
+    let console = Console { nick: config.nick.clone() };
+    let mut bot = match blob {
+        Some(blob) => IrcBot::from_blob_file(console, &config, blob)?,
+        None => IrcBot::new(console, &config)?,
+    };
+    let mut nick = nick.to_string();
+    let mut channel = channel
+        .map(str::to_string)
+        .or_else(|| config.channels.first().map(|c| c.name.clone()))
+        .unwrap_or_else(|| "#repl".to_string());
+
+    println!("Talking as {} in {}. Type /help for REPL commands, or /quit to leave.", nick, channel);
+    let stdin = io::stdin();
+    loop {
+        print!("{}> ", channel);
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                error!("could not read from stdin: {}", e);
+                break;
+            }
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (target, text) = match (words.next().unwrap(), words.next()) {
+            ("/quit", _) => break,
+            ("/help", _) => {
+                println!("/nick <nick>           talk as someone else");
+                println!("/join <channel>        talk in another channel");
+                println!("/msg <message>         say something to the bot in private");
+                println!("/quit                  leave, forgetting everything said here");
+                println!("Anything else is said in {}, including commands like \"{} help\".", channel,
+                         config.command_prefix(Some(&channel)));
+                continue;
+            }
+            ("/nick", Some(new_nick)) => {
+                nick = new_nick.to_string();
+                println!("Talking as {}", nick);
+                continue;
+            }
+            ("/join", Some(new_channel)) if config::is_channel_name(new_channel) => {
+                channel = new_channel.to_string();
+                println!("Talking in {}", channel);
+                continue;
+            }
+            ("/msg", Some(_)) => (config.nick.clone(), line["/msg".len()..].trim().to_string()),
+            (command, _) if command.starts_with('/') => {
+                println!("Unknown REPL command or missing argument; type /help for the list");
+                continue;
+            }
+            _ => (channel.clone(), line.to_string()),
+        };
+        bot.handle(Message {
+            tags: None,
+            prefix: Some(Prefix::new_from_str(&format!("{0}!{0}@repl", nick))),
+            command: Command::PRIVMSG(target, text),
+        });
+        bot.send_queue().flush();
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use crate::config;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Where the reply to a command goes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Route {
+    /// Said in the channel the command came from, addressed to the sender.
+    Channel,
+    /// Sent to the sender as a notice.
+    Notice,
+    /// Sent to the sender as a private message.
+    Private,
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "channel" => Ok(Route::Channel),
+            "notice" => Ok(Route::Notice),
+            "pm" => Ok(Route::Private),
+            _ => Err(format!("reply route must be \"channel\", \"notice\", or \"pm\", not {:?}", s)),
+        }
+    }
+}
+
+/// Every command with a reply, and where that reply goes unless the `reply_<command>` option says otherwise.
+pub const COMMANDS: &[(&str, Route)] = &[
+    ("emulate", Route::Channel),
+    ("mix", Route::Channel),
+    ("force", Route::Channel),
+    ("all", Route::Channel),
+    ("ignore", Route::Private),
+    ("listen", Route::Private),
+    ("chance", Route::Private),
+    ("forget", Route::Channel),
+    ("alias", Route::Channel),
+    ("status", Route::Channel),
+    ("ignored", Route::Private),
+    ("stats", Route::Channel),
+    ("reload", Route::Channel),
+    ("settings", Route::Private),
+    ("prune", Route::Channel),
+    ("join", Route::Channel),
+    ("part", Route::Channel),
+    ("deleteme", Route::Private),
+    ("topwords", Route::Channel),
+    ("vocab", Route::Channel),
+    ("source", Route::Channel),
+    ("help", Route::Channel),
+];
+
+/// Longest reply, in bytes, that's sure to fit in an IRC line along with the command, target, and our prefix.
+pub const MAX_REPLY_LEN: usize = 400;
+
+/// Builds a reply out of a prefix followed by as many items as fit in `MAX_REPLY_LEN`, saying how many were left out.
+pub fn fit_list(prefix: &str, items: &[String]) -> String {
+    let mut reply = prefix.to_string();
+    for (i, item) in items.iter().enumerate() {
+        let separator = if i == 0 { "" } else { ", " };
+        let rest = items.len() - i - 1;
+        // leave room to say how many more there are, unless this is the last one
+        let more = if rest == 0 { 0 } else { format!(", and {} more", rest).len() };
+        if reply.len() + separator.len() + item.len() + more > MAX_REPLY_LEN {
+            reply.push_str(&format!("{}and {} more", separator, items.len() - i));
+            break;
+        }
+        reply.push_str(separator);
+        reply.push_str(item);
+    }
+    reply
+}
+
+/// Where each command's replies go.
+pub struct Routes {
+    routes: HashMap<&'static str, Route>,
+}
+
+impl Routes {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let routes = COMMANDS
+            .iter()
+            .map(|&(command, default)| {
+                let route = config::option(options, &format!("reply_{}", command))?.unwrap_or(default);
+                Ok((command, route))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Routes { routes })
+    }
+
+    pub fn get(&self, command: &str) -> Route {
+        self.routes.get(command).cloned().unwrap_or(Route::Channel)
+    }
+}
@@ -0,0 +1,205 @@
+use crate::casemap;
+use crate::config::{Channel, Server as ServerConfig};
+use toml::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// Options that take effect while the bot is running, which are the only ones that can be changed at runtime. The rest,
+/// like where the chains are stored or the keys and passwords it logs in with, are only read at startup, and some of
+/// them shouldn't be said out loud in a channel anyway.
+const RUNTIME_OPTIONS: &[&str] = &[
+    "activity_max_chance", "activity_min_chance", "activity_target", "backoff", "backups", "bot_message_rate",
+    "bot_nick_suffix", "bot_usermode", "chance", "channel_burst", "channel_rate", "chatter_max_age", "command_cost",
+    "command_prefix", "compression", "compression_level", "conversation_depth", "conversation_window",
+    "decay_half_life", "era_buckets", "generate_attempts", "global_max_age", "identify_timeout", "ignore",
+    "max_train_tokens", "max_words", "memory_limit", "mention_chain", "mention_chance", "min_letter_ratio",
+    "min_words", "nick_mentions", "order", "output_lines", "playback", "playback_cutoff", "pm_chat", "post_idle_limit",
+    "post_max_interval", "post_min_interval", "prune_interval", "prune_threshold", "quit_message", "recent_lines",
+    "rejoin_delay", "repeat_similarity", "repeat_window", "reply_cooldown", "reply_cooldown_messages", "reply_cost",
+    "save_after_messages", "save_interval", "scrub_window", "self_train", "send_burst", "send_delay", "send_queue_size",
+    "settings_max_age", "shadow", "short_commands", "strip_address", "strip_formatting", "temperature", "timezone",
+    "tokenizer", "training_cap", "training_cap_period", "urls", "user_burst", "user_rate",
+];
+
+/// Gets whether an option takes effect when it's changed while the bot is running. Besides those listed, that's the
+/// `reply_<command>` options that say where a command's replies go.
+fn is_runtime_option(name: &str) -> bool {
+    RUNTIME_OPTIONS.contains(&name) || (name.starts_with("reply_") && name.len() > "reply_".len())
+}
+
+/// Settings admins have changed while the bot was running, which are saved along with the chains so they outlast a
+/// restart, and win over whatever the config says.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RuntimeSettings {
+    /// Options, by name, written the way they'd be written in the config.
+    options: BTreeMap<String, String>,
+    /// Channel settings, by casefolded channel and then by name, with values written the way they'd be written in
+    /// the config, e.g. `0.5`, `false`, or `["a", "b"]`.
+    channels: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl RuntimeSettings {
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty() && self.channels.is_empty()
+    }
+
+    /// Applies the settings on top of a config. Any that no longer make sense with it, like those for channels that
+    /// aren't in it anymore, are left out with a warning, but kept for if they do again.
+    pub fn apply(&self, config: &ServerConfig) -> ServerConfig {
+        let mut applied = config.clone();
+        for (channel, name, value) in self.entries() {
+            match with_setting(&applied, channel, name, value) {
+                Ok(config) => applied = config,
+                Err(e) => warn!("not applying runtime setting {}: {}", describe(channel, name, value), e),
+            }
+        }
+        applied
+    }
+
+    /// Changes a setting, for the whole server or a channel in it, as long as the config it goes on top of would
+    /// still check out with it.
+    pub fn set(&mut self, config: &ServerConfig, channel: Option<&str>, name: &str, value: &str)
+        -> Result<(), String>
+    {
+        with_setting(&self.apply(config), channel, name, value)?;
+        match channel {
+            Some(channel) => {
+                self.channels
+                    .entry(casemap::fold(channel))
+                    .or_default()
+                    .insert(name.to_string(), value.to_string());
+            }
+            None => {
+                self.options.insert(name.to_string(), value.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Goes back to what the config says about a setting, returning whether it had been changed.
+    pub fn reset(&mut self, channel: Option<&str>, name: &str) -> bool {
+        match channel {
+            Some(channel) => {
+                let channel = casemap::fold(channel);
+                let removed = self.channels.get_mut(&channel).map(|c| c.remove(name).is_some()).unwrap_or(false);
+                if self.channels.get(&channel).map(BTreeMap::is_empty).unwrap_or(false) {
+                    self.channels.remove(&channel);
+                }
+                removed
+            }
+            None => self.options.remove(name).is_some(),
+        }
+    }
+
+    /// Describes every setting, server-wide ones first, like `save_interval = 30m` or `#chan chance = 0.5`.
+    pub fn list(&self) -> Vec<String> {
+        self.entries().map(|(channel, name, value)| describe(channel, name, value)).collect()
+    }
+
+    fn entries<'a>(&'a self) -> Box<dyn Iterator<Item = (Option<&'a str>, &'a str, &'a str)> + 'a> {
+        let options = self.options.iter().map(|(name, value)| (None, name.as_str(), value.as_str()));
+        let channels = self.channels.iter().flat_map(|(channel, settings)| {
+            settings.iter().map(move |(name, value)| (Some(channel.as_str()), name.as_str(), value.as_str()))
+        });
+        Box::new(options.chain(channels))
+    }
+}
+
+fn describe(channel: Option<&str>, name: &str, value: &str) -> String {
+    match channel {
+        Some(channel) => format!("{} {} = {}", channel, name, value),
+        None => format!("{} = {}", name, value),
+    }
+}
+
+/// Gets a copy of a config with one setting changed, checking that it's a setting there is and that the config still
+/// checks out.
+fn with_setting(config: &ServerConfig, channel: Option<&str>, name: &str, value: &str)
+    -> Result<ServerConfig, String>
+{
+    let mut config = config.clone();
+    match channel {
+        Some(channel) => {
+            let entry = config.channels
+                .iter_mut()
+                .find(|c| casemap::eq(&c.name, channel))
+                .ok_or_else(|| format!("I'm not in {}", channel))?;
+            *entry = with_channel_setting(entry, name, value)?;
+        }
+        None if !is_runtime_option(name) => return Err(format!("{} can't be changed while I'm running", name)),
+        None => {
+            config.options.get_or_insert_with(HashMap::new).insert(name.to_string(), value.to_string());
+        }
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+/// Gets a copy of a channel's config with one field changed, going through TOML so that any field can be set from
+/// text.
+fn with_channel_setting(channel: &Channel, name: &str, value: &str) -> Result<Channel, String> {
+    if name == "name" {
+        return Err("a channel's name can't be changed".to_string());
+    }
+    let mut fields = Value::try_from(channel).map_err(|e| e.to_string())?;
+    fields.as_table_mut().unwrap().insert(name.to_string(), parse_value(value));
+    let changed = fields.try_into::<Channel>().map_err(|e| format!("invalid value {:?} for {}: {}", value, name, e))?;
+    // fields a channel doesn't have don't make it through
+    if Value::try_from(&changed).ok().and_then(|fields| fields.get(name).cloned()).is_none() {
+        return Err(format!("channels don't have a setting called {}", name));
+    }
+    Ok(changed)
+}
+
+/// Reads a value written the way it would be in the config, taking anything that isn't valid TOML as a string, so
+/// words don't need quotes.
+fn parse_value(value: &str) -> Value {
+    format!("value = {}", value)
+        .parse::<Value>()
+        .ok()
+        .and_then(|table| table.get("value").cloned())
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuntimeSettings;
+    use crate::config::Server as ServerConfig;
+
+    #[test]
+    fn settings_go_on_top_of_the_config() {
+        let config = toml::from_str::<ServerConfig>(r##"
+            address = "irc.example.com"
+            nick = "markov"
+            [[channels]]
+            name = "#test"
+            [options]
+            chance = "0.1"
+        "##).unwrap();
+        let mut settings = RuntimeSettings::default();
+        settings.set(&config, None, "chance", "0.2").unwrap();
+        settings.set(&config, Some("#TEST"), "respond", "false").unwrap();
+        settings.set(&config, Some("#test"), "triggers", "[\"pizza\"]").unwrap();
+        assert!(settings.set(&config, None, "chance", "lots").is_err());
+        assert!(settings.set(&config, Some("#test"), "chance", "2").is_err());
+        assert!(settings.set(&config, Some("#test"), "colour", "red").is_err());
+        assert!(settings.set(&config, Some("#elsewhere"), "respond", "false").is_err());
+        assert!(settings.set(&config, None, "chanse", "0.5").is_err());
+        assert!(settings.set(&config, None, "encryption_key", "hunter2").is_err());
+        assert_eq!(settings.list(), vec!["chance = 0.2", "#test respond = false", "#test triggers = [\"pizza\"]"]);
+
+        let applied = settings.apply(&config);
+        assert_eq!(applied.options.as_ref().unwrap()["chance"], "0.2");
+        assert_eq!(applied.channels[0].respond, Some(false));
+        assert_eq!(applied.channels[0].triggers, Some(vec!["pizza".to_string()]));
+        // a channel that's gone is skipped until it's back
+        let mut without = config.clone();
+        without.channels.clear();
+        assert!(settings.apply(&without).channels.is_empty());
+
+        assert!(settings.reset(Some("#test"), "respond"));
+        assert!(!settings.reset(Some("#test"), "respond"));
+        assert!(settings.reset(Some("#test"), "triggers"));
+        assert!(settings.reset(None, "chance"));
+        assert!(settings.is_empty());
+    }
+}
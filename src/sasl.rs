@@ -0,0 +1,165 @@
+use crate::chat::ChatServer;
+use crate::config::Server as ServerConfig;
+use irc::client::prelude::*;
+use irc::proto::CapSubCommand;
+use std::str::FromStr;
+
+/// Most base64 bytes sent in one AUTHENTICATE message; longer payloads are split up.
+const CHUNK_SIZE: usize = 400;
+
+/// How the bot proves who it is to services.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mechanism {
+    /// An account name and password.
+    Plain,
+    /// The client certificate presented when connecting.
+    External,
+}
+
+impl FromStr for Mechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_uppercase().as_str() {
+            "PLAIN" => Ok(Mechanism::Plain),
+            "EXTERNAL" => Ok(Mechanism::External),
+            _ => Err(format!("sasl_mechanism must be \"PLAIN\" or \"EXTERNAL\", not {:?}", s)),
+        }
+    }
+}
+
+/// Logs in to an account with SASL while the connection registers.
+///
+/// The exchange goes: `CAP REQ :sasl` before registering, `AUTHENTICATE <mechanism>` once the server acks it, the
+/// credentials once the server answers with `AUTHENTICATE +`, and `CAP END` when the server says whether it worked.
+/// Registration doesn't finish until `CAP END`, so a failure is logged and the bot carries on without an account.
+#[derive(Clone, Debug)]
+pub struct Sasl {
+    mechanism: Mechanism,
+    username: String,
+    password: String,
+    /// Whether the exchange is over, one way or the other.
+    done: bool,
+}
+
+impl Sasl {
+    /// Gets the SASL login for a server, if it's configured to use one.
+    pub fn from_config(config: &ServerConfig) -> Result<Option<Self>, String> {
+        let mechanism = match config.sasl_mechanism {
+            Some(ref mechanism) => mechanism.parse()?,
+            None if config.sasl_username.is_some() || config.sasl_password.is_some() => Mechanism::Plain,
+            None => return Ok(None),
+        };
+        if mechanism == Mechanism::Plain && (config.sasl_username.is_none() || config.sasl_password.is_none()) {
+            return Err("SASL PLAIN needs both sasl_username and sasl_password".to_string());
+        }
+        Ok(Some(Sasl {
+            mechanism,
+            username: config.sasl_username.clone().unwrap_or_default(),
+            password: config.sasl_password.clone().unwrap_or_default(),
+            done: false,
+        }))
+    }
+
+    /// Starts registering with the server, asking for SASL before sending the usual NICK and USER.
+    ///
+    /// This takes the place of `identify()`, which would end capability negotiation right away.
+    pub fn register(&self, server: &dyn ChatServer, config: &ServerConfig) -> ::irc::error::Result<()> {
+        if self.mechanism == Mechanism::External {
+            // the irc backend has no way to present a client certificate of its own
+            warn!("sasl_mechanism is EXTERNAL for {}, which only works if something in front of the bot, like \
+                   stunnel, presents a client certificate", config.address);
+        }
+        let user = config.user.clone().unwrap_or_else(|| config.nick.clone());
+        server.send(Command::CAP(None, CapSubCommand::REQ, None, Some("sasl".to_string())))?;
+        server.send(Command::NICK(config.nick.clone()))?;
+        server.send(Command::USER(user.clone(), "0".to_string(), user))
+    }
+
+    /// Handles a message that's part of the SASL exchange, returning whether it was one.
+    pub fn handle(&mut self, server: &dyn ChatServer, msg: &Message) -> bool {
+        if self.done {
+            return false;
+        }
+        match msg.command {
+            Command::CAP(_, CapSubCommand::ACK, ref caps, ref trailing) if has_sasl(caps, trailing) => {
+                debug!("server supports SASL, authenticating with {:?}", self.mechanism);
+                let mechanism = match self.mechanism {
+                    Mechanism::Plain => "PLAIN",
+                    Mechanism::External => "EXTERNAL",
+                };
+                self.send(server, Command::AUTHENTICATE(mechanism.to_string()));
+            }
+            Command::CAP(_, CapSubCommand::NAK, ref caps, ref trailing) if has_sasl(caps, trailing) => {
+                self.fail(server, "the server doesn't support SASL");
+            }
+            Command::AUTHENTICATE(ref data) if data == "+" => {
+                let payload = match self.mechanism {
+                    Mechanism::Plain => {
+                        base64::encode(format!("{0}\0{0}\0{1}", self.username, self.password).as_bytes())
+                    }
+                    // the certificate says who we are, so there's nothing to send
+                    Mechanism::External => String::new(),
+                };
+                for chunk in chunks(&payload) {
+                    self.send(server, Command::AUTHENTICATE(chunk));
+                }
+            }
+            Command::Response(Response::RPL_LOGGEDIN, ref args) => {
+                // <me> <nick!user@host> <account>
+                let account = args.get(2).map(String::as_str).unwrap_or("?");
+                event!(info, "sasl", "logged in to {} as {}", server.address(), account);
+            }
+            Command::Response(Response::RPL_SASLSUCCESS, _) => {
+                self.done = true;
+                self.send(server, Command::CAP(None, CapSubCommand::END, None, None));
+            }
+            Command::Response(Response::ERR_NICKLOCKED, ref args) |
+            Command::Response(Response::ERR_SASLFAIL, ref args) |
+            Command::Response(Response::ERR_SASLTOOLONG, ref args) |
+            Command::Response(Response::ERR_SASLABORTED, ref args) => {
+                // <me> :<reason>
+                let reason = args.get(1).map(String::as_str).unwrap_or("no reason given");
+                self.fail(server, reason);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Gives up on logging in and lets registration finish without an account.
+    fn fail(&mut self, server: &dyn ChatServer, reason: &str) {
+        event!(error, "sasl", "SASL {:?} login as {:?} on {} failed: {}; continuing without logging in",
+               self.mechanism, self.username, server.address(), reason);
+        self.done = true;
+        self.send(server, Command::CAP(None, CapSubCommand::END, None, None));
+    }
+
+    fn send(&self, server: &dyn ChatServer, command: Command) {
+        if let Err(e) = server.send(command) {
+            error!("could not send SASL message: {}", e);
+        }
+    }
+}
+
+/// Gets whether the capability list of a CAP message mentions sasl.
+fn has_sasl(caps: &Option<String>, trailing: &Option<String>) -> bool {
+    caps.iter()
+        .chain(trailing.iter())
+        .flat_map(|caps| caps.split_whitespace())
+        .any(|cap| cap.eq_ignore_ascii_case("sasl"))
+}
+
+/// Splits a base64 payload into AUTHENTICATE arguments. An empty payload, or one that ends on a full chunk, is
+/// finished off with "+".
+fn chunks(payload: &str) -> Vec<String> {
+    let mut chunks = payload
+        .as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>();
+    if payload.len().is_multiple_of(CHUNK_SIZE) {
+        chunks.push("+".to_string());
+    }
+    chunks
+}
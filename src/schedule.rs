@@ -0,0 +1,154 @@
+use crate::casemap;
+use crate::config;
+use rand::{self, Rng};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Least seconds between scheduled posts in a channel.
+pub const DEFAULT_POST_MIN_INTERVAL: u64 = 3600;
+/// Most seconds between scheduled posts in a channel.
+pub const DEFAULT_POST_MAX_INTERVAL: u64 = 6 * 3600;
+/// Seconds a channel can go without anyone saying anything before scheduled posts stop.
+pub const DEFAULT_POST_IDLE_LIMIT: u64 = 3 * 3600;
+/// Longest the scheduler sleeps between checks, so that channels turned on by a reload don't wait too long.
+pub const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When each channel with scheduled posts gets its next one.
+///
+/// Each post comes a random time, between the minimum and maximum interval, after the last one. A post that comes due
+/// while nobody's said anything in the channel for longer than the idle limit is skipped, so the bot doesn't end up
+/// talking to itself in an empty room.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    min_interval: Duration,
+    max_interval: Duration,
+    idle_limit: Duration,
+    /// When each channel, casefolded, gets its next post.
+    next: HashMap<String, Instant>,
+    /// When someone other than the bot last said something in each channel, casefolded.
+    active: HashMap<String, Instant>,
+}
+
+impl Schedule {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let seconds = |key: &str, default: u64| {
+            config::option(options, key).map(|x| Duration::from_secs(x.unwrap_or(default)))
+        };
+        let min_interval = seconds("post_min_interval", DEFAULT_POST_MIN_INTERVAL)?;
+        let max_interval = seconds("post_max_interval", DEFAULT_POST_MAX_INTERVAL)?;
+        if min_interval > max_interval {
+            return Err("post_min_interval is more than post_max_interval".to_string());
+        }
+        Ok(Schedule {
+            min_interval,
+            max_interval,
+            idle_limit: seconds("post_idle_limit", DEFAULT_POST_IDLE_LIMIT)?,
+            next: HashMap::new(),
+            active: HashMap::new(),
+        })
+    }
+
+    /// Takes the settings from a freshly built schedule, keeping track of activity. Posts already scheduled are
+    /// scheduled again, in case the intervals changed.
+    pub fn reconfigure(&mut self, fresh: Schedule) {
+        self.min_interval = fresh.min_interval;
+        self.max_interval = fresh.max_interval;
+        self.idle_limit = fresh.idle_limit;
+        self.next.clear();
+    }
+
+    /// Notes that someone said something in a channel.
+    pub fn record_activity(&mut self, channel: &str, now: Instant) {
+        self.active.insert(casemap::fold(channel), now);
+    }
+
+    /// Takes the channels whose post is due, out of the given ones with scheduled posts turned on, and schedules the
+    /// next post for each of them. Channels that are idle come due without being returned.
+    pub fn due<S: AsRef<str>>(&mut self, channels: &[S], now: Instant) -> Vec<String> {
+        let mut due = Vec::new();
+        for channel in channels {
+            let (channel, key) = (channel.as_ref(), casemap::fold(channel.as_ref()));
+            let next = match self.next.get(&key) {
+                Some(&next) => next,
+                None => {
+                    let next = now + self.interval();
+                    debug!("scheduled a post in {} for {}s from now", channel, (next - now).as_secs());
+                    self.next.insert(key, next);
+                    continue;
+                }
+            };
+            if next > now {
+                continue;
+            }
+            let idle = self.active.get(&key).map(|&active| now.duration_since(active) > self.idle_limit).unwrap_or(true);
+            if idle {
+                debug!("skipping the scheduled post in {}, since it's been idle too long", channel);
+            } else {
+                due.push(channel.to_string());
+            }
+            self.next.insert(key, now + self.interval());
+        }
+        // channels that had posts turned off since don't need to be kept track of
+        self.next.retain(|key, _| channels.iter().any(|c| casemap::fold(c.as_ref()) == *key));
+        due
+    }
+
+    /// Gets how long until the next post comes due, up to `MAX_CHECK_INTERVAL`.
+    pub fn until_next(&self, now: Instant) -> Duration {
+        self.next
+            .values()
+            .map(|&next| if next > now { next - now } else { Duration::from_secs(0) })
+            .min()
+            .unwrap_or(MAX_CHECK_INTERVAL)
+            .min(MAX_CHECK_INTERVAL)
+    }
+
+    /// Picks how long to wait for the next post.
+    fn interval(&self) -> Duration {
+        let (min, max) = (self.min_interval.as_secs(), self.max_interval.as_secs());
+        if max <= min {
+            return self.min_interval;
+        }
+        Duration::from_secs(rand::thread_rng().gen_range(min, max + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn schedule() -> Schedule {
+        let options = [("post_min_interval", "60"), ("post_max_interval", "120"), ("post_idle_limit", "300")]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        Schedule::from_options(&options).unwrap()
+    }
+
+    #[test]
+    fn posts_between_the_intervals() {
+        let mut schedule = schedule();
+        let start = Instant::now();
+        schedule.record_activity("#a", start);
+        assert!(schedule.due(&["#a"], start).is_empty());
+        assert!(schedule.until_next(start) >= Duration::from_secs(60));
+        assert!(schedule.due(&["#a"], start + Duration::from_secs(59)).is_empty());
+        assert_eq!(schedule.due(&["#A"], start + Duration::from_secs(120)), vec!["#A"]);
+        // the next one's scheduled from when that one went out
+        assert!(schedule.due(&["#a"], start + Duration::from_secs(121)).is_empty());
+    }
+
+    #[test]
+    fn skips_idle_channels() {
+        let mut schedule = schedule();
+        let start = Instant::now();
+        schedule.due(&["#a", "#b"], start);
+        schedule.record_activity("#a", start);
+        let later = start + Duration::from_secs(120);
+        assert_eq!(schedule.due(&["#a", "#b"], later), vec!["#a"]);
+        let much_later = later + Duration::from_secs(400);
+        assert!(schedule.due(&["#a", "#b"], much_later).is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+use crate::chat::ChatServer;
+use crate::config;
+use crate::logging;
+use irc::error::Result;
+use std::collections::{HashMap, VecDeque};
+use std::result;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Lines that can go out back to back before the bot has to slow down.
+pub const DEFAULT_SEND_BURST: usize = 4;
+/// Milliseconds between lines once the burst is used up.
+pub const DEFAULT_SEND_DELAY: u64 = 1500;
+/// Most lines of each priority that are kept waiting.
+pub const DEFAULT_SEND_QUEUE_SIZE: usize = 20;
+/// Seconds a random reply can wait to be sent before it's too late to bother.
+pub const DEFAULT_CHATTER_MAX_AGE: u64 = 30;
+
+/// How much a line matters, which decides what goes out first and what gets dropped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    /// Replies to commands, and anything else someone asked for. These always go before chatter.
+    Reply,
+    /// Random replies that nobody asked for. These are dropped once they've waited too long.
+    Chatter,
+}
+
+/// How fast lines are sent, and how many are kept waiting.
+#[derive(Clone, Debug)]
+pub struct Pacing {
+    pub burst: usize,
+    /// Time between lines once the burst is used up; 0 sends everything right away.
+    pub delay: Duration,
+    pub max_queued: usize,
+    pub max_age: Duration,
+}
+
+impl Pacing {
+    pub fn from_options(options: &HashMap<String, String>) -> result::Result<Self, String> {
+        Ok(Pacing {
+            burst: config::option(options, "send_burst")?.unwrap_or(DEFAULT_SEND_BURST),
+            delay: Duration::from_millis(config::option(options, "send_delay")?.unwrap_or(DEFAULT_SEND_DELAY)),
+            max_queued: config::option(options, "send_queue_size")?.unwrap_or(DEFAULT_SEND_QUEUE_SIZE),
+            max_age: Duration::from_secs(config::option(options, "chatter_max_age")?
+                .unwrap_or(DEFAULT_CHATTER_MAX_AGE)),
+        })
+    }
+}
+
+/// A line waiting to be sent.
+struct Outgoing {
+    notice: bool,
+    target: String,
+    text: String,
+    queued: Instant,
+}
+
+struct State {
+    server: Arc<dyn ChatServer>,
+    pacing: Pacing,
+    replies: VecDeque<Outgoing>,
+    chatter: VecDeque<Outgoing>,
+    /// How many lines can go out right now; one more comes back every `pacing.delay`, up to `pacing.burst`.
+    allowance: f64,
+    refilled: Instant,
+    sent: u64,
+    dropped: u64,
+    /// Whether the send thread has taken a line off the queue and is sending it right now.
+    sending: bool,
+    closed: bool,
+}
+
+impl State {
+    /// Tops up the allowance, returning how long until there's enough of it to send a line.
+    fn refill(&mut self) -> Duration {
+        let now = Instant::now();
+        let burst = self.pacing.burst.max(1) as f64;
+        if self.pacing.delay == Duration::from_secs(0) {
+            self.allowance = burst;
+        } else {
+            let elapsed = now.duration_since(self.refilled);
+            self.allowance = (self.allowance + elapsed.as_secs_f64() / self.pacing.delay.as_secs_f64()).min(burst);
+        }
+        self.refilled = now;
+        if self.allowance >= 1.0 {
+            Duration::from_secs(0)
+        } else {
+            let secs = (1.0 - self.allowance) * self.pacing.delay.as_secs_f64();
+            Duration::from_millis((secs * 1000.0).ceil() as u64)
+        }
+    }
+
+    /// Drops chatter that's waited too long to still make sense.
+    fn drop_stale(&mut self) {
+        let max_age = self.pacing.max_age;
+        let before = self.chatter.len();
+        self.chatter.retain(|line| line.queued.elapsed() <= max_age);
+        let stale = before - self.chatter.len();
+        if stale > 0 {
+            debug!("dropped {} lines of chatter that waited too long to be sent", stale);
+            self.dropped += stale as u64;
+        }
+    }
+}
+
+/// Lines waiting to be sent to the server, which a thread of their own sends at a pace the server won't kick the bot
+/// for flooding over.
+///
+/// Replies go before chatter. When a priority has too many lines waiting, the oldest one is dropped to make room.
+pub struct SendQueue {
+    shared: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl SendQueue {
+    pub fn new(server: Arc<dyn ChatServer>, pacing: Pacing) -> Self {
+        let state = State {
+            server,
+            allowance: pacing.burst as f64,
+            pacing,
+            replies: VecDeque::new(),
+            chatter: VecDeque::new(),
+            refilled: Instant::now(),
+            sent: 0,
+            dropped: 0,
+            sending: false,
+            closed: false,
+        };
+        SendQueue {
+            shared: Arc::new((Mutex::new(state), Condvar::new())),
+        }
+    }
+
+    /// Starts the thread that sends what's queued. Lines queued before this wait for it.
+    pub fn start(&self, name: &str) {
+        let shared = self.shared.clone();
+        let name = name.to_string();
+        thread::spawn(move || {
+            logging::set_server(&name);
+            debug!("starting send thread for {}", name);
+            run(&shared);
+        });
+    }
+
+    /// Changes how fast lines are sent, keeping what's queued.
+    pub fn set_pacing(&self, pacing: Pacing) {
+        let mut state = self.shared.0.lock().unwrap();
+        state.pacing = pacing;
+        self.shared.1.notify_all();
+    }
+
+    /// Switches over to a new connection after a reconnect. Whatever was waiting to go to the old one is dropped,
+    /// since it's stale by now.
+    pub fn set_server(&self, server: Arc<dyn ChatServer>) {
+        let mut state = self.shared.0.lock().unwrap();
+        let stale = state.replies.len() + state.chatter.len();
+        if stale > 0 {
+            info!("dropped {} lines that were waiting to be sent before the reconnect", stale);
+            state.dropped += stale as u64;
+        }
+        state.replies.clear();
+        state.chatter.clear();
+        state.server = server;
+    }
+
+    /// Queues a line to be sent as a `PRIVMSG`, or a `NOTICE` if `notice` is set.
+    pub fn push(&self, priority: Priority, notice: bool, target: &str, text: &str) {
+        let mut state = self.shared.0.lock().unwrap();
+        let max_queued = state.pacing.max_queued.max(1);
+        let dropped = {
+            let queue = match priority {
+                Priority::Reply => &mut state.replies,
+                Priority::Chatter => &mut state.chatter,
+            };
+            let mut dropped = 0;
+            while queue.len() >= max_queued {
+                queue.pop_front();
+                dropped += 1;
+            }
+            queue.push_back(Outgoing {
+                notice,
+                target: target.to_string(),
+                text: text.to_string(),
+                queued: Instant::now(),
+            });
+            dropped
+        };
+        if dropped > 0 {
+            warn!("the send queue is full; dropped {} lines of {:?}", dropped, priority);
+            state.dropped += dropped;
+        }
+        self.shared.1.notify_all();
+    }
+
+    /// Sends everything that's waiting right away, on this thread and without any pacing, for when there's no send
+    /// thread to do it.
+    pub fn flush(&self) {
+        let (server, lines) = {
+            let mut state = self.shared.0.lock().unwrap();
+            let lines = {
+                let state = &mut *state;
+                state.replies.drain(..).chain(state.chatter.drain(..)).collect::<Vec<_>>()
+            };
+            (state.server.clone(), lines)
+        };
+        for line in lines {
+            match send(&*server, &line) {
+                Ok(()) => self.shared.0.lock().unwrap().sent += 1,
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+
+    /// Waits for every reply that's queued to be sent, for when the bot is about to quit. Chatter is dropped instead,
+    /// since nobody's waiting for it.
+    ///
+    /// Returns whether everything went out before `timeout` was up.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.0.lock().unwrap();
+        let chatter = state.chatter.len();
+        if chatter > 0 {
+            debug!("dropped {} lines of chatter on the way out", chatter);
+            state.chatter.clear();
+            state.dropped += chatter as u64;
+        }
+        while !state.replies.is_empty() || state.sending {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.shared.1.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    /// Gets how many lines have been sent.
+    pub fn sent(&self) -> u64 {
+        self.shared.0.lock().unwrap().sent
+    }
+
+    /// Gets how many lines were dropped instead of being sent.
+    pub fn dropped(&self) -> u64 {
+        self.shared.0.lock().unwrap().dropped
+    }
+
+    /// Gets how many lines are waiting to be sent.
+    pub fn len(&self) -> usize {
+        let state = self.shared.0.lock().unwrap();
+        state.replies.len() + state.chatter.len()
+    }
+}
+
+impl Drop for SendQueue {
+    fn drop(&mut self) {
+        self.shared.0.lock().unwrap().closed = true;
+        self.shared.1.notify_all();
+    }
+}
+
+/// Sends queued lines as fast as the pacing allows, until the queue is dropped.
+fn run(shared: &(Mutex<State>, Condvar)) {
+    let (ref lock, ref wakeup) = *shared;
+    let mut state = lock.lock().unwrap();
+    loop {
+        if state.closed {
+            return;
+        }
+        state.drop_stale();
+        if state.replies.is_empty() && state.chatter.is_empty() {
+            state = wakeup.wait(state).unwrap();
+            continue;
+        }
+        let wait = state.refill();
+        if wait > Duration::from_secs(0) {
+            state = wakeup.wait_timeout(state, wait).unwrap().0;
+            continue;
+        }
+        let line = match state.replies.pop_front() {
+            Some(line) => line,
+            None => state.chatter.pop_front().unwrap(),
+        };
+        state.allowance -= 1.0;
+        state.sending = true;
+        let server = state.server.clone();
+        // the lock isn't held while sending, so the bot can keep queueing
+        drop(state);
+        let sent = send(&*server, &line);
+        state = lock.lock().unwrap();
+        state.sending = false;
+        match sent {
+            Ok(()) => state.sent += 1,
+            Err(e) => error!("{}", e),
+        }
+        // anyone waiting for the queue to drain wants to know
+        wakeup.notify_all();
+    }
+}
+
+fn send(server: &dyn ChatServer, line: &Outgoing) -> Result<()> {
+    if line.notice {
+        server.send_notice(&line.target, &line.text)
+    } else {
+        server.send_privmsg(&line.target, &line.text)
+    }
+}
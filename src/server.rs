@@ -0,0 +1,344 @@
+use crate::bot::IrcBot;
+use crate::chat::IrcConnection;
+use crate::config::Server as ServerConfig;
+use crate::error::{self, Error};
+use crate::reconnect::Backoff;
+use crate::sasl::Sasl;
+use crate::shutdown::Shutdown;
+use futures::StreamExt;
+use irc::client::prelude::*;
+use irc::client::ClientStream;
+use irc::proto::CapSubCommand;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+use tokio::time;
+
+/// Most seconds the task goes before checking whether a save is due, so that a shorter interval set with `!markov
+/// save every` takes effect soon.
+const SAVE_CHECK_INTERVAL: u64 = 60;
+
+/// Seconds the task waits before trying again after a save fails.
+const SAVE_RETRY_DELAY: u64 = 30;
+
+/// Most seconds to wait for the server to close the connection once the bot has quit.
+const CLOSE_TIMEOUT: u64 = 10;
+
+/// Something to do with a server's bot, on the task that owns it.
+type Job = Box<dyn FnOnce(&mut IrcBot) + Send>;
+
+/// A connection that's on its way up.
+type Connecting = Pin<Box<dyn Future<Output = error::Result<(IrcConnection, ClientStream)>> + Send>>;
+
+/// Gets at a bot from outside of its server's task, which is the only thing that holds it.
+///
+/// Whatever's asked of the bot runs on the task between messages, so it never has to be locked.
+#[derive(Clone)]
+pub struct BotHandle {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl BotHandle {
+    /// Has the task run something with the bot, without waiting for it. Returns whether the task is still there to
+    /// run it.
+    pub fn send<F>(&self, job: F) -> bool
+        where F: FnOnce(&mut IrcBot) + Send + 'static
+    {
+        self.jobs.send(Box::new(job)).is_ok()
+    }
+
+    /// Runs something with the bot and waits for what it comes back with, or None if the task is gone.
+    pub async fn ask<T, F>(&self, job: F) -> Option<T>
+        where F: FnOnce(&mut IrcBot) -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.send(move |bot| {
+            let _ = sender.send(job(bot));
+        });
+        receiver.await.ok()
+    }
+
+    /// Like `ask`, but blocking, for threads that aren't part of the runtime, like the ones serving HTTP.
+    pub fn call<T, F>(&self, job: F) -> Option<T>
+        where F: FnOnce(&mut IrcBot) -> T + Send + 'static,
+              T: Send + 'static
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.send(move |bot| {
+            let _ = sender.send(job(bot));
+        });
+        receiver.blocking_recv().ok()
+    }
+}
+
+/// Opens a connection to a server and identifies with it, asking for SASL first if it's configured. What the server
+/// says comes in through the stream, which also has to be read for anything sent through the connection to go out.
+///
+/// Channels listed in the config are joined by the irc crate once the server accepts us. `server-time` is always
+/// asked for, so that messages played back by a bouncer can be told apart from new ones.
+pub async fn connect(config: ServerConfig) -> error::Result<(IrcConnection, ClientStream)> {
+    let mut client = Client::from_config(config.irc_config())
+        .await
+        .map_err(|e| Error::Connect(format!("could not connect: {}", e)))?;
+    // asked for on its own, since a server that doesn't know one capability in a request refuses all of them
+    client.send(Command::CAP(None, CapSubCommand::REQ, None, Some("server-time".to_string())))
+        .map_err(|e| Error::Connect(format!("could not identify: {}", e)))?;
+    match Sasl::from_config(&config).map_err(Error::Config)? {
+        Some(sasl) => sasl.register(&IrcConnection::new(&client, &config), &config),
+        None => client.identify(),
+    }.map_err(|e| Error::Connect(format!("could not identify: {}", e)))?;
+    let stream = client.stream()
+        .map_err(|e| Error::Connect(format!("could not read from the connection: {}", e)))?;
+    Ok((IrcConnection::new(&client, &config), stream))
+}
+
+/// Where a server's connection is at.
+enum Connection {
+    /// Connected, and reading what the server says.
+    Up(ClientStream),
+    /// Waiting to try connecting again.
+    Waiting(time::Instant),
+    /// Trying to connect.
+    Connecting(Connecting),
+}
+
+/// What happened with a connection.
+enum Event {
+    Message(Message),
+    Dropped(Option<irc::error::Error>),
+    Retry,
+    Connected(error::Result<(IrcConnection, ClientStream)>),
+}
+
+impl Connection {
+    /// Waits for something to happen with the connection. Nothing is lost if this is dropped before it's done.
+    async fn next(&mut self) -> Event {
+        match *self {
+            Connection::Up(ref mut stream) => match stream.next().await {
+                Some(Ok(message)) => Event::Message(message),
+                Some(Err(e)) => Event::Dropped(Some(e)),
+                None => Event::Dropped(None),
+            },
+            Connection::Waiting(when) => {
+                time::sleep_until(when).await;
+                Event::Retry
+            }
+            Connection::Connecting(ref mut connecting) => Event::Connected(connecting.await),
+        }
+    }
+}
+
+/// Builds the allchain of every channel with chains a step at a time, whenever there's nothing else to do, so that
+/// the first message in a big channel doesn't have to wait for it.
+struct Allchains {
+    channels: Vec<String>,
+    /// Which of the channels is being built.
+    current: usize,
+    /// How many user chains have been merged into the current channel's allchain.
+    steps: usize,
+    started: Instant,
+    channel_started: Instant,
+}
+
+impl Allchains {
+    fn new(channels: Vec<String>) -> Self {
+        Allchains {
+            channels,
+            current: 0,
+            steps: 0,
+            started: Instant::now(),
+            channel_started: Instant::now(),
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.current >= self.channels.len()
+    }
+
+    /// Merges one more user chain into the allchain being built.
+    fn step(&mut self, bot: &mut IrcBot) {
+        let channel = &self.channels[self.current];
+        if !bot.build_allchain_step(channel) {
+            self.steps += 1;
+            return;
+        }
+        info!("built the allchain for {} from {} chains in {:?} ({}/{})", channel, self.steps,
+              self.channel_started.elapsed(), self.current + 1, self.channels.len());
+        self.current += 1;
+        self.steps = 0;
+        self.channel_started = Instant::now();
+        if self.done() {
+            event!(info, "allchains", "built {} allchains in {:?}", self.channels.len(), self.started.elapsed());
+        }
+    }
+}
+
+/// A server's bot, along with everything it takes to keep it connected and running.
+pub struct Server {
+    name: String,
+    bot: IrcBot,
+    connection: Connection,
+    chain_file: String,
+    build_allchains: bool,
+    backoff: Backoff,
+    jobs: mpsc::UnboundedReceiver<Job>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Sets up a server around a bot and the stream from its connection, along with a handle to get at the bot once
+    /// the server's running.
+    pub fn new(name: String, bot: IrcBot, stream: ClientStream, chain_file: String, shutdown: Shutdown)
+        -> (Self, BotHandle)
+    {
+        let build_allchains = bot.builds_allchains();
+        let (delay, max_delay) = bot.reconnect_delays();
+        let backoff = Backoff::new(delay, max_delay);
+        let (sender, jobs) = mpsc::unbounded_channel();
+        let connection = Connection::Up(stream);
+        let server = Server { name, bot, connection, chain_file, build_allchains, backoff, jobs, shutdown };
+        (server, BotHandle { jobs: sender })
+    }
+
+    /// Runs the bot until the program shuts down.
+    ///
+    /// Everything happens here, one thing at a time: handling what the server says, saving, scheduled posts, whatever
+    /// is asked through the bot's handle, and reconnecting when the connection drops. Allchains are built in between,
+    /// when there's nothing else to do. Once the program starts shutting down, the bot quits and saves one last time.
+    pub async fn run(mut self) {
+        debug!("starting task for {}", self.name);
+        let mut allchains = Allchains::new(if self.build_allchains { self.bot.chain_channels() } else { Vec::new() });
+        let mut identify_at = self.identify_deadline();
+        // the interval can be changed while the bot runs, so it's checked every so often instead of slept through
+        let mut save_at = self.next_save(false);
+        let mut schedule_at = time::Instant::now();
+        loop {
+            tokio::select! {
+                biased;
+                _ = self.shutdown.wait() => break,
+                event = self.connection.next() => {
+                    if let Some(deadline) = self.connection_event(event) {
+                        identify_at = Some(deadline);
+                    }
+                }
+                Some(job) = self.jobs.recv() => task::block_in_place(|| job(&mut self.bot)),
+                _ = time::sleep_until(save_at) => save_at = self.save_if_due(),
+                _ = time::sleep_until(schedule_at) => schedule_at = time::Instant::now() + self.run_schedule(),
+                _ = time::sleep_until(identify_at.unwrap_or_else(time::Instant::now)), if identify_at.is_some() => {
+                    identify_at = None;
+                    let connection = self.bot.counters().reconnects;
+                    self.bot.identify_timed_out(connection);
+                }
+                _ = task::yield_now(), if !allchains.done() => allchains.step(&mut self.bot),
+            }
+        }
+        if let Connection::Up(ref mut stream) = self.connection {
+            let bot = &mut self.bot;
+            task::block_in_place(|| bot.quit());
+            close(&self.name, stream).await;
+        }
+        info!("saving {} one last time", self.name);
+        task::block_in_place(|| self.save());
+    }
+
+    /// Follows what happened with the connection, returning when to give up on NickServ if there's a new
+    /// connection that waits on it.
+    fn connection_event(&mut self, event: Event) -> Option<time::Instant> {
+        match event {
+            Event::Message(message) => {
+                self.backoff.reset();
+                self.bot.handle(message);
+            }
+            Event::Dropped(e) => {
+                if let Some(e) = e {
+                    error!("{}: {}", self.name, e);
+                }
+                warn!("disconnected from {}", self.name);
+                self.wait_to_reconnect();
+            }
+            Event::Retry => {
+                // the config may have been reloaded since the last connection
+                self.connection = Connection::Connecting(Box::pin(connect(self.bot.config().clone())));
+            }
+            Event::Connected(Ok((connection, stream))) => {
+                event!(info, "reconnect", "reconnected to {}", self.name);
+                self.bot.set_server(connection);
+                self.connection = Connection::Up(stream);
+                return self.identify_deadline();
+            }
+            Event::Connected(Err(e)) => {
+                error!("could not reconnect to {}: {}", self.name, e);
+                self.wait_to_reconnect();
+            }
+        }
+        None
+    }
+
+    fn wait_to_reconnect(&mut self) {
+        let delay = self.backoff.next_delay();
+        event!(info, "reconnect", "reconnecting to {} in {}s", self.name, delay.as_secs());
+        self.connection = Connection::Waiting(time::Instant::now() + delay);
+    }
+
+    /// Gets when to join channels anyway if NickServ hasn't identified the bot by then, if they wait on it at all.
+    fn identify_deadline(&self) -> Option<time::Instant> {
+        self.bot.identify_timeout().map(|timeout| time::Instant::now() + timeout)
+    }
+
+    /// Posts in channels with scheduled posts that are due, and rejoins channels the bot was kicked from once it's
+    /// waited long enough, returning how long until there's more to do.
+    fn run_schedule(&mut self) -> Duration {
+        let mut wait = self.bot.post_scheduled();
+        if let Some(rejoin) = self.bot.rejoin_kicked(Instant::now()) {
+            wait = wait.min(rejoin);
+        }
+        if let Some(reclaim) = self.bot.reclaim_nick(Instant::now()) {
+            wait = wait.min(reclaim);
+        }
+        wait
+    }
+
+    /// Saves if it's time to, returning when to check again.
+    fn save_if_due(&mut self) -> time::Instant {
+        let failed = self.bot.next_save_in() == Duration::from_secs(0) && task::block_in_place(|| {
+            self.bot.prune_if_due();
+            !self.save()
+        });
+        self.next_save(failed)
+    }
+
+    /// Gets when to check whether a save is due. A save that failed is due again right away, so it's given a moment
+    /// before it's tried again.
+    fn next_save(&self, failed: bool) -> time::Instant {
+        let wait = if failed {
+            Duration::from_secs(SAVE_RETRY_DELAY)
+        } else {
+            self.bot.next_save_in().min(Duration::from_secs(SAVE_CHECK_INTERVAL))
+        };
+        time::Instant::now() + wait
+    }
+
+    fn save(&mut self) -> bool {
+        match self.bot.save() {
+            Ok(()) => true,
+            Err(e) => {
+                error!("error writing {}: {}", self.chain_file, e);
+                false
+            }
+        }
+    }
+}
+
+/// Keeps reading from the server after the bot quits until the server hangs up, since what the bot sends only goes
+/// out while the connection is read. A server that doesn't hang up isn't worth waiting on forever.
+async fn close(name: &str, stream: &mut ClientStream) {
+    let closed = time::timeout(Duration::from_secs(CLOSE_TIMEOUT), async {
+        while let Some(Ok(_)) = stream.next().await {}
+    });
+    if closed.await.is_err() {
+        warn!("{} didn't close the connection after the bot quit, so it's being dropped", name);
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Tells every task when the program is shutting down, and wakes up any of them that are waiting on it so they can
+/// finish right away instead of polling.
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Shutdown { sender: Arc::new(sender), receiver }
+    }
+
+    /// Starts shutting down.
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Waits until the program starts shutting down.
+    pub async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow_and_update() {
+            // the sender lives as long as this does, so this only fails if something's gone very wrong
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
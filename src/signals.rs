@@ -0,0 +1,72 @@
+use crate::error;
+#[cfg(unix)]
+use crate::error::Error;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal as UnixSignal, SignalKind};
+
+/// Something the bot was asked to do from outside by a signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// SIGINT or SIGTERM: save, quit, and exit.
+    Shutdown,
+    /// SIGHUP: read the config file again.
+    Reload,
+    /// SIGUSR1: save the chains right away.
+    Save,
+}
+
+/// The signals the bot listens for, once it's started listening. This has to be set up on the runtime.
+#[cfg(unix)]
+pub struct Signals {
+    interrupt: UnixSignal,
+    terminate: UnixSignal,
+    hangup: UnixSignal,
+    user1: UnixSignal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    pub fn listen() -> error::Result<Self> {
+        let listen = |kind| signal(kind).map_err(|e| Error::Setup(format!("could not set signal handlers: {}", e)));
+        Ok(Signals {
+            interrupt: listen(SignalKind::interrupt())?,
+            terminate: listen(SignalKind::terminate())?,
+            hangup: listen(SignalKind::hangup())?,
+            user1: listen(SignalKind::user_defined1())?,
+        })
+    }
+
+    /// Waits for the next signal.
+    pub async fn next(&mut self) -> Signal {
+        let (name, signal) = tokio::select! {
+            _ = self.interrupt.recv() => ("SIGINT", Signal::Shutdown),
+            _ = self.terminate.recv() => ("SIGTERM", Signal::Shutdown),
+            _ = self.hangup.recv() => ("SIGHUP", Signal::Reload),
+            _ = self.user1.recv() => ("SIGUSR1", Signal::Save),
+        };
+        info!("caught {}", name);
+        signal
+    }
+}
+
+/// Only ctrl-c can be caught where there are no Unix signals, so that's all `next` will ever return there.
+#[cfg(not(unix))]
+pub struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    pub fn listen() -> error::Result<Self> {
+        Ok(Signals)
+    }
+
+    /// Waits for the next signal.
+    pub async fn next(&mut self) -> Signal {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("could not listen for ctrl-c: {}", e);
+            // nothing else is coming, and shutting down over it would be worse than not being able to
+            futures::future::pending::<()>().await;
+        }
+        info!("ctrl-c caught");
+        Signal::Shutdown
+    }
+}
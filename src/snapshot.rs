@@ -0,0 +1,137 @@
+use chrono::{DateTime, Local, NaiveDateTime};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How the time a snapshot was taken is written in its file name.
+const TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+/// Longest name a snapshot can be given.
+const MAX_NAME_LEN: usize = 32;
+
+/// A copy of a chain file, saved under a name so that it can be rolled back to later.
+///
+/// Snapshots are CBOR blobs, whatever the storage, named `<name>.<time>.cbor`. On disk they're kept in
+/// `<chain file>.snapshots`; other blob stores keep them wherever they like. Taking another snapshot with the same name
+/// doesn't replace the old one; restoring a name picks its newest snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub taken: NaiveDateTime,
+    /// Where the snapshot is kept, like its path, for the blob store it came from to find it by.
+    pub location: String,
+}
+
+impl Snapshot {
+    /// Gets when the snapshot was taken, for people to read.
+    pub fn when(&self) -> String {
+        self.taken.format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+}
+
+/// Gets the directory a chain file's snapshots are kept in.
+pub fn dir(chain_file: &str) -> PathBuf {
+    PathBuf::from(format!("{}.snapshots", chain_file))
+}
+
+/// Checks that a snapshot name is short and only has letters, digits, dashes, and underscores in it, so that it's
+/// safe to put in a file name.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(format!("snapshot names have to be 1 to {} characters long", MAX_NAME_LEN));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("snapshot names can only have letters, digits, - and _ in them".to_string());
+    }
+    Ok(())
+}
+
+/// Gets the file name a snapshot with the given name, taken at the given time, is saved under.
+pub fn file_name(name: &str, taken: NaiveDateTime) -> String {
+    format!("{}.{}.cbor", name, taken.format(TIME_FORMAT))
+}
+
+/// Gets the name of a snapshot and when it was taken back out of its file name, or None if it isn't a snapshot's.
+pub fn parse_file_name(file_name: &str) -> Option<(String, NaiveDateTime)> {
+    let stem = Path::new(file_name)
+        .file_stem()
+        .filter(|_| file_name.ends_with(".cbor"))
+        .and_then(|stem| stem.to_str())?;
+    let mut parts = stem.rsplitn(2, '.');
+    let taken = NaiveDateTime::parse_from_str(parts.next()?, TIME_FORMAT).ok()?;
+    Some((parts.next()?.to_string(), taken))
+}
+
+/// Picks where a new snapshot of a chain file goes, creating the snapshot directory if it's not there yet.
+pub fn new_path(chain_file: &str, name: &str, now: DateTime<Local>) -> io::Result<Snapshot> {
+    check_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let dir = dir(chain_file);
+    fs::create_dir_all(&dir)?;
+    let taken = now.naive_local();
+    let location = dir.join(file_name(name, taken)).to_string_lossy().into_owned();
+    Ok(Snapshot { name: name.to_string(), taken, location })
+}
+
+/// Lists a chain file's snapshots, oldest first. Files in the snapshot directory that don't look like snapshots are
+/// left out.
+pub fn list(chain_file: &str) -> io::Result<Vec<Snapshot>> {
+    let entries = match fs::read_dir(dir(chain_file)) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let parsed = path.file_name().and_then(|f| f.to_str()).and_then(parse_file_name);
+        if let Some((name, taken)) = parsed {
+            snapshots.push(Snapshot { name, taken, location: path.to_string_lossy().into_owned() });
+        }
+    }
+    sort(&mut snapshots);
+    Ok(snapshots)
+}
+
+/// Puts snapshots in the order they were taken, oldest first.
+pub fn sort(snapshots: &mut [Snapshot]) {
+    snapshots.sort_by(|a, b| (a.taken, &a.name).cmp(&(b.taken, &b.name)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_name, dir, list, new_path, parse_file_name};
+    use chrono::{Duration, Local};
+    use std::env;
+    use std::fs::{self, File};
+
+    #[test]
+    fn lists_and_finds_snapshots() {
+        let chain_file = env::temp_dir().join(format!("markov-bot-snapshot-test-{}.cbor", ::std::process::id()));
+        let chain_file = chain_file.to_str().unwrap();
+        assert_eq!(list(chain_file).unwrap(), vec![]);
+
+        let now = Local::now();
+        let older = new_path(chain_file, "before-flood", now - Duration::seconds(60)).unwrap();
+        let newer = new_path(chain_file, "before-flood", now).unwrap();
+        let other = new_path(chain_file, "other", now - Duration::seconds(30)).unwrap();
+        for snapshot in &[&older, &newer, &other] {
+            File::create(&snapshot.location).unwrap();
+        }
+        File::create(dir(chain_file).join("notes.txt")).unwrap();
+
+        let names = list(chain_file).unwrap().into_iter().map(|s| s.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["before-flood", "other", "before-flood"]);
+        assert_eq!(list(chain_file).unwrap()[2].location, newer.location);
+        assert_eq!(parse_file_name("before-flood.20200101-120000.cbor").map(|(name, _)| name),
+                   Some("before-flood".to_string()));
+        assert_eq!(parse_file_name("notes.txt"), None);
+        fs::remove_dir_all(dir(chain_file)).unwrap();
+    }
+
+    #[test]
+    fn names_have_to_be_safe() {
+        assert!(check_name("pre_flood-2").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("../chains").is_err());
+        assert!(check_name(&"x".repeat(40)).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::casemap;
+use crate::config;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Most tokens a message can have and still be trained on.
+pub const DEFAULT_MAX_TRAIN_TOKENS: usize = 60;
+/// Least share of a message's characters, not counting spaces, that have to be letters for it to be trained on.
+pub const DEFAULT_MIN_LETTER_RATIO: f64 = 0.5;
+/// Seconds that saying the same thing again counts as repeating it.
+pub const DEFAULT_REPEAT_WINDOW: u64 = 300;
+
+/// Keeps things that aren't really talking, like pasted stack traces, long links, and lines said over and over, out
+/// of the chains.
+#[derive(Clone, Debug)]
+pub struct SpamFilter {
+    /// Most tokens a message can have, or 0 for no limit.
+    max_tokens: usize,
+    min_letter_ratio: f64,
+    /// How long a repeated line counts as a repeat, where 0 lets repeats through.
+    repeat_window: Duration,
+    /// The last thing each user said in each channel and when, keyed by casefolded (channel, nick).
+    last: HashMap<(String, String), (String, Instant)>,
+}
+
+impl SpamFilter {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        let min_letter_ratio = config::option(options, "min_letter_ratio")?.unwrap_or(DEFAULT_MIN_LETTER_RATIO);
+        if !(0.0..=1.0).contains(&min_letter_ratio) {
+            return Err("min_letter_ratio must be between 0.0 and 1.0".to_string());
+        }
+        Ok(SpamFilter {
+            max_tokens: config::option(options, "max_train_tokens")?.unwrap_or(DEFAULT_MAX_TRAIN_TOKENS),
+            min_letter_ratio,
+            repeat_window: Duration::from_secs(config::option(options, "repeat_window")?
+                .unwrap_or(DEFAULT_REPEAT_WINDOW)),
+            last: HashMap::new(),
+        })
+    }
+
+    /// Takes the settings from a freshly built filter, keeping track of what everyone said last.
+    pub fn reconfigure(&mut self, fresh: SpamFilter) {
+        self.max_tokens = fresh.max_tokens;
+        self.min_letter_ratio = fresh.min_letter_ratio;
+        self.repeat_window = fresh.repeat_window;
+    }
+
+    /// Checks a message someone just said, which splits into `tokens` tokens, returning why it shouldn't be trained
+    /// on, if it shouldn't be.
+    pub fn check(&mut self, channel: &str, user: &str, message: &str, tokens: usize, now: Instant)
+        -> Option<&'static str>
+    {
+        let repeated = self.record(channel, user, message, now);
+        self.junk(message, tokens).or(if repeated { Some("repeated") } else { None })
+    }
+
+    /// Checks a message on its own, without regard to who said it or when, returning why it shouldn't be trained on,
+    /// if it shouldn't be.
+    pub fn junk(&self, message: &str, tokens: usize) -> Option<&'static str> {
+        if self.max_tokens > 0 && tokens > self.max_tokens {
+            return Some("too long");
+        }
+        let (letters, total) = message
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .fold((0, 0), |(letters, total), c| (letters + c.is_alphabetic() as usize, total + 1));
+        if total > 0 && (letters as f64) < total as f64 * self.min_letter_ratio {
+            return Some("mostly not letters");
+        }
+        None
+    }
+
+    /// Remembers what a user said, returning whether they said the same thing last time, not long ago.
+    fn record(&mut self, channel: &str, user: &str, message: &str, now: Instant) -> bool {
+        let window = self.repeat_window;
+        self.last.retain(|_, &mut (_, when)| now.duration_since(when) <= window);
+        let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+        let key = (casemap::fold(channel), casemap::fold(user));
+        let repeated = self.last.get(&key).map(|(last, _)| *last == message).unwrap_or(false);
+        if window.as_secs() > 0 {
+            self.last.insert(key, (message, now));
+        }
+        repeated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpamFilter;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    fn filter() -> SpamFilter {
+        let options = [("max_train_tokens", "5"), ("min_letter_ratio", "0.5"), ("repeat_window", "60")]
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        SpamFilter::from_options(&options).unwrap()
+    }
+
+    #[test]
+    fn skips_long_and_symbol_heavy_messages() {
+        let filter = filter();
+        assert_eq!(filter.junk("one two three", 3), None);
+        assert_eq!(filter.junk("one two three four five six", 6), Some("too long"));
+        assert_eq!(filter.junk("at Foo.bar(Foo.java:123)", 2), None);
+        assert_eq!(filter.junk("0x7fff5fbff8c8 0x00000001 +++", 3), Some("mostly not letters"));
+        assert_eq!(filter.junk("", 0), None);
+    }
+
+    #[test]
+    fn skips_lines_repeated_within_the_window() {
+        let mut filter = filter();
+        let start = Instant::now();
+        assert_eq!(filter.check("#a", "alice", "buy now", 2, start), None);
+        assert_eq!(filter.check("#a", "Alice", "buy  now", 2, start + Duration::from_secs(10)), Some("repeated"));
+        // someone else, somewhere else, or much later is fine
+        assert_eq!(filter.check("#a", "bob", "buy now", 2, start), None);
+        assert_eq!(filter.check("#b", "alice", "buy now", 2, start), None);
+        assert_eq!(filter.check("#a", "alice", "buy now", 2, start + Duration::from_secs(100)), None);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::atomic;
+use crate::checksum;
+use crate::compress::{self, Compression};
+use markov_chain::Chain;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const SPLIT_VERSION: u32 = 1;
+
+/// Each user's chain in a channel, by casefolded nick.
+pub type ChannelChains = HashMap<String, Chain<String>>;
+
+/// The index of a split data directory: which channels have files, and everything else that gets saved, which is
+/// small enough to always be loaded.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Index<T> {
+    version: u32,
+    /// Casefolded channels that have a file in the channels directory.
+    pub channels: BTreeSet<String>,
+    pub rest: T,
+}
+
+impl<T> Index<T> {
+    pub fn new(channels: BTreeSet<String>, rest: T) -> Self {
+        Index { version: SPLIT_VERSION, channels, rest }
+    }
+}
+
+/// One channel's chains, as saved in its own file.
+#[derive(Serialize, Deserialize)]
+struct ChannelFile {
+    version: u32,
+    channel: String,
+    chains: ChannelChains,
+}
+
+/// A data directory that keeps each channel's chains in a file of its own, so that a corrupt file only loses one
+/// channel, and channels can be loaded as they're needed instead of all at startup.
+///
+/// The directory holds `index.cbor`, and a file for each channel in `channels/`, named after the channel with
+/// anything that isn't a letter, digit, `-`, or `_` percent-encoded.
+#[derive(Clone, Debug)]
+pub struct SplitStore {
+    dir: PathBuf,
+    backups: usize,
+    compression: Compression,
+}
+
+impl SplitStore {
+    /// Opens a data directory, creating it if it's not there yet. Files are written with the given compression, and up
+    /// to `backups` older copies of each are kept.
+    pub fn open(dir: &str, backups: usize, compression: Compression) -> io::Result<Self> {
+        let store = SplitStore { dir: PathBuf::from(dir), backups, compression };
+        fs::create_dir_all(store.dir.join("channels"))?;
+        Ok(store)
+    }
+
+    /// Changes how files are compressed from the next time they're written.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.cbor")
+    }
+
+    fn channel_path(&self, channel: &str) -> PathBuf {
+        self.dir.join("channels").join(format!("{}.cbor", encode(channel)))
+    }
+
+    /// Reads the index, failing with `NotFound` if nothing has been saved yet.
+    pub fn load_index<T: DeserializeOwned>(&self) -> io::Result<Index<T>> {
+        let data = compress::decompress(checksum::strip(&fs::read(self.index_path())?)?.to_vec())?;
+        let index = cbor::from_slice::<Index<T>>(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid index: {}", e)))?;
+        if index.version > SPLIT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("index version {} is newer than the supported version {}",
+                                              index.version, SPLIT_VERSION)));
+        }
+        Ok(index)
+    }
+
+    /// Reads a channel's chains, which are empty if it has no file.
+    ///
+    /// A file that can't be decoded is moved aside to `<file>.corrupt`, so that it isn't saved over and can be looked
+    /// at, and an error is returned; the channel starts over empty after that.
+    pub fn load_channel(&self, channel: &str) -> io::Result<ChannelChains> {
+        let path = self.channel_path(channel);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        let decoded = checksum::strip(&data)
+            .and_then(|data| compress::decompress(data.to_vec()))
+            .map_err(|e| e.to_string())
+            .and_then(|data| cbor::from_slice::<ChannelFile>(&data).map_err(|e| e.to_string()));
+        match decoded {
+            Ok(file) => Ok(file.chains),
+            Err(e) => {
+                fs::rename(&path, format!("{}.corrupt", path.display()))?;
+                Err(io::Error::new(io::ErrorKind::InvalidData,
+                                   format!("invalid channel file {}, moved aside: {}", path.display(), e)))
+            }
+        }
+    }
+
+    /// Writes a channel's chains, or removes its file if it has none.
+    pub fn save_channel(&self, channel: &str, chains: Option<&ChannelChains>) -> io::Result<()> {
+        let path = self.channel_path(channel);
+        match chains.filter(|chains| !chains.is_empty()) {
+            Some(chains) => {
+                let file = ChannelFile { version: SPLIT_VERSION, channel: channel.to_string(), chains: chains.clone() };
+                let data = self.compression.compress(cbor::to_vec(&file).unwrap())?;
+                atomic::write(&path.to_string_lossy(), &data, self.backups)
+            }
+            None => match fs::remove_file(&path) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            },
+        }
+    }
+
+    /// Writes the index. This goes last in a save, so that it never lists a channel whose file isn't written yet.
+    pub fn save_index<T: Serialize>(&self, index: &Index<T>) -> io::Result<()> {
+        let data = self.compression.compress(cbor::to_vec(index).unwrap())?;
+        atomic::write(&self.index_path().to_string_lossy(), &data, self.backups)
+    }
+
+    /// Removes the files of channels that aren't in the given set, for when the whole directory is written at once.
+    pub fn remove_others(&self, keep: &BTreeSet<String>) -> io::Result<()> {
+        let keep = keep.iter().map(|channel| self.channel_path(channel)).collect::<BTreeSet<_>>();
+        for path in self.channel_files()? {
+            if !keep.contains(&path) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets how much space the index and channel files take up, not counting backups.
+    pub fn size(&self) -> io::Result<u64> {
+        let mut size = fs::metadata(self.index_path())?.len();
+        for path in self.channel_files()? {
+            size += fs::metadata(&path)?.len();
+        }
+        Ok(size)
+    }
+
+    /// Gets how much space the index and channel files take up, and how much they would without compression. This
+    /// reads every file, so it's meant for the command line rather than a running bot.
+    pub fn sizes(&self) -> io::Result<(u64, u64)> {
+        let (mut size, mut raw) = compress::file_sizes(&self.index_path())?;
+        for path in self.channel_files()? {
+            let (file_size, file_raw) = compress::file_sizes(&path)?;
+            size += file_size;
+            raw += file_raw;
+        }
+        Ok((size, raw))
+    }
+
+    fn channel_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(self.dir.join("channels"))? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("cbor") {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Turns a channel name into something that's safe to use as a file name.
+fn encode(channel: &str) -> String {
+    let mut encoded = String::new();
+    for b in channel.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, Index, SplitStore};
+    use crate::compress::Compression;
+    use markov_chain::Chain;
+    use std::collections::{BTreeSet, HashMap};
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn channel_names_make_safe_file_names() {
+        assert_eq!(encode("#rust-beginners"), "%23rust-beginners");
+        assert_eq!(encode("#../etc"), "%23%2E%2E%2Fetc");
+    }
+
+    #[test]
+    fn corrupt_channels_are_moved_aside() {
+        let dir = env::temp_dir().join(format!("markov-bot-split-test-{}.data", process::id()));
+        let store = SplitStore::open(dir.to_str().unwrap(), 0, Compression::Gzip(6)).unwrap();
+        let mut chain = Chain::new(1);
+        chain.train(vec!["hello".to_string()]);
+        let chains = vec![("alice".to_string(), chain)].into_iter().collect::<HashMap<_, _>>();
+        store.save_channel("#good", Some(&chains)).unwrap();
+        fs::write(dir.join("channels").join("%23bad.cbor"), b"not cbor").unwrap();
+        let channels = ["#good", "#bad"].iter().map(|c| c.to_string()).collect::<BTreeSet<_>>();
+        store.save_index(&Index::new(channels, ())).unwrap();
+
+        let index = store.load_index::<()>().unwrap();
+        assert_eq!(index.channels.len(), 2);
+        assert_eq!(store.load_channel("#good").unwrap()["alice"].chain(), chains["alice"].chain());
+        assert!(store.load_channel("#bad").is_err());
+        assert!(dir.join("channels").join("%23bad.cbor.corrupt").exists());
+        // once it's out of the way, the channel is just empty
+        assert!(store.load_channel("#bad").unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
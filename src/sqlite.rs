@@ -0,0 +1,470 @@
+use crate::bot::{ChainMap, UserSettings, UserSettingsMap};
+use markov_chain::Chain;
+use crate::quote::QuoteMap;
+use crate::rawchain::RawChain;
+use crate::runtime::RuntimeSettings;
+use rusqlite::{self, Connection, OptionalExtension, NO_PARAMS};
+use crate::stats::LifetimeCounters;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS chains (
+        channel TEXT NOT NULL,
+        user TEXT NOT NULL,
+        chain_order INTEGER NOT NULL,
+        PRIMARY KEY (channel, user)
+    );
+    CREATE TABLE IF NOT EXISTS transitions (
+        channel TEXT NOT NULL,
+        user TEXT NOT NULL,
+        state TEXT NOT NULL,
+        next TEXT NOT NULL,
+        weight INTEGER NOT NULL,
+        PRIMARY KEY (channel, user, state, next)
+    );
+    CREATE TABLE IF NOT EXISTS user_settings (
+        channel TEXT NOT NULL,
+        user TEXT NOT NULL,
+        ignored INTEGER NOT NULL,
+        chance REAL NOT NULL,
+        no_emulate INTEGER NOT NULL DEFAULT 0,
+        capped_tokens INTEGER NOT NULL DEFAULT 0,
+        cap_started INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (channel, user)
+    );
+    CREATE TABLE IF NOT EXISTS aliases (
+        alias TEXT PRIMARY KEY,
+        nick TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS bot_overrides (
+        nick TEXT PRIMARY KEY,
+        bot INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS last_seen (
+        nick TEXT PRIMARY KEY,
+        seen INTEGER NOT NULL
+    );
+";
+
+/// Everything that gets loaded out of a database.
+pub struct Contents {
+    pub chains: ChainMap,
+    pub user_settings: UserSettingsMap,
+    pub aliases: HashMap<String, String>,
+    pub bot_overrides: HashMap<String, bool>,
+    pub last_pruned: Option<i64>,
+    pub last_seen: HashMap<String, i64>,
+    pub lifetime: LifetimeCounters,
+    pub runtime_settings: RuntimeSettings,
+    pub topic_chains: HashMap<String, Chain<String>>,
+    pub quotes: QuoteMap,
+    pub order: usize,
+}
+
+/// Chains and user settings kept in SQLite, so a save only rewrites the chains that changed since the last one
+/// instead of the whole corpus.
+///
+/// Transitions are stored one per row, with the state and the next word as JSON so that sentence boundaries (the
+/// `None`s) survive.
+pub struct SqliteStore {
+    conn: Connection,
+    path: String,
+}
+
+impl SqliteStore {
+    /// Opens a database, creating it and its tables if they don't exist yet.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(to_io)?;
+        conn.execute_batch(SCHEMA).map_err(to_io)?;
+        add_column(&conn, "user_settings", "no_emulate", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(&conn, "user_settings", "capped_tokens", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(&conn, "user_settings", "cap_started", "INTEGER NOT NULL DEFAULT 0")?;
+        Ok(SqliteStore { conn, path: path.to_string() })
+    }
+
+    /// Gets the size of the database file on disk.
+    pub fn file_size(&self) -> Option<u64> {
+        fs::metadata(&self.path).ok().map(|m| m.len())
+    }
+
+    /// Loads everything in the database, or None if nothing has been saved to it yet.
+    pub fn load(&self) -> io::Result<Option<Contents>> {
+        let order = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'order'", NO_PARAMS, |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(to_io)?;
+        let order = match order {
+            Some(order) => order.parse::<usize>().map_err(|e| invalid(e.to_string()))?,
+            None => return Ok(None),
+        };
+
+        let mut raw: HashMap<(String, String), RawChain> = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT channel, user, chain_order FROM chains").map_err(to_io)?;
+            let rows = stmt
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)?)))
+                .map_err(to_io)?;
+            for row in rows {
+                let (channel, user, chain_order) = row.map_err(to_io)?;
+                let chain = RawChain::new(chain_order as usize);
+                raw.insert((channel, user), chain);
+            }
+        }
+        {
+            let mut stmt = self.conn
+                .prepare("SELECT channel, user, state, next, weight FROM transitions")
+                .map_err(to_io)?;
+            let rows = stmt
+                .query_map(NO_PARAMS, |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?,
+                        row.get::<_, i64>(4)?))
+                })
+                .map_err(to_io)?;
+            for row in rows {
+                let (channel, user, state, next, weight) = row.map_err(to_io)?;
+                let state: Vec<Option<String>> = serde_json::from_str(&state).map_err(|e| invalid(e.to_string()))?;
+                let next: Option<String> = serde_json::from_str(&next).map_err(|e| invalid(e.to_string()))?;
+                let chain = raw.get_mut(&(channel, user))
+                    .ok_or_else(|| invalid("transition for a chain that doesn't exist".to_string()))?;
+                chain.chain
+                    .entry(state)
+                    .or_insert_with(HashMap::new)
+                    .insert(next, weight as u32);
+            }
+        }
+        let mut chains: ChainMap = HashMap::new();
+        for ((channel, user), chain) in raw {
+            let chain = chain.into_chain().map_err(invalid)?;
+            chains.entry(channel).or_default().insert(user, chain);
+        }
+
+        let mut user_settings: UserSettingsMap = HashMap::new();
+        {
+            let mut stmt = self.conn
+                .prepare("SELECT channel, user, ignored, chance, no_emulate, capped_tokens, cap_started \
+                          FROM user_settings")
+                .map_err(to_io)?;
+            let rows = stmt
+                .query_map(NO_PARAMS, |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get::<_, i64>(5)?,
+                        row.get(6)?))
+                })
+                .map_err(to_io)?;
+            for row in rows {
+                let (channel, user, ignore, chance, no_emulate, capped_tokens, cap_started):
+                    (String, String, bool, f64, bool, i64, i64) = row.map_err(to_io)?;
+                let capped_tokens = capped_tokens as u64;
+                let settings = UserSettings { ignore, chance, no_emulate, capped_tokens, cap_started };
+                user_settings
+                    .entry(channel)
+                    .or_default()
+                    .insert(user, settings);
+            }
+        }
+
+        let mut aliases = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT alias, nick FROM aliases").map_err(to_io)?;
+            let rows = stmt
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(to_io)?;
+            for row in rows {
+                let (alias, nick): (String, String) = row.map_err(to_io)?;
+                aliases.insert(alias, nick);
+            }
+        }
+
+        let mut bot_overrides = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT nick, bot FROM bot_overrides").map_err(to_io)?;
+            let rows = stmt
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(to_io)?;
+            for row in rows {
+                let (nick, bot): (String, bool) = row.map_err(to_io)?;
+                bot_overrides.insert(nick, bot);
+            }
+        }
+
+        let mut last_seen = HashMap::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT nick, seen FROM last_seen").map_err(to_io)?;
+            let rows = stmt
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(to_io)?;
+            for row in rows {
+                let (nick, seen): (String, i64) = row.map_err(to_io)?;
+                last_seen.insert(nick, seen);
+            }
+        }
+
+        let last_pruned = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'last_pruned'", NO_PARAMS, |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(to_io)?;
+        let last_pruned = match last_pruned {
+            Some(t) => Some(t.parse::<i64>().map_err(|e| invalid(e.to_string()))?),
+            None => None,
+        };
+
+        // kept as JSON, since nothing needs to look inside it but the bot
+        let lifetime = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'lifetime'", NO_PARAMS, |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(to_io)?;
+        let lifetime = match lifetime {
+            Some(json) => serde_json::from_str(&json).map_err(|e| invalid(e.to_string()))?,
+            None => LifetimeCounters::default(),
+        };
+        let runtime_settings = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'runtime_settings'", NO_PARAMS, |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()
+            .map_err(to_io)?;
+        let runtime_settings = match runtime_settings {
+            Some(json) => serde_json::from_str(&json).map_err(|e| invalid(e.to_string()))?,
+            None => RuntimeSettings::default(),
+        };
+        let topic_chains = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'topic_chains'", NO_PARAMS, |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(to_io)?;
+        let topic_chains = match topic_chains {
+            Some(json) => topics_from_json(&json)?,
+            None => HashMap::new(),
+        };
+        let quotes = self.conn
+            .query_row("SELECT value FROM meta WHERE key = 'quotes'", NO_PARAMS, |row| row.get::<_, String>(0))
+            .optional()
+            .map_err(to_io)?;
+        let quotes = match quotes {
+            Some(json) => serde_json::from_str(&json).map_err(|e| invalid(e.to_string()))?,
+            None => HashMap::new(),
+        };
+
+        Ok(Some(Contents {
+            chains,
+            user_settings,
+            aliases,
+            bot_overrides,
+            last_pruned,
+            last_seen,
+            lifetime,
+            runtime_settings,
+            topic_chains,
+            quotes,
+            order,
+        }))
+    }
+
+    /// Gets the casefolded (channel, user) pair of every chain in the database.
+    pub fn chain_keys(&self) -> io::Result<HashSet<(String, String)>> {
+        let mut stmt = self.conn.prepare("SELECT channel, user FROM chains").map_err(to_io)?;
+        let rows = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(to_io)?;
+        let mut keys = HashSet::new();
+        for row in rows {
+            keys.insert(row.map_err(to_io)?);
+        }
+        Ok(keys)
+    }
+
+    /// Loads a single user's chain for a channel, or None if there isn't one.
+    pub fn load_chain(&self, channel: &str, user: &str) -> io::Result<Option<Chain<String>>> {
+        let order = self.conn
+            .query_row(
+                "SELECT chain_order FROM chains WHERE channel = ?1 AND user = ?2",
+                &[channel, user],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .map_err(to_io)?;
+        let mut chain = match order {
+            Some(order) => RawChain::new(order as usize),
+            None => return Ok(None),
+        };
+        let mut stmt = self.conn
+            .prepare("SELECT state, next, weight FROM transitions WHERE channel = ?1 AND user = ?2")
+            .map_err(to_io)?;
+        let rows = stmt
+            .query_map(&[channel, user], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+            })
+            .map_err(to_io)?;
+        for row in rows {
+            let (state, next, weight) = row.map_err(to_io)?;
+            let state: Vec<Option<String>> = serde_json::from_str(&state).map_err(|e| invalid(e.to_string()))?;
+            let next: Option<String> = serde_json::from_str(&next).map_err(|e| invalid(e.to_string()))?;
+            chain.chain
+                .entry(state)
+                .or_insert_with(HashMap::new)
+                .insert(next, weight as u32);
+        }
+        chain.into_chain().map(Some).map_err(invalid)
+    }
+
+    /// Saves the chains listed in `changed`, along with all of the user settings, aliases, bot overrides, last seen
+    /// times, lifetime counters, runtime settings, topic chains, and quotes, in one transaction.
+    ///
+    /// A changed chain that's no longer in `chains` has been forgotten, and is deleted.
+    #[allow(clippy::too_many_arguments)]
+    pub fn save(
+        &mut self,
+        chains: &ChainMap,
+        changed: &HashSet<(String, String)>,
+        user_settings: &UserSettingsMap,
+        aliases: &HashMap<String, String>,
+        bot_overrides: &HashMap<String, bool>,
+        last_pruned: Option<i64>,
+        last_seen: &HashMap<String, i64>,
+        lifetime: &LifetimeCounters,
+        runtime_settings: &RuntimeSettings,
+        topic_chains: &HashMap<String, Chain<String>>,
+        quotes: &QuoteMap,
+        order: usize,
+    ) -> io::Result<()> {
+        let tx = self.conn.transaction().map_err(to_io)?;
+        for (channel, user) in changed {
+            tx.execute("DELETE FROM transitions WHERE channel = ?1 AND user = ?2", &[channel, user])
+                .map_err(to_io)?;
+            tx.execute("DELETE FROM chains WHERE channel = ?1 AND user = ?2", &[channel, user])
+                .map_err(to_io)?;
+            let chain = match chains.get(channel).and_then(|users| users.get(user)) {
+                Some(chain) => chain,
+                None => continue,
+            };
+            tx.execute(
+                "INSERT INTO chains (channel, user, chain_order) VALUES (?1, ?2, ?3)",
+                params![channel, user, chain.order() as i64],
+            ).map_err(to_io)?;
+            let mut stmt = tx
+                .prepare("INSERT INTO transitions (channel, user, state, next, weight) VALUES (?1, ?2, ?3, ?4, ?5)")
+                .map_err(to_io)?;
+            for (state, link) in chain.chain() {
+                let state = serde_json::to_string(state).unwrap();
+                for (next, weight) in link {
+                    let next = serde_json::to_string(next).unwrap();
+                    stmt.execute(params![channel, user, state, next, i64::from(*weight)])
+                        .map_err(to_io)?;
+                }
+            }
+        }
+
+        // settings, aliases, overrides, and last seen times are small enough to rewrite every time
+        tx.execute("DELETE FROM user_settings", NO_PARAMS).map_err(to_io)?;
+        for (channel, users) in user_settings {
+            for (user, settings) in users {
+                tx.execute(
+                    "INSERT INTO user_settings (channel, user, ignored, chance, no_emulate, capped_tokens, \
+                     cap_started) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![channel, user, settings.ignore, settings.chance, settings.no_emulate,
+                            settings.capped_tokens as i64, settings.cap_started],
+                ).map_err(to_io)?;
+            }
+        }
+        tx.execute("DELETE FROM aliases", NO_PARAMS).map_err(to_io)?;
+        for (alias, nick) in aliases {
+            tx.execute("INSERT INTO aliases (alias, nick) VALUES (?1, ?2)", &[alias, nick])
+                .map_err(to_io)?;
+        }
+        tx.execute("DELETE FROM bot_overrides", NO_PARAMS).map_err(to_io)?;
+        for (nick, bot) in bot_overrides {
+            tx.execute("INSERT INTO bot_overrides (nick, bot) VALUES (?1, ?2)", params![nick, bot])
+                .map_err(to_io)?;
+        }
+        tx.execute("DELETE FROM last_seen", NO_PARAMS).map_err(to_io)?;
+        for (nick, seen) in last_seen {
+            tx.execute("INSERT INTO last_seen (nick, seen) VALUES (?1, ?2)", params![nick, seen])
+                .map_err(to_io)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('order', ?1)",
+            &[&order.to_string()],
+        ).map_err(to_io)?;
+        if let Some(last_pruned) = last_pruned {
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES ('last_pruned', ?1)",
+                &[&last_pruned.to_string()],
+            ).map_err(to_io)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('lifetime', ?1)",
+            &[&serde_json::to_string(lifetime).unwrap()],
+        ).map_err(to_io)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('runtime_settings', ?1)",
+            &[&serde_json::to_string(runtime_settings).unwrap()],
+        ).map_err(to_io)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('topic_chains', ?1)",
+            &[&topics_to_json(topic_chains)],
+        ).map_err(to_io)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('quotes', ?1)",
+            &[&serde_json::to_string(quotes).unwrap()],
+        ).map_err(to_io)?;
+        tx.commit().map_err(to_io)
+    }
+}
+
+/// Topic chains as they're kept in `meta`, by channel: each chain's order and its transitions as (state, next word,
+/// weight), since JSON can't have the states as keys.
+type TopicsJson = HashMap<String, (usize, Vec<(Vec<Option<String>>, Option<String>, u32)>)>;
+
+fn topics_to_json(topic_chains: &HashMap<String, Chain<String>>) -> String {
+    let topics: TopicsJson = topic_chains
+        .iter()
+        .map(|(channel, chain)| {
+            let transitions = chain.chain()
+                .iter()
+                .flat_map(|(state, link)| link.iter().map(move |(next, weight)| (state.clone(), next.clone(), *weight)))
+                .collect();
+            (channel.clone(), (chain.order(), transitions))
+        })
+        .collect();
+    serde_json::to_string(&topics).unwrap()
+}
+
+fn topics_from_json(json: &str) -> io::Result<HashMap<String, Chain<String>>> {
+    let topics: TopicsJson = serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?;
+    let mut topic_chains = HashMap::new();
+    for (channel, (order, transitions)) in topics {
+        let mut raw = RawChain::new(order);
+        for (state, next, weight) in transitions {
+            raw.chain.entry(state).or_default().insert(next, weight);
+        }
+        topic_chains.insert(channel, raw.into_chain().map_err(invalid)?);
+    }
+    Ok(topic_chains)
+}
+
+/// Adds a column to a table made before the column was in the schema, unless it's already there.
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> io::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).map_err(to_io)?;
+    let columns = stmt
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(1))
+        .map_err(to_io)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(to_io)?;
+    if !columns.iter().any(|c| c == column) {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .map_err(to_io)?;
+    }
+    Ok(())
+}
+
+fn to_io(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid database: {}", message))
+}
@@ -0,0 +1,243 @@
+use chrono::{DateTime, Local};
+use crate::generate;
+use markov_chain::Chain;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::time::{Duration, Instant};
+
+/// Running counters for a bot, reported by `!markov stats`.
+pub struct Counters {
+    pub started: Instant,
+    /// Channel messages seen this session, trained on or not.
+    pub messages_seen: u64,
+    pub messages_trained: u64,
+    pub last_save: Option<DateTime<Local>>,
+    /// How long the last save took.
+    pub last_save_duration: Option<Duration>,
+    pub saves: u64,
+    pub reconnects: u64,
+    /// Messages only logged this session, which would have been sent if it weren't for shadow mode.
+    pub shadowed: u64,
+    /// Size of the blob file as of the last save.
+    pub blob_size: Option<u64>,
+    /// Size the blob file would be without compression, as of the last save, if it's known.
+    pub blob_raw_size: Option<u64>,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Counters {
+            started: Instant::now(),
+            messages_seen: 0,
+            messages_trained: 0,
+            last_save: None,
+            last_save_duration: None,
+            saves: 0,
+            reconnects: 0,
+            shadowed: 0,
+            blob_size: None,
+            blob_raw_size: None,
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Records a save that just finished after taking the given time.
+    pub fn record_save(&mut self, duration: Duration) {
+        self.last_save = Some(Local::now());
+        self.last_save_duration = Some(duration);
+        self.saves += 1;
+    }
+}
+
+/// Counters kept for as long as the chains are, saved along with them and reported by `!markov stats`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LifetimeCounters {
+    /// Messages trained on, by casefolded channel and then by canonical nick.
+    pub trained: HashMap<String, HashMap<String, u64>>,
+    /// Replies generated from the chains and sent, in channels or in private chats.
+    pub replies_sent: u64,
+    pub commands_handled: u64,
+}
+
+impl LifetimeCounters {
+    /// Counts a message trained on. `channel` is casefolded and `user` is a canonical nick.
+    pub fn record_trained(&mut self, channel: &str, user: &str) {
+        *self.trained
+            .entry(channel.to_string())
+            .or_default()
+            .entry(user.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Gets how many messages have been trained on from a user in a channel.
+    pub fn trained_from(&self, channel: &str, user: &str) -> u64 {
+        self.trained.get(channel).and_then(|users| users.get(user)).cloned().unwrap_or(0)
+    }
+
+    /// Gets how many messages have been trained on, from everyone, everywhere.
+    pub fn total_trained(&self) -> u64 {
+        self.trained.values().flat_map(|users| users.values()).sum()
+    }
+
+    /// Adds what's been counted for one user onto another's, everywhere, when one becomes an alias of the other.
+    pub fn merge_user(&mut self, old: &str, new: &str) {
+        for users in self.trained.values_mut() {
+            if let Some(count) = users.remove(old) {
+                *users.entry(new.to_string()).or_insert(0) += count;
+            }
+        }
+    }
+
+    /// Drops the count for a user in a channel, along with the channel if nobody else is left in it.
+    pub fn forget(&mut self, channel: &str, user: &str) {
+        let empty = match self.trained.get_mut(channel) {
+            Some(users) => {
+                users.remove(user);
+                users.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.trained.remove(channel);
+        }
+    }
+}
+
+/// Guess at the overhead of a hash map entry, on top of what's in it.
+const ENTRY_SIZE: u64 = 32;
+
+/// Gets the size of a word in a chain, counting the `Option<String>` it's kept in.
+fn word_size(word: &Option<String>) -> u64 {
+    24 + word.as_ref().map(|w| w.len() as u64).unwrap_or(0)
+}
+
+/// How much a chain, or a group of chains, has learned.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChainStats {
+    /// Total weight of every transition, which is roughly the number of words trained on.
+    pub tokens: u64,
+    /// Number of distinct states that have at least one transition out of them.
+    pub states: u64,
+    /// Rough number of bytes the chain takes up in memory.
+    pub bytes: u64,
+}
+
+impl ChainStats {
+    pub fn of(chain: &Chain<String>) -> Self {
+        let links = chain.chain();
+        ChainStats {
+            tokens: links
+                .values()
+                .map(|link| link.values().map(|&w| u64::from(w)).sum::<u64>())
+                .sum(),
+            states: links.len() as u64,
+            bytes: links
+                .iter()
+                .map(|(state, link)| {
+                    let state_bytes = state.iter().map(word_size).sum::<u64>() + ENTRY_SIZE;
+                    let link_bytes = link.keys().map(|w| word_size(w) + 4 + ENTRY_SIZE).sum::<u64>();
+                    state_bytes + link_bytes
+                })
+                .sum(),
+        }
+    }
+}
+
+impl Add for ChainStats {
+    type Output = ChainStats;
+
+    fn add(self, other: ChainStats) -> ChainStats {
+        ChainStats {
+            tokens: self.tokens + other.tokens,
+            states: self.states + other.states,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+/// Gets how many times each word comes up in a chain, most common first.
+///
+/// A word's count is the weight of every transition into it, which is how many times it was trained on.
+pub fn word_counts(chain: &Chain<String>) -> Vec<(&str, u64)> {
+    let mut counts = HashMap::new();
+    for link in chain.chain().values() {
+        for (next, &weight) in link {
+            match *next {
+                Some(ref word) if !generate::is_boundary(word) => {
+                    *counts.entry(word.as_str()).or_insert(0) += u64::from(weight);
+                }
+                _ => {}
+            }
+        }
+    }
+    sorted_counts(counts)
+}
+
+/// Gets how many times each word started a sentence in a chain, most common first.
+///
+/// Sentences start right after the start token, or from a state of nothing but padding in chains trained before there
+/// were boundary tokens.
+pub fn start_counts(chain: &Chain<String>) -> Vec<(&str, u64)> {
+    let padding = vec![None; chain.order()];
+    let mut counts = HashMap::new();
+    for (state, link) in chain.chain() {
+        if *state != padding && state.last() != Some(&Some(generate::START.to_string())) {
+            continue;
+        }
+        for (next, &weight) in link {
+            match *next {
+                Some(ref word) if !generate::is_boundary(word) => {
+                    *counts.entry(word.as_str()).or_insert(0) += u64::from(weight);
+                }
+                _ => {}
+            }
+        }
+    }
+    sorted_counts(counts)
+}
+
+/// Gets the number of distinct words in a chain.
+pub fn vocabulary(chain: &Chain<String>) -> usize {
+    word_counts(chain).len()
+}
+
+fn sorted_counts(counts: HashMap<&str, u64>) -> Vec<(&str, u64)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    // ties go alphabetically, so the same chain always gives the same answer
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+}
+
+/// Formats a duration like `3d 4h 12m`, leaving off the larger units while they're zero.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, mins)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Formats a byte count with a binary unit.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
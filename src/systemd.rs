@@ -0,0 +1,78 @@
+use std::env;
+use std::process;
+use std::time::Duration;
+
+/// Tells systemd about a change in the bot's state, like `READY=1`, when it's running the bot as a `Type=notify`
+/// service. Does nothing unless systemd set `NOTIFY_SOCKET`.
+pub fn notify(state: &str) {
+    if let Ok(socket) = env::var("NOTIFY_SOCKET") {
+        if let Err(e) = send(&socket, state) {
+            warn!("could not notify systemd at {}: {}", socket, e);
+        }
+    }
+}
+
+/// Gets how often to ping the watchdog, which is half of what systemd allows between pings, or None if the watchdog
+/// isn't on for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|&usec| usec > 0)
+        .map(|usec| Duration::from_micros(usec / 2))
+}
+
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> ::std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+    let sender = UnixDatagram::unbound()?;
+    if let Some(name) = socket.strip_prefix('@') {
+        send_abstract(&sender, name, state)
+    } else {
+        sender.send_to(state.as_bytes(), socket).map(|_| ())
+    }
+}
+
+/// Sends to a socket in Linux's abstract namespace, which `NOTIFY_SOCKET` marks with a leading `@`.
+#[cfg(target_os = "linux")]
+fn send_abstract(sender: &::std::os::unix::net::UnixDatagram, name: &str, state: &str) -> ::std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+    let address = SocketAddr::from_abstract_name(name.as_bytes())?;
+    sender.send_to_addr(state.as_bytes(), &address).map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(_: &::std::os::unix::net::UnixDatagram, _: &str, _: &str) -> ::std::io::Result<()> {
+    Err(::std::io::Error::new(::std::io::ErrorKind::Other, "abstract sockets are only supported on Linux"))
+}
+
+#[cfg(not(unix))]
+fn send(_: &str, _: &str) -> ::std::io::Result<()> {
+    Err(::std::io::Error::new(::std::io::ErrorKind::Other, "systemd notifications are only supported on Unix"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::send;
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixDatagram;
+    use std::process;
+
+    #[test]
+    fn sends_state_to_the_notify_socket() {
+        let path = env::temp_dir().join(format!("markov-bot-notify-{}.sock", process::id()));
+        let listener = UnixDatagram::bind(&path).unwrap();
+        send(path.to_str().unwrap(), "READY=1\nSTATUS=connected to 1 server").unwrap();
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &b"READY=1\nSTATUS=connected to 1 server"[..]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::{self, Channel};
+use std::collections::HashMap;
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How messages are split into the tokens chains are trained on, and how generated tokens are put back together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tokenizer {
+    /// Split on whitespace, so punctuation stays stuck to the words around it.
+    Whitespace,
+    /// Split on Unicode word boundaries, which gives every CJK character and punctuation mark a token of its own.
+    Unicode,
+    /// Split on whitespace, then split punctuation off the start and end of each word.
+    Punctuation,
+}
+
+impl FromStr for Tokenizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "whitespace" => Ok(Tokenizer::Whitespace),
+            "unicode" => Ok(Tokenizer::Unicode),
+            "punctuation" => Ok(Tokenizer::Punctuation),
+            _ => Err(format!("tokenizer must be \"whitespace\", \"unicode\", or \"punctuation\", not {:?}", s)),
+        }
+    }
+}
+
+impl Tokenizer {
+    pub fn from_options(options: &HashMap<String, String>) -> Result<Self, String> {
+        Ok(config::option(options, "tokenizer")?.unwrap_or(Tokenizer::Whitespace))
+    }
+
+    /// Applies a channel's override, if it has one.
+    pub fn for_channel(self, channel: Option<&Channel>) -> Self {
+        channel
+            .and_then(|c| c.tokenizer.as_ref())
+            .map(|x| x.parse::<Tokenizer>().unwrap())
+            .unwrap_or(self)
+    }
+
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        match *self {
+            Tokenizer::Whitespace => text.split_whitespace().map(str::to_string).collect(),
+            Tokenizer::Unicode => text
+                .split_word_bounds()
+                .filter(|token| !token.trim().is_empty())
+                .map(str::to_string)
+                .collect(),
+            Tokenizer::Punctuation => text.split_whitespace().flat_map(split_punctuation).collect(),
+        }
+    }
+
+    /// Puts tokens back together into a sentence, leaving out the spaces that punctuation and CJK text go without.
+    pub fn join<S: AsRef<str>>(&self, tokens: &[S]) -> String {
+        let mut sentence = String::new();
+        for token in tokens {
+            let token = token.as_ref();
+            let space = match (sentence.chars().last(), token.chars().next()) {
+                (None, _) | (_, None) => false,
+                _ if *self == Tokenizer::Whitespace => true,
+                (Some(prev), Some(next)) => needs_space(prev, next),
+            };
+            if space {
+                sentence.push(' ');
+            }
+            sentence.push_str(token);
+        }
+        sentence
+    }
+
+    /// Counts the words in a sentence, which is how many tokens it has that aren't just punctuation.
+    pub fn count_words(&self, sentence: &str) -> usize {
+        self.tokenize(sentence)
+            .iter()
+            .filter(|token| token.chars().any(char::is_alphanumeric))
+            .count()
+    }
+}
+
+/// Splits a word into whatever punctuation leads it, the word itself, and whatever punctuation follows it. Words that
+/// are nothing but punctuation, like `:)`, are left alone.
+fn split_punctuation(word: &str) -> Vec<String> {
+    let start = match word.find(|c: char| !is_punctuation(c)) {
+        Some(start) => start,
+        None => return vec![word.to_string()],
+    };
+    let end = word.rfind(|c: char| !is_punctuation(c))
+        .map(|i| i + word[i..].chars().next().unwrap().len_utf8())
+        .unwrap();
+    [&word[..start], &word[start..end], &word[end..]]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || "…“”‘’«»¿¡。、，！？：；「」『』（）".contains(c)
+}
+
+/// Gets whether there goes a space between two tokens, given the characters either side of it.
+fn needs_space(prev: char, next: char) -> bool {
+    let closes = ".,!?;:)]}%…”’»。、，！？：；」』）".contains(next);
+    let opens = "([{“‘«¿¡「『（".contains(prev);
+    !(closes || opens || (is_unspaced(prev) && is_unspaced(next)))
+}
+
+/// Gets whether a character is from a script that's written without spaces between words.
+fn is_unspaced(c: char) -> bool {
+    match c as u32 {
+        0x3000..=0x303F | // CJK punctuation
+        0x3040..=0x30FF | // hiragana and katakana
+        0x3400..=0x4DBF | // CJK extension A
+        0x4E00..=0x9FFF | // CJK unified ideographs
+        0xFF00..=0xFFEF => true, // fullwidth forms
+        _ => false,
+    }
+}
@@ -0,0 +1,71 @@
+use regex::{self, Regex};
+
+/// Words and regexes to look for in messages, like the blacklist that keeps them from being trained on and
+/// generated sentences from being said.
+///
+/// Plain entries match a whole word, or run of words, in any case. Entries written between slashes, like
+/// `/fo+bar/`, are regexes searched for anywhere in the text, and only ignore case if they say so with `(?i)`.
+#[derive(Clone, Debug, Default)]
+pub struct WordList {
+    patterns: Vec<Regex>,
+}
+
+impl WordList {
+    /// Parses every entry of a list, failing on the first bad one.
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let patterns = entries
+            .iter()
+            .map(|s| s.as_ref().trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                if s.len() > 1 && s.starts_with('/') && s.ends_with('/') {
+                    Regex::new(&s[1..s.len() - 1]).map_err(|e| format!("invalid regex {}: {}", s, e))
+                } else {
+                    // \b doesn't work for words that start or end with punctuation, so look for anything but a word
+                    // character instead
+                    let words = s.split_whitespace().map(regex::escape).collect::<Vec<_>>().join(r"\s+");
+                    Regex::new(&format!(r"(?i)(?:^|[^\w]){}(?:$|[^\w])", words))
+                        .map_err(|e| format!("invalid word {}: {}", s, e))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(WordList { patterns })
+    }
+
+    /// Adds the entries of another list to this one.
+    pub fn extend(&mut self, other: &WordList) {
+        self.patterns.extend(other.patterns.iter().cloned());
+    }
+
+    /// Checks whether anything on the list shows up in some text.
+    pub fn matches(&self, text: &str) -> bool {
+        self.patterns.iter().any(|re| re.is_match(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WordList;
+
+    #[test]
+    fn words_match_whole_words_in_any_case() {
+        let list = WordList::parse(&["heck", "c++", "darn it"]).unwrap();
+        assert!(list.matches("what the heck"));
+        assert!(list.matches("HECK, no."));
+        assert!(!list.matches("the hecklers are back"));
+        assert!(list.matches("I write c++ for a living"));
+        assert!(!list.matches("c++11 is fine"));
+        assert!(list.matches("oh darn  it"));
+        assert!(!list.matches("darn, it's late"));
+    }
+
+    #[test]
+    fn regexes_search_anywhere() {
+        let list = WordList::parse(&[r"/bad\w*/", "/(?i)spoiler/"]).unwrap();
+        assert!(list.matches("notbadatall"));
+        assert!(list.matches("SPOILERS ahead"));
+        assert!(!list.matches("BAD"));
+        assert!(WordList::parse(&["/(/"]).is_err());
+        assert!(!WordList::default().matches("anything"));
+    }
+}